@@ -0,0 +1,566 @@
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result, anyhow, bail};
+use flate2::Compression;
+use flate2::read::{DeflateDecoder, GzDecoder};
+use flate2::write::{DeflateEncoder, GzEncoder};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Zip,
+    Tar,
+    TarGz,
+}
+
+impl Format {
+    fn detect(path: &Path) -> Option<Self> {
+        let name = path.file_name()?.to_string_lossy().to_ascii_lowercase();
+        if name.ends_with(".zip") {
+            Some(Format::Zip)
+        } else if name.ends_with(".tar") {
+            Some(Format::Tar)
+        } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Some(Format::TarGz)
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MemberRef {
+    pub archive: PathBuf,
+    pub member: String,
+    pub format: Format,
+}
+
+impl MemberRef {
+    // `bundle.zip!docs/readme.md`; anything whose left side is not a known
+    // archive extension is treated as an ordinary path.
+    pub fn parse(raw: &str) -> Option<Self> {
+        let (archive, member) = raw.split_once('!')?;
+        let member = normalize_member(member);
+        if archive.is_empty() || member.is_empty() {
+            return None;
+        }
+        let archive = PathBuf::from(archive);
+        let format = Format::detect(&archive)?;
+        Some(MemberRef {
+            archive,
+            member,
+            format,
+        })
+    }
+
+    pub fn read(&self) -> Result<Vec<u8>> {
+        let raw = std::fs::read(&self.archive)
+            .with_context(|| format!("reading archive {}", self.archive.display()))?;
+        read_member(self.format, &raw, &self.member).with_context(|| self.to_string())
+    }
+
+    // Returns the complete rewritten archive; the caller owns the atomic write.
+    pub fn rewrite(&self, data: &[u8]) -> Result<Vec<u8>> {
+        let raw = std::fs::read(&self.archive)
+            .with_context(|| format!("reading archive {}", self.archive.display()))?;
+        rewrite_member(self.format, &raw, &self.member, data).with_context(|| self.to_string())
+    }
+}
+
+impl std::fmt::Display for MemberRef {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}!{}", self.archive.display(), self.member)
+    }
+}
+
+// Drops empty, `.` and `..` segments so a member name can never reach
+// outside the staging directory it is copied into.
+fn normalize_member(member: &str) -> String {
+    member
+        .split(['/', '\\'])
+        .filter(|part| !matches!(*part, "" | "." | ".."))
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+pub fn read_member(format: Format, raw: &[u8], member: &str) -> Result<Vec<u8>> {
+    match format {
+        Format::Zip => {
+            let entries = zip_entries(raw)?;
+            let entry = find_zip_entry(&entries, member)?;
+            zip_inflate(entry)
+        }
+        Format::Tar => tar_read(raw, member),
+        Format::TarGz => tar_read(&gunzip(raw)?, member),
+    }
+}
+
+pub fn rewrite_member(format: Format, raw: &[u8], member: &str, data: &[u8]) -> Result<Vec<u8>> {
+    match format {
+        Format::Zip => zip_rewrite(raw, member, data),
+        Format::Tar => tar_rewrite(raw, member, data),
+        Format::TarGz => {
+            let tar = tar_rewrite(&gunzip(raw)?, member, data)?;
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(&tar)?;
+            Ok(encoder.finish()?)
+        }
+    }
+}
+
+fn gunzip(raw: &[u8]) -> Result<Vec<u8>> {
+    let mut out = Vec::new();
+    GzDecoder::new(raw)
+        .read_to_end(&mut out)
+        .context("decompressing gzip stream")?;
+    Ok(out)
+}
+
+fn u16_at(raw: &[u8], at: usize) -> Result<u16> {
+    raw.get(at..at + 2)
+        .map(|b| u16::from_le_bytes([b[0], b[1]]))
+        .ok_or_else(|| anyhow!("zip structure truncated at offset {at}"))
+}
+
+fn u32_at(raw: &[u8], at: usize) -> Result<u32> {
+    raw.get(at..at + 4)
+        .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .ok_or_else(|| anyhow!("zip structure truncated at offset {at}"))
+}
+
+const ZIP_LOCAL: u32 = 0x0403_4b50;
+const ZIP_CENTRAL: u32 = 0x0201_4b50;
+const ZIP_END: u32 = 0x0605_4b50;
+
+struct ZipEntry<'a> {
+    name: String,
+    // Fixed 46-byte central header followed by name, extra, and comment; kept
+    // verbatim so attributes and timestamps survive a rewrite.
+    central: Vec<u8>,
+    data: &'a [u8],
+    method: u16,
+    flags: u16,
+    crc: u32,
+    size: u32,
+}
+
+struct ZipArchive<'a> {
+    entries: Vec<ZipEntry<'a>>,
+    comment: &'a [u8],
+}
+
+fn zip_entries(raw: &[u8]) -> Result<Vec<ZipEntry<'_>>> {
+    Ok(zip_parse(raw)?.entries)
+}
+
+fn zip_parse(raw: &[u8]) -> Result<ZipArchive<'_>> {
+    let floor = raw.len().saturating_sub(22 + u16::MAX as usize);
+    let end = (floor..=raw.len().saturating_sub(22))
+        .rev()
+        .find(|&at| u32_at(raw, at).ok() == Some(ZIP_END))
+        .ok_or_else(|| anyhow!("not a zip archive (no end of central directory)"))?;
+    let count = u16_at(raw, end + 10)?;
+    let cd_offset = u32_at(raw, end + 16)?;
+    if count == u16::MAX || cd_offset == u32::MAX {
+        bail!("zip64 archives are not supported");
+    }
+    let comment_len = u16_at(raw, end + 20)? as usize;
+    let comment = raw
+        .get(end + 22..end + 22 + comment_len)
+        .ok_or_else(|| anyhow!("zip comment truncated"))?;
+    let mut entries = Vec::new();
+    let mut at = cd_offset as usize;
+    for _ in 0..count {
+        if u32_at(raw, at)? != ZIP_CENTRAL {
+            bail!("corrupt zip central directory at offset {at}");
+        }
+        let flags = u16_at(raw, at + 8)?;
+        let method = u16_at(raw, at + 10)?;
+        let crc = u32_at(raw, at + 16)?;
+        let compressed = u32_at(raw, at + 20)? as usize;
+        let size = u32_at(raw, at + 24)?;
+        let name_len = u16_at(raw, at + 28)? as usize;
+        let extra_len = u16_at(raw, at + 30)? as usize;
+        let comment_len = u16_at(raw, at + 32)? as usize;
+        let local = u32_at(raw, at + 42)? as usize;
+        let record_len = 46 + name_len + extra_len + comment_len;
+        let central = raw
+            .get(at..at + record_len)
+            .ok_or_else(|| anyhow!("zip central directory truncated"))?
+            .to_vec();
+        let name = String::from_utf8_lossy(&central[46..46 + name_len]).into_owned();
+        if u32_at(raw, local)? != ZIP_LOCAL {
+            bail!("corrupt zip local header for {name}");
+        }
+        let start =
+            local + 30 + u16_at(raw, local + 26)? as usize + u16_at(raw, local + 28)? as usize;
+        let data = raw
+            .get(start..start + compressed)
+            .ok_or_else(|| anyhow!("zip data for {name} is truncated"))?;
+        entries.push(ZipEntry {
+            name,
+            central,
+            data,
+            method,
+            flags,
+            crc,
+            size,
+        });
+        at += record_len;
+    }
+    Ok(ZipArchive { entries, comment })
+}
+
+fn find_zip_entry<'e, 'a>(entries: &'e [ZipEntry<'a>], member: &str) -> Result<&'e ZipEntry<'a>> {
+    entries
+        .iter()
+        .find(|entry| normalize_member(&entry.name) == member)
+        .ok_or_else(|| anyhow!("member not found in archive"))
+}
+
+fn zip_inflate(entry: &ZipEntry) -> Result<Vec<u8>> {
+    if entry.flags & 1 != 0 {
+        bail!("encrypted zip members are not supported");
+    }
+    let data = match entry.method {
+        0 => entry.data.to_vec(),
+        8 => {
+            let mut out = Vec::with_capacity(entry.size as usize);
+            DeflateDecoder::new(entry.data)
+                .read_to_end(&mut out)
+                .context("inflating zip member")?;
+            out
+        }
+        other => bail!("unsupported zip compression method {other}"),
+    };
+    let mut crc = flate2::Crc::new();
+    crc.update(&data);
+    if crc.sum() != entry.crc {
+        bail!("zip member failed its CRC check");
+    }
+    Ok(data)
+}
+
+fn zip_rewrite(raw: &[u8], member: &str, data: &[u8]) -> Result<Vec<u8>> {
+    let archive = zip_parse(raw)?;
+    let target = find_zip_entry(&archive.entries, member)?;
+    // Decoding first rejects encrypted or exotic members before anything is
+    // re-encoded.
+    zip_inflate(target)?;
+    let replacement = match target.method {
+        0 => data.to_vec(),
+        _ => {
+            let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(data)?;
+            encoder.finish()?
+        }
+    };
+    let mut crc = flate2::Crc::new();
+    crc.update(data);
+    let too_big =
+        |len: usize| u32::try_from(len).map_err(|_| anyhow!("zip64 sizes are not supported"));
+
+    let mut out = Vec::with_capacity(raw.len() + replacement.len());
+    let mut central = Vec::new();
+    for entry in &archive.entries {
+        let is_target = std::ptr::eq(entry, target);
+        let (body, entry_crc, size) = if is_target {
+            (replacement.as_slice(), crc.sum(), too_big(data.len())?)
+        } else {
+            (entry.data, entry.crc, entry.size)
+        };
+        let offset = too_big(out.len())?;
+        let flags = entry.flags & !0x0008;
+        let name = &entry.central[46..46 + u16_at(&entry.central, 28)? as usize];
+        out.extend_from_slice(&ZIP_LOCAL.to_le_bytes());
+        out.extend_from_slice(&entry.central[6..8]);
+        out.extend_from_slice(&flags.to_le_bytes());
+        out.extend_from_slice(&entry.method.to_le_bytes());
+        out.extend_from_slice(&entry.central[12..16]);
+        out.extend_from_slice(&entry_crc.to_le_bytes());
+        out.extend_from_slice(&too_big(body.len())?.to_le_bytes());
+        out.extend_from_slice(&size.to_le_bytes());
+        out.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        out.extend_from_slice(&0u16.to_le_bytes());
+        out.extend_from_slice(name);
+        out.extend_from_slice(body);
+
+        let mut record = entry.central.clone();
+        record[8..10].copy_from_slice(&flags.to_le_bytes());
+        record[16..20].copy_from_slice(&entry_crc.to_le_bytes());
+        record[20..24].copy_from_slice(&too_big(body.len())?.to_le_bytes());
+        record[24..28].copy_from_slice(&size.to_le_bytes());
+        record[42..46].copy_from_slice(&offset.to_le_bytes());
+        central.extend_from_slice(&record);
+    }
+    let cd_offset = too_big(out.len())?;
+    out.extend_from_slice(&central);
+    let count = archive.entries.len() as u16;
+    out.extend_from_slice(&ZIP_END.to_le_bytes());
+    out.extend_from_slice(&[0; 4]);
+    out.extend_from_slice(&count.to_le_bytes());
+    out.extend_from_slice(&count.to_le_bytes());
+    out.extend_from_slice(&too_big(central.len())?.to_le_bytes());
+    out.extend_from_slice(&cd_offset.to_le_bytes());
+    out.extend_from_slice(&(archive.comment.len() as u16).to_le_bytes());
+    out.extend_from_slice(archive.comment);
+    Ok(out)
+}
+
+const TAR_BLOCK: usize = 512;
+
+struct TarMember {
+    header: usize,
+    data: usize,
+    size: usize,
+}
+
+fn tar_field(header: &[u8], range: std::ops::Range<usize>) -> String {
+    let field = &header[range];
+    let end = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+    String::from_utf8_lossy(&field[..end]).into_owned()
+}
+
+fn tar_size(header: &[u8]) -> Result<usize> {
+    if header[124] & 0x80 != 0 {
+        bail!("base-256 tar sizes are not supported");
+    }
+    let digits = tar_field(header, 124..136);
+    let digits = digits.trim_matches([' ', '\0']);
+    if digits.is_empty() {
+        return Ok(0);
+    }
+    usize::from_str_radix(digits, 8).map_err(|_| anyhow!("invalid tar size field '{digits}'"))
+}
+
+fn tar_find(raw: &[u8], member: &str) -> Result<TarMember> {
+    let mut at = 0;
+    let mut long_name: Option<String> = None;
+    let mut pax_size = false;
+    while at + TAR_BLOCK <= raw.len() {
+        let header = &raw[at..at + TAR_BLOCK];
+        if header.iter().all(|&b| b == 0) {
+            break;
+        }
+        let size = tar_size(header)?;
+        let data = at + TAR_BLOCK;
+        let next = data + size.div_ceil(TAR_BLOCK) * TAR_BLOCK;
+        let body = raw
+            .get(data..data + size)
+            .ok_or_else(|| anyhow!("tar entry at offset {at} is truncated"))?;
+        match header[156] {
+            b'L' => {
+                long_name = Some(tar_field(body, 0..body.len()));
+                at = next;
+                continue;
+            }
+            b'x' => {
+                let records = String::from_utf8_lossy(body);
+                for record in records.lines() {
+                    let Some((_, field)) = record.split_once(' ') else {
+                        continue;
+                    };
+                    if let Some(path) = field.strip_prefix("path=") {
+                        long_name = Some(path.to_string());
+                    }
+                    pax_size |= field.starts_with("size=");
+                }
+                at = next;
+                continue;
+            }
+            _ => {}
+        }
+        let name = long_name.take().unwrap_or_else(|| {
+            let name = tar_field(header, 0..100);
+            let prefix = if &header[257..262] == b"ustar" {
+                tar_field(header, 345..500)
+            } else {
+                String::new()
+            };
+            if prefix.is_empty() {
+                name
+            } else {
+                format!("{prefix}/{name}")
+            }
+        });
+        let regular = matches!(header[156], b'0' | 0 | b'7');
+        if regular && normalize_member(&name) == member {
+            if pax_size {
+                bail!("tar members sized by pax headers are not supported");
+            }
+            return Ok(TarMember {
+                header: at,
+                data,
+                size,
+            });
+        }
+        pax_size = false;
+        at = next;
+    }
+    bail!("member not found in archive")
+}
+
+fn tar_read(raw: &[u8], member: &str) -> Result<Vec<u8>> {
+    let found = tar_find(raw, member)?;
+    Ok(raw[found.data..found.data + found.size].to_vec())
+}
+
+fn tar_set_size(header: &mut [u8], len: usize) {
+    header[124..136].copy_from_slice(format!("{len:011o}\0").as_bytes());
+    header[148..156].fill(b' ');
+    let checksum: u32 = header.iter().map(|&b| b as u32).sum();
+    header[148..156].copy_from_slice(format!("{checksum:06o}\0 ").as_bytes());
+}
+
+fn tar_rewrite(raw: &[u8], member: &str, data: &[u8]) -> Result<Vec<u8>> {
+    let found = tar_find(raw, member)?;
+    if data.len() >= 1 << 33 {
+        bail!("member is too large for a ustar size field");
+    }
+    let mut header = raw[found.header..found.data].to_vec();
+    tar_set_size(&mut header, data.len());
+
+    let old_end = found.data + found.size.div_ceil(TAR_BLOCK) * TAR_BLOCK;
+    let mut out = Vec::with_capacity(raw.len() + data.len());
+    out.extend_from_slice(&raw[..found.header]);
+    out.extend_from_slice(&header);
+    out.extend_from_slice(data);
+    out.resize(out.len().div_ceil(TAR_BLOCK) * TAR_BLOCK, 0);
+    out.extend_from_slice(&raw[old_end..]);
+    Ok(out)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    pub(crate) fn tar_entry(name: &str, data: &[u8]) -> Vec<u8> {
+        let mut header = vec![0u8; TAR_BLOCK];
+        header[..name.len()].copy_from_slice(name.as_bytes());
+        header[100..108].copy_from_slice(b"0000644\0");
+        header[156] = b'0';
+        header[257..263].copy_from_slice(b"ustar\0");
+        tar_set_size(&mut header, data.len());
+        let mut block = header;
+        block.extend_from_slice(data);
+        block.resize(block.len().div_ceil(TAR_BLOCK) * TAR_BLOCK, 0);
+        block
+    }
+
+    fn zip_with(members: &[(&str, &[u8], u16)]) -> Vec<u8> {
+        let mut out = Vec::new();
+        let mut central = Vec::new();
+        for (name, data, method) in members {
+            let body = if *method == 8 {
+                let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(data).unwrap();
+                encoder.finish().unwrap()
+            } else {
+                data.to_vec()
+            };
+            let mut crc = flate2::Crc::new();
+            crc.update(data);
+            let mut fixed = Vec::new();
+            fixed.extend_from_slice(&20u16.to_le_bytes());
+            fixed.extend_from_slice(&0u16.to_le_bytes());
+            fixed.extend_from_slice(&method.to_le_bytes());
+            fixed.extend_from_slice(&[0; 4]);
+            fixed.extend_from_slice(&crc.sum().to_le_bytes());
+            fixed.extend_from_slice(&(body.len() as u32).to_le_bytes());
+            fixed.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            fixed.extend_from_slice(&(name.len() as u16).to_le_bytes());
+            fixed.extend_from_slice(&0u16.to_le_bytes());
+            central.extend_from_slice(&ZIP_CENTRAL.to_le_bytes());
+            central.extend_from_slice(&20u16.to_le_bytes());
+            central.extend_from_slice(&fixed);
+            central.extend_from_slice(&[0; 10]);
+            central.extend_from_slice(&(out.len() as u32).to_le_bytes());
+            central.extend_from_slice(name.as_bytes());
+            out.extend_from_slice(&ZIP_LOCAL.to_le_bytes());
+            out.extend_from_slice(&fixed);
+            out.extend_from_slice(name.as_bytes());
+            out.extend_from_slice(&body);
+        }
+        let offset = out.len() as u32;
+        out.extend_from_slice(&central);
+        out.extend_from_slice(&ZIP_END.to_le_bytes());
+        out.extend_from_slice(&[0; 4]);
+        out.extend_from_slice(&(members.len() as u16).to_le_bytes());
+        out.extend_from_slice(&(members.len() as u16).to_le_bytes());
+        out.extend_from_slice(&(central.len() as u32).to_le_bytes());
+        out.extend_from_slice(&offset.to_le_bytes());
+        out.extend_from_slice(&0u16.to_le_bytes());
+        out
+    }
+
+    #[test]
+    fn member_refs_require_an_archive_extension() {
+        let parsed = MemberRef::parse("dist/bundle.tgz!./docs/readme.md").unwrap();
+        assert_eq!(parsed.archive, PathBuf::from("dist/bundle.tgz"));
+        assert_eq!(parsed.member, "docs/readme.md");
+        assert_eq!(parsed.format, Format::TarGz);
+        assert!(MemberRef::parse("notes!.txt").is_none());
+        assert!(MemberRef::parse("bundle.zip!").is_none());
+        let escaping = MemberRef::parse("bundle.tar!../../etc/./x").unwrap();
+        assert_eq!(escaping.member, "etc/x");
+        assert!(MemberRef::parse("bundle.tar!../..").is_none());
+    }
+
+    #[test]
+    fn parent_segments_in_entry_names_still_match() {
+        let mut raw = tar_entry("../../x.txt", b"inside\n");
+        raw.extend([0u8; 1024]);
+        assert_eq!(
+            read_member(Format::Tar, &raw, "x.txt").unwrap(),
+            b"inside\n"
+        );
+    }
+
+    #[test]
+    fn zip_members_round_trip_and_keep_neighbours() {
+        let raw = zip_with(&[
+            ("a.txt", b"alpha\n", 0),
+            ("docs/readme.md", b"hello world\n", 8),
+        ]);
+        assert_eq!(
+            read_member(Format::Zip, &raw, "docs/readme.md").unwrap(),
+            b"hello world\n"
+        );
+        let rewritten = rewrite_member(Format::Zip, &raw, "docs/readme.md", b"hi\n").unwrap();
+        assert_eq!(
+            read_member(Format::Zip, &rewritten, "docs/readme.md").unwrap(),
+            b"hi\n"
+        );
+        assert_eq!(
+            read_member(Format::Zip, &rewritten, "a.txt").unwrap(),
+            b"alpha\n"
+        );
+        assert!(read_member(Format::Zip, &raw, "missing.txt").is_err());
+    }
+
+    #[test]
+    fn tar_members_are_resized_in_place() {
+        let mut raw = tar_entry("a.txt", b"alpha\n");
+        raw.extend(tar_entry("b.txt", b"bravo\n"));
+        raw.extend([0u8; TAR_BLOCK * 2]);
+        let long = vec![b'x'; 700];
+        let rewritten = rewrite_member(Format::Tar, &raw, "a.txt", &long).unwrap();
+        assert_eq!(read_member(Format::Tar, &rewritten, "a.txt").unwrap(), long);
+        assert_eq!(
+            read_member(Format::Tar, &rewritten, "b.txt").unwrap(),
+            b"bravo\n"
+        );
+        let gz = rewrite_member(
+            Format::TarGz,
+            &{
+                let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(&raw).unwrap();
+                encoder.finish().unwrap()
+            },
+            "b.txt",
+            b"B\n",
+        )
+        .unwrap();
+        assert_eq!(read_member(Format::TarGz, &gz, "b.txt").unwrap(), b"B\n");
+    }
+}
@@ -0,0 +1,136 @@
+use std::fs;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FileAttributes {
+    pub readonly: bool,
+    pub hidden: bool,
+    pub system: bool,
+}
+
+impl FileAttributes {
+    pub fn read(path: &Path) -> Option<Self> {
+        let metadata = fs::metadata(path).ok()?;
+        #[cfg(windows)]
+        {
+            use std::os::windows::fs::MetadataExt;
+            use windows_sys::Win32::Storage::FileSystem::{
+                FILE_ATTRIBUTE_HIDDEN, FILE_ATTRIBUTE_READONLY, FILE_ATTRIBUTE_SYSTEM,
+            };
+            let raw = metadata.file_attributes();
+            Some(Self {
+                readonly: raw & FILE_ATTRIBUTE_READONLY != 0,
+                hidden: raw & FILE_ATTRIBUTE_HIDDEN != 0,
+                system: raw & FILE_ATTRIBUTE_SYSTEM != 0,
+            })
+        }
+        #[cfg(not(windows))]
+        {
+            Some(Self {
+                readonly: metadata.permissions().readonly(),
+                hidden: false,
+                system: false,
+            })
+        }
+    }
+
+    pub fn labels(&self) -> Vec<&'static str> {
+        let mut labels = Vec::new();
+        if self.readonly {
+            labels.push("read-only");
+        }
+        if self.hidden {
+            labels.push("hidden");
+        }
+        if self.system {
+            labels.push("system");
+        }
+        labels
+    }
+}
+
+pub fn set_readonly(path: &Path, readonly: bool) -> Result<()> {
+    let mut permissions = fs::metadata(path)
+        .with_context(|| format!("reading attributes of {}", path.display()))?
+        .permissions();
+    if permissions.readonly() == readonly {
+        return Ok(());
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = permissions.mode();
+        permissions.set_mode(if readonly {
+            mode & !0o222
+        } else {
+            mode | 0o200
+        });
+    }
+    #[cfg(not(unix))]
+    permissions.set_readonly(readonly);
+    fs::set_permissions(path, permissions)
+        .with_context(|| format!("updating attributes of {}", path.display()))
+}
+
+#[cfg(windows)]
+pub fn restore(path: &Path, attributes: FileAttributes) -> Result<()> {
+    use std::os::windows::ffi::OsStrExt;
+    use windows_sys::Win32::Storage::FileSystem::{
+        FILE_ATTRIBUTE_HIDDEN, FILE_ATTRIBUTE_NORMAL, FILE_ATTRIBUTE_READONLY,
+        FILE_ATTRIBUTE_SYSTEM, GetFileAttributesW, INVALID_FILE_ATTRIBUTES, SetFileAttributesW,
+    };
+    let wide: Vec<u16> = path.as_os_str().encode_wide().chain(Some(0)).collect();
+    let current = unsafe { GetFileAttributesW(wide.as_ptr()) };
+    if current == INVALID_FILE_ATTRIBUTES {
+        return Err(std::io::Error::last_os_error())
+            .with_context(|| format!("reading attributes of {}", path.display()));
+    }
+    let mut wanted =
+        current & !(FILE_ATTRIBUTE_READONLY | FILE_ATTRIBUTE_HIDDEN | FILE_ATTRIBUTE_SYSTEM);
+    if attributes.readonly {
+        wanted |= FILE_ATTRIBUTE_READONLY;
+    }
+    if attributes.hidden {
+        wanted |= FILE_ATTRIBUTE_HIDDEN;
+    }
+    if attributes.system {
+        wanted |= FILE_ATTRIBUTE_SYSTEM;
+    }
+    if wanted == 0 {
+        wanted = FILE_ATTRIBUTE_NORMAL;
+    }
+    if unsafe { SetFileAttributesW(wide.as_ptr(), wanted) } == 0 {
+        return Err(std::io::Error::last_os_error())
+            .with_context(|| format!("restoring attributes of {}", path.display()));
+    }
+    Ok(())
+}
+
+#[cfg(not(windows))]
+pub fn restore(path: &Path, attributes: FileAttributes) -> Result<()> {
+    set_readonly(path, attributes.readonly)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn readonly_round_trip() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("locked.txt");
+        fs::write(&path, "x").unwrap();
+        assert!(!FileAttributes::read(&path).unwrap().readonly);
+        set_readonly(&path, true).unwrap();
+        let attrs = FileAttributes::read(&path).unwrap();
+        assert!(attrs.readonly);
+        assert_eq!(attrs.labels(), vec!["read-only"]);
+        set_readonly(&path, false).unwrap();
+        restore(&path, attrs).unwrap();
+        assert!(FileAttributes::read(&path).unwrap().readonly);
+        set_readonly(&path, false).unwrap();
+    }
+}
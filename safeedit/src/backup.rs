@@ -0,0 +1,335 @@
+use std::env;
+use std::fs;
+use std::io::{Read, Write};
+use std::path::{Component, Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use flate2::{Compression, read::GzDecoder, write::GzEncoder};
+use serde::Deserialize;
+use time::OffsetDateTime;
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, ValueEnum, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum BackupNaming {
+    #[default]
+    Index,
+    Timestamp,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct BackupOptions {
+    pub disabled: bool,
+    pub dir: Option<PathBuf>,
+    pub naming: BackupNaming,
+    pub keep: Option<usize>,
+    pub max_age_days: Option<u64>,
+    pub compress_over: Option<u64>,
+}
+
+const GZIP_SUFFIX: &str = ".gz";
+
+pub fn create(path: &Path, options: &BackupOptions) -> Result<Option<PathBuf>> {
+    if options.disabled || !path.exists() {
+        return Ok(None);
+    }
+    let base = backup_base(path, options.dir.as_deref());
+    if let Some(parent) = base.parent()
+        && !parent.as_os_str().is_empty()
+    {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("creating backup directory {}", parent.display()))?;
+    }
+
+    let stamp = match options.naming {
+        BackupNaming::Index => None,
+        BackupNaming::Timestamp => {
+            let now = OffsetDateTime::now_utc();
+            Some(format!(
+                "{:04}{:02}{:02}T{:02}{:02}{:02}Z",
+                now.year(),
+                u8::from(now.month()),
+                now.day(),
+                now.hour(),
+                now.minute(),
+                now.second()
+            ))
+        }
+    };
+    let compress = options.compress_over.is_some_and(|threshold| {
+        fs::metadata(path).is_ok_and(|metadata| metadata.len() > threshold)
+    });
+    let mut attempt = 0usize;
+    let created = loop {
+        let candidate = match &stamp {
+            Some(stamp) => timestamped_candidate(&base, stamp, attempt),
+            None => candidate(&base, attempt),
+        };
+        let compressed = with_gzip_suffix(&candidate);
+        if !candidate.exists() && !compressed.exists() {
+            if compress {
+                write_compressed(path, &compressed)?;
+                break compressed;
+            }
+            fs::copy(path, &candidate)
+                .with_context(|| format!("creating backup {}", candidate.display()))?;
+            break candidate;
+        }
+        attempt += 1;
+    };
+    for pruned in prune(path, options, &created)? {
+        println!("pruned old backup {}", pruned.display());
+    }
+    Ok(Some(created))
+}
+
+pub fn read(backup: &Path) -> Result<Vec<u8>> {
+    let raw = fs::read(backup).with_context(|| format!("reading {}", backup.display()))?;
+    if !is_compressed(backup) {
+        return Ok(raw);
+    }
+    let mut data = Vec::new();
+    GzDecoder::new(raw.as_slice())
+        .read_to_end(&mut data)
+        .with_context(|| format!("decompressing {}", backup.display()))?;
+    Ok(data)
+}
+
+pub fn is_compressed(backup: &Path) -> bool {
+    backup
+        .file_name()
+        .and_then(|name| name.to_str())
+        .is_some_and(|name| name.ends_with(GZIP_SUFFIX))
+}
+
+fn with_gzip_suffix(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(GZIP_SUFFIX);
+    PathBuf::from(name)
+}
+
+fn write_compressed(source: &Path, target: &Path) -> Result<()> {
+    let data = fs::read(source).with_context(|| format!("reading {}", source.display()))?;
+    let file = fs::File::create(target)
+        .with_context(|| format!("creating backup {}", target.display()))?;
+    let mut encoder = GzEncoder::new(file, Compression::default());
+    encoder
+        .write_all(&data)
+        .and_then(|_| encoder.finish().map(|_| ()))
+        .with_context(|| format!("compressing backup {}", target.display()))
+}
+
+fn prune(path: &Path, options: &BackupOptions, keep_path: &Path) -> Result<Vec<PathBuf>> {
+    if options.keep.is_none() && options.max_age_days.is_none() {
+        return Ok(Vec::new());
+    }
+    let backups = list(path, options.dir.as_deref())?;
+    let cutoff = options
+        .max_age_days
+        .and_then(|days| SystemTime::now().checked_sub(Duration::from_secs(days * 86_400)));
+    let excess = options
+        .keep
+        .map(|keep| backups.len().saturating_sub(keep.max(1)))
+        .unwrap_or(0);
+    let mut removed = Vec::new();
+    for (index, backup) in backups.iter().enumerate() {
+        if backup == keep_path {
+            continue;
+        }
+        let expired = cutoff.is_some_and(|cutoff| {
+            fs::metadata(backup)
+                .and_then(|metadata| metadata.modified())
+                .is_ok_and(|modified| modified < cutoff)
+        });
+        if index < excess || expired {
+            fs::remove_file(backup)
+                .with_context(|| format!("removing old backup {}", backup.display()))?;
+            removed.push(backup.clone());
+        }
+    }
+    Ok(removed)
+}
+
+pub fn list(path: &Path, dir: Option<&Path>) -> Result<Vec<PathBuf>> {
+    let base = backup_base(path, dir);
+    let Some(name) = base.file_name().and_then(|name| name.to_str()) else {
+        return Ok(Vec::new());
+    };
+    let prefix = format!("{name}.bak");
+    let parent = base
+        .parent()
+        .filter(|p| !p.as_os_str().is_empty())
+        .unwrap_or_else(|| Path::new("."));
+    let Ok(listing) = fs::read_dir(parent) else {
+        return Ok(Vec::new());
+    };
+    let mut found = Vec::new();
+    for item in listing {
+        let item = item?;
+        let file_name = item.file_name();
+        let Some(file_name) = file_name.to_str() else {
+            continue;
+        };
+        let file_name = file_name.strip_suffix(GZIP_SUFFIX).unwrap_or(file_name);
+        let indexed = file_name
+            .strip_prefix(&prefix)
+            .is_some_and(|suffix| suffix.chars().all(|ch| ch.is_ascii_digit()));
+        let timestamped = file_name
+            .strip_prefix(name)
+            .and_then(|rest| rest.strip_prefix('.'))
+            .and_then(|rest| rest.strip_suffix(".bak"))
+            .is_some_and(is_timestamp_stamp);
+        if indexed || timestamped {
+            let modified = item.metadata()?.modified()?;
+            found.push((modified, item.path()));
+        }
+    }
+    found.sort();
+    Ok(found.into_iter().map(|(_, path)| path).collect())
+}
+
+fn backup_base(path: &Path, dir: Option<&Path>) -> PathBuf {
+    let Some(dir) = dir else {
+        return path.to_path_buf();
+    };
+    let absolute = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+    let relative = env::current_dir()
+        .ok()
+        .and_then(|cwd| absolute.strip_prefix(cwd).ok().map(Path::to_path_buf))
+        .unwrap_or_else(|| {
+            absolute
+                .components()
+                .filter(|component| matches!(component, Component::Normal(_)))
+                .collect()
+        });
+    dir.join(relative)
+}
+
+pub fn is_timestamp_stamp(stamp: &str) -> bool {
+    let (main, counter) = stamp.split_once('-').unwrap_or((stamp, "0"));
+    main.len() == 16
+        && main.as_bytes()[8] == b'T'
+        && main.ends_with('Z')
+        && main[..8].chars().all(|ch| ch.is_ascii_digit())
+        && main[9..15].chars().all(|ch| ch.is_ascii_digit())
+        && !counter.is_empty()
+        && counter.chars().all(|ch| ch.is_ascii_digit())
+}
+
+fn timestamped_candidate(base: &Path, stamp: &str, attempt: usize) -> PathBuf {
+    let name = base
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or("safeedit_file");
+    let backup_name = if attempt == 0 {
+        format!("{name}.{stamp}.bak")
+    } else {
+        format!("{name}.{stamp}-{attempt}.bak")
+    };
+    base.with_file_name(backup_name)
+}
+
+fn candidate(base: &Path, index: usize) -> PathBuf {
+    let name = base
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or("safeedit_file");
+    let suffix = if index == 0 {
+        ".bak".to_string()
+    } else {
+        format!(".bak{index}")
+    };
+    base.with_file_name(format!("{name}{suffix}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn backup_dir_mirrors_relative_layout() {
+        let temp = tempdir().unwrap();
+        let source = temp.path().join("src").join("lib.rs");
+        fs::create_dir_all(source.parent().unwrap()).unwrap();
+        fs::write(&source, "one").unwrap();
+        let store = temp.path().join("backups");
+        let options = BackupOptions {
+            dir: Some(store.clone()),
+            ..BackupOptions::default()
+        };
+        let first = create(&source, &options).unwrap().unwrap();
+        let second = create(&source, &options).unwrap().unwrap();
+        assert!(first.starts_with(&store));
+        assert!(first.ends_with(Path::new("src").join("lib.rs.bak")));
+        assert!(second.ends_with(Path::new("src").join("lib.rs.bak1")));
+        assert!(!temp.path().join("src").join("lib.rs.bak").exists());
+        assert_eq!(list(&source, Some(&store)).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn default_layout_sits_next_to_file() {
+        let temp = tempdir().unwrap();
+        let source = temp.path().join("notes.txt");
+        fs::write(&source, "x").unwrap();
+        let backup = create(&source, &BackupOptions::default()).unwrap().unwrap();
+        assert_eq!(backup, temp.path().join("notes.txt.bak"));
+        assert_eq!(list(&source, None).unwrap(), vec![backup]);
+        let disabled = BackupOptions {
+            disabled: true,
+            ..BackupOptions::default()
+        };
+        assert!(create(&source, &disabled).unwrap().is_none());
+    }
+
+    #[test]
+    fn timestamped_backups_respect_keep_limit() {
+        let temp = tempdir().unwrap();
+        let source = temp.path().join("gen.rs");
+        fs::write(&source, "x").unwrap();
+        let options = BackupOptions {
+            naming: BackupNaming::Timestamp,
+            keep: Some(2),
+            ..BackupOptions::default()
+        };
+        let mut created = Vec::new();
+        for _ in 0..4 {
+            created.push(create(&source, &options).unwrap().unwrap());
+        }
+        let name = created[0].file_name().unwrap().to_str().unwrap();
+        assert!(
+            name.starts_with("gen.rs.") && name.ends_with("Z.bak"),
+            "{name}"
+        );
+        let remaining = list(&source, None).unwrap();
+        assert_eq!(remaining.len(), 2);
+        assert!(remaining.contains(&created[3]));
+        assert!(is_timestamp_stamp("20251108T140000Z-3"));
+        assert!(!is_timestamp_stamp("20251108-140000"));
+    }
+
+    #[test]
+    fn large_backups_are_gzipped_and_readable() {
+        let temp = tempdir().unwrap();
+        let source = temp.path().join("big.txt");
+        let content = "generated line\n".repeat(200);
+        fs::write(&source, &content).unwrap();
+        let options = BackupOptions {
+            compress_over: Some(1024),
+            ..BackupOptions::default()
+        };
+        let backup = create(&source, &options).unwrap().unwrap();
+        assert_eq!(backup, temp.path().join("big.txt.bak.gz"));
+        assert!(fs::metadata(&backup).unwrap().len() < content.len() as u64);
+        assert_eq!(read(&backup).unwrap(), content.as_bytes());
+        let next = create(&source, &options).unwrap().unwrap();
+        assert_eq!(next, temp.path().join("big.txt.bak1.gz"));
+        assert_eq!(list(&source, None).unwrap().len(), 2);
+
+        let small = temp.path().join("small.txt");
+        fs::write(&small, "tiny").unwrap();
+        let plain = create(&small, &options).unwrap().unwrap();
+        assert!(!is_compressed(&plain));
+    }
+}
@@ -1,14 +1,104 @@
+use std::collections::BTreeMap;
 use std::fs;
 use std::path::{Path, PathBuf};
 
-use anyhow::{Context, Result};
-use serde::Deserialize;
+use anyhow::{Context, Result, bail};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
 
-use crate::{ColorChoice, PagerMode};
+use crate::integrity::sha256_hex;
+use crate::normalize::UnicodeForm;
+use crate::{ColorChoice, DiffFormat, LineEndingChoice, LineEndingStyle, PagerMode, WriteMode};
+
+const PROGRESS_DIR: &str = "batch";
 
 #[derive(Debug, Deserialize)]
 pub struct BatchPlan {
-    pub steps: Vec<PlanEntry>,
+    #[serde(default)]
+    pub vars: BTreeMap<String, String>,
+    pub steps: Vec<PlanStep>,
+    // Hash of the plan after vars, --set values, and includes are expanded,
+    // so `--resume` notices edits to included plans too.
+    #[serde(skip)]
+    pub fingerprint: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PlanStep {
+    #[serde(default)]
+    pub when: Option<StepCondition>,
+    #[serde(default)]
+    pub on_error: OnError,
+    #[serde(flatten)]
+    pub entry: PlanEntry,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum OnError {
+    #[default]
+    Abort,
+    Skip,
+    Continue,
+}
+
+#[derive(Debug, Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct StepCondition {
+    pub exists: Option<PathBuf>,
+    pub missing: Option<PathBuf>,
+    pub glob: Option<String>,
+    pub changed: Option<bool>,
+    pub env: Option<String>,
+}
+
+impl StepCondition {
+    pub fn unmet(&self, previous_changed: bool) -> Result<Option<String>> {
+        if let Some(path) = &self.exists
+            && !path.exists()
+        {
+            return Ok(Some(format!("{} does not exist", path.display())));
+        }
+        if let Some(path) = &self.missing
+            && path.exists()
+        {
+            return Ok(Some(format!("{} exists", path.display())));
+        }
+        if let Some(pattern) = &self.glob {
+            let mut matches = glob::glob(pattern)
+                .with_context(|| format!("invalid glob pattern '{pattern}' in when clause"))?;
+            if !matches.any(|entry| entry.is_ok()) {
+                return Ok(Some(format!("glob '{pattern}' matched nothing")));
+            }
+        }
+        if let Some(expected) = self.changed
+            && expected != previous_changed
+        {
+            return Ok(Some(if expected {
+                "previous step changed no files".to_string()
+            } else {
+                "previous step changed files".to_string()
+            }));
+        }
+        if let Some(spec) = &self.env {
+            let (name, wanted) = match spec.split_once('=') {
+                Some((name, value)) => (name, Some(value)),
+                None => (spec.as_str(), None),
+            };
+            let actual = std::env::var(name).ok().filter(|value| !value.is_empty());
+            let met = match wanted {
+                Some(wanted) => actual.as_deref() == Some(wanted),
+                None => actual.is_some(),
+            };
+            if !met {
+                return Ok(Some(match wanted {
+                    Some(wanted) => format!("${name} is not '{wanted}'"),
+                    None => format!("${name} is not set"),
+                }));
+            }
+        }
+        Ok(None)
+    }
 }
 
 #[derive(Debug, Deserialize)]
@@ -18,6 +108,11 @@ pub enum PlanEntry {
     Normalize(NormalizePlan),
     Block(BlockPlan),
     Rename(RenamePlan),
+    Write(WritePlan),
+    Apply(ApplyPlan),
+    Insert(InsertPlan),
+    Script(ScriptPlan),
+    Include(IncludePlan),
 }
 
 impl PlanEntry {
@@ -27,6 +122,11 @@ impl PlanEntry {
             PlanEntry::Normalize(_) => "normalize",
             PlanEntry::Block(_) => "block",
             PlanEntry::Rename(_) => "rename",
+            PlanEntry::Write(_) => "write",
+            PlanEntry::Apply(_) => "apply",
+            PlanEntry::Insert(_) => "insert",
+            PlanEntry::Script(_) => "script",
+            PlanEntry::Include(_) => "include",
         }
     }
 }
@@ -40,16 +140,42 @@ pub struct PlanCommon {
     pub encoding: Option<String>,
     pub apply: Option<bool>,
     pub auto_apply: Option<bool>,
+    pub approve_policy: Option<PathBuf>,
     pub no_backup: Option<bool>,
+    pub backup_dir: Option<PathBuf>,
+    pub backup_naming: Option<crate::backup::BackupNaming>,
+    pub backup_keep: Option<usize>,
+    pub backup_max_age: Option<u64>,
+    pub backup_compress: Option<bool>,
+    pub backup_compress_threshold: Option<u64>,
     pub context: Option<usize>,
     pub pager: Option<PagerMode>,
+    pub diff_format: Option<DiffFormat>,
+    pub line_numbers: Option<bool>,
+    pub page_lines: Option<usize>,
+    pub max_diff_lines: Option<usize>,
+    pub max_diff_bytes: Option<usize>,
     #[serde(default)]
     pub color: Option<ColorChoice>,
     pub json: Option<bool>,
+    pub json_file: Option<PathBuf>,
     pub include_hidden: Option<bool>,
     #[serde(default)]
     pub exclude: Option<Vec<String>>,
     pub undo_log: Option<PathBuf>,
+    pub save_applied_patch: Option<PathBuf>,
+    pub preview_dir: Option<PathBuf>,
+    pub strict_encoding: Option<bool>,
+    pub verify_write: Option<bool>,
+    pub no_preserve_metadata: Option<bool>,
+    pub preserve_mtime: Option<bool>,
+    pub force_readonly: Option<bool>,
+    pub max_file_size: Option<u64>,
+    pub force_large: Option<bool>,
+    pub keep_going: Option<bool>,
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    pub set_mtime: Option<time::OffsetDateTime>,
+    pub min_detection_score: Option<f32>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -59,21 +185,41 @@ pub struct ReplacePlan {
     pub pattern: String,
     pub replacement: Option<String>,
     #[serde(default)]
+    pub with_url: Option<String>,
+    #[serde(default)]
+    pub url_sha256: Option<String>,
+    #[serde(default)]
     pub regex: bool,
     #[serde(default)]
     pub literal: bool,
     #[serde(default)]
+    pub no_expand: bool,
+    #[serde(default)]
+    pub case_aware: bool,
+    #[serde(default)]
+    pub allow_binary_literal: bool,
+    #[serde(default)]
     pub diff_only: bool,
     #[serde(default)]
     pub count: Option<usize>,
     #[serde(default)]
     pub expect: Option<usize>,
     #[serde(default)]
+    pub total_count: Option<usize>,
+    #[serde(default)]
+    pub expect_total: Option<usize>,
+    #[serde(default)]
     pub after_line: Option<usize>,
     #[serde(default)]
+    pub skip_if_contains: Option<String>,
+    #[serde(default)]
     pub with_stdin: bool,
     #[serde(default)]
     pub with_clipboard: bool,
+    #[serde(default)]
+    pub if_hash: Option<String>,
+    #[serde(default)]
+    pub if_unchanged_since: Option<String>,
 }
 
 #[derive(Debug, Deserialize, Default)]
@@ -87,20 +233,64 @@ pub struct NormalizePlan {
     #[serde(default)]
     pub strip_control: Option<bool>,
     #[serde(default)]
+    pub strip_bidi: Option<bool>,
+    #[serde(default)]
+    pub fail_on_bidi: Option<bool>,
+    #[serde(default)]
+    pub map_confusables: Option<bool>,
+    #[serde(default)]
+    pub ascii_punctuation: Option<bool>,
+    #[serde(default)]
+    pub map_char: Vec<String>,
+    #[serde(default)]
+    pub strip_class: Vec<String>,
+    #[serde(default)]
     pub trim_trailing_space: Option<bool>,
     #[serde(default)]
     pub ensure_eol: Option<bool>,
     #[serde(default)]
+    pub tabs_to_spaces: Option<usize>,
+    #[serde(default)]
+    pub spaces_to_tabs: Option<usize>,
+    #[serde(default)]
+    pub max_blank_lines: Option<usize>,
+    #[serde(default)]
+    pub max_line_length: Option<usize>,
+    #[serde(default)]
+    pub wrap_long_lines: Option<bool>,
+    #[serde(default)]
+    pub fail_on_long_lines: Option<bool>,
+    #[serde(default)]
+    pub convert_eol: Option<LineEndingStyle>,
+    #[serde(default)]
+    pub strip_bom: Option<bool>,
+    #[serde(default)]
+    pub add_bom: Option<bool>,
+    #[serde(default)]
+    pub unicode_form: Option<UnicodeForm>,
+    #[serde(default)]
     pub report_format: Option<String>,
     #[serde(default)]
+    pub locations: Option<bool>,
+    #[serde(default)]
     pub scan_encoding: Option<bool>,
     #[serde(default)]
     pub scan_zero_width: Option<bool>,
     #[serde(default)]
     pub scan_control: Option<bool>,
     #[serde(default)]
+    pub scan_bidi: Option<bool>,
+    #[serde(default)]
+    pub scan_confusables: Option<bool>,
+    #[serde(default)]
+    pub scan_punctuation: Option<bool>,
+    #[serde(default)]
     pub scan_trailing_space: Option<bool>,
     #[serde(default)]
+    pub scan_indentation: Option<bool>,
+    #[serde(default)]
+    pub scan_eol: Option<bool>,
+    #[serde(default)]
     pub scan_final_newline: Option<bool>,
 }
 
@@ -110,6 +300,10 @@ pub struct BlockPlan {
     pub common: PlanCommon,
     pub body: Option<String>,
     pub body_file: Option<PathBuf>,
+    #[serde(default)]
+    pub body_url: Option<String>,
+    #[serde(default)]
+    pub url_sha256: Option<String>,
     pub start_marker: Option<String>,
     pub end_marker: Option<String>,
     pub insert_after: Option<String>,
@@ -132,16 +326,654 @@ pub struct RenamePlan {
     pub case_aware: bool,
 }
 
-pub fn load_plan(path: &Path) -> Result<BatchPlan> {
-    let data = fs::read(path).with_context(|| format!("reading plan {}", path.display()))?;
-    if path
-        .extension()
+#[derive(Debug, Deserialize)]
+pub struct WritePlan {
+    #[serde(default)]
+    pub common: PlanCommon,
+    pub path: PathBuf,
+    pub body: Option<String>,
+    pub body_file: Option<PathBuf>,
+    pub template: Option<PathBuf>,
+    #[serde(default)]
+    pub body_url: Option<String>,
+    #[serde(default)]
+    pub url_sha256: Option<String>,
+    #[serde(default)]
+    pub vars: BTreeMap<String, String>,
+    #[serde(default)]
+    pub allow_overwrite: bool,
+    #[serde(default)]
+    pub mode: Option<WriteMode>,
+    #[serde(default)]
+    pub if_hash: Option<String>,
+    #[serde(default)]
+    pub if_unchanged_since: Option<String>,
+    #[serde(default)]
+    pub line_ending: Option<LineEndingChoice>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ApplyPlan {
+    #[serde(default)]
+    pub common: PlanCommon,
+    pub patches: Vec<PathBuf>,
+    pub root: Option<PathBuf>,
+    #[serde(default)]
+    pub offset_window: Option<usize>,
+}
+
+#[derive(Debug, Deserialize, Default)]
+pub struct InsertPlan {
+    #[serde(default)]
+    pub common: PlanCommon,
+    pub after: Option<String>,
+    pub before: Option<String>,
+    pub body: Option<String>,
+    pub body_file: Option<PathBuf>,
+}
+
+// Parsed in full so plans keep loading, but script steps fail as
+// unsupported until the transform runner exists.
+#[derive(Debug, Deserialize)]
+pub struct ScriptPlan {
+    #[serde(default)]
+    #[allow(dead_code)]
+    pub common: PlanCommon,
+    pub script: PathBuf,
+    #[serde(default)]
+    #[allow(dead_code)]
+    pub args: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct IncludePlan {
+    pub plan: PathBuf,
+    #[serde(default)]
+    pub vars: serde_json::Map<String, Value>,
+}
+
+// Mirrors BatchPlan but leaves vars untyped, so each file can be checked
+// straight from its source text and serde reports line/column positions.
+#[derive(Deserialize)]
+struct PlanSource {
+    #[serde(default)]
+    #[allow(dead_code)]
+    vars: serde::de::IgnoredAny,
+    #[allow(dead_code)]
+    steps: Vec<PlanStep>,
+}
+
+pub fn load_plan(path: &Path, overrides: &BTreeMap<String, String>) -> Result<BatchPlan> {
+    let plan = load_expanded(path, overrides, &mut Vec::new())?;
+    let fingerprint = sha256_hex(&serde_json::to_vec(&plan)?);
+    Ok(BatchPlan {
+        fingerprint,
+        ..serde_json::from_value(plan)?
+    })
+}
+
+fn is_json(path: &Path) -> bool {
+    path.extension()
         .and_then(|ext| ext.to_str())
         .map(|ext| ext.eq_ignore_ascii_case("json"))
         .unwrap_or(false)
-    {
+}
+
+fn read_plan_value(path: &Path) -> Result<Value> {
+    let data = fs::read(path).with_context(|| format!("reading plan {}", path.display()))?;
+    let json = is_json(path);
+    let checked = if json {
+        serde_json::from_slice::<PlanSource>(&data).map_err(anyhow::Error::from)
+    } else {
+        serde_yaml::from_slice::<PlanSource>(&data).map_err(anyhow::Error::from)
+    };
+    checked.with_context(|| format!("invalid plan {}", path.display()))?;
+    if json {
         Ok(serde_json::from_slice(&data)?)
     } else {
         Ok(serde_yaml::from_slice(&data)?)
     }
 }
+
+fn load_expanded(
+    path: &Path,
+    overrides: &BTreeMap<String, String>,
+    stack: &mut Vec<PathBuf>,
+) -> Result<Value> {
+    let key = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    if stack.contains(&key) {
+        bail!("plan {} includes itself", path.display());
+    }
+    stack.push(key);
+    let raw = read_plan_value(path)?;
+    let mut plan = resolve_vars(raw, overrides)
+        .with_context(|| format!("expanding variables in plan {}", path.display()))?;
+    if let Some(steps) = plan.get_mut("steps").and_then(Value::as_array_mut) {
+        let mut expanded = Vec::with_capacity(steps.len());
+        for step in steps.drain(..) {
+            if step.get("command").and_then(Value::as_str) == Some("include") {
+                expanded.extend(include_steps(path, &step, stack)?);
+            } else {
+                expanded.push(step);
+            }
+        }
+        *steps = expanded;
+    }
+    stack.pop();
+    Ok(plan)
+}
+
+fn include_steps(parent: &Path, step: &Value, stack: &mut Vec<PathBuf>) -> Result<Vec<Value>> {
+    let Some(fields) = step.as_object() else {
+        bail!("include step must be a mapping");
+    };
+    if let Some(key) = fields
+        .keys()
+        .find(|key| !matches!(key.as_str(), "command" | "plan" | "vars"))
+    {
+        bail!("include steps only accept 'plan' and 'vars' (found '{key}')");
+    }
+    let include: IncludePlan =
+        serde_json::from_value(step.clone()).context("invalid include step")?;
+    let target = parent
+        .parent()
+        .unwrap_or_else(|| Path::new(""))
+        .join(&include.plan);
+    let vars = scalar_vars(&Value::Object(include.vars))?;
+    let included = load_expanded(&target, &vars, stack)
+        .with_context(|| format!("including plan {}", target.display()))?;
+    let included_vars = included.get("vars").cloned().unwrap_or(Value::Null);
+    let Some(Value::Array(mut steps)) = included.get("steps").cloned() else {
+        bail!("included plan {} has no 'steps' list", target.display());
+    };
+    // Write templates read plan vars at run time; carry the included plan's
+    // values along since they will not be in the top-level vars.
+    if let Value::Object(defaults) = included_vars {
+        for step in &mut steps {
+            if step.get("command").and_then(Value::as_str) != Some("write")
+                || step.get("template").is_none()
+            {
+                continue;
+            }
+            let Some(object) = step.as_object_mut() else {
+                continue;
+            };
+            let vars = object
+                .entry("vars")
+                .or_insert_with(|| Value::Object(Default::default()));
+            if let Value::Object(vars) = vars {
+                for (key, value) in &defaults {
+                    vars.entry(key.clone()).or_insert_with(|| value.clone());
+                }
+            }
+        }
+    }
+    Ok(steps)
+}
+
+fn scalar_vars(declared: &Value) -> Result<BTreeMap<String, String>> {
+    let Some(declared) = declared.as_object() else {
+        bail!("'vars' must be a mapping of names to values");
+    };
+    let mut vars = BTreeMap::new();
+    for (key, value) in declared {
+        let value = match value {
+            Value::String(text) => text.clone(),
+            Value::Number(_) | Value::Bool(_) => value.to_string(),
+            Value::Null => String::new(),
+            _ => bail!("variable '{key}' must be a string, number, or boolean"),
+        };
+        vars.insert(key.clone(), value);
+    }
+    Ok(vars)
+}
+
+fn resolve_vars(mut raw: Value, overrides: &BTreeMap<String, String>) -> Result<Value> {
+    let mut vars = match raw.get("vars") {
+        Some(declared) => scalar_vars(declared)?,
+        None => BTreeMap::new(),
+    };
+    vars.extend(overrides.iter().map(|(k, v)| (k.clone(), v.clone())));
+    if let Some(steps) = raw.get_mut("steps") {
+        interpolate(steps, &vars)?;
+    }
+    if let Some(object) = raw.as_object_mut() {
+        object.insert(
+            "vars".into(),
+            Value::Object(
+                vars.into_iter()
+                    .map(|(key, value)| (key, Value::String(value)))
+                    .collect(),
+            ),
+        );
+    }
+    Ok(raw)
+}
+
+fn interpolate(value: &mut Value, vars: &BTreeMap<String, String>) -> Result<()> {
+    match value {
+        Value::String(text) if text.contains("{{") => {
+            *text = crate::template::render(text, vars)
+                .context("define it under 'vars:' or pass --set name=value")?;
+        }
+        Value::Array(items) => {
+            for item in items {
+                interpolate(item, vars)?;
+            }
+        }
+        Value::Object(fields) => {
+            for field in fields.values_mut() {
+                interpolate(field, vars)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+pub fn append_step(plan: &Path, fields: Vec<(&str, Value)>) -> Result<()> {
+    let existing = match fs::read(plan) {
+        Ok(data) if !data.trim_ascii().is_empty() => Some(data),
+        Ok(_) => None,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => None,
+        Err(err) => return Err(err).with_context(|| format!("reading plan {}", plan.display())),
+    };
+    let mut document: serde_yaml::Value = match &existing {
+        Some(data) if is_json(plan) => {
+            serde_yaml::to_value(serde_json::from_slice::<Value>(data)?)?
+        }
+        Some(data) => serde_yaml::from_slice(data)?,
+        None => serde_yaml::Mapping::new().into(),
+    };
+    let Some(mapping) = document.as_mapping_mut() else {
+        bail!(
+            "plan {} is not a mapping with a 'steps' list",
+            plan.display()
+        );
+    };
+    let steps = mapping
+        .entry("steps".into())
+        .or_insert_with(|| serde_yaml::Sequence::new().into());
+    if steps.is_null() {
+        *steps = serde_yaml::Sequence::new().into();
+    }
+    let Some(steps) = steps.as_sequence_mut() else {
+        bail!("'steps' in plan {} must be a list", plan.display());
+    };
+    let mut step = serde_yaml::Mapping::new();
+    for (key, value) in fields {
+        step.insert(key.into(), serde_yaml::to_value(value)?);
+    }
+    steps.push(step.into());
+    let text = if is_json(plan) {
+        serde_json::to_string_pretty(&document)? + "\n"
+    } else {
+        serde_yaml::to_string(&document)?
+    };
+    fs::write(plan, text).with_context(|| format!("writing plan {}", plan.display()))
+}
+
+#[derive(Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct BatchProgress {
+    pub plan: PathBuf,
+    pub fingerprint: String,
+    pub completed: Vec<usize>,
+    // Completed steps that wrote files, so a resumed run still sees them for
+    // a following `when: { changed: ... }`.
+    #[serde(default)]
+    pub changed: Vec<usize>,
+}
+
+impl BatchProgress {
+    pub fn new(path: &Path, plan: &BatchPlan) -> Self {
+        Self {
+            plan: std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf()),
+            fingerprint: plan.fingerprint.clone(),
+            completed: Vec::new(),
+            changed: Vec::new(),
+        }
+    }
+
+    pub fn state_file(&self) -> PathBuf {
+        self.state_file_in(&crate::logging::state_dir().join(PROGRESS_DIR))
+    }
+
+    fn state_file_in(&self, root: &Path) -> PathBuf {
+        let key = sha256_hex(self.plan.to_string_lossy().as_bytes());
+        root.join(format!("{}.json", &key[..16]))
+    }
+
+    pub fn resume(&mut self) -> Result<()> {
+        self.resume_from(&self.state_file())
+    }
+
+    fn resume_from(&mut self, state: &Path) -> Result<()> {
+        let Ok(data) = fs::read(state) else {
+            bail!(
+                "no saved progress for plan {}; run it without --resume",
+                self.plan.display()
+            );
+        };
+        let saved: BatchProgress = serde_json::from_slice(&data)
+            .with_context(|| format!("parsing batch progress {}", state.display()))?;
+        if saved.fingerprint != self.fingerprint {
+            bail!(
+                "plan {}, an included plan, or the --set values changed since the interrupted run; rerun without --resume",
+                self.plan.display()
+            );
+        }
+        self.completed = saved.completed;
+        self.changed = saved.changed;
+        Ok(())
+    }
+
+    pub fn is_done(&self, step: usize) -> bool {
+        self.completed.contains(&step)
+    }
+
+    pub fn changed(&self, step: usize) -> bool {
+        self.changed.contains(&step)
+    }
+
+    pub fn mark_done(&mut self, step: usize, changed: bool, state: &Path) -> Result<()> {
+        if !self.is_done(step) {
+            self.completed.push(step);
+        }
+        if changed && !self.changed(step) {
+            self.changed.push(step);
+        }
+        if let Some(dir) = state.parent() {
+            fs::create_dir_all(dir).with_context(|| format!("creating {}", dir.display()))?;
+        }
+        fs::write(state, serde_json::to_vec_pretty(self)?)
+            .with_context(|| format!("writing batch progress {}", state.display()))
+    }
+
+    pub fn clear(state: &Path) -> Result<()> {
+        match fs::remove_file(state) {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+                Err(err).with_context(|| format!("removing batch progress {}", state.display()))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plans_accept_every_step_kind() {
+        let plan: BatchPlan = serde_yaml::from_str(
+            r#"
+steps:
+  - command: write
+    path: src/generated.rs
+    template: templates/mod.rs.tmpl
+    vars: { name: widget }
+    mode: append
+  - command: apply
+    patches: [fix.patch]
+    root: src
+  - command: insert
+    after: "use std::fs;"
+    body: "use std::io;"
+    common: { targets: [src/lib.rs] }
+  - command: script
+    script: scripts/migrate.rhai
+    args: [--dry]
+  - command: rename
+    from: Foo
+    to: Bar
+"#,
+        )
+        .unwrap();
+        let kinds: Vec<_> = plan.steps.iter().map(|step| step.entry.kind()).collect();
+        assert!(plan.vars.is_empty());
+        assert_eq!(kinds, ["write", "apply", "insert", "script", "rename"]);
+        let PlanEntry::Write(write) = &plan.steps[0].entry else {
+            panic!("expected write step");
+        };
+        assert_eq!(write.mode, Some(WriteMode::Append));
+        assert_eq!(write.vars["name"], "widget");
+    }
+
+    #[test]
+    fn plan_vars_interpolate_with_cli_overrides() {
+        let raw: Value = serde_yaml::from_str(
+            r#"
+vars:
+  service: billing
+  version: 2
+steps:
+  - command: replace
+    pattern: "api/v1/{{ service }}"
+    replacement: "api/v{{version}}/{{service}}"
+    common: { globs: ["services/{{service}}/**/*.rs"] }
+"#,
+        )
+        .unwrap();
+        let overrides = BTreeMap::from([("service".to_string(), "orders".to_string())]);
+        let plan: BatchPlan =
+            serde_json::from_value(resolve_vars(raw.clone(), &overrides).unwrap()).unwrap();
+        let PlanEntry::Replace(step) = &plan.steps[0].entry else {
+            panic!("expected replace step");
+        };
+        assert_eq!(step.pattern, "api/v1/orders");
+        assert_eq!(step.replacement.as_deref(), Some("api/v2/orders"));
+        assert_eq!(
+            step.common.globs.as_deref(),
+            Some(&["services/orders/**/*.rs".to_string()][..])
+        );
+        assert_eq!(plan.vars["version"], "2");
+
+        let mut missing = raw;
+        missing["vars"] = serde_json::json!({});
+        let err = resolve_vars(missing, &BTreeMap::new()).unwrap_err();
+        assert!(format!("{err:#}").contains("--set"), "{err:#}");
+    }
+
+    #[test]
+    fn when_clauses_gate_steps() {
+        let temp = tempfile::tempdir().unwrap();
+        let present = temp.path().join("Cargo.toml");
+        fs::write(&present, "").unwrap();
+        let plan: BatchPlan = serde_yaml::from_str(&format!(
+            r#"
+steps:
+  - command: rename
+    from: a
+    to: b
+    when:
+      exists: {present}
+      glob: '{dir}/*.toml'
+  - command: rename
+    from: a
+    to: b
+    when: {{ changed: true }}
+    on_error: continue
+"#,
+            present = present.display(),
+            dir = temp.path().display()
+        ))
+        .unwrap();
+        assert_eq!(plan.steps[0].on_error, OnError::Abort);
+        assert_eq!(plan.steps[1].on_error, OnError::Continue);
+        let first = plan.steps[0].when.as_ref().unwrap();
+        assert_eq!(first.unmet(false).unwrap(), None);
+        let second = plan.steps[1].when.as_ref().unwrap();
+        assert_eq!(
+            second.unmet(false).unwrap().as_deref(),
+            Some("previous step changed no files")
+        );
+        assert_eq!(second.unmet(true).unwrap(), None);
+
+        let absent = StepCondition {
+            glob: Some(format!("{}/*.lock", temp.path().display())),
+            ..StepCondition::default()
+        };
+        assert!(absent.unmet(true).unwrap().is_some());
+        let env = StepCondition {
+            env: Some("SAFEEDIT_TEST_SURELY_UNSET_VAR".into()),
+            ..StepCondition::default()
+        };
+        assert!(env.unmet(true).unwrap().unwrap().contains("not set"));
+        assert!(serde_yaml::from_str::<StepCondition>("exist: x").is_err());
+    }
+
+    #[test]
+    fn include_steps_inline_other_plans() {
+        let temp = tempfile::tempdir().unwrap();
+        let shared = temp.path().join("shared");
+        fs::create_dir(&shared).unwrap();
+        fs::write(
+            shared.join("normalize.yaml"),
+            r#"
+vars: { root: src, header: generated }
+steps:
+  - command: normalize
+    common: { globs: ["{{root}}/**/*.rs"] }
+  - command: write
+    path: "{{root}}/HEADER"
+    template: header.tmpl
+"#,
+        )
+        .unwrap();
+        let main = temp.path().join("plan.yaml");
+        fs::write(
+            &main,
+            r#"
+vars: { service: billing }
+steps:
+  - command: include
+    plan: shared/normalize.yaml
+    vars: { root: "services/{{service}}" }
+  - command: replace
+    pattern: old
+    replacement: new
+"#,
+        )
+        .unwrap();
+        let plan = load_plan(&main, &BTreeMap::new()).unwrap();
+        assert_eq!(plan.steps.len(), 3);
+        let PlanEntry::Normalize(step) = &plan.steps[0].entry else {
+            panic!("expected normalize step");
+        };
+        assert_eq!(
+            step.common.globs.as_deref(),
+            Some(&["services/billing/**/*.rs".to_string()][..])
+        );
+        let PlanEntry::Write(step) = &plan.steps[1].entry else {
+            panic!("expected write step");
+        };
+        assert_eq!(step.path, PathBuf::from("services/billing/HEADER"));
+        assert_eq!(step.vars["header"], "generated");
+        assert!(matches!(plan.steps[2].entry, PlanEntry::Replace(_)));
+
+        fs::write(
+            shared.join("normalize.yaml"),
+            "steps:\n  - command: include\n    plan: ../plan.yaml\n",
+        )
+        .unwrap();
+        let err = load_plan(&main, &BTreeMap::new()).unwrap_err();
+        assert!(format!("{err:#}").contains("includes itself"), "{err:#}");
+    }
+
+    #[test]
+    fn plan_errors_point_at_the_source_line() {
+        let temp = tempfile::tempdir().unwrap();
+        let path = temp.path().join("plan.yaml");
+        fs::write(
+            &path,
+            "steps:\n  - command: normalize\n  - command: replace\n    patern: x\n",
+        )
+        .unwrap();
+        let err = format!("{:#}", load_plan(&path, &BTreeMap::new()).unwrap_err());
+        assert!(err.contains("steps[1]"), "{err}");
+        assert!(err.contains("line 3"), "{err}");
+    }
+
+    #[test]
+    fn progress_resumes_only_for_the_same_plan() {
+        let temp = tempfile::tempdir().unwrap();
+        let plan = temp.path().join("plan.yaml");
+        let shared = temp.path().join("shared.yaml");
+        fs::write(
+            &plan,
+            "steps:\n  - command: include\n    plan: shared.yaml\n",
+        )
+        .unwrap();
+        fs::write(
+            &shared,
+            "steps:\n  - command: replace\n    pattern: a\n    replacement: b\n",
+        )
+        .unwrap();
+        let vars = BTreeMap::from([("env".to_string(), "prod".to_string())]);
+        let progress_for = |vars: &BTreeMap<String, String>| {
+            BatchProgress::new(&plan, &load_plan(&plan, vars).unwrap())
+        };
+        let mut progress = progress_for(&vars);
+        let state = progress.state_file_in(&temp.path().join("state"));
+        assert!(progress.resume_from(&state).is_err());
+        progress.mark_done(0, true, &state).unwrap();
+        progress.mark_done(2, false, &state).unwrap();
+
+        let mut resumed = progress_for(&vars);
+        resumed.resume_from(&state).unwrap();
+        assert!(resumed.is_done(0) && resumed.is_done(2) && !resumed.is_done(1));
+        assert!(resumed.changed(0) && !resumed.changed(2));
+
+        let mut changed = progress_for(&BTreeMap::new());
+        let err = changed.resume_from(&state).unwrap_err();
+        assert!(err.to_string().contains("changed"), "{err}");
+
+        // Editing only the included plan must also invalidate the progress.
+        fs::write(
+            &shared,
+            "steps:\n  - command: replace\n    pattern: a\n    replacement: c\n",
+        )
+        .unwrap();
+        let mut edited = progress_for(&vars);
+        assert!(edited.resume_from(&state).is_err());
+
+        BatchProgress::clear(&state).unwrap();
+        BatchProgress::clear(&state).unwrap();
+        assert!(!state.exists());
+    }
+
+    #[test]
+    fn recorded_steps_append_to_loadable_plans() {
+        let temp = tempfile::tempdir().unwrap();
+        for name in ["recorded.yaml", "recorded.json"] {
+            let path = temp.path().join(name);
+            append_step(
+                &path,
+                vec![
+                    ("command", Value::from("replace")),
+                    ("pattern", Value::from("old")),
+                    ("replacement", Value::from("new")),
+                ],
+            )
+            .unwrap();
+            append_step(
+                &path,
+                vec![
+                    ("command", Value::from("rename")),
+                    ("from", Value::from("a")),
+                    ("to", Value::from("b")),
+                    ("common", serde_json::json!({ "globs": ["src/**/*.rs"] })),
+                ],
+            )
+            .unwrap();
+            let plan = load_plan(&path, &BTreeMap::new()).unwrap();
+            assert_eq!(plan.steps.len(), 2, "{name}");
+            let PlanEntry::Rename(step) = &plan.steps[1].entry else {
+                panic!("expected rename step in {name}");
+            };
+            assert_eq!(step.common.globs.as_deref().map(<[_]>::len), Some(1));
+        }
+        let yaml = fs::read_to_string(temp.path().join("recorded.yaml")).unwrap();
+        assert!(yaml.starts_with("steps:\n- command: replace"), "{yaml}");
+    }
+}
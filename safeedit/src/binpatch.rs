@@ -0,0 +1,229 @@
+use std::ops::Range;
+
+use anyhow::{Result, anyhow, bail};
+
+use crate::diff::paint_change;
+
+const ROW_BYTES: usize = 16;
+// Edits are in place; anything larger belongs in a real binary diff tool.
+pub const MAX_PATCH_BYTES: usize = 4096;
+
+// `0x1F4`, `0o764`, `0b1010`, or plain decimal.
+pub fn parse_offset(raw: &str) -> Result<usize> {
+    let trimmed = raw.trim().replace('_', "");
+    let lower = trimmed.to_ascii_lowercase();
+    let parsed = if let Some(hex) = lower.strip_prefix("0x") {
+        usize::from_str_radix(hex, 16)
+    } else if let Some(octal) = lower.strip_prefix("0o") {
+        usize::from_str_radix(octal, 8)
+    } else if let Some(binary) = lower.strip_prefix("0b") {
+        usize::from_str_radix(binary, 2)
+    } else {
+        lower.parse()
+    };
+    parsed.map_err(|_| anyhow!("invalid offset '{raw}' (use decimal or 0x-prefixed hex)"))
+}
+
+// Accepts `0a0b`, `0a 0b`, `0a:0b`, or `0x0a0b`.
+pub fn parse_hex(raw: &str, flag: &str) -> Result<Vec<u8>> {
+    let trimmed = raw.trim();
+    let digits: String = trimmed
+        .strip_prefix("0x")
+        .or_else(|| trimmed.strip_prefix("0X"))
+        .unwrap_or(trimmed)
+        .chars()
+        .filter(|ch| !matches!(ch, ' ' | ':' | '-' | '_'))
+        .collect();
+    if digits.is_empty() {
+        bail!("{flag} needs at least one byte");
+    }
+    if !digits.len().is_multiple_of(2) {
+        bail!("{flag} has an odd number of hex digits: '{raw}'");
+    }
+    (0..digits.len())
+        .step_by(2)
+        .map(|idx| {
+            u8::from_str_radix(&digits[idx..idx + 2], 16)
+                .map_err(|_| anyhow!("{flag} is not valid hex: '{raw}'"))
+        })
+        .collect()
+}
+
+pub struct LiteralReplace {
+    pub original: Vec<u8>,
+    pub patched: Vec<u8>,
+    pub occurrences: usize,
+}
+
+// `replace --allow-binary-literal`: non-overlapping byte substitution of the
+// UTF-8 pattern, left to right, stopping after `limit` hits. None when the
+// pattern never occurs.
+pub fn replace_literal(
+    data: Vec<u8>,
+    pattern: &[u8],
+    replacement: &[u8],
+    limit: Option<usize>,
+) -> Option<LiteralReplace> {
+    if pattern.is_empty() {
+        return None;
+    }
+    let mut patched = Vec::with_capacity(data.len());
+    let mut occurrences = 0;
+    let mut idx = 0;
+    while idx < data.len() {
+        if limit.is_none_or(|limit| occurrences < limit) && data[idx..].starts_with(pattern) {
+            patched.extend_from_slice(replacement);
+            idx += pattern.len();
+            occurrences += 1;
+        } else {
+            patched.push(data[idx]);
+            idx += 1;
+        }
+    }
+    (occurrences > 0).then_some(LiteralReplace {
+        original: data,
+        patched,
+        occurrences,
+    })
+}
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+// Byte ranges where `old` and `new` differ; a length change counts as a
+// difference running to the end of the longer buffer.
+pub fn changed_ranges(old: &[u8], new: &[u8]) -> Vec<Range<usize>> {
+    let longest = old.len().max(new.len());
+    let mut ranges: Vec<Range<usize>> = Vec::new();
+    for idx in 0..longest {
+        if old.get(idx) == new.get(idx) {
+            continue;
+        }
+        match ranges.last_mut() {
+            Some(last) if last.end == idx => last.end = idx + 1,
+            _ => ranges.push(idx..idx + 1),
+        }
+    }
+    ranges
+}
+
+// Hex-dump rows around each changed range: unchanged rows are prefixed with
+// a space, changed rows appear as a `-` (before) / `+` (after) pair.
+pub fn preview(old: &[u8], new: &[u8], context_rows: usize, colorize: bool) -> Vec<String> {
+    let changed = changed_ranges(old, new);
+    let longest = old.len().max(new.len());
+    let total_rows = longest.div_ceil(ROW_BYTES);
+    let mut wanted: Vec<Range<usize>> = Vec::new();
+    for range in &changed {
+        let first = (range.start / ROW_BYTES).saturating_sub(context_rows);
+        let last = ((range.end - 1) / ROW_BYTES + context_rows + 1).min(total_rows);
+        match wanted.last_mut() {
+            Some(previous) if previous.end >= first => previous.end = previous.end.max(last),
+            _ => wanted.push(first..last),
+        }
+    }
+    let mut lines = Vec::new();
+    for (idx, rows) in wanted.into_iter().enumerate() {
+        if idx > 0 {
+            lines.push("...".to_string());
+        }
+        for row in rows {
+            let span = row * ROW_BYTES..(row + 1) * ROW_BYTES;
+            if changed
+                .iter()
+                .any(|range| range.start < span.end && span.start < range.end)
+            {
+                lines.push(render_row('-', old, new, span.clone(), colorize, false));
+                lines.push(render_row('+', new, old, span, colorize, true));
+            } else {
+                lines.push(render_row(' ', old, new, span, false, false));
+            }
+        }
+    }
+    lines
+}
+
+fn render_row(
+    marker: char,
+    data: &[u8],
+    other: &[u8],
+    span: Range<usize>,
+    colorize: bool,
+    added: bool,
+) -> String {
+    let mut hex = String::new();
+    let mut ascii = String::new();
+    for idx in span.clone() {
+        if idx > span.start {
+            hex.push(' ');
+        }
+        let Some(byte) = data.get(idx) else {
+            hex.push_str("  ");
+            continue;
+        };
+        let cell = format!("{byte:02x}");
+        let shown = if *byte == b' ' || byte.is_ascii_graphic() {
+            (*byte as char).to_string()
+        } else {
+            ".".to_string()
+        };
+        if colorize && other.get(idx) != Some(byte) {
+            hex.push_str(&paint_change(&cell, added));
+            ascii.push_str(&paint_change(&shown, added));
+        } else {
+            hex.push_str(&cell);
+            ascii.push_str(&shown);
+        }
+    }
+    format!("{marker} {:08x}  {hex}  |{ascii}|", span.start)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn offsets_and_hex_parse() {
+        assert_eq!(parse_offset("0x1F4").unwrap(), 500);
+        assert_eq!(parse_offset("500").unwrap(), 500);
+        assert_eq!(parse_offset("0b11").unwrap(), 3);
+        assert!(parse_offset("0xZZ").is_err());
+        assert_eq!(
+            parse_hex("0a0b", "--expect-bytes").unwrap(),
+            vec![0x0a, 0x0b]
+        );
+        assert_eq!(
+            parse_hex("DE:AD be ef", "--x").unwrap(),
+            vec![0xde, 0xad, 0xbe, 0xef]
+        );
+        assert!(parse_hex("abc", "--x").is_err());
+        assert!(parse_hex("", "--x").is_err());
+        assert_eq!(to_hex(&[0, 255]), "00ff");
+    }
+
+    #[test]
+    fn literal_replace_is_byte_level_and_honours_limit() {
+        let data = b"\0key=old\0key=old\0".to_vec();
+        let all = replace_literal(data.clone(), b"old", b"newer", None).unwrap();
+        assert_eq!(all.occurrences, 2);
+        assert_eq!(all.patched, b"\0key=newer\0key=newer\0");
+        let first = replace_literal(data.clone(), b"old", b"new", Some(1)).unwrap();
+        assert_eq!(first.patched, b"\0key=new\0key=old\0");
+        assert!(replace_literal(data, b"missing", b"x", None).is_none());
+    }
+
+    #[test]
+    fn preview_shows_changed_rows_with_context() {
+        let old: Vec<u8> = (0..64).collect();
+        let mut new = old.clone();
+        new[33] = 0xff;
+        assert_eq!(changed_ranges(&old, &new), vec![33..34]);
+        let lines = preview(&old, &new, 1, false);
+        assert_eq!(lines.len(), 4);
+        assert!(lines[0].starts_with("  00000010  10 11"));
+        assert!(lines[1].starts_with("- 00000020  20 21 22"));
+        assert!(lines[2].starts_with("+ 00000020  20 ff 22"));
+        assert!(lines[3].starts_with("  00000030"));
+    }
+}
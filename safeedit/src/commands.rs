@@ -1,3 +1,5 @@
+use std::cell::RefCell;
+
 use anyhow::{Result, anyhow, bail};
 use regex::{Regex, RegexBuilder};
 
@@ -11,9 +13,105 @@ pub struct ReplaceOptions {
     pub pattern: String,
     pub replacement: String,
     pub allow_captures: bool,
+    pub case_aware: bool,
     pub count: Option<usize>,
     pub expect: Option<usize>,
     pub after_line: Option<usize>,
+    pub skip_if_contains: Option<String>,
+    pub quiet: bool,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MatchCounts {
+    pub occurrences: usize,
+    pub lines: usize,
+}
+
+impl MatchCounts {
+    pub fn describe(&self) -> String {
+        format!(
+            "{} occurrence{} on {} line{}",
+            self.occurrences,
+            if self.occurrences == 1 { "" } else { "s" },
+            self.lines,
+            if self.lines == 1 { "" } else { "s" }
+        )
+    }
+}
+
+// Counts matches and the distinct lines they start on; matches must be
+// recorded in text order.
+struct MatchTally {
+    index: LineIndex,
+    counts: MatchCounts,
+    last_line: usize,
+}
+
+impl MatchTally {
+    fn new(text: &str) -> Self {
+        Self {
+            index: LineIndex::new(text),
+            counts: MatchCounts::default(),
+            last_line: 0,
+        }
+    }
+
+    fn record(&mut self, offset: usize) {
+        let line = self.index.line_at(offset);
+        if line != self.last_line {
+            self.counts.lines += 1;
+            self.last_line = line;
+        }
+        self.counts.occurrences += 1;
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReplaceEdit {
+    Changed { text: String, counts: MatchCounts },
+    NoMatch,
+    AlreadyDone(String),
+    ExpectMismatch(ExpectMismatch),
+}
+
+// What `--expect` found instead: the match count and 1-based line:column of
+// each match, so the file can be reported without failing the whole run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExpectMismatch {
+    pub expected: usize,
+    pub found: usize,
+    pub locations: Vec<(usize, usize)>,
+}
+
+impl ExpectMismatch {
+    pub fn describe(&self) -> String {
+        let mut text = format!(
+            "expected {} matches but found {}",
+            self.expected, self.found
+        );
+        if !self.locations.is_empty() {
+            let shown: Vec<String> = self
+                .locations
+                .iter()
+                .take(10)
+                .map(|(line, column)| format!("{line}:{column}"))
+                .collect();
+            text.push_str(&format!(" at {}", shown.join(", ")));
+            if self.locations.len() > shown.len() {
+                text.push_str(&format!(" (+{} more)", self.locations.len() - shown.len()));
+            }
+        }
+        text
+    }
+}
+
+// The planned change plus, for files left alone because the edit already
+// happened, the reason to report as "already done".
+pub struct ReplaceRun {
+    pub result: Option<TransformResult>,
+    pub counts: MatchCounts,
+    pub already_done: Option<String>,
+    pub expect_mismatch: Option<ExpectMismatch>,
 }
 
 #[derive(Debug, Clone)]
@@ -50,6 +148,13 @@ pub struct BlockOptions {
     pub allow_marker_overlap: bool,
 }
 
+// Files without the block's markers are reported, not fatal, so one `block`
+// run can sweep a whole glob.
+pub struct BlockRun {
+    pub result: Option<TransformResult>,
+    pub markers_missing: Option<String>,
+}
+
 #[derive(Debug, Clone)]
 pub struct RenameOptions {
     pub from: String,
@@ -62,98 +167,256 @@ pub fn run_replace(
     entry: &FileEntry,
     encoding: &EncodingStrategy,
     options: &ReplaceOptions,
-) -> Result<Option<TransformResult>> {
+) -> Result<ReplaceRun> {
     let context = TransformContext { entry, encoding };
-
-    run_transform(&context, |decoded| apply_replace(decoded, options))
+    let outcome = RefCell::new((MatchCounts::default(), None, None));
+    let result = run_transform(&context, |decoded| {
+        Ok(match plan_replace(decoded, options)? {
+            ReplaceEdit::Changed { text, counts } => {
+                outcome.borrow_mut().0 = counts;
+                Some(text)
+            }
+            ReplaceEdit::NoMatch => None,
+            ReplaceEdit::AlreadyDone(reason) => {
+                outcome.borrow_mut().1 = Some(reason);
+                None
+            }
+            ReplaceEdit::ExpectMismatch(mismatch) => {
+                outcome.borrow_mut().2 = Some(mismatch);
+                None
+            }
+        })
+    })?;
+    let (counts, already_done, expect_mismatch) = outcome.into_inner();
+    Ok(ReplaceRun {
+        result,
+        counts,
+        already_done,
+        expect_mismatch,
+    })
 }
 
 pub fn run_block(
     entry: &FileEntry,
     encoding: &EncodingStrategy,
     options: &BlockOptions,
-) -> Result<Option<TransformResult>> {
+) -> Result<BlockRun> {
     let context = TransformContext { entry, encoding };
-    run_transform(&context, |decoded| apply_block(decoded, options))
+    let markers_missing = RefCell::new(None);
+    let result = run_transform(&context, |decoded| {
+        if let Some(reason) = missing_marker(&decoded.text, &options.target) {
+            *markers_missing.borrow_mut() = Some(reason);
+            return Ok(None);
+        }
+        apply_block(decoded, options)
+    })?;
+    Ok(BlockRun {
+        result,
+        markers_missing: markers_missing.into_inner(),
+    })
 }
 
 pub fn run_rename(
     entry: &FileEntry,
     encoding: &EncodingStrategy,
     options: &RenameOptions,
-) -> Result<Option<TransformResult>> {
+) -> Result<(Option<TransformResult>, MatchCounts)> {
     let context = TransformContext { entry, encoding };
-    run_transform(&context, |decoded| apply_rename(decoded, options))
+    let counts = RefCell::new(MatchCounts::default());
+    let result = run_transform(&context, |decoded| {
+        Ok(apply_rename(decoded, options)?.map(|(text, found)| {
+            *counts.borrow_mut() = found;
+            text
+        }))
+    })?;
+    Ok((result, counts.into_inner()))
 }
 
-fn apply_replace(decoded: &DecodedText, options: &ReplaceOptions) -> Result<Option<String>> {
-    let regex = Regex::new(&options.pattern).map_err(|err| anyhow!("invalid pattern: {err}"))?;
+// References in a regex replacement, following the regex crate's expansion
+// rules: `$$` is a literal `$`, `${name}` is braced, an unbraced `$name` runs
+// as far as `[0-9A-Za-z_]` allows (so `$1a` names a group `1a`; write
+// `${1}a`), and a `$` that starts no reference is kept as written.
+fn template_refs(template: &str) -> Vec<&str> {
+    let mut refs = Vec::new();
+    let mut rest = template;
+    while let Some(idx) = rest.find('$') {
+        rest = &rest[idx + 1..];
+        if let Some(after) = rest.strip_prefix('$') {
+            rest = after;
+        } else if let Some(braced) = rest.strip_prefix('{')
+            && let Some(close) = braced.find('}')
+            && close > 0
+        {
+            refs.push(&braced[..close]);
+            rest = &braced[close + 1..];
+        } else {
+            let len = rest
+                .find(|ch: char| !(ch.is_ascii_alphanumeric() || ch == '_'))
+                .unwrap_or(rest.len());
+            if len > 0 {
+                refs.push(&rest[..len]);
+            }
+            rest = &rest[len..];
+        }
+    }
+    refs
+}
+
+// Unknown groups silently expand to nothing, which is rarely what a
+// replacement meant; callers print these before touching any file.
+pub fn template_warnings(pattern: &str, template: &str) -> Result<Vec<String>> {
+    let regex = Regex::new(pattern).map_err(|err| anyhow!("invalid pattern: {err}"))?;
+    let mut warnings = Vec::new();
+    let mut seen = Vec::new();
+    for name in template_refs(template) {
+        let exists = match name.parse::<usize>() {
+            Ok(index) => index < regex.captures_len(),
+            Err(_) => regex.capture_names().flatten().any(|group| group == name),
+        };
+        if exists || seen.contains(&name) {
+            continue;
+        }
+        seen.push(name);
+        let digits = name.len()
+            - name
+                .trim_start_matches(|ch: char| ch.is_ascii_digit())
+                .len();
+        let hint = if digits > 0 && digits < name.len() {
+            format!(
+                "; write ${{{}}}{} to follow group {} with text",
+                &name[..digits],
+                &name[digits..],
+                &name[..digits]
+            )
+        } else {
+            String::new()
+        };
+        warnings.push(format!(
+            "replacement references group '{name}' which the pattern does not define; it expands to an empty string{hint} (use $$ for a literal $, or --no-expand)"
+        ));
+    }
+    Ok(warnings)
+}
+
+pub fn apply_replace(decoded: &DecodedText, options: &ReplaceOptions) -> Result<Option<String>> {
+    Ok(match plan_replace(decoded, options)? {
+        ReplaceEdit::Changed { text, .. } => Some(text),
+        ReplaceEdit::NoMatch | ReplaceEdit::AlreadyDone(_) => None,
+        ReplaceEdit::ExpectMismatch(mismatch) => bail!(mismatch.describe()),
+    })
+}
+
+pub fn plan_replace(decoded: &DecodedText, options: &ReplaceOptions) -> Result<ReplaceEdit> {
+    if let Some(marker) = options.skip_if_contains.as_deref()
+        && decoded.text.contains(marker)
+    {
+        return Ok(ReplaceEdit::AlreadyDone(format!(
+            "already contains \"{}\"",
+            preview_marker(marker)
+        )));
+    }
+    // --case-aware matches any casing and re-cases the replacement to follow
+    // each match, as rename does.
+    let regex = RegexBuilder::new(&options.pattern)
+        .case_insensitive(options.case_aware)
+        .build()
+        .map_err(|err| anyhow!("invalid pattern: {err}"))?;
     let mut output = String::with_capacity(decoded.text.len());
     let mut last_end = 0usize;
     let mut replacements = 0usize;
     let mut filtered_by_line = 0usize;
     let mut capture_buffer = String::new();
-    let line_index = options.after_line.map(|_| LineIndex::new(&decoded.text));
+    let mut tally = MatchTally::new(&decoded.text);
+    let mut offsets = Vec::new();
     let template = options.replacement.as_str();
 
     for caps in regex.captures_iter(&decoded.text) {
         let matched = caps.get(0).expect("match group");
-        let eligible = if let (Some(limit), Some(index)) = (options.after_line, line_index.as_ref())
-        {
-            index.line_at(matched.start()) > limit
-        } else {
-            true
-        };
+        let eligible = options
+            .after_line
+            .is_none_or(|limit| tally.index.line_at(matched.start()) > limit);
 
         if !eligible {
             filtered_by_line += 1;
             continue;
         }
 
-        if let Some(limit) = options.count {
-            if replacements >= limit {
-                break;
-            }
+        if let Some(limit) = options.count
+            && replacements >= limit
+        {
+            break;
         }
 
         output.push_str(&decoded.text[last_end..matched.start()]);
 
+        capture_buffer.clear();
         if options.allow_captures {
-            capture_buffer.clear();
             caps.expand(template, &mut capture_buffer);
-            output.push_str(&capture_buffer);
         } else {
-            output.push_str(template);
+            capture_buffer.push_str(template);
+        }
+        if options.case_aware {
+            output.push_str(&adjust_case(matched.as_str(), &capture_buffer));
+        } else {
+            output.push_str(&capture_buffer);
         }
 
         last_end = matched.end();
         replacements += 1;
+        tally.record(matched.start());
+        offsets.push(matched.start());
     }
 
     if replacements == 0 {
-        if options.after_line.is_some() && filtered_by_line > 0 {
+        if let Some(limit) = options.after_line
+            && filtered_by_line > 0
+        {
+            if options.quiet {
+                return Ok(ReplaceEdit::NoMatch);
+            }
             println!(
-                "no matches after line {}; {} occurrence(s) were at or before that line",
-                options.after_line.unwrap(),
-                filtered_by_line
+                "no matches after line {limit}; {filtered_by_line} occurrence(s) were at or before that line"
             );
-            return Ok(None);
+            return Ok(ReplaceEdit::NoMatch);
         }
-        report_suggestions(&decoded.text, &options.pattern);
-        return Ok(None);
+        if !options.quiet {
+            report_suggestions(&decoded.text, &options.pattern);
+        }
+        return Ok(ReplaceEdit::NoMatch);
     }
 
     output.push_str(&decoded.text[last_end..]);
 
-    if let Some(expected) = options.expect {
-        if replacements != expected {
-            return Err(anyhow!(
-                "expected {expected} matches but found {replacements}"
-            ));
-        }
+    // Re-running a migration whose replacement reproduces the match (e.g. a
+    // regex that normalises already-normalised text) must not count as a change.
+    if output == decoded.text {
+        return Ok(ReplaceEdit::AlreadyDone(format!(
+            "replacement leaves all {replacements} match(es) unchanged"
+        )));
     }
 
-    Ok(Some(output))
+    if let Some(expected) = options.expect
+        && replacements != expected
+    {
+        let locations = offsets
+            .iter()
+            .map(|&offset| {
+                let line = tally.index.line_at(offset);
+                let start = tally.index.starts[line - 1];
+                (line, decoded.text[start..offset].chars().count() + 1)
+            })
+            .collect();
+        return Ok(ReplaceEdit::ExpectMismatch(ExpectMismatch {
+            expected,
+            found: replacements,
+            locations,
+        }));
+    }
+
+    Ok(ReplaceEdit::Changed {
+        text: output,
+        counts: tally.counts,
+    })
 }
 
 fn apply_block(decoded: &DecodedText, options: &BlockOptions) -> Result<Option<String>> {
@@ -170,20 +433,20 @@ fn apply_block(decoded: &DecodedText, options: &BlockOptions) -> Result<Option<S
 
     let indent = block_indent(text, location.indent_anchor);
     let desired = adjust_block_body(existing, &options.body, text, &indent);
-    if !options.allow_marker_overlap {
-        if let BlockTarget::Range { start, end } = &options.target {
-            if contains_marker(&desired, start) {
-                bail!(
-                    "block body still contains start marker \"{}\"; use --allow-marker-overlap or widen the sentinel",
-                    preview_marker(start)
-                );
-            }
-            if contains_marker(&desired, end) {
-                bail!(
-                    "block body still contains end marker \"{}\"; use --allow-marker-overlap or widen the sentinel",
-                    preview_marker(end)
-                );
-            }
+    if !options.allow_marker_overlap
+        && let BlockTarget::Range { start, end } = &options.target
+    {
+        if contains_marker(&desired, start) {
+            bail!(
+                "block body still contains start marker \"{}\"; use --allow-marker-overlap or widen the sentinel",
+                preview_marker(start)
+            );
+        }
+        if contains_marker(&desired, end) {
+            bail!(
+                "block body still contains end marker \"{}\"; use --allow-marker-overlap or widen the sentinel",
+                preview_marker(end)
+            );
         }
     }
 
@@ -226,6 +489,33 @@ fn locate_block_region(
     }
 }
 
+// The same "not found" checks the locate_* functions bail on; empty markers
+// are left for them to reject.
+fn missing_marker(text: &str, target: &BlockTarget) -> Option<String> {
+    match target {
+        BlockTarget::Range { start, end } if !start.is_empty() && !end.is_empty() => {
+            match text.find(start.as_str()) {
+                None => Some(format!("start marker '{start}' not found")),
+                Some(pos) if !text[pos + start.len()..].contains(end.as_str()) => {
+                    Some(format!("end marker '{end}' not found after start marker"))
+                }
+                Some(_) => None,
+            }
+        }
+        BlockTarget::InsertAfter { marker }
+            if !marker.is_empty() && !text.contains(marker.as_str()) =>
+        {
+            Some(format!("insert-after marker '{marker}' not found"))
+        }
+        BlockTarget::InsertBefore { marker }
+            if !marker.is_empty() && !text.contains(marker.as_str()) =>
+        {
+            Some(format!("insert-before marker '{marker}' not found"))
+        }
+        _ => None,
+    }
+}
+
 fn locate_block_range(
     text: &str,
     start_marker: &str,
@@ -312,10 +602,10 @@ fn locate_insert_before(text: &str, marker: &str, lines: &LineIndex) -> Result<B
 }
 
 fn enforce_block_expectation(expect: Option<usize>, actual: usize) -> Result<()> {
-    if let Some(expected) = expect {
-        if actual != expected {
-            bail!("expected {expected} block match(es) but found {actual}");
-        }
+    if let Some(expected) = expect
+        && actual != expected
+    {
+        bail!("expected {expected} block match(es) but found {actual}");
     }
     Ok(())
 }
@@ -491,7 +781,10 @@ fn extract_trailing_indent(existing: &str) -> &str {
     ""
 }
 
-fn apply_rename(decoded: &DecodedText, options: &RenameOptions) -> Result<Option<String>> {
+fn apply_rename(
+    decoded: &DecodedText,
+    options: &RenameOptions,
+) -> Result<Option<(String, MatchCounts)>> {
     let mut pattern = regex::escape(&options.from);
     if options.word_boundary {
         pattern = format!(r"\b{pattern}\b");
@@ -504,11 +797,11 @@ fn apply_rename(decoded: &DecodedText, options: &RenameOptions) -> Result<Option
         .build()
         .map_err(|err| anyhow!("invalid pattern: {err}"))?;
     let replacement = options.to.clone();
-    let mut matches = 0usize;
+    let mut tally = MatchTally::new(&decoded.text);
 
     let replaced = regex
         .replace_all(&decoded.text, |caps: &regex::Captures<'_>| {
-            matches += 1;
+            tally.record(caps.get(0).expect("match group").start());
             if options.case_aware {
                 adjust_case(&caps[0], &replacement)
             } else {
@@ -517,7 +810,7 @@ fn apply_rename(decoded: &DecodedText, options: &RenameOptions) -> Result<Option
         })
         .into_owned();
 
-    if matches == 0 {
+    if tally.counts.occurrences == 0 {
         println!(
             "rename: no matches for '{}'{}",
             options.from,
@@ -531,7 +824,7 @@ fn apply_rename(decoded: &DecodedText, options: &RenameOptions) -> Result<Option
         return Ok(None);
     }
 
-    Ok(Some(replaced))
+    Ok(Some((replaced, tally.counts)))
 }
 
 fn report_suggestions(text: &str, pattern: &str) {
@@ -843,19 +1136,78 @@ mod tests {
         assert!(result.is_none());
     }
 
+    #[test]
+    fn replace_reports_how_many_matches_it_changed() {
+        let decoded = decoded_text("foo foo foo\n");
+        let mut options = literal_options("foo", "bar", None);
+        options.count = Some(2);
+        assert_eq!(
+            plan_replace(&decoded, &options).expect("replace"),
+            ReplaceEdit::Changed {
+                text: "bar bar foo\n".into(),
+                counts: MatchCounts {
+                    occurrences: 2,
+                    lines: 1,
+                },
+            }
+        );
+    }
+
+    #[test]
+    fn replace_reports_already_done_instead_of_double_substituting() {
+        let decoded = decoded_text("use new_api::client;\n");
+        let mut options = literal_options("new_api", "new_api", None);
+        assert!(matches!(
+            plan_replace(&decoded, &options).expect("replace"),
+            ReplaceEdit::AlreadyDone(_)
+        ));
+        options = literal_options("api", "new_api", None);
+        options.skip_if_contains = Some("new_api".into());
+        options.expect = Some(1);
+        assert!(matches!(
+            plan_replace(&decoded, &options).expect("replace"),
+            ReplaceEdit::AlreadyDone(reason) if reason.contains("new_api")
+        ));
+        assert!(
+            apply_replace(&decoded, &options)
+                .expect("replace")
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn expect_mismatch_reports_match_locations() {
+        let decoded = decoded_text("let a = 1;\nlet b = a + a;\n");
+        let mut options = literal_options("a", "x", None);
+        options.expect = Some(2);
+        let ReplaceEdit::ExpectMismatch(mismatch) =
+            plan_replace(&decoded, &options).expect("replace")
+        else {
+            panic!("expected a mismatch report");
+        };
+        assert_eq!(mismatch.found, 3);
+        assert_eq!(mismatch.locations, vec![(1, 5), (2, 9), (2, 13)]);
+        assert_eq!(
+            mismatch.describe(),
+            "expected 2 matches but found 3 at 1:5, 2:9, 2:13"
+        );
+        assert!(apply_replace(&decoded, &options).is_err());
+    }
+
     #[test]
     fn rename_word_boundary_and_case_aware() {
-        let decoded = decoded_text("Foo foo FOO");
+        let decoded = decoded_text("Foo foo FOO\nfoo\n");
         let options = RenameOptions {
             from: "foo".into(),
             to: "bar".into(),
             word_boundary: true,
             case_aware: true,
         };
-        let replaced = apply_rename(&decoded, &options)
+        let (replaced, counts) = apply_rename(&decoded, &options)
             .expect("rename")
             .expect("text");
-        assert_eq!(replaced, "Bar bar BAR");
+        assert_eq!(replaced, "Bar bar BAR\nbar\n");
+        assert_eq!(counts.describe(), "4 occurrences on 2 lines");
     }
 
     #[test]
@@ -1034,6 +1386,65 @@ mod tests {
         assert!(format!("{err:#}").contains("expected 2 block"));
     }
 
+    #[test]
+    fn replacement_templates_expand_named_groups_and_flag_unknown_ones() {
+        let decoded = decoded_text("id=7\n");
+        let mut options = literal_options("", "${key}:$value$$ ${1}x", None);
+        options.pattern = r"(?P<key>\w+)=(?P<value>\d+)".into();
+        options.allow_captures = true;
+        let expanded = apply_replace(&decoded, &options).unwrap().unwrap();
+        assert_eq!(expanded, "id:7$ idx\n");
+        options.allow_captures = false;
+        let literal = apply_replace(&decoded, &options).unwrap().unwrap();
+        assert_eq!(literal, "${key}:$value$$ ${1}x\n");
+
+        assert_eq!(
+            template_refs("$$a ${b} $c-d $ $1a ${}"),
+            vec!["b", "c", "1a"]
+        );
+        assert!(
+            template_warnings(&options.pattern, "${key} $2")
+                .unwrap()
+                .is_empty()
+        );
+        let warnings = template_warnings(&options.pattern, "$1a ${nope} $nope").unwrap();
+        assert_eq!(warnings.len(), 2);
+        assert!(warnings[0].contains("write ${1}a"));
+        assert!(warnings[1].contains("'nope'"));
+    }
+
+    #[test]
+    fn case_aware_replace_follows_each_match() {
+        let decoded = decoded_text("color Color COLOR coLor\n");
+        let mut options = literal_options("color", "colour", None);
+        options.case_aware = true;
+        let replaced = apply_replace(&decoded, &options).unwrap().unwrap();
+        assert_eq!(replaced, "colour Colour COLOUR colour\n");
+    }
+
+    #[test]
+    fn missing_markers_are_named_per_target() {
+        let range = |start: &str, end: &str| BlockTarget::Range {
+            start: start.into(),
+            end: end.into(),
+        };
+        let text = "a\n# END\n# BEGIN\nb\n";
+        assert_eq!(
+            missing_marker(text, &range("# START", "# END")).as_deref(),
+            Some("start marker '# START' not found")
+        );
+        assert_eq!(
+            missing_marker(text, &range("# BEGIN", "# END")).as_deref(),
+            Some("end marker '# END' not found after start marker")
+        );
+        assert!(missing_marker(text, &range("# END", "# BEGIN")).is_none());
+        let after = BlockTarget::InsertAfter {
+            marker: "fn main".into(),
+        };
+        assert!(missing_marker(text, &after).is_some());
+        assert!(missing_marker(text, &range("", "# END")).is_none());
+    }
+
     fn decoded_text(text: &str) -> DecodedText {
         EncodingStrategy::new(None)
             .expect("strategy")
@@ -1049,9 +1460,12 @@ mod tests {
             pattern: regex::escape(pattern),
             replacement: replacement.to_string(),
             allow_captures: false,
+            case_aware: false,
             count: None,
             expect: None,
             after_line,
+            skip_if_contains: None,
+            quiet: false,
         }
     }
 }
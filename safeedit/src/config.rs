@@ -0,0 +1,285 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result, anyhow, bail};
+use serde::Deserialize;
+
+use crate::diff::Palette;
+use crate::normalize::CharClass;
+
+pub const CONFIG_FILE: &str = ".safeedit.toml";
+
+#[derive(Debug, Deserialize, Default, Clone)]
+pub struct Config {
+    #[serde(default)]
+    pub strip_classes: BTreeMap<String, CharClassSpec>,
+    #[serde(default)]
+    pub log: LogConfig,
+    #[serde(default)]
+    pub diff_colors: DiffColorConfig,
+    #[serde(default)]
+    pub limits: LimitsConfig,
+}
+
+#[derive(Debug, Deserialize, Default, Clone)]
+pub struct LimitsConfig {
+    pub max_diff_lines: Option<usize>,
+    pub max_diff_bytes: Option<usize>,
+}
+
+#[derive(Debug, Deserialize, Default, Clone)]
+pub struct DiffColorConfig {
+    pub theme: Option<String>,
+    pub added: Option<String>,
+    pub removed: Option<String>,
+    pub context: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Default, Clone)]
+pub struct LogConfig {
+    pub file: Option<PathBuf>,
+    pub global: Option<bool>,
+    pub max_entries: Option<usize>,
+    pub max_bytes: Option<u64>,
+    pub keep_rotated: Option<usize>,
+    pub retain_days: Option<u64>,
+}
+
+#[derive(Debug, Deserialize, Default, Clone)]
+pub struct CharClassSpec {
+    #[serde(default)]
+    pub ranges: Vec<String>,
+    #[serde(default)]
+    pub categories: Vec<String>,
+}
+
+impl Config {
+    pub fn load() -> Result<Self> {
+        let mut config = Config::default();
+        for path in config_paths() {
+            if path.is_file() {
+                config.merge(Self::load_file(&path)?);
+            }
+        }
+        Ok(config)
+    }
+
+    pub fn load_file(path: &Path) -> Result<Self> {
+        let raw =
+            fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))?;
+        toml::from_str(&raw).with_context(|| format!("parsing {}", path.display()))
+    }
+
+    fn merge(&mut self, other: Config) {
+        self.strip_classes.extend(other.strip_classes);
+        if other.log.file.is_some() {
+            self.log.file = other.log.file;
+        }
+        if other.log.global.is_some() {
+            self.log.global = other.log.global;
+        }
+        if other.log.max_entries.is_some() {
+            self.log.max_entries = other.log.max_entries;
+        }
+        if other.log.max_bytes.is_some() {
+            self.log.max_bytes = other.log.max_bytes;
+        }
+        if other.log.keep_rotated.is_some() {
+            self.log.keep_rotated = other.log.keep_rotated;
+        }
+        if other.log.retain_days.is_some() {
+            self.log.retain_days = other.log.retain_days;
+        }
+        if other.limits.max_diff_lines.is_some() {
+            self.limits.max_diff_lines = other.limits.max_diff_lines;
+        }
+        if other.limits.max_diff_bytes.is_some() {
+            self.limits.max_diff_bytes = other.limits.max_diff_bytes;
+        }
+        let colors = other.diff_colors;
+        if colors.theme.is_some() {
+            self.diff_colors.theme = colors.theme;
+        }
+        if colors.added.is_some() {
+            self.diff_colors.added = colors.added;
+        }
+        if colors.removed.is_some() {
+            self.diff_colors.removed = colors.removed;
+        }
+        if colors.context.is_some() {
+            self.diff_colors.context = colors.context;
+        }
+    }
+
+    pub fn diff_palette(&self) -> Result<Palette> {
+        let colors = &self.diff_colors;
+        let mut palette = match colors.theme.as_deref() {
+            Some(theme) => Palette::theme(theme)
+                .with_context(|| format!("[diff_colors] theme in {CONFIG_FILE}"))?,
+            None => Palette::default(),
+        };
+        for (name, spec, slot) in [
+            ("added", &colors.added, &mut palette.added),
+            ("removed", &colors.removed, &mut palette.removed),
+            ("context", &colors.context, &mut palette.context),
+        ] {
+            if let Some(spec) = spec {
+                *slot = crate::diff::parse_color(spec)
+                    .with_context(|| format!("[diff_colors] {name} in {CONFIG_FILE}"))?;
+            }
+        }
+        Ok(palette)
+    }
+
+    pub fn strip_class(&self, name: &str) -> Result<CharClass> {
+        let spec = match self.strip_classes.get(name) {
+            Some(spec) => spec.clone(),
+            None => builtin_class(name).ok_or_else(|| {
+                let mut known: Vec<&str> = self.strip_classes.keys().map(String::as_str).collect();
+                known.extend(BUILTIN_CLASSES);
+                anyhow!(
+                    "unknown strip class '{name}' (known: {}); define it under [strip_classes.{name}] in {CONFIG_FILE}",
+                    known.join(", ")
+                )
+            })?,
+        };
+        let mut class = CharClass {
+            name: name.to_string(),
+            ranges: Vec::new(),
+            categories: Vec::new(),
+        };
+        for range in &spec.ranges {
+            class
+                .ranges
+                .push(parse_range(range).with_context(|| format!("strip class '{name}' range"))?);
+        }
+        for category in &spec.categories {
+            let valid = matches!(category.len(), 1 | 2)
+                && category.starts_with(['L', 'M', 'N', 'P', 'S', 'Z', 'C']);
+            if !valid {
+                bail!("strip class '{name}' has invalid Unicode category '{category}'");
+            }
+            class.categories.push(category.clone());
+        }
+        if class.ranges.is_empty() && class.categories.is_empty() {
+            bail!("strip class '{name}' defines no ranges or categories");
+        }
+        Ok(class)
+    }
+}
+
+const BUILTIN_CLASSES: [&str; 1] = ["emoji"];
+
+fn builtin_class(name: &str) -> Option<CharClassSpec> {
+    match name {
+        "emoji" => Some(CharClassSpec {
+            ranges: [
+                "U+1F000..U+1FAFF",
+                "U+2600..U+27BF",
+                "U+FE0F",
+                "U+200D",
+                "U+E0020..U+E007F",
+            ]
+            .iter()
+            .map(|range| range.to_string())
+            .collect(),
+            categories: Vec::new(),
+        }),
+        _ => None,
+    }
+}
+
+pub fn home_dir() -> Option<PathBuf> {
+    std::env::var_os("HOME")
+        .or_else(|| std::env::var_os("USERPROFILE"))
+        .map(PathBuf::from)
+}
+
+fn config_paths() -> Vec<PathBuf> {
+    let mut paths = Vec::new();
+    if let Some(home) = home_dir() {
+        paths.push(home.join(CONFIG_FILE));
+    }
+    if let Ok(cwd) = std::env::current_dir() {
+        let mut project: Vec<PathBuf> = cwd.ancestors().map(|dir| dir.join(CONFIG_FILE)).collect();
+        project.reverse();
+        for path in project {
+            if !paths.contains(&path) {
+                paths.push(path);
+            }
+        }
+    }
+    paths
+}
+
+fn parse_range(spec: &str) -> Result<(u32, u32)> {
+    let (start, end) = match spec.split_once("..") {
+        Some((start, end)) => (start, end),
+        None => (spec, spec),
+    };
+    let start = parse_codepoint(start)?;
+    let end = parse_codepoint(end)?;
+    if start > end {
+        bail!("range '{spec}' is reversed");
+    }
+    Ok((start, end))
+}
+
+fn parse_codepoint(raw: &str) -> Result<u32> {
+    let trimmed = raw.trim();
+    let hex = trimmed
+        .strip_prefix("U+")
+        .or_else(|| trimmed.strip_prefix("u+"))
+        .or_else(|| trimmed.strip_prefix("0x"))
+        .unwrap_or(trimmed);
+    u32::from_str_radix(hex, 16).map_err(|_| anyhow!("invalid code point '{trimmed}'"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strip_class_parses_ranges_and_categories() {
+        let config: Config = toml::from_str(
+            r#"
+            [strip_classes.symbols]
+            ranges = ["U+2190..U+21FF", "0x2122"]
+            categories = ["Sm"]
+            "#,
+        )
+        .expect("config");
+        let class = config.strip_class("symbols").expect("class");
+        assert!(class.contains('\u{2192}'));
+        assert!(class.contains('\u{2122}'));
+        assert!(class.contains('+'));
+        assert!(!class.contains('a'));
+        assert!(
+            config
+                .strip_class("emoji")
+                .expect("builtin")
+                .contains('\u{1F600}')
+        );
+        assert!(config.strip_class("missing").is_err());
+    }
+
+    #[test]
+    fn diff_colors_layer_overrides_on_a_theme() {
+        let config: Config = toml::from_str(
+            r#"
+            [diff_colors]
+            theme = "colorblind"
+            removed = "bold bright-red"
+            context = "90"
+            "#,
+        )
+        .expect("config");
+        let palette = config.diff_palette().expect("palette");
+        assert_eq!(palette.added, Some("\x1b[34m".to_string()));
+        assert_eq!(palette.removed, Some("\x1b[1;91m".to_string()));
+        assert_eq!(palette.context, Some("\x1b[90m".to_string()));
+        let bad: Config = toml::from_str("[diff_colors]\nadded = \"chartreuse\"").unwrap();
+        assert!(bad.diff_palette().is_err());
+    }
+}
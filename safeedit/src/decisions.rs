@@ -0,0 +1,152 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+
+use crate::integrity::sha256_hex;
+use crate::logging;
+
+const DECISIONS_FILE: &str = "decisions.json";
+
+static FRESH: AtomicBool = AtomicBool::new(false);
+static RUN_KEY: Mutex<Option<String>> = Mutex::new(None);
+
+// Operation hash -> file -> sha256 of the file when its change was declined.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Decisions {
+    #[serde(default)]
+    rejected: BTreeMap<String, BTreeMap<String, String>>,
+}
+
+impl Decisions {
+    fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let data = fs::read(path).with_context(|| format!("reading {}", path.display()))?;
+        serde_json::from_slice(&data).with_context(|| format!("invalid {}", path.display()))
+    }
+
+    fn save(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("creating directory {}", parent.display()))?;
+        }
+        fs::write(path, serde_json::to_string_pretty(self)? + "\n")
+            .with_context(|| format!("writing {}", path.display()))
+    }
+
+    fn is_rejected(&self, key: &str, file: &str, content_hash: &str) -> bool {
+        self.rejected
+            .get(key)
+            .and_then(|files| files.get(file))
+            .is_some_and(|hash| hash == content_hash)
+    }
+
+    fn reject(&mut self, key: &str, file: String, content_hash: String) {
+        self.rejected
+            .entry(key.to_string())
+            .or_default()
+            .insert(file, content_hash);
+    }
+}
+
+pub fn set_fresh() {
+    FRESH.store(true, Ordering::Relaxed);
+}
+
+// Clears the run key when the command that set it returns, so a later batch
+// step never inherits another step's declined files.
+pub struct RunGuard;
+
+impl Drop for RunGuard {
+    fn drop(&mut self) {
+        if let Ok(mut key) = RUN_KEY.lock() {
+            *key = None;
+        }
+    }
+}
+
+// The operation hash covers the command and the fields that define its edit
+// (the same ones `--record` writes), not the file selection or apply flags,
+// so a rerun that only adds `--apply` still recognizes earlier answers.
+pub fn begin(command: &str, fields: &[(&str, JsonValue)]) -> RunGuard {
+    let mut material = command.to_string();
+    for (name, value) in fields {
+        material.push('\0');
+        material.push_str(name);
+        material.push('=');
+        material.push_str(&value.to_string());
+    }
+    if let Ok(mut key) = RUN_KEY.lock() {
+        *key = Some(sha256_hex(material.as_bytes()));
+    }
+    RunGuard
+}
+
+fn current(path: &Path) -> Option<(String, String, String)> {
+    let key = RUN_KEY.lock().ok()?.clone()?;
+    let bytes = fs::read(path).ok()?;
+    let file = std::path::absolute(path)
+        .unwrap_or_else(|_| path.to_path_buf())
+        .to_string_lossy()
+        .into_owned();
+    Some((key, file, sha256_hex(&bytes)))
+}
+
+// True when this exact operation was declined for this exact file content
+// in an earlier run (and --fresh is not set).
+pub fn previously_rejected(path: &Path) -> bool {
+    if FRESH.load(Ordering::Relaxed) {
+        return false;
+    }
+    let Some((key, file, hash)) = current(path) else {
+        return false;
+    };
+    Decisions::load(&logging::state_dir().join(DECISIONS_FILE))
+        .map(|decisions| decisions.is_rejected(&key, &file, &hash))
+        .unwrap_or(false)
+}
+
+pub fn remember_rejection(path: &Path) {
+    let Some((key, file, hash)) = current(path) else {
+        return;
+    };
+    let store = logging::state_dir().join(DECISIONS_FILE);
+    let result = Decisions::load(&store).and_then(|mut decisions| {
+        decisions.reject(&key, file, hash);
+        decisions.save(&store)
+    });
+    if let Err(err) = result {
+        println!(
+            "warning: recording the decision for {}: {err:#}",
+            path.display()
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn rejections_match_operation_and_content() {
+        let dir = tempdir().unwrap();
+        let store = dir.path().join(".safeedit/decisions.json");
+        let mut decisions = Decisions::load(&store).unwrap();
+        decisions.reject("op", "/src/a.rs".into(), "h1".into());
+        decisions.save(&store).unwrap();
+
+        let loaded = Decisions::load(&store).unwrap();
+        assert!(loaded.is_rejected("op", "/src/a.rs", "h1"));
+        assert!(!loaded.is_rejected("op", "/src/a.rs", "h2"));
+        assert!(!loaded.is_rejected("other", "/src/a.rs", "h1"));
+        assert!(!loaded.is_rejected("op", "/src/b.rs", "h1"));
+    }
+}
@@ -0,0 +1,96 @@
+use std::fs;
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+
+use crate::encoding::EncodingStrategy;
+use crate::files::FileEntry;
+
+#[derive(Debug, Serialize)]
+pub struct EncodingInventory {
+    pub path: String,
+    pub bytes: u64,
+    pub binary: bool,
+    pub encoding: Option<String>,
+    pub source: Option<String>,
+    pub bom: Option<bool>,
+    pub score: Option<f32>,
+    pub decode_errors: Option<bool>,
+    pub replacement_chars: Option<usize>,
+    pub error_likelihood: &'static str,
+}
+
+pub fn collect(entry: &FileEntry, encoding: &EncodingStrategy) -> Result<EncodingInventory> {
+    if entry.metadata.is_probably_binary {
+        return Ok(EncodingInventory {
+            path: entry.path.display().to_string(),
+            bytes: entry.metadata.len,
+            binary: true,
+            encoding: None,
+            source: None,
+            bom: None,
+            score: None,
+            decode_errors: None,
+            replacement_chars: None,
+            error_likelihood: "n/a",
+        });
+    }
+    let bytes =
+        fs::read(&entry.path).with_context(|| format!("reading {}", entry.path.display()))?;
+    let decoded = encoding.decode(&bytes);
+    let replacement_chars = decoded.text.matches('\u{FFFD}').count();
+    Ok(EncodingInventory {
+        path: entry.path.display().to_string(),
+        bytes: bytes.len() as u64,
+        binary: false,
+        encoding: Some(decoded.decision.encoding.name().to_string()),
+        source: Some(decoded.decision.source.to_string()),
+        bom: Some(decoded.bom),
+        score: Some(decoded.decision.score),
+        decode_errors: Some(decoded.had_errors),
+        replacement_chars: Some(replacement_chars),
+        error_likelihood: error_likelihood(
+            decoded.had_errors,
+            replacement_chars,
+            decoded.decision.score,
+        ),
+    })
+}
+
+pub fn error_likelihood(had_errors: bool, replacement_chars: usize, score: f32) -> &'static str {
+    if had_errors || replacement_chars > 0 {
+        "high"
+    } else if score < 0.6 {
+        "medium"
+    } else {
+        "low"
+    }
+}
+
+pub fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn likelihood_prefers_decode_errors() {
+        assert_eq!(error_likelihood(true, 0, 1.0), "high");
+        assert_eq!(error_likelihood(false, 2, 1.0), "high");
+        assert_eq!(error_likelihood(false, 0, 0.3), "medium");
+        assert_eq!(error_likelihood(false, 0, 0.9), "low");
+    }
+
+    #[test]
+    fn csv_field_quotes_when_needed() {
+        assert_eq!(csv_field("plain"), "plain");
+        assert_eq!(csv_field("a,b"), "\"a,b\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+    }
+}
@@ -1,31 +1,211 @@
 use std::io::{self, Write};
 use std::path::Path;
+use std::sync::OnceLock;
 
-use anyhow::Result;
+use anyhow::{Result, anyhow, bail};
+use regex::Regex;
+use serde_json::{Value, json};
 use similar::{ChangeTag, DiffTag, TextDiff};
 
-use crate::PagerMode;
 use crate::logging::{LineSpan, LineSpanKind};
+use crate::review::build_interactive_regex;
+use crate::{DiffFormat, PagerMode};
 
-const RED: &str = "\x1b[31m";
-const GREEN: &str = "\x1b[32m";
 const RESET: &str = "\x1b[0m";
+const COLOR_NAMES: [&str; 8] = [
+    "black", "red", "green", "yellow", "blue", "magenta", "cyan", "white",
+];
+
+static PALETTE: OnceLock<Palette> = OnceLock::new();
+
+// Escape sequences for each kind of diff line; `None` leaves that kind
+// uncolored. Set once at startup from `[diff_colors]` in the config.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Palette {
+    pub added: Option<String>,
+    pub removed: Option<String>,
+    pub context: Option<String>,
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Self {
+            added: Some(sgr("32")),
+            removed: Some(sgr("31")),
+            context: None,
+        }
+    }
+}
+
+impl Palette {
+    pub fn theme(name: &str) -> Result<Self> {
+        let (added, removed) = match name {
+            "default" => return Ok(Self::default()),
+            "high-contrast" => ("1;92", "1;91"),
+            "colorblind" => ("34", "33"),
+            "mono" => ("1", "2"),
+            other => bail!(
+                "unknown diff color theme '{other}' (expected default, high-contrast, colorblind, or mono)"
+            ),
+        };
+        Ok(Self {
+            added: Some(sgr(added)),
+            removed: Some(sgr(removed)),
+            context: None,
+        })
+    }
+}
+
+pub fn set_palette(palette: Palette) {
+    let _ = PALETTE.set(palette);
+}
+
+fn palette() -> &'static Palette {
+    PALETTE.get_or_init(Palette::default)
+}
+
+fn sgr(params: &str) -> String {
+    format!("\x1b[{params}m")
+}
+
+// Accepts color names (`green`, `bright-red`), an optional `bold `/`dim `
+// prefix, raw SGR parameters such as `1;32`, or `none`.
+pub fn parse_color(spec: &str) -> Result<Option<String>> {
+    let spec = spec.trim().to_lowercase();
+    if spec == "none" || spec.is_empty() {
+        return Ok(None);
+    }
+    if spec.chars().all(|ch| ch.is_ascii_digit() || ch == ';') {
+        return Ok(Some(sgr(&spec)));
+    }
+    let mut params = Vec::new();
+    let mut name = spec.as_str();
+    for (prefix, code) in [("bold ", "1"), ("dim ", "2")] {
+        if let Some(rest) = name.strip_prefix(prefix) {
+            params.push(code.to_string());
+            name = rest.trim_start();
+        }
+    }
+    let (base, name) = match name.strip_prefix("bright-") {
+        Some(rest) => (90, rest),
+        None => (30, name),
+    };
+    let index = COLOR_NAMES
+        .iter()
+        .position(|known| *known == name)
+        .ok_or_else(|| {
+            anyhow!(
+                "unknown color '{spec}' (use {}, a bright- variant, or SGR codes like 1;32)",
+                COLOR_NAMES.join(", ")
+            )
+        })?;
+    params.push((base + index).to_string());
+    Ok(Some(sgr(&params.join(";"))))
+}
+
+fn paint(text: &str, color: Option<&str>) -> String {
+    match color {
+        Some(code) => format!("{code}{text}{RESET}"),
+        None => text.to_string(),
+    }
+}
+
+pub fn paint_change(text: &str, added: bool) -> String {
+    let colors = palette();
+    let color = if added {
+        &colors.added
+    } else {
+        &colors.removed
+    };
+    paint(text, color.as_deref())
+}
 
 pub const DIFF_MAX_LINES: usize = 5_000;
 pub const DIFF_MAX_BYTES: usize = 5 * 1024 * 1024;
 pub const DIFF_MAX_LINE_BYTES: usize = 64 * 1024;
 pub const DIFF_LINE_TRUNCATION_SUFFIX: &str = "... (line truncated)\n";
-const PAGE_LINES: usize = 200;
+const FALLBACK_PAGE_LINES: usize = 200;
+const MIN_PAGE_LINES: usize = 5;
+
+static DEFAULT_LIMITS: OnceLock<DiffLimits> = OnceLock::new();
+
+// Output caps for diff previews and review; 0 disables a cap.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DiffLimits {
+    pub max_lines: usize,
+    pub max_bytes: usize,
+}
+
+impl Default for DiffLimits {
+    fn default() -> Self {
+        Self {
+            max_lines: DIFF_MAX_LINES,
+            max_bytes: DIFF_MAX_BYTES,
+        }
+    }
+}
+
+impl DiffLimits {
+    // Per-invocation flags override the `[limits]` config, which overrides
+    // the built-in caps.
+    pub fn resolve(max_lines: Option<usize>, max_bytes: Option<usize>) -> Self {
+        let base = DEFAULT_LIMITS.get().copied().unwrap_or_default();
+        Self {
+            max_lines: max_lines.unwrap_or(base.max_lines),
+            max_bytes: max_bytes.unwrap_or(base.max_bytes),
+        }
+    }
+
+    pub fn lines_exceeded(&self, lines: usize) -> bool {
+        self.max_lines != 0 && lines >= self.max_lines
+    }
+
+    pub fn bytes_exceeded(&self, bytes: usize) -> bool {
+        self.max_bytes != 0 && bytes >= self.max_bytes
+    }
+}
+
+pub fn set_default_limits(limits: DiffLimits) {
+    let _ = DEFAULT_LIMITS.set(limits);
+}
 
 pub struct DiffDisplayConfig {
     pub context: usize,
     pub colorize: bool,
     pub pager_mode: PagerMode,
     pub interactive: bool,
+    pub format: DiffFormat,
+    pub line_numbers: bool,
+    pub page_lines: Option<usize>,
+    pub limits: DiffLimits,
 }
 
-pub fn display_diff(old: &str, new: &str, config: &DiffDisplayConfig) -> Result<()> {
-    let buffer = DiffBuffer::build(old, new, config.context, config.colorize);
+pub fn display_diff(
+    old_path: &Path,
+    new_path: &Path,
+    old: &str,
+    new: &str,
+    config: &DiffDisplayConfig,
+) -> Result<()> {
+    let _timer = crate::timing::start(crate::timing::Phase::Diff, Some(new_path));
+    match config.format {
+        DiffFormat::Inline => {}
+        _ if old == new => return Ok(()),
+        DiffFormat::Unified => {
+            let text = unified_diff(old_path, new_path, old, new, config.context);
+            print_unified(&text, config.colorize);
+            crate::explain::print(new_path, old, new);
+            return Ok(());
+        }
+        DiffFormat::Json => {
+            println!(
+                "{}",
+                hunks_json(old_path, new_path, old, new, config.context)
+            );
+            return Ok(());
+        }
+    }
+    let buffer = DiffBuffer::build(old, new, config);
     if buffer.lines.is_empty() {
         return Ok(());
     }
@@ -33,11 +213,11 @@ pub fn display_diff(old: &str, new: &str, config: &DiffDisplayConfig) -> Result<
     let viewer_requested = match config.pager_mode {
         PagerMode::Always => true,
         PagerMode::Never => false,
-        PagerMode::Auto => buffer.line_count() > PAGE_LINES,
+        PagerMode::Auto => buffer.line_count() > page_lines(config.page_lines),
     };
 
     if viewer_requested && config.interactive {
-        run_internal_pager(&buffer)?;
+        run_internal_pager(&buffer, config.page_lines)?;
     } else {
         if viewer_requested && !config.interactive && config.pager_mode != PagerMode::Never {
             println!("(pager disabled: non-interactive session; showing diff inline)");
@@ -46,6 +226,7 @@ pub fn display_diff(old: &str, new: &str, config: &DiffDisplayConfig) -> Result<
     }
 
     buffer.print_warnings();
+    crate::explain::print(new_path, old, new);
     Ok(())
 }
 
@@ -55,6 +236,7 @@ struct DiffBuffer {
     truncated: bool,
     truncate_reason: Option<TruncateReason>,
     line_truncations: usize,
+    limits: DiffLimits,
 }
 
 #[derive(Clone, Copy)]
@@ -64,10 +246,16 @@ enum TruncateReason {
 }
 
 impl DiffBuffer {
-    fn build(old: &str, new: &str, context: usize, colorize: bool) -> Self {
+    fn build(old: &str, new: &str, config: &DiffDisplayConfig) -> Self {
         let diff = TextDiff::configure()
             .algorithm(similar::Algorithm::Myers)
             .diff_lines(old, new);
+        let gutter_width = diff
+            .old_slices()
+            .len()
+            .max(diff.new_slices().len())
+            .to_string()
+            .len();
 
         let mut buffer = DiffBuffer {
             lines: Vec::new(),
@@ -75,28 +263,42 @@ impl DiffBuffer {
             truncated: false,
             truncate_reason: None,
             line_truncations: 0,
+            limits: config.limits,
         };
 
-        'outer: for (idx, group) in diff.grouped_ops(context).iter().enumerate() {
+        'outer: for (idx, group) in diff.grouped_ops(config.context).iter().enumerate() {
             if idx > 0 && !buffer.push_line("...\n".to_string()) {
                 break;
             }
 
             for op in group {
                 for change in diff.iter_changes(op) {
+                    let colors = palette();
                     let (symbol, style) = match change.tag() {
-                        ChangeTag::Delete => ('-', Some(RED)),
-                        ChangeTag::Insert => ('+', Some(GREEN)),
-                        ChangeTag::Equal => (' ', None),
+                        ChangeTag::Delete => ('-', colors.removed.as_deref()),
+                        ChangeTag::Insert => ('+', colors.added.as_deref()),
+                        ChangeTag::Equal => (' ', colors.context.as_deref()),
+                    };
+                    let gutter = if config.line_numbers {
+                        let number = |index: Option<usize>| {
+                            index.map_or_else(String::new, |index| (index + 1).to_string())
+                        };
+                        format!(
+                            "{:>gutter_width$} {:>gutter_width$} | ",
+                            number(change.old_index()),
+                            number(change.new_index())
+                        )
+                    } else {
+                        String::new()
                     };
-                    let line = if colorize {
+                    let line = if config.colorize {
                         if let Some(style_code) = style {
-                            format!("{style_code}{symbol} {change}{RESET}")
+                            format!("{gutter}{style_code}{symbol} {change}{RESET}")
                         } else {
-                            format!("{symbol} {change}")
+                            format!("{gutter}{symbol} {change}")
                         }
                     } else {
-                        format!("{symbol} {change}")
+                        format!("{gutter}{symbol} {change}")
                     };
 
                     if !buffer.push_line(line) {
@@ -118,13 +320,13 @@ impl DiffBuffer {
         self.total_bytes = self.total_bytes.saturating_add(line.len());
         self.lines.push(line);
 
-        if self.lines.len() >= DIFF_MAX_LINES {
+        if self.limits.lines_exceeded(self.lines.len()) {
             self.truncated = true;
             self.truncate_reason = Some(TruncateReason::LineCount);
             return false;
         }
 
-        if self.total_bytes >= DIFF_MAX_BYTES {
+        if self.limits.bytes_exceeded(self.total_bytes) {
             self.truncated = true;
             self.truncate_reason = Some(TruncateReason::ByteCount);
             return false;
@@ -147,10 +349,12 @@ impl DiffBuffer {
         if let Some(reason) = self.truncate_reason {
             match reason {
                 TruncateReason::LineCount => println!(
-                    "(diff truncated at ~{DIFF_MAX_LINES} lines; rerun with --pager never and narrower targets to view everything)"
+                    "(diff truncated at ~{} lines; raise --max-diff-lines (0 = unlimited) or narrow the targets to view everything)",
+                    self.limits.max_lines
                 ),
                 TruncateReason::ByteCount => println!(
-                    "(diff truncated after ~{DIFF_MAX_BYTES} bytes; rerun with --pager never and narrower targets to view everything)"
+                    "(diff truncated after ~{} bytes; raise --max-diff-bytes (0 = unlimited) or narrow the targets to view everything)",
+                    self.limits.max_bytes
                 ),
             }
         }
@@ -181,31 +385,89 @@ fn truncate_line(line: &mut String) {
     line.push_str(DIFF_LINE_TRUNCATION_SUFFIX);
 }
 
-fn run_internal_pager(buffer: &DiffBuffer) -> Result<()> {
+// `--page-lines` wins; otherwise pages fill the terminal minus the header
+// and prompt rows, falling back to a fixed size when the height is unknown.
+fn page_lines(requested: Option<usize>) -> usize {
+    if let Some(lines) = requested.filter(|lines| *lines > 0) {
+        return lines;
+    }
+    crossterm::terminal::size()
+        .ok()
+        .filter(|(_, rows)| *rows > 0)
+        .map(|(_, rows)| usize::from(rows).saturating_sub(3).max(MIN_PAGE_LINES))
+        .unwrap_or(FALLBACK_PAGE_LINES)
+}
+
+fn run_internal_pager(buffer: &DiffBuffer, requested_page_lines: Option<usize>) -> Result<()> {
     if buffer.lines.is_empty() {
         println!("(diff is empty)");
         return Ok(());
     }
 
-    let total_pages = buffer.line_count().div_ceil(PAGE_LINES);
+    let mut page_size = page_lines(requested_page_lines);
     let mut page = 0usize;
+    let mut search: Option<Regex> = None;
+    let mut cursor: Option<usize> = None;
 
     loop {
-        render_page(buffer, page, total_pages);
+        // Re-query every round so a resized terminal gets matching pages,
+        // keeping the first visible line on screen.
+        let resized = page_lines(requested_page_lines);
+        if resized != page_size {
+            page = page * page_size / resized;
+            page_size = resized;
+        }
+        let total_pages = buffer.line_count().div_ceil(page_size);
+        render_page(buffer, page, total_pages, page_size);
         if total_pages == 1 {
             break;
         }
 
-        print!("pager [Enter/n=next, p=prev, g <line>=goto, h=head, t=tail, q=quit]: ");
+        print!(
+            "pager [Enter=next, p=prev, /text=search, n/N=next/prev match, g <line>=goto, h=head, t=tail, q=quit]: "
+        );
         io::stdout().flush()?;
-        let mut input = String::new();
-        let bytes = io::stdin().read_line(&mut input)?;
-        if bytes == 0 {
+        let input = crate::input::read_line()?;
+        if input.is_empty() {
             println!("stdin closed; leaving diff pager.");
             break;
         }
 
         match parse_pager_command(input.trim()) {
+            PagerCommand::Search(pattern) if pattern.is_empty() => {
+                search = None;
+                println!("cleared search pattern.");
+            }
+            PagerCommand::Search(pattern) => match build_interactive_regex(&pattern) {
+                Ok(regex) => {
+                    search = Some(regex);
+                    cursor = None;
+                    match find_match(buffer, search.as_ref(), page * page_size, true) {
+                        Some(hit) => {
+                            cursor = Some(hit);
+                            page = hit / page_size;
+                            println!("match at diff line {}; n/N for next/previous.", hit + 1);
+                        }
+                        None => println!("no matches from this page on."),
+                    }
+                }
+                Err(err) => println!("invalid search pattern: {err}"),
+            },
+            PagerCommand::FindPrev if search.is_none() => {
+                println!("no search pattern set. Type /text (or /re:regex) first.");
+            }
+            PagerCommand::FindNext if search.is_none() => {
+                if page + 1 >= total_pages {
+                    break;
+                }
+                page += 1;
+            }
+            PagerCommand::FindNext => {
+                jump_to_match(buffer, &search, &mut cursor, &mut page, page_size, true)
+            }
+            PagerCommand::FindPrev => {
+                jump_to_match(buffer, &search, &mut cursor, &mut page, page_size, false)
+            }
             PagerCommand::Next => {
                 if page + 1 >= total_pages {
                     break;
@@ -229,7 +491,7 @@ fn run_internal_pager(buffer: &DiffBuffer) -> Result<()> {
                 let target = line
                     .saturating_sub(1)
                     .min(buffer.line_count().saturating_sub(1));
-                page = target / PAGE_LINES;
+                page = target / page_size;
             }
             PagerCommand::Quit => break,
             PagerCommand::Help => print_pager_help(),
@@ -239,9 +501,50 @@ fn run_internal_pager(buffer: &DiffBuffer) -> Result<()> {
     Ok(())
 }
 
-fn render_page(buffer: &DiffBuffer, page: usize, total_pages: usize) {
-    let start = page * PAGE_LINES;
-    let mut end = start + PAGE_LINES;
+fn jump_to_match(
+    buffer: &DiffBuffer,
+    search: &Option<Regex>,
+    cursor: &mut Option<usize>,
+    page: &mut usize,
+    page_size: usize,
+    forward: bool,
+) {
+    let from = match *cursor {
+        Some(line) if forward => line + 1,
+        Some(line) => line,
+        None => *page * page_size,
+    };
+    match find_match(buffer, search.as_ref(), from, forward) {
+        Some(hit) => {
+            *cursor = Some(hit);
+            *page = hit / page_size;
+            println!("match at diff line {}", hit + 1);
+        }
+        None if forward => println!("no later matches."),
+        None => println!("no earlier matches."),
+    }
+}
+
+// Forward searches start at `from`; backward searches look strictly before it.
+fn find_match(
+    buffer: &DiffBuffer,
+    search: Option<&Regex>,
+    from: usize,
+    forward: bool,
+) -> Option<usize> {
+    let regex = search?;
+    if forward {
+        (from..buffer.line_count()).find(|&index| regex.is_match(&buffer.lines[index]))
+    } else {
+        (0..from.min(buffer.line_count()))
+            .rev()
+            .find(|&index| regex.is_match(&buffer.lines[index]))
+    }
+}
+
+fn render_page(buffer: &DiffBuffer, page: usize, total_pages: usize, page_size: usize) {
+    let start = page * page_size;
+    let mut end = start + page_size;
     if end > buffer.line_count() {
         end = buffer.line_count();
     }
@@ -267,14 +570,26 @@ enum PagerCommand {
     Head,
     Tail,
     GotoLine(usize),
+    Search(String),
+    FindNext,
+    FindPrev,
     Quit,
     Help,
 }
 
 fn parse_pager_command(input: &str) -> PagerCommand {
-    if input.is_empty() || input.eq_ignore_ascii_case("n") {
+    if input.is_empty() {
         return PagerCommand::Next;
     }
+    if let Some(pattern) = input.strip_prefix('/') {
+        return PagerCommand::Search(pattern.trim().to_string());
+    }
+    if input == "n" {
+        return PagerCommand::FindNext;
+    }
+    if input == "N" {
+        return PagerCommand::FindPrev;
+    }
     if input.eq_ignore_ascii_case("p") {
         return PagerCommand::Prev;
     }
@@ -293,24 +608,98 @@ fn parse_pager_command(input: &str) -> PagerCommand {
     let mut parts = input.split_whitespace();
     if let Some(cmd) = parts.next() {
         if cmd.eq_ignore_ascii_case("g") {
-            if let Some(num) = parts.next() {
-                if let Ok(value) = num.parse::<usize>() {
-                    return PagerCommand::GotoLine(value);
-                }
+            if let Some(num) = parts.next()
+                && let Ok(value) = num.parse::<usize>()
+            {
+                return PagerCommand::GotoLine(value);
             }
             return PagerCommand::Help;
         }
-        if cmd.chars().all(|ch| ch.is_ascii_digit()) {
-            if let Ok(value) = cmd.parse::<usize>() {
-                return PagerCommand::GotoLine(value);
-            }
+        if cmd.chars().all(|ch| ch.is_ascii_digit())
+            && let Ok(value) = cmd.parse::<usize>()
+        {
+            return PagerCommand::GotoLine(value);
         }
     }
     PagerCommand::Help
 }
 
 fn print_pager_help() {
-    println!("Commands: Enter/n=next, p=previous, g <line>=jump to line, h=head, t=tail, q=quit.");
+    println!(
+        "Commands: Enter=next page (n too until a search is set), p=previous, /text or /re:regex=search, n/N=next/previous match, g <line>=jump to line, h=head, t=tail, q=quit."
+    );
+}
+
+const STAT_BAR_WIDTH: usize = 40;
+
+pub fn count_changes(old: &str, new: &str) -> (usize, usize) {
+    let diff = TextDiff::from_lines(old, new);
+    let mut added = 0;
+    let mut removed = 0;
+    for op in diff.ops() {
+        match op.tag() {
+            DiffTag::Equal => {}
+            DiffTag::Delete => removed += op.old_range().len(),
+            DiffTag::Insert => added += op.new_range().len(),
+            DiffTag::Replace => {
+                removed += op.old_range().len();
+                added += op.new_range().len();
+            }
+        }
+    }
+    (added, removed)
+}
+
+// `git diff --stat` layout: one row per file with its change count and a
+// +/- bar scaled so the busiest file fills STAT_BAR_WIDTH columns.
+pub fn format_diff_stat(files: &[(String, usize, usize)], colorize: bool) -> Vec<String> {
+    let name_width = files.iter().map(|(name, ..)| name.chars().count()).max();
+    let Some(name_width) = name_width else {
+        return Vec::new();
+    };
+    let busiest = files
+        .iter()
+        .map(|(_, add, del)| add + del)
+        .max()
+        .unwrap_or(0);
+    let count_width = busiest.to_string().len();
+    let scale = |value: usize| {
+        if busiest <= STAT_BAR_WIDTH {
+            value
+        } else {
+            (value * STAT_BAR_WIDTH).div_ceil(busiest)
+        }
+    };
+    let mut lines = Vec::with_capacity(files.len() + 1);
+    let (mut total_added, mut total_removed) = (0, 0);
+    for (name, added, removed) in files {
+        total_added += added;
+        total_removed += removed;
+        let plus = "+".repeat(scale(*added));
+        let minus = "-".repeat(scale(*removed));
+        let bar = if colorize {
+            let colors = palette();
+            format!(
+                "{}{}",
+                paint(&plus, colors.added.as_deref()),
+                paint(&minus, colors.removed.as_deref())
+            )
+        } else {
+            format!("{plus}{minus}")
+        };
+        lines.push(format!(
+            " {name:<name_width$} | {:>count_width$} {bar}",
+            added + removed
+        ));
+    }
+    lines.push(format!(
+        " {} file{} changed, {total_added} insertion{}(+), {total_removed} deletion{}(-)",
+        files.len(),
+        if files.len() == 1 { "" } else { "s" },
+        if total_added == 1 { "" } else { "s" },
+        if total_removed == 1 { "" } else { "s" },
+    ));
+    lines
 }
 
 pub fn summarize_lines(old: &str, new: &str) -> String {
@@ -391,10 +780,179 @@ pub fn unified_diff(
         .to_string()
 }
 
+fn print_unified(text: &str, colorize: bool) {
+    let mut out = io::stdout().lock();
+    let colors = palette();
+    for line in text.split_inclusive('\n') {
+        let style = match line.as_bytes().first() {
+            _ if !colorize || line.starts_with("---") || line.starts_with("+++") => None,
+            Some(b'-') => colors.removed.as_deref(),
+            Some(b'+') => colors.added.as_deref(),
+            Some(b' ') => colors.context.as_deref(),
+            _ => None,
+        };
+        let body = line.trim_end_matches('\n');
+        let end = &line[body.len()..];
+        let _ = write!(out, "{}{end}", paint(body, style));
+    }
+    if !text.ends_with('\n') {
+        let _ = writeln!(out);
+    }
+}
+
+// One JSON object per file: hunk ranges are 1-based like unified headers and
+// each line carries its tag (`+`, `-`, or ` `) and text without the newline.
+pub fn hunks_json(old_path: &Path, new_path: &Path, old: &str, new: &str, context: usize) -> Value {
+    let diff = TextDiff::from_lines(old, new);
+    let hunks: Vec<Value> = diff
+        .grouped_ops(context)
+        .iter()
+        .filter_map(|group| {
+            let first = group.first()?;
+            let last = group.last()?;
+            let old_range = first.old_range().start..last.old_range().end;
+            let new_range = first.new_range().start..last.new_range().end;
+            let lines: Vec<Value> = group
+                .iter()
+                .flat_map(|op| diff.iter_changes(op))
+                .map(|change| {
+                    let tag = match change.tag() {
+                        ChangeTag::Delete => "-",
+                        ChangeTag::Insert => "+",
+                        ChangeTag::Equal => " ",
+                    };
+                    let text = change.value().trim_end_matches(['\r', '\n']);
+                    json!({ "tag": tag, "text": text })
+                })
+                .collect();
+            Some(json!({
+                "old_start": old_range.start + 1,
+                "old_lines": old_range.len(),
+                "new_start": new_range.start + 1,
+                "new_lines": new_range.len(),
+                "lines": lines,
+            }))
+        })
+        .collect();
+    json!({
+        "old_path": old_path.display().to_string(),
+        "new_path": new_path.display().to_string(),
+        "hunks": hunks,
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn pager_search_commands_find_matches_both_ways() {
+        assert!(matches!(
+            parse_pager_command("/needle"),
+            PagerCommand::Search(pattern) if pattern == "needle"
+        ));
+        assert!(matches!(parse_pager_command("n"), PagerCommand::FindNext));
+        assert!(matches!(parse_pager_command("N"), PagerCommand::FindPrev));
+        assert!(matches!(parse_pager_command(""), PagerCommand::Next));
+        let config = DiffDisplayConfig {
+            context: 5,
+            colorize: false,
+            pager_mode: PagerMode::Never,
+            interactive: false,
+            format: DiffFormat::Inline,
+            line_numbers: false,
+            page_lines: None,
+            limits: DiffLimits::default(),
+        };
+        let buffer = DiffBuffer::build("a\nneedle\nb\nneedle\n", "A\nneedle\nb\nneedle\n", &config);
+        let regex = build_interactive_regex("needle").unwrap();
+        assert_eq!(find_match(&buffer, Some(&regex), 0, true), Some(2));
+        assert_eq!(find_match(&buffer, Some(&regex), 3, true), Some(4));
+        assert_eq!(find_match(&buffer, Some(&regex), 4, false), Some(2));
+        assert_eq!(find_match(&buffer, Some(&regex), 2, false), None);
+    }
+
+    #[test]
+    fn zero_limits_disable_truncation() {
+        let mut config = DiffDisplayConfig {
+            context: 0,
+            colorize: false,
+            pager_mode: PagerMode::Never,
+            interactive: false,
+            format: DiffFormat::Inline,
+            line_numbers: false,
+            page_lines: None,
+            limits: DiffLimits {
+                max_lines: 3,
+                max_bytes: 0,
+            },
+        };
+        let old = "a\nb\nc\nd\n";
+        let new = "1\n2\n3\n4\n";
+        let buffer = DiffBuffer::build(old, new, &config);
+        assert_eq!(buffer.line_count(), 3);
+        assert!(buffer.truncated);
+        config.limits.max_lines = 0;
+        let buffer = DiffBuffer::build(old, new, &config);
+        assert_eq!(buffer.line_count(), 8);
+        assert!(!buffer.truncated);
+    }
+
+    #[test]
+    fn explicit_page_lines_override_terminal_height() {
+        assert_eq!(page_lines(Some(12)), 12);
+        assert!(page_lines(Some(0)) >= MIN_PAGE_LINES);
+        assert!(page_lines(None) >= MIN_PAGE_LINES);
+    }
+
+    #[test]
+    fn line_number_gutter_tracks_old_and_new_lines() {
+        let config = DiffDisplayConfig {
+            context: 1,
+            colorize: false,
+            pager_mode: PagerMode::Never,
+            interactive: false,
+            format: DiffFormat::Inline,
+            line_numbers: true,
+            page_lines: None,
+            limits: DiffLimits::default(),
+        };
+        let buffer = DiffBuffer::build("a\nb\nc\n", "a\nB\nc\n", &config);
+        assert_eq!(
+            buffer.lines,
+            ["1 1 |   a\n", "2   | - b\n", "  2 | + B\n", "3 3 |   c\n"]
+        );
+    }
+
+    #[test]
+    fn diff_stat_scales_bars_and_totals_changes() {
+        assert_eq!(count_changes("a\nb\nc\n", "a\nB\nc\nd\n"), (2, 1));
+        let files = vec![
+            ("src/lib.rs".to_string(), 80, 0),
+            ("a.txt".to_string(), 1, 1),
+        ];
+        let lines = format_diff_stat(&files, false);
+        assert_eq!(lines[0], format!(" src/lib.rs | 80 {}", "+".repeat(40)));
+        assert_eq!(lines[1], " a.txt      |  2 +-");
+        assert_eq!(
+            lines[2],
+            " 2 files changed, 81 insertions(+), 1 deletion(-)"
+        );
+    }
+
+    #[test]
+    fn hunks_json_reports_ranges_and_tagged_lines() {
+        let path = Path::new("a.txt");
+        let value = hunks_json(path, path, "one\ntwo\nthree\n", "one\n2\nthree\n", 0);
+        let hunks = value["hunks"].as_array().unwrap();
+        assert_eq!(hunks.len(), 1);
+        assert_eq!(hunks[0]["old_start"], 2);
+        assert_eq!(hunks[0]["old_lines"], 1);
+        assert_eq!(hunks[0]["new_start"], 2);
+        assert_eq!(hunks[0]["lines"][0], json!({ "tag": "-", "text": "two" }));
+        assert_eq!(hunks[0]["lines"][1], json!({ "tag": "+", "text": "2" }));
+    }
+
     #[test]
     fn collect_line_spans_marks_modified_and_added() {
         let old = "one\nold\nthree\n";
@@ -0,0 +1,142 @@
+use std::fs::{self, OpenOptions};
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use anyhow::{Context, Result, bail};
+
+use crate::wizard::split_args;
+
+fn editor_command() -> Result<Vec<String>> {
+    editor_parts(std::env::var("EDITOR").ok().as_deref())
+}
+
+// Quotes group words as in a shell, so `"C:\Program Files\Microsoft VS
+// Code\bin\code.cmd" --wait` stays one program; backslashes are literal.
+fn editor_parts(configured: Option<&str>) -> Result<Vec<String>> {
+    match configured.filter(|value| !value.trim().is_empty()) {
+        Some(value) => split_args(value).context("parsing $EDITOR"),
+        None if cfg!(windows) => Ok(vec!["notepad".to_string()]),
+        None => Ok(vec!["vi".to_string()]),
+    }
+}
+
+pub fn open_in_editor(path: &Path, line: Option<usize>) -> Result<()> {
+    let mut parts = editor_command()?;
+    if parts.is_empty() {
+        bail!("no editor configured; set $EDITOR");
+    }
+    let program = parts.remove(0);
+    let mut command = Command::new(&program);
+    command.args(&parts);
+    if let Some(line) = line
+        && supports_line_argument(&program)
+    {
+        command.arg(format!("+{line}"));
+    }
+    command.arg(path);
+    let status = command
+        .status()
+        .with_context(|| format!("launching editor '{program}'"))?;
+    if !status.success() {
+        bail!("editor '{program}' exited with {status}");
+    }
+    Ok(())
+}
+
+// `--body-edit`: the body is whatever the user saves in a scratch file that
+// borrows the target's extension, so the editor picks the right syntax mode.
+// Saving an empty file aborts, as with an empty commit message.
+pub fn edit_text(target: &Path, description: &str) -> Result<String> {
+    if crate::input::no_input() {
+        bail!("--body-edit needs an editor but --no-input is set");
+    }
+    let extension = target
+        .extension()
+        .map(|ext| format!(".{}", ext.to_string_lossy()))
+        .unwrap_or_default();
+    let stamp = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_nanos())
+        .unwrap_or_default();
+    let scratch = create_scratch(
+        &std::env::temp_dir(),
+        &format!("safeedit-body-{}-{stamp}", std::process::id()),
+        &extension,
+    )?;
+    let edited = open_in_editor(&scratch, None).and_then(|_| {
+        fs::read_to_string(&scratch)
+            .with_context(|| format!("reading scratch file {}", scratch.display()))
+    });
+    let _ = fs::remove_file(&scratch);
+    let text = edited?;
+    if text.trim().is_empty() {
+        bail!("{description} left empty in the editor; aborting");
+    }
+    Ok(text)
+}
+
+// Created with `create_new`, so an existing file or symlink at the chosen name
+// is never opened; the next suffix is tried instead.
+fn create_scratch(dir: &Path, stem: &str, extension: &str) -> Result<PathBuf> {
+    for attempt in 0..100 {
+        let path = dir.join(format!("{stem}-{attempt}{extension}"));
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(_) => return Ok(path),
+            Err(err) if err.kind() == ErrorKind::AlreadyExists => continue,
+            Err(err) => {
+                return Err(err)
+                    .with_context(|| format!("creating scratch file {}", path.display()));
+            }
+        }
+    }
+    bail!("could not create a scratch file in {}", dir.display())
+}
+
+fn supports_line_argument(program: &str) -> bool {
+    let file_name = program.rsplit(['/', '\\']).next().unwrap_or(program);
+    let stem = file_name.split('.').next().unwrap_or(file_name);
+    !stem.eq_ignore_ascii_case("notepad")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn notepad_skips_line_argument() {
+        assert!(!supports_line_argument("C:\\Windows\\notepad.exe"));
+        assert!(supports_line_argument("/usr/bin/vim"));
+    }
+
+    #[test]
+    fn quoted_editor_paths_keep_their_spaces() {
+        let parts = editor_parts(Some(
+            r#""C:\Program Files\Microsoft VS Code\bin\code.cmd" --wait"#,
+        ))
+        .unwrap();
+        assert_eq!(
+            parts,
+            [r"C:\Program Files\Microsoft VS Code\bin\code.cmd", "--wait"]
+        );
+        assert!(supports_line_argument(&parts[0]));
+        assert_eq!(
+            editor_parts(Some("'/opt/my editor/bin/ed' -n")).unwrap(),
+            ["/opt/my editor/bin/ed", "-n"]
+        );
+        assert!(editor_parts(Some("\"/opt/ed -n")).is_err());
+        assert_eq!(editor_parts(Some("  ")).unwrap().len(), 1);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn scratch_file_never_follows_existing_symlink() {
+        let dir = tempfile::tempdir().unwrap();
+        let victim = dir.path().join("victim.txt");
+        fs::write(&victim, "keep").unwrap();
+        std::os::unix::fs::symlink(&victim, dir.path().join("body-0.rs")).unwrap();
+        let scratch = create_scratch(dir.path(), "body", ".rs").unwrap();
+        assert_eq!(scratch, dir.path().join("body-1.rs"));
+        assert_eq!(fs::read_to_string(&victim).unwrap(), "keep");
+    }
+}
@@ -1,4 +1,6 @@
 use std::fmt;
+use std::io::{self, Write};
+use std::path::Path;
 
 use anyhow::{Result, anyhow};
 use chardetng::EncodingDetector;
@@ -28,12 +30,18 @@ impl fmt::Display for EncodingSource {
 pub struct EncodingDecision {
     pub encoding: &'static Encoding,
     pub source: EncodingSource,
+    // A heuristic 0..1 score, not a probability (chardetng reports none):
+    // 1.0 for a BOM, an override, or valid UTF-8; otherwise the detector's
+    // plausible/implausible verdict, marked down for sparse evidence and for
+    // decode errors. See `detect_auto`.
+    pub score: f32,
 }
 
 #[derive(Debug, Clone)]
 pub struct DecodedText {
     pub text: String,
     pub had_errors: bool,
+    pub bom: bool,
     pub decision: EncodingDecision,
 }
 
@@ -41,6 +49,13 @@ pub struct DecodedText {
 pub struct EncodingStrategy {
     override_encoding: Option<&'static Encoding>,
     override_label: Option<String>,
+    score_guard: Option<ScoreGuard>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct ScoreGuard {
+    min: f32,
+    prompt: bool,
 }
 
 impl EncodingStrategy {
@@ -52,15 +67,58 @@ impl EncodingStrategy {
             Ok(Self {
                 override_encoding: Some(encoding),
                 override_label: Some(trimmed.to_string()),
+                score_guard: None,
             })
         } else {
             Ok(Self {
                 override_encoding: None,
                 override_label: None,
+                score_guard: None,
             })
         }
     }
 
+    pub fn with_min_score(mut self, min: f32, prompt: bool) -> Self {
+        self.score_guard = Some(ScoreGuard { min, prompt });
+        self
+    }
+
+    pub fn accept(&self, path: &Path, decision: &EncodingDecision) -> Result<bool> {
+        let Some(guard) = self.score_guard else {
+            return Ok(true);
+        };
+        if decision.score >= guard.min {
+            return Ok(true);
+        }
+        println!(
+            "warning: {} detected as {} with low detection score {:.2} (minimum {:.2})",
+            path.display(),
+            decision.encoding.name(),
+            decision.score,
+            guard.min
+        );
+        if !guard.prompt {
+            println!(
+                "skipping {} (pass --encoding to decode it explicitly)",
+                path.display()
+            );
+            return Ok(false);
+        }
+        loop {
+            print!("Continue with {}? [y/N]: ", decision.encoding.name());
+            io::stdout().flush()?;
+            let input = crate::input::read_line()?;
+            match input.trim().to_lowercase().as_str() {
+                "y" | "yes" => return Ok(true),
+                "n" | "no" | "" => {
+                    println!("skipped {}", path.display());
+                    return Ok(false);
+                }
+                _ => println!("Please enter y or n."),
+            }
+        }
+    }
+
     pub fn describe(&self) -> String {
         if let (Some(label), Some(enc)) = (&self.override_label, self.override_encoding) {
             format!(
@@ -78,6 +136,7 @@ impl EncodingStrategy {
             return EncodingDecision {
                 encoding,
                 source: EncodingSource::Override,
+                score: 1.0,
             };
         }
 
@@ -90,16 +149,60 @@ impl EncodingStrategy {
         DecodedText {
             text: cow.into_owned(),
             had_errors,
+            bom: detect_bom(bytes).is_some(),
             decision,
         }
     }
 }
 
+pub fn unencodable_chars(text: &str, encoding: &'static Encoding) -> Vec<(usize, usize, char)> {
+    let mut found = Vec::new();
+    let mut buf = [0u8; 4];
+    for (idx, line) in text.split('\n').enumerate() {
+        for (col, ch) in line.chars().enumerate() {
+            let (_, _, had_errors) = encoding.encode(ch.encode_utf8(&mut buf));
+            if had_errors {
+                found.push((idx + 1, col + 1, ch));
+            }
+        }
+    }
+    found
+}
+
+pub fn encode_text(text: &str, encoding: &'static Encoding, bom: bool) -> (Vec<u8>, bool) {
+    let mut out = Vec::with_capacity(text.len() + 3);
+    if encoding == UTF_16LE || encoding == UTF_16BE {
+        let little_endian = encoding == UTF_16LE;
+        if bom {
+            out.extend_from_slice(if little_endian {
+                &[0xFF, 0xFE]
+            } else {
+                &[0xFE, 0xFF]
+            });
+        }
+        for unit in text.encode_utf16() {
+            out.extend_from_slice(&if little_endian {
+                unit.to_le_bytes()
+            } else {
+                unit.to_be_bytes()
+            });
+        }
+        return (out, false);
+    }
+    if bom && encoding == UTF_8 {
+        out.extend_from_slice(&[0xEF, 0xBB, 0xBF]);
+    }
+    let (encoded, _, had_errors) = encoding.encode(text);
+    out.extend_from_slice(&encoded);
+    (out, had_errors)
+}
+
 fn detect_auto(bytes: &[u8]) -> EncodingDecision {
     if let Some(encoding) = detect_bom(bytes) {
         return EncodingDecision {
             encoding,
             source: EncodingSource::Bom,
+            score: 1.0,
         };
     }
 
@@ -107,16 +210,29 @@ fn detect_auto(bytes: &[u8]) -> EncodingDecision {
         return EncodingDecision {
             encoding: UTF_8,
             source: EncodingSource::AssumedUtf8,
+            score: 1.0,
         };
     }
 
     let mut detector = EncodingDetector::new();
     detector.feed(bytes, true);
-    let encoding = detector.guess(None, true);
+    let (encoding, plausible) = detector.guess_assess(None, true);
+    let non_ascii = bytes.iter().filter(|byte| !byte.is_ascii()).count();
+    // Fixed steps rather than anything measured: a plausible guess starts
+    // high, an implausible one low, and each weak signal (fewer than eight
+    // non-ASCII bytes to go on, bytes the guess cannot decode) costs 0.2.
+    let mut score: f32 = if plausible { 0.9 } else { 0.3 };
+    if non_ascii < 8 {
+        score -= 0.2;
+    }
+    if encoding.decode_without_bom_handling(bytes).1 {
+        score -= 0.2;
+    }
 
     EncodingDecision {
         encoding,
         source: EncodingSource::Detector,
+        score: score.clamp(0.05, 1.0),
     }
 }
 
@@ -152,6 +268,43 @@ mod tests {
         assert_eq!(decision.encoding.name(), "UTF-8");
     }
 
+    #[test]
+    fn bom_round_trips_through_encode() {
+        let strategy = EncodingStrategy::new(None).expect("strategy");
+        let decoded = strategy.decode(b"\xEF\xBB\xBFhi");
+        assert!(decoded.bom);
+        assert_eq!(decoded.text, "hi");
+        let (bytes, _) = encode_text(&decoded.text, decoded.decision.encoding, decoded.bom);
+        assert_eq!(bytes, b"\xEF\xBB\xBFhi");
+
+        let utf16 = strategy.decode(&[0xFF, 0xFE, 0x68, 0x00]);
+        let (bytes, _) = encode_text(&utf16.text, utf16.decision.encoding, false);
+        assert_eq!(bytes, vec![0x68, 0x00]);
+    }
+
+    #[test]
+    fn unencodable_chars_reports_positions() {
+        let windows_1252 = Encoding::for_label(b"windows-1252").expect("encoding");
+        let found = unencodable_chars("caf\u{e9}\nok \u{4E2D}\u{6587}", windows_1252);
+        assert_eq!(found, vec![(2, 4, '\u{4E2D}'), (2, 5, '\u{6587}')]);
+    }
+
+    #[test]
+    fn detection_score_reflects_evidence() {
+        assert_eq!(detect_auto(b"plain ascii").score, 1.0);
+        let sparse = detect_auto(b"caf\xe9");
+        assert_eq!(sparse.source, EncodingSource::Detector);
+        assert!(sparse.score < 0.8);
+        let rich = detect_auto(
+            "Le caf\u{e9} d\u{e9}j\u{e0} \u{e9}t\u{e9} pr\u{ea}t, \u{e0} l'\u{e9}cole \u{e9}l\u{e8}ve"
+                .chars()
+                .map(|ch| ch as u32 as u8)
+                .collect::<Vec<u8>>()
+                .as_slice(),
+        );
+        assert!(rich.score > sparse.score);
+    }
+
     #[test]
     fn bom_detection_takes_precedence() {
         let data = [0xFF, 0xFE, 0x61, 0x00];
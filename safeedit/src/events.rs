@@ -0,0 +1,116 @@
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use anyhow::{Context, Result};
+
+pub const SCHEMA_VERSION: u32 = 1;
+
+static STAGED_DIFF: Mutex<Option<(PathBuf, String)>> = Mutex::new(None);
+static SINK: Mutex<Option<(PathBuf, File)>> = Mutex::new(None);
+static APPLIED_PATCH: Mutex<Option<(PathBuf, File)>> = Mutex::new(None);
+
+pub fn stage_diff(path: &Path, diff: String) {
+    if let Ok(mut staged) = STAGED_DIFF.lock() {
+        *staged = Some((path.to_path_buf(), diff));
+    }
+}
+
+pub fn take_diff(path: &Path) -> Option<String> {
+    let mut staged = STAGED_DIFF.lock().ok()?;
+    match staged.take() {
+        Some((staged_path, diff)) if staged_path == path => Some(diff),
+        other => {
+            *staged = other;
+            None
+        }
+    }
+}
+
+pub fn peek_diff(path: &Path) -> Option<String> {
+    let staged = STAGED_DIFF.lock().ok()?;
+    match staged.as_ref() {
+        Some((staged_path, diff)) if staged_path == path => Some(diff.clone()),
+        _ => None,
+    }
+}
+
+// The first event for a path truncates the file; later events in the same
+// run (including every step of a batch) append to it.
+pub fn write_line(path: &Path, line: &str) -> Result<()> {
+    append_once(&SINK, path, &format!("{line}\n"), "JSON event file")
+}
+
+// Same run-scoped truncate-then-append behaviour for --save-applied-patch, so
+// every file touched by the run ends up in one patch.
+pub fn append_applied_patch(path: &Path, diff: &str) -> Result<()> {
+    append_once(&APPLIED_PATCH, path, diff, "applied patch")
+}
+
+fn append_once(
+    sink: &Mutex<Option<(PathBuf, File)>>,
+    path: &Path,
+    text: &str,
+    what: &str,
+) -> Result<()> {
+    let mut sink = sink
+        .lock()
+        .map_err(|_| anyhow::anyhow!("{what} sink poisoned"))?;
+    if sink.as_ref().map(|(open, _)| open.as_path()) != Some(path) {
+        let file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(path)
+            .with_context(|| format!("opening {what} {}", path.display()))?;
+        *sink = Some((path.to_path_buf(), file));
+    }
+    if let Some((_, file)) = sink.as_mut() {
+        file.write_all(text.as_bytes())
+            .with_context(|| format!("writing {what} {}", path.display()))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn staged_diffs_are_claimed_by_matching_path_only() {
+        stage_diff(Path::new("events/a.txt"), "diff a".into());
+        assert_eq!(take_diff(Path::new("events/b.txt")), None);
+        assert_eq!(
+            take_diff(Path::new("events/a.txt")).as_deref(),
+            Some("diff a")
+        );
+        assert_eq!(take_diff(Path::new("events/a.txt")), None);
+    }
+
+    #[test]
+    fn event_file_is_truncated_once_then_appended() {
+        let temp = tempfile::tempdir().unwrap();
+        let path = temp.path().join("events.ndjson");
+        std::fs::write(&path, "stale\n").unwrap();
+        write_line(&path, "{\"n\":1}").unwrap();
+        write_line(&path, "{\"n\":2}").unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "{\"n\":1}\n{\"n\":2}\n"
+        );
+    }
+
+    #[test]
+    fn applied_patches_accumulate_across_files() {
+        let temp = tempfile::tempdir().unwrap();
+        let path = temp.path().join("run.diff");
+        std::fs::write(&path, "stale\n").unwrap();
+        append_applied_patch(&path, "--- a.txt\n+++ a.txt\n").unwrap();
+        append_applied_patch(&path, "--- b.txt\n+++ b.txt\n").unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "--- a.txt\n+++ a.txt\n--- b.txt\n+++ b.txt\n"
+        );
+    }
+}
@@ -0,0 +1,351 @@
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use encoding_rs::{Encoding, UTF_8};
+use similar::{DiffTag, TextDiff};
+
+use crate::encoding::{DecodedText, unencodable_chars};
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static NOTED: Mutex<Option<Noted>> = Mutex::new(None);
+
+// Regions beyond this are summarized as "and N more".
+const LISTED_REGIONS: usize = 4;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Target {
+    encoding: &'static Encoding,
+    bom: bool,
+}
+
+struct Noted {
+    path: PathBuf,
+    from: Target,
+    to: Target,
+}
+
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+// Decode sites record what the file was read as, so the paragraph printed
+// after its diff can speak to the encoding as well as the text.
+pub fn note_decoded(path: &Path, decoded: &DecodedText) {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+    let target = Target {
+        encoding: decoded.decision.encoding,
+        bom: decoded.bom,
+    };
+    if let Ok(mut noted) = NOTED.lock() {
+        *noted = Some(Noted {
+            path: path.to_path_buf(),
+            from: target,
+            to: target,
+        });
+    }
+}
+
+// For edits that write a different encoding or BOM than they read.
+pub fn note_target(path: &Path, encoding: &'static Encoding, bom: bool) {
+    if let Ok(mut noted) = NOTED.lock()
+        && let Some(noted) = noted.as_mut()
+        && noted.path == path
+    {
+        noted.to = Target { encoding, bom };
+    }
+}
+
+pub fn print(path: &Path, old: &str, new: &str) {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+    let noted = NOTED.lock().ok().and_then(|mut noted| match noted.take() {
+        Some(entry) if entry.path == path => Some((entry.from, entry.to)),
+        other => {
+            *noted = other;
+            None
+        }
+    });
+    if let Some(text) = explain(old, new, noted) {
+        println!("explain: {text}");
+    }
+}
+
+fn explain(old: &str, new: &str, encoding: Option<(Target, Target)>) -> Option<String> {
+    let encoding_changed = encoding.is_some_and(|(from, to)| from != to);
+    if old == new && !encoding_changed {
+        return None;
+    }
+    let mut sentences = Vec::new();
+    if old != new {
+        sentences.push(describe_lines(old, new));
+        if old.split_whitespace().eq(new.split_whitespace()) {
+            sentences.push("Only whitespace changes; no visible text is altered.".to_string());
+        }
+    } else {
+        sentences.push("The text itself is unchanged.".to_string());
+    }
+    let old_eol = LineEndings::count(old);
+    let new_eol = LineEndings::count(new);
+    let eol_sentence = describe_line_endings(&old_eol, &new_eol);
+    match (encoding, new_eol.uniform()) {
+        (Some((from, to)), Some(style)) if from == to && eol_sentence.is_none() => {
+            sentences.push(format!(
+                "It stays {} with {style} line endings.",
+                describe_target(to)
+            ));
+        }
+        (Some((from, to)), None) if from == to && eol_sentence.is_none() => {
+            sentences.push(format!("It stays {}.", describe_target(to)));
+        }
+        (_, Some(style)) if encoding.is_none() && eol_sentence.is_none() => {
+            sentences.push(format!("Line endings stay {style}."));
+        }
+        _ => {}
+    }
+    sentences.extend(eol_sentence);
+    match (old.ends_with('\n'), new.ends_with('\n')) {
+        (true, false) if !new.is_empty() => {
+            sentences.push("The final newline is removed.".to_string())
+        }
+        (false, true) if !old.is_empty() => {
+            sentences.push("A final newline is added at the end of the file.".to_string())
+        }
+        _ => {}
+    }
+    if let Some((from, to)) = encoding {
+        if from.encoding != to.encoding {
+            sentences.push(format!(
+                "It is re-encoded from {} to {}.",
+                from.encoding.name(),
+                to.encoding.name()
+            ));
+        }
+        match (from.bom, to.bom) {
+            (false, true) => sentences.push("A byte-order mark is added.".to_string()),
+            (true, false) => sentences.push("The byte-order mark is removed.".to_string()),
+            _ => {}
+        }
+        if to.encoding != UTF_8 {
+            let lost = unencodable_chars(new, to.encoding)
+                .len()
+                .saturating_sub(unencodable_chars(old, to.encoding).len());
+            if lost > 0 {
+                sentences.push(format!(
+                    "{lost} new character(s) cannot be represented in {} and would be written lossily (--strict-encoding refuses the write).",
+                    to.encoding.name()
+                ));
+            }
+        }
+    }
+    Some(sentences.join(" "))
+}
+
+fn describe_target(target: Target) -> String {
+    if target.bom {
+        format!("{} with a byte-order mark", target.encoding.name())
+    } else {
+        target.encoding.name().to_string()
+    }
+}
+
+// Counts and regions use the current file's line numbers, so insertions are
+// placed "after line N" rather than at a line that does not exist yet.
+fn describe_lines(old: &str, new: &str) -> String {
+    let diff = TextDiff::from_lines(old, new);
+    let old_lines = diff.old_slices().len();
+    let new_lines = diff.new_slices().len();
+    let (mut rewritten, mut added, mut removed) = (0, 0, 0);
+    let mut regions = Vec::new();
+    for op in diff.ops() {
+        let (before, after) = (op.old_range(), op.new_range());
+        match op.tag() {
+            DiffTag::Equal => continue,
+            DiffTag::Insert => {
+                added += after.len();
+                regions.push(match before.start {
+                    0 => "at the start of the file".to_string(),
+                    start if start == old_lines => "at the end of the file".to_string(),
+                    start => format!("after line {start}"),
+                });
+                continue;
+            }
+            DiffTag::Delete => removed += before.len(),
+            DiffTag::Replace => {
+                let paired = before.len().min(after.len());
+                rewritten += paired;
+                removed += before.len() - paired;
+                added += after.len() - paired;
+            }
+        }
+        regions.push(if before.len() == 1 {
+            format!("line {}", before.start + 1)
+        } else {
+            format!("lines {}-{}", before.start + 1, before.end)
+        });
+    }
+    let mut counts = Vec::new();
+    for (verb, lines) in [
+        ("rewrites", rewritten),
+        ("adds", added),
+        ("removes", removed),
+    ] {
+        if lines > 0 {
+            counts.push(format!("{verb} {lines} line{}", plural(lines)));
+        }
+    }
+    let mut listed: Vec<String> = regions.iter().take(LISTED_REGIONS).cloned().collect();
+    if regions.len() > LISTED_REGIONS {
+        listed.push(format!("{} more", regions.len() - LISTED_REGIONS));
+    }
+    let mut sentence = format!(
+        "{} in {} place{} ({}).",
+        capitalize(&join_list(&counts)),
+        regions.len(),
+        plural(regions.len()),
+        join_list(&listed)
+    );
+    if old_lines != new_lines {
+        sentence.push_str(&format!(
+            " The file goes from {old_lines} to {new_lines} line{}.",
+            plural(new_lines)
+        ));
+    }
+    sentence
+}
+
+#[derive(Debug, Default)]
+struct LineEndings {
+    lf: usize,
+    crlf: usize,
+    cr: usize,
+}
+
+impl LineEndings {
+    fn count(text: &str) -> Self {
+        let mut counts = LineEndings::default();
+        let bytes = text.as_bytes();
+        for (idx, byte) in bytes.iter().enumerate() {
+            match byte {
+                b'\n' if idx > 0 && bytes[idx - 1] == b'\r' => counts.crlf += 1,
+                b'\n' => counts.lf += 1,
+                b'\r' if bytes.get(idx + 1) != Some(&b'\n') => counts.cr += 1,
+                _ => {}
+            }
+        }
+        counts
+    }
+
+    fn styles(&self) -> Vec<(&'static str, usize)> {
+        [("LF", self.lf), ("CRLF", self.crlf), ("CR", self.cr)]
+            .into_iter()
+            .filter(|(_, count)| *count > 0)
+            .collect()
+    }
+
+    fn uniform(&self) -> Option<&'static str> {
+        match self.styles().as_slice() {
+            [(style, _)] => Some(style),
+            _ => None,
+        }
+    }
+}
+
+fn describe_line_endings(old: &LineEndings, new: &LineEndings) -> Option<String> {
+    let (old_styles, new_styles) = (old.styles(), new.styles());
+    match (old.uniform(), new.uniform()) {
+        (Some(before), Some(after)) if before != after => Some(format!(
+            "Line endings change from {before} to {after} throughout."
+        )),
+        (Some(before), None) if new_styles.len() > 1 => {
+            let stray: usize = new_styles
+                .iter()
+                .filter(|(style, _)| *style != before)
+                .map(|(_, count)| count)
+                .sum();
+            let others: Vec<&str> = new_styles
+                .iter()
+                .filter(|(style, _)| *style != before)
+                .map(|(style, _)| *style)
+                .collect();
+            Some(format!(
+                "{stray} line{} will end in {} although the file uses {before}, leaving mixed line endings.",
+                plural(stray),
+                others.join("/")
+            ))
+        }
+        (None, Some(after)) if old_styles.len() > 1 => {
+            Some(format!("Mixed line endings become uniformly {after}."))
+        }
+        (None, None) if old_styles.len() > 1 && new_styles.len() > 1 => {
+            Some("The file keeps its mixed line endings.".to_string())
+        }
+        _ => None,
+    }
+}
+
+fn join_list(items: &[String]) -> String {
+    match items {
+        [] => String::new(),
+        [only] => only.clone(),
+        [init @ .., last] => format!("{} and {last}", init.join(", ")),
+    }
+}
+
+fn capitalize(text: &str) -> String {
+    let mut chars = text.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+fn plural(count: usize) -> &'static str {
+    if count == 1 { "" } else { "s" }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paragraphs_cover_counts_regions_and_line_endings() {
+        let text = explain("a\nb\nc\n", "a\nB\nc\nd\n", None).unwrap();
+        assert_eq!(
+            text,
+            "Rewrites 1 line and adds 1 line in 2 places (line 2 and at the end of the file). \
+             The file goes from 3 to 4 lines. Line endings stay LF."
+        );
+
+        let mixed = explain("a\r\nb\r\n", "a\r\nx\nb\r\n", None).unwrap();
+        assert!(mixed.contains("Adds 1 line in 1 place (after line 1)"));
+        assert!(mixed.contains("1 line will end in LF although the file uses CRLF"));
+
+        let whitespace = explain("a  b\n", "a b", None).unwrap();
+        assert!(whitespace.contains("Only whitespace changes"));
+        assert!(whitespace.contains("The final newline is removed."));
+    }
+
+    #[test]
+    fn encoding_changes_are_described() {
+        let latin1 = Encoding::for_label(b"windows-1252").unwrap();
+        let from = Target {
+            encoding: latin1,
+            bom: false,
+        };
+        let to = Target {
+            encoding: UTF_8,
+            bom: true,
+        };
+        let converted = explain("caf\u{e9}\n", "caf\u{e9}\n", Some((from, to))).unwrap();
+        assert!(converted.contains("re-encoded from windows-1252 to UTF-8"));
+        assert!(converted.contains("A byte-order mark is added."));
+
+        let lossy = explain("a\n", "\u{2603}\n", Some((from, from))).unwrap();
+        assert!(lossy.contains("It stays windows-1252 with LF line endings."));
+        assert!(lossy.contains("1 new character(s) cannot be represented"));
+    }
+}
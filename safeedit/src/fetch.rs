@@ -0,0 +1,126 @@
+use std::io::Read;
+use std::process::{Command, Stdio};
+
+use anyhow::{Context, Result, anyhow, bail};
+
+use crate::integrity::sha256_hex;
+
+pub const CURL_ENV: &str = "SAFEEDIT_CURL";
+pub const DEFAULT_MAX_BYTES: u64 = 10 * 1024 * 1024;
+
+#[derive(Clone, Debug, Default)]
+pub struct FetchOptions {
+    pub max_bytes: u64,
+    pub sha256: Option<String>,
+}
+
+// Downloads with the system curl so no TLS stack is linked in; only https is
+// allowed, including across redirects.
+pub fn fetch_text(url: &str, options: &FetchOptions) -> Result<String> {
+    if !url.starts_with("https://") {
+        bail!("refusing to fetch '{url}': only https:// URLs are supported");
+    }
+    if let Some(expected) = &options.sha256
+        && (expected.len() != 64 || !expected.chars().all(|ch| ch.is_ascii_hexdigit()))
+    {
+        bail!("--url-sha256 must be 64 hex characters");
+    }
+    let mut parts = curl_command();
+    let mut command = Command::new(parts.remove(0));
+    command.args(parts).args([
+        "--fail",
+        "--silent",
+        "--show-error",
+        "--location",
+        "--proto",
+        "=https",
+        "--proto-redir",
+        "=https",
+    ]);
+    if options.max_bytes > 0 {
+        command
+            .arg("--max-filesize")
+            .arg(options.max_bytes.to_string());
+    }
+    let mut child = command
+        .arg("--")
+        .arg(url)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("running curl for {url}"))?;
+    let mut body = Vec::new();
+    let limit = match options.max_bytes {
+        0 => u64::MAX,
+        max => max + 1,
+    };
+    child
+        .stdout
+        .take()
+        .ok_or_else(|| anyhow!("curl produced no output stream"))?
+        .take(limit)
+        .read_to_end(&mut body)
+        .with_context(|| format!("downloading {url}"))?;
+    if options.max_bytes > 0 && body.len() as u64 > options.max_bytes {
+        let _ = child.kill();
+        let _ = child.wait();
+        bail!(
+            "{url} is larger than the {} byte limit (raise it with --url-max-size)",
+            options.max_bytes
+        );
+    }
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        bail!(
+            "fetching {url} failed ({}): {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    verify_checksum(url, &body, options.sha256.as_deref())?;
+    String::from_utf8(body).map_err(|_| anyhow!("{url} did not return UTF-8 text"))
+}
+
+fn verify_checksum(url: &str, body: &[u8], expected: Option<&str>) -> Result<()> {
+    let Some(expected) = expected else {
+        return Ok(());
+    };
+    let actual = sha256_hex(body);
+    if !actual.eq_ignore_ascii_case(expected) {
+        bail!("checksum mismatch for {url}: expected sha256 {expected}, got {actual}");
+    }
+    Ok(())
+}
+
+fn curl_command() -> Vec<String> {
+    let parts: Vec<String> = std::env::var(CURL_ENV)
+        .unwrap_or_default()
+        .split_whitespace()
+        .map(str::to_string)
+        .collect();
+    if parts.is_empty() {
+        vec!["curl".to_string()]
+    } else {
+        parts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plain_http_and_bad_checksums_are_rejected() {
+        let options = FetchOptions::default();
+        assert!(fetch_text("http://example.com/a.txt", &options).is_err());
+        let bad = FetchOptions {
+            max_bytes: 0,
+            sha256: Some("abc".into()),
+        };
+        assert!(fetch_text("https://example.com/a.txt", &bad).is_err());
+        let digest = sha256_hex(b"body");
+        assert!(verify_checksum("u", b"body", Some(&digest.to_uppercase())).is_ok());
+        assert!(verify_checksum("u", b"other", Some(&digest)).is_err());
+    }
+}
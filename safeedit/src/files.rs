@@ -69,14 +69,14 @@ fn append_path(
     let metadata = match fs::metadata(&canonical) {
         Ok(meta) => meta,
         Err(err) => {
-            if err.kind() == ErrorKind::NotFound {
-                if let Some(suggestion) = suggest_path(path) {
-                    bail!(
-                        "unable to read metadata for {}; did you mean {}?",
-                        path.display(),
-                        suggestion.display()
-                    );
-                }
+            if err.kind() == ErrorKind::NotFound
+                && let Some(suggestion) = suggest_path(path)
+            {
+                bail!(
+                    "unable to read metadata for {}; did you mean {}?",
+                    path.display(),
+                    suggestion.display()
+                );
             }
             return Err(err)
                 .with_context(|| format!("unable to read metadata for {}", canonical.display()));
@@ -294,12 +294,12 @@ fn collect_simple_names(suffixes: &[PathBuf]) -> Vec<OsString> {
     let mut seen = HashSet::new();
     let mut names = Vec::new();
     for suffix in suffixes {
-        if suffix.components().count() == 1 {
-            if let Some(name) = suffix.file_name() {
-                let os = name.to_os_string();
-                if seen.insert(os.clone()) {
-                    names.push(os);
-                }
+        if suffix.components().count() == 1
+            && let Some(name) = suffix.file_name()
+        {
+            let os = name.to_os_string();
+            if seen.insert(os.clone()) {
+                names.push(os);
             }
         }
     }
@@ -359,14 +359,12 @@ fn search_sibling_directories(
                     return Some(hit);
                 }
             }
-        } else if path.is_file() {
-            if let Some(name) = path.file_name() {
-                if simple_names.iter().any(|target| target == name) {
-                    if let Some(hit) = check_candidate(path.clone(), checked) {
-                        return Some(hit);
-                    }
-                }
-            }
+        } else if path.is_file()
+            && let Some(name) = path.file_name()
+            && simple_names.iter().any(|target| target == name)
+            && let Some(hit) = check_candidate(path.clone(), checked)
+        {
+            return Some(hit);
         }
     }
 
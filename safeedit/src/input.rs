@@ -0,0 +1,148 @@
+use std::io;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
+
+use anyhow::{Result, bail};
+use clap::ValueEnum;
+use is_terminal::IsTerminal;
+
+static NO_INPUT: AtomicBool = AtomicBool::new(false);
+static HINTED: AtomicBool = AtomicBool::new(false);
+static TIMEOUT: Mutex<Option<(Duration, TimeoutDefault)>> = Mutex::new(None);
+static LINES: Mutex<Option<Receiver<String>>> = Mutex::new(None);
+
+#[derive(Clone, Copy, Debug, ValueEnum, PartialEq, Eq, Default)]
+pub enum TimeoutDefault {
+    Apply,
+    #[default]
+    Skip,
+    Quit,
+}
+
+impl TimeoutDefault {
+    pub fn label(self) -> &'static str {
+        match self {
+            TimeoutDefault::Apply => "apply",
+            TimeoutDefault::Skip => "skip",
+            TimeoutDefault::Quit => "quit",
+        }
+    }
+}
+
+pub enum Answer {
+    Line(String),
+    TimedOut(TimeoutDefault),
+}
+
+pub fn configure(no_input: bool, timeout: Option<(Duration, TimeoutDefault)>) {
+    NO_INPUT.store(no_input, Ordering::Relaxed);
+    if let Ok(mut current) = TIMEOUT.lock() {
+        *current = timeout;
+    }
+}
+
+pub fn no_input() -> bool {
+    NO_INPUT.load(Ordering::Relaxed)
+}
+
+// Called right before any prompt reads stdin. Under --no-input the prompt
+// becomes an error; otherwise a non-TTY stdin gets a one-time hint so CI
+// logs show why the run is waiting.
+pub fn ensure_prompt_allowed(question: &str) -> Result<()> {
+    if no_input() {
+        bail!(
+            "{question} needs an answer but --no-input is set; pass --yes or --approve-policy to decide without prompting"
+        );
+    }
+    if !io::stdin().is_terminal() && !HINTED.swap(true, Ordering::Relaxed) {
+        eprintln!(
+            "note: stdin is not a terminal; waiting for an answer (pass --no-input to fail instead)"
+        );
+    }
+    Ok(())
+}
+
+// Reads one answer line (empty at end of input). With --prompt-timeout the
+// read happens on a long-lived reader thread so an unanswered prompt can
+// give up without leaving a second reader racing for the next line.
+pub fn read_answer() -> Result<Answer> {
+    let timeout = TIMEOUT.lock().ok().and_then(|current| *current);
+    let Some((limit, fallback)) = timeout else {
+        let mut input = String::new();
+        io::stdin().read_line(&mut input)?;
+        return Ok(Answer::Line(input));
+    };
+    let mut lines = LINES
+        .lock()
+        .map_err(|_| anyhow::anyhow!("prompt reader poisoned"))?;
+    let receiver = lines.get_or_insert_with(spawn_reader);
+    match receiver.recv_timeout(limit) {
+        Ok(line) => Ok(Answer::Line(line)),
+        Err(RecvTimeoutError::Disconnected) => Ok(Answer::Line(String::new())),
+        Err(RecvTimeoutError::Timeout) => Ok(Answer::TimedOut(fallback)),
+    }
+}
+
+// Prompts without a timeout fallback (pager, encoding check, --review-plan,
+// recover, step mode) read here. Once --prompt-timeout has started the reader
+// thread it owns stdin, so they take the next line from it instead of racing
+// it. Returns an empty string at end of input.
+pub fn read_line() -> Result<String> {
+    let lines = LINES
+        .lock()
+        .map_err(|_| anyhow::anyhow!("prompt reader poisoned"))?;
+    if let Some(receiver) = lines.as_ref() {
+        return Ok(receiver.recv().unwrap_or_default());
+    }
+    drop(lines);
+    let mut input = String::new();
+    io::stdin().read_line(&mut input)?;
+    Ok(input)
+}
+
+fn spawn_reader() -> Receiver<String> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        loop {
+            let mut line = String::new();
+            match io::stdin().read_line(&mut line) {
+                Ok(0) | Err(_) => break,
+                Ok(_) => {
+                    if sender.send(line).is_err() {
+                        break;
+                    }
+                }
+            }
+        }
+    });
+    receiver
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn no_input_turns_prompts_into_errors() {
+        configure(true, None);
+        let err = ensure_prompt_allowed("applying the change to a.txt").unwrap_err();
+        configure(false, None);
+        assert!(err.to_string().contains("--no-input"));
+    }
+
+    #[test]
+    fn plain_prompts_share_the_timeout_reader() {
+        let (sender, receiver) = mpsc::channel();
+        *LINES.lock().unwrap() = Some(receiver);
+        sender.send("y\n".to_string()).unwrap();
+        drop(sender);
+        let first = read_line();
+        let closed = read_line();
+        *LINES.lock().unwrap() = None;
+        assert_eq!(first.unwrap(), "y\n");
+        assert_eq!(closed.unwrap(), "");
+    }
+}
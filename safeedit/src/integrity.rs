@@ -0,0 +1,181 @@
+use std::fs;
+use std::path::Path;
+
+use anyhow::{Context, Result, anyhow, bail};
+use sha2::{Digest, Sha256};
+use time::{OffsetDateTime, format_description::well_known::Rfc3339};
+
+pub fn sha256_hex(bytes: &[u8]) -> String {
+    Sha256::digest(bytes)
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Precondition {
+    pub if_hash: Option<String>,
+    pub if_unchanged_since: Option<OffsetDateTime>,
+}
+
+impl Precondition {
+    pub fn from_args(if_hash: Option<&str>, since: Option<&str>) -> Result<Option<Self>> {
+        if if_hash.is_none() && since.is_none() {
+            return Ok(None);
+        }
+        let if_hash = match if_hash {
+            Some(raw) => {
+                let hex = raw.trim();
+                let hex = hex
+                    .strip_prefix("sha256:")
+                    .unwrap_or(hex)
+                    .to_ascii_lowercase();
+                if hex.len() != 64 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+                    bail!("--if-hash expects a 64-character sha256 hex digest, got `{raw}`");
+                }
+                Some(hex)
+            }
+            None => None,
+        };
+        let if_unchanged_since = since
+            .map(|raw| {
+                OffsetDateTime::parse(raw, &Rfc3339)
+                    .map_err(|err| anyhow!("invalid --if-unchanged-since `{raw}`: {err}"))
+            })
+            .transpose()?;
+        Ok(Some(Self {
+            if_hash,
+            if_unchanged_since,
+        }))
+    }
+
+    pub fn check(&self, path: &Path) -> Result<()> {
+        if let Some(expected) = &self.if_hash {
+            let bytes = fs::read(path).with_context(|| {
+                format!("--if-hash given but {} could not be read", path.display())
+            })?;
+            let actual = sha256_hex(&bytes);
+            if &actual != expected {
+                bail!(
+                    "{} changed on disk: expected sha256 {expected}, found {actual}",
+                    path.display()
+                );
+            }
+        }
+        if let Some(since) = self.if_unchanged_since
+            && let Ok(metadata) = fs::metadata(path)
+        {
+            let modified = OffsetDateTime::from(
+                metadata
+                    .modified()
+                    .with_context(|| format!("reading mtime of {}", path.display()))?,
+            );
+            if modified > since {
+                let stamp = modified.format(&Rfc3339).unwrap_or_default();
+                bail!(
+                    "{} was modified at {stamp}, after --if-unchanged-since",
+                    path.display()
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Drift {
+    Intact,
+    Modified,
+    Missing,
+    Reappeared,
+}
+
+impl Drift {
+    pub fn label(self) -> &'static str {
+        match self {
+            Drift::Intact => "intact",
+            Drift::Modified => "modified",
+            Drift::Missing => "missing",
+            Drift::Reappeared => "reappeared",
+        }
+    }
+}
+
+pub fn check_recorded(path: &Path, expected: Option<&str>) -> Result<(Drift, Option<String>)> {
+    let actual = match fs::read(path) {
+        Ok(bytes) => Some(sha256_hex(&bytes)),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => None,
+        Err(err) => return Err(err).with_context(|| format!("reading {}", path.display())),
+    };
+    Ok((drift(expected, actual.as_deref()), actual))
+}
+
+pub fn drift(expected: Option<&str>, actual: Option<&str>) -> Drift {
+    match (expected, actual) {
+        (Some(expected), Some(actual)) if expected == actual => Drift::Intact,
+        (Some(_), Some(_)) => Drift::Modified,
+        (Some(_), None) => Drift::Missing,
+        (None, Some(_)) => Drift::Reappeared,
+        (None, None) => Drift::Intact,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn hash_precondition_detects_changes() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("file.txt");
+        fs::write(&path, "hello\n").unwrap();
+        let digest = sha256_hex(b"hello\n");
+        let guard = Precondition::from_args(Some(&digest.to_uppercase()), None)
+            .unwrap()
+            .unwrap();
+        guard.check(&path).unwrap();
+        fs::write(&path, "changed\n").unwrap();
+        assert!(guard.check(&path).is_err());
+        assert!(Precondition::from_args(Some("abc"), None).is_err());
+        assert!(Precondition::from_args(None, None).unwrap().is_none());
+    }
+
+    #[test]
+    fn recorded_hashes_classify_drift() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("file.txt");
+        let recorded = sha256_hex(b"one");
+        assert_eq!(
+            check_recorded(&path, Some(&recorded)).unwrap().0,
+            Drift::Missing
+        );
+        assert_eq!(check_recorded(&path, None).unwrap(), (Drift::Intact, None));
+        fs::write(&path, "one").unwrap();
+        assert_eq!(
+            check_recorded(&path, Some(&recorded)).unwrap(),
+            (Drift::Intact, Some(recorded.clone()))
+        );
+        assert_eq!(check_recorded(&path, None).unwrap().0, Drift::Reappeared);
+        fs::write(&path, "two").unwrap();
+        assert_eq!(
+            check_recorded(&path, Some(&recorded)).unwrap().0,
+            Drift::Modified
+        );
+    }
+
+    #[test]
+    fn unchanged_since_compares_mtime() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("file.txt");
+        fs::write(&path, "x").unwrap();
+        let past = Precondition::from_args(None, Some("2000-01-01T00:00:00Z"))
+            .unwrap()
+            .unwrap();
+        assert!(past.check(&path).is_err());
+        let future = Precondition::from_args(None, Some("2999-01-01T00:00:00Z"))
+            .unwrap()
+            .unwrap();
+        future.check(&path).unwrap();
+    }
+}
@@ -0,0 +1,306 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use time::{OffsetDateTime, format_description::well_known::Rfc3339};
+use walkdir::WalkDir;
+
+use crate::integrity::sha256_hex;
+use crate::logging;
+
+const JOURNAL_DIR: &str = "journal";
+pub const TEMP_PREFIX: &str = ".safeedit-tmp-";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JournalEntry {
+    pub id: String,
+    pub path: PathBuf,
+    pub pid: u32,
+    pub started: String,
+    pub original_hash: Option<String>,
+    pub new_hash: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JournalState {
+    Completed,
+    NotStarted,
+    Incomplete,
+}
+
+impl JournalState {
+    pub fn label(self) -> &'static str {
+        match self {
+            JournalState::Completed => "completed (journal left behind)",
+            JournalState::NotStarted => "not started (original content intact)",
+            JournalState::Incomplete => "incomplete (content matches neither version)",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct PendingWrite {
+    pub entry: JournalEntry,
+    pub state: JournalState,
+    dir: PathBuf,
+}
+
+impl PendingWrite {
+    pub fn original_bytes(&self) -> Result<Option<Vec<u8>>> {
+        let path = self.dir.join(format!("{}.orig", self.entry.id));
+        if !path.exists() {
+            return Ok(None);
+        }
+        fs::read(&path)
+            .map(Some)
+            .with_context(|| format!("reading {}", path.display()))
+    }
+
+    pub fn new_bytes(&self) -> Result<Vec<u8>> {
+        let path = self.dir.join(format!("{}.new", self.entry.id));
+        fs::read(&path).with_context(|| format!("reading {}", path.display()))
+    }
+
+    pub fn discard(&self) -> Result<()> {
+        remove_entry_files(&self.dir, &self.entry.id)
+    }
+
+    // The listing is taken before any path lock, so recover re-reads each
+    // entry once it holds the lock; `None` means someone else resolved it.
+    pub fn reload(&self) -> Result<Option<PendingWrite>> {
+        let path = self.dir.join(format!("{}.json", self.entry.id));
+        if !path.exists() {
+            return Ok(None);
+        }
+        load_entry(&self.dir, &path).map(Some)
+    }
+
+    pub fn writer_running(&self) -> bool {
+        self.entry.pid != std::process::id() && process_running(self.entry.pid)
+    }
+}
+
+#[cfg(unix)]
+fn process_running(pid: u32) -> bool {
+    let Ok(pid) = libc::pid_t::try_from(pid) else {
+        return false;
+    };
+    if pid <= 0 {
+        return false;
+    }
+    // Signal 0 only probes; EPERM means the process exists under another user.
+    let probed = unsafe { libc::kill(pid, 0) };
+    probed == 0 || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+#[cfg(windows)]
+fn process_running(pid: u32) -> bool {
+    use windows_sys::Win32::Foundation::{
+        CloseHandle, ERROR_ACCESS_DENIED, GetLastError, STILL_ACTIVE,
+    };
+    use windows_sys::Win32::System::Threading::{
+        GetExitCodeProcess, OpenProcess, PROCESS_QUERY_LIMITED_INFORMATION,
+    };
+    unsafe {
+        let handle = OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, 0, pid);
+        if handle.is_null() {
+            return GetLastError() == ERROR_ACCESS_DENIED;
+        }
+        let mut code = 0u32;
+        let queried = GetExitCodeProcess(handle, &mut code) != 0;
+        CloseHandle(handle);
+        queried && code == STILL_ACTIVE as u32
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+fn process_running(_pid: u32) -> bool {
+    false
+}
+
+pub struct JournalGuard {
+    dir: PathBuf,
+    id: String,
+}
+
+impl Drop for JournalGuard {
+    fn drop(&mut self) {
+        let _ = remove_entry_files(&self.dir, &self.id);
+    }
+}
+
+pub fn begin(path: &Path, original: Option<&[u8]>, new: &[u8]) -> Result<JournalGuard> {
+    begin_in(&logging::state_dir().join(JOURNAL_DIR), path, original, new)
+}
+
+fn begin_in(dir: &Path, path: &Path, original: Option<&[u8]>, new: &[u8]) -> Result<JournalGuard> {
+    fs::create_dir_all(dir).with_context(|| format!("creating {}", dir.display()))?;
+    let now = OffsetDateTime::now_utc();
+    let id = format!("{}-{}", std::process::id(), now.unix_timestamp_nanos());
+    let absolute = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+    let entry = JournalEntry {
+        id: id.clone(),
+        path: absolute,
+        pid: std::process::id(),
+        started: now.format(&Rfc3339).unwrap_or_else(|_| "unknown".into()),
+        original_hash: original.map(sha256_hex),
+        new_hash: sha256_hex(new),
+    };
+    let guard = JournalGuard {
+        dir: dir.to_path_buf(),
+        id: id.clone(),
+    };
+    if let Some(bytes) = original {
+        fs::write(dir.join(format!("{id}.orig")), bytes)
+            .with_context(|| format!("journaling original of {}", path.display()))?;
+    }
+    fs::write(dir.join(format!("{id}.new")), new)
+        .with_context(|| format!("journaling new content of {}", path.display()))?;
+    fs::write(
+        dir.join(format!("{id}.json")),
+        serde_json::to_vec_pretty(&entry)?,
+    )
+    .with_context(|| format!("journaling {}", path.display()))?;
+    Ok(guard)
+}
+
+pub fn pending() -> Result<Vec<PendingWrite>> {
+    pending_in(&logging::state_dir().join(JOURNAL_DIR))
+}
+
+fn pending_in(dir: &Path) -> Result<Vec<PendingWrite>> {
+    let mut writes = Vec::new();
+    let Ok(listing) = fs::read_dir(dir) else {
+        return Ok(writes);
+    };
+    for item in listing {
+        let path = item?.path();
+        if path.extension().and_then(|ext| ext.to_str()) != Some("json") {
+            continue;
+        }
+        writes.push(load_entry(dir, &path)?);
+    }
+    writes.sort_by(|a, b| a.entry.id.cmp(&b.entry.id));
+    Ok(writes)
+}
+
+fn load_entry(dir: &Path, path: &Path) -> Result<PendingWrite> {
+    let data = fs::read(path).with_context(|| format!("reading {}", path.display()))?;
+    let entry: JournalEntry = serde_json::from_slice(&data)
+        .with_context(|| format!("parsing journal entry {}", path.display()))?;
+    let current = fs::read(&entry.path).ok().map(|bytes| sha256_hex(&bytes));
+    let state = if current.as_deref() == Some(entry.new_hash.as_str()) {
+        JournalState::Completed
+    } else if current == entry.original_hash {
+        JournalState::NotStarted
+    } else {
+        JournalState::Incomplete
+    };
+    Ok(PendingWrite {
+        entry,
+        state,
+        dir: dir.to_path_buf(),
+    })
+}
+
+pub fn find_temp_files(root: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let walker = WalkDir::new(root).follow_links(false).into_iter();
+    for entry in walker.filter_entry(|e| e.file_name() != ".git") {
+        let entry = entry?;
+        if entry.file_type().is_file()
+            && entry
+                .file_name()
+                .to_str()
+                .is_some_and(|name| name.starts_with(TEMP_PREFIX))
+        {
+            files.push(entry.into_path());
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+fn remove_entry_files(dir: &Path, id: &str) -> Result<()> {
+    for suffix in ["json", "orig", "new"] {
+        let path = dir.join(format!("{id}.{suffix}"));
+        if path.exists() {
+            fs::remove_file(&path).with_context(|| format!("removing {}", path.display()))?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn journal_survives_only_until_dropped() {
+        let temp = tempdir().unwrap();
+        let journal = temp.path().join("journal");
+        let target = temp.path().join("file.txt");
+        fs::write(&target, "old").unwrap();
+        let guard = begin_in(&journal, &target, Some(b"old"), b"new").unwrap();
+        let pending = pending_in(&journal).unwrap();
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].state, JournalState::NotStarted);
+        assert_eq!(pending[0].original_bytes().unwrap().unwrap(), b"old");
+
+        fs::write(&target, "ne").unwrap();
+        assert_eq!(
+            pending_in(&journal).unwrap()[0].state,
+            JournalState::Incomplete
+        );
+        fs::write(&target, "new").unwrap();
+        assert_eq!(
+            pending_in(&journal).unwrap()[0].state,
+            JournalState::Completed
+        );
+        drop(guard);
+        assert!(pending_in(&journal).unwrap().is_empty());
+    }
+
+    #[test]
+    fn reload_sees_later_state_and_resolved_entries() {
+        let temp = tempdir().unwrap();
+        let journal = temp.path().join("journal");
+        let target = temp.path().join("file.txt");
+        fs::write(&target, "old").unwrap();
+        let guard = begin_in(&journal, &target, Some(b"old"), b"new").unwrap();
+        let listed = pending_in(&journal).unwrap().remove(0);
+        assert_eq!(listed.state, JournalState::NotStarted);
+        // Entries written by this process never count as a live writer.
+        assert!(!listed.writer_running());
+
+        fs::write(&target, "new").unwrap();
+        let reloaded = listed.reload().unwrap().expect("entry still journaled");
+        assert_eq!(reloaded.state, JournalState::Completed);
+
+        drop(guard);
+        assert!(listed.reload().unwrap().is_none());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn live_writer_pids_are_detected() {
+        assert!(process_running(1));
+        let mut child = std::process::Command::new("true").spawn().unwrap();
+        let pid = child.id();
+        child.wait().unwrap();
+        assert!(!process_running(pid));
+    }
+
+    #[test]
+    fn finds_leftover_temp_files() {
+        let temp = tempdir().unwrap();
+        let nested = temp.path().join("src");
+        fs::create_dir_all(&nested).unwrap();
+        fs::write(nested.join(".safeedit-tmp-1-2"), "x").unwrap();
+        fs::write(nested.join("keep.txt"), "x").unwrap();
+        let found = find_temp_files(temp.path()).unwrap();
+        assert_eq!(found, vec![nested.join(".safeedit-tmp-1-2")]);
+    }
+}
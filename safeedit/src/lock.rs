@@ -0,0 +1,142 @@
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+use anyhow::{Context, Result, bail};
+use same_file::Handle;
+
+use crate::integrity::sha256_hex;
+use crate::logging;
+
+const LOCK_DIR: &str = "locks";
+const OPEN_RETRIES: usize = 50;
+
+pub struct PathLock {
+    _file: File,
+    lock_file: PathBuf,
+}
+
+impl Drop for PathLock {
+    // The lock file is removed while still held; a run that was waiting on it
+    // sees the path is gone (or replaced) once it gets the lock, and retries.
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.lock_file);
+    }
+}
+
+// Locks live under the workspace state directory, so runs started from
+// different subdirectories still contend for the same file.
+pub fn lock_path(path: &Path) -> Result<PathLock> {
+    lock_path_in(&logging::state_dir().join(LOCK_DIR), path)
+}
+
+fn lock_path_in(dir: &Path, path: &Path) -> Result<PathLock> {
+    let key = lock_key(path);
+    fs::create_dir_all(dir).with_context(|| format!("creating {}", dir.display()))?;
+    let lock_file = dir.join(format!("{}.lock", &sha256_hex(key.as_bytes())[..32]));
+    let mut announced = false;
+    let mut denied = 0usize;
+    loop {
+        let file = match OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&lock_file)
+        {
+            Ok(file) => file,
+            // Windows refuses to open a file whose deletion is pending.
+            Err(err) if err.kind() == ErrorKind::PermissionDenied && denied < OPEN_RETRIES => {
+                denied += 1;
+                thread::sleep(Duration::from_millis(20));
+                continue;
+            }
+            Err(err) => {
+                return Err(err)
+                    .with_context(|| format!("opening lock file {}", lock_file.display()));
+            }
+        };
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => {
+                if !announced {
+                    println!(
+                        "waiting for another safeedit run to finish writing {}...",
+                        path.display()
+                    );
+                    announced = true;
+                }
+                file.lock()
+                    .with_context(|| format!("locking {}", lock_file.display()))?;
+            }
+            Err(TryLockError::Error(err)) => {
+                return Err(err).with_context(|| format!("locking {}", lock_file.display()));
+            }
+        }
+        if still_current(&file, &lock_file)? {
+            return Ok(PathLock {
+                _file: file,
+                lock_file,
+            });
+        }
+        if denied >= OPEN_RETRIES {
+            bail!("could not lock {}", lock_file.display());
+        }
+    }
+}
+
+fn still_current(file: &File, lock_file: &Path) -> Result<bool> {
+    let held = Handle::from_file(file.try_clone()?)?;
+    Ok(Handle::from_path(lock_file).is_ok_and(|current| current == held))
+}
+
+fn lock_key(path: &Path) -> String {
+    let resolved = fs::canonicalize(path).unwrap_or_else(|_| {
+        let parent = path
+            .parent()
+            .filter(|p| !p.as_os_str().is_empty())
+            .unwrap_or_else(|| Path::new("."));
+        let base = fs::canonicalize(parent)
+            .or_else(|_| std::path::absolute(parent))
+            .unwrap_or_else(|_| parent.to_path_buf());
+        match path.file_name() {
+            Some(name) => base.join(name),
+            None => PathBuf::from(path),
+        }
+    });
+    resolved.to_string_lossy().into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn lock_key_is_stable_for_missing_files() {
+        let dir = tempdir().unwrap();
+        let existing = dir.path().join("a.txt");
+        fs::write(&existing, "x").unwrap();
+        let dotted = dir.path().join(".").join("a.txt");
+        assert_eq!(lock_key(&existing), lock_key(&dotted));
+        let missing = dir.path().join("new.txt");
+        assert!(lock_key(&missing).ends_with("new.txt"));
+        fs::write(&missing, "y").unwrap();
+        assert_eq!(lock_key(&missing), lock_key(&dir.path().join("new.txt")));
+    }
+
+    #[test]
+    fn lock_files_are_removed_on_release() {
+        let dir = tempdir().unwrap();
+        let target = dir.path().join("a.txt");
+        let locks = dir.path().join("locks");
+        let first = lock_path_in(&locks, &target).unwrap();
+        assert_eq!(fs::read_dir(&locks).unwrap().count(), 1);
+        drop(first);
+        assert_eq!(fs::read_dir(&locks).unwrap().count(), 0);
+        let second = lock_path_in(&locks, &target).unwrap();
+        drop(second);
+        assert_eq!(fs::read_dir(&locks).unwrap().count(), 0);
+    }
+}
@@ -1,14 +1,244 @@
 use std::fs::{self, OpenOptions};
 use std::io::{BufRead, BufReader, Write};
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, SystemTime};
 
 use anyhow::{Context, Result};
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use time::{OffsetDateTime, format_description::well_known::Rfc3339};
 
+use crate::config::{self, Config, LogConfig};
+
 const LOG_DIR: &str = ".safeedit";
 const LOG_FILE: &str = "change_log.jsonl";
-const MAX_ENTRIES: usize = 500;
+const DEFAULT_MAX_ENTRIES: usize = 500;
+pub const LOG_FILE_ENV: &str = "SAFEEDIT_LOG_FILE";
+const WORKSPACE_MARKERS: [&str; 3] = [LOG_DIR, config::CONFIG_FILE, ".git"];
+
+static LOCATION: OnceLock<LogLocation> = OnceLock::new();
+static INVOCATION: OnceLock<Invocation> = OnceLock::new();
+static INVOCATION_LOGGED: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Invocation {
+    pub id: String,
+    pub argv: Vec<String>,
+    pub cwd: String,
+}
+
+// Written to the change log once, before this run's first entry; each entry
+// refers back to it by id so `history` can group entries and `rerun` can
+// repeat the command.
+pub fn set_invocation(argv: Vec<String>) {
+    let cwd = std::env::current_dir()
+        .map(|dir| dir.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let stamp = OffsetDateTime::now_utc().unix_timestamp_nanos();
+    let seed = format!("{stamp}:{}:{}", std::process::id(), argv.join("\0"));
+    let id = crate::integrity::sha256_hex(seed.as_bytes())[..10].to_string();
+    let _ = INVOCATION.set(Invocation { id, argv, cwd });
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogLocation {
+    pub file: PathBuf,
+    pub workspace: PathBuf,
+    pub retention: LogRetention,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogRetention {
+    pub max_entries: usize,
+    pub max_bytes: Option<u64>,
+    pub keep_rotated: Option<usize>,
+    pub max_age: Option<Duration>,
+}
+
+impl LogRetention {
+    fn from_config(config: &LogConfig) -> Self {
+        Self {
+            max_entries: config.max_entries.unwrap_or(DEFAULT_MAX_ENTRIES).max(1),
+            max_bytes: config.max_bytes,
+            keep_rotated: config.keep_rotated,
+            max_age: config
+                .retain_days
+                .map(|days| Duration::from_secs(days * 86_400)),
+        }
+    }
+}
+
+pub fn configure(cli_file: Option<PathBuf>, global: bool) -> Result<()> {
+    let config = Config::load()?;
+    let cwd = std::env::current_dir().context("reading current directory")?;
+    let location = resolve_location(
+        cli_file,
+        global,
+        std::env::var_os(LOG_FILE_ENV).map(PathBuf::from),
+        &config.log,
+        &cwd,
+        config::home_dir().as_deref(),
+    );
+    let _ = LOCATION.set(location);
+    Ok(())
+}
+
+// Journal, locks, sessions, decisions, and batch progress all live here, so
+// runs started from any subdirectory of a workspace share the same state.
+pub fn state_dir() -> PathBuf {
+    location().workspace.join(LOG_DIR)
+}
+
+pub fn location() -> &'static LogLocation {
+    LOCATION.get_or_init(default_location)
+}
+
+// Unit tests that drive whole commands share one throwaway workspace, so their
+// log, journal, and lock files never land in the checkout.
+#[cfg(test)]
+fn default_location() -> LogLocation {
+    let workspace = tempfile::tempdir().expect("test workspace").keep();
+    LogLocation {
+        file: workspace.join(LOG_DIR).join(LOG_FILE),
+        workspace,
+        retention: LogRetention::from_config(&LogConfig::default()),
+    }
+}
+
+#[cfg(not(test))]
+fn default_location() -> LogLocation {
+    let cwd = std::env::current_dir().unwrap_or_else(|_| PathBuf::from("."));
+    let config = Config::load().unwrap_or_default();
+    resolve_location(
+        None,
+        false,
+        std::env::var_os(LOG_FILE_ENV).map(PathBuf::from),
+        &config.log,
+        &cwd,
+        config::home_dir().as_deref(),
+    )
+}
+
+fn resolve_location(
+    cli_file: Option<PathBuf>,
+    global: bool,
+    env_file: Option<PathBuf>,
+    config: &LogConfig,
+    cwd: &Path,
+    home: Option<&Path>,
+) -> LogLocation {
+    let detected = find_workspace(cwd, home);
+    let workspace = detected.clone().unwrap_or_else(|| cwd.to_path_buf());
+    let global_file = || home.map(|home| home.join(LOG_DIR).join(LOG_FILE));
+    let file = if let Some(file) = cli_file {
+        cwd.join(file)
+    } else if let Some(file) = env_file.filter(|file| !file.as_os_str().is_empty()) {
+        cwd.join(file)
+    } else if global && let Some(file) = global_file() {
+        file
+    } else if let Some(file) = &config.file {
+        workspace.join(file)
+    } else if config.global == Some(true)
+        && let Some(file) = global_file()
+    {
+        file
+    } else if let Some(root) = &detected {
+        root.join(LOG_DIR).join(LOG_FILE)
+    } else {
+        global_file().unwrap_or_else(|| cwd.join(LOG_DIR).join(LOG_FILE))
+    };
+    LogLocation {
+        file,
+        workspace,
+        retention: LogRetention::from_config(config),
+    }
+}
+
+// The home directory holds the global log and config, so only a repository
+// there marks it as a workspace; otherwise every directory under $HOME would
+// share one workspace after the first run outside a repository.
+fn find_workspace(cwd: &Path, home: Option<&Path>) -> Option<PathBuf> {
+    cwd.ancestors()
+        .find(|dir| {
+            WORKSPACE_MARKERS
+                .iter()
+                .filter(|marker| home != Some(*dir) || **marker == ".git")
+                .any(|marker| dir.join(marker).exists())
+        })
+        .map(Path::to_path_buf)
+}
+
+static PENDING_WRITES: Mutex<Vec<(PathBuf, WriteRecord)>> = Mutex::new(Vec::new());
+static APPLIED_CHANGES: AtomicUsize = AtomicUsize::new(0);
+
+static TIMED_OUT_PROMPTS: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+
+static CAPTURED_EVENTS: Mutex<Option<Vec<serde_json::Value>>> = Mutex::new(None);
+
+static STAGED_NAMES: Mutex<Vec<(PathBuf, PathBuf)>> = Mutex::new(Vec::new());
+
+// Archive members and remote files are edited through a temporary staging
+// copy; the log, sessions, and events name them by their source instead.
+pub fn register_staged(staged: &Path, name: PathBuf) {
+    if let Ok(mut names) = STAGED_NAMES.lock() {
+        names.retain(|(existing, _)| existing != staged);
+        names.push((staged.to_path_buf(), name));
+    }
+}
+
+pub fn staged_name(path: &Path) -> Option<PathBuf> {
+    let names = STAGED_NAMES.lock().ok()?;
+    names
+        .iter()
+        .find(|(staged, _)| staged == path)
+        .map(|(_, name)| name.clone())
+}
+
+pub fn recorded_name(path: &Path) -> PathBuf {
+    staged_name(path).unwrap_or_else(|| path.to_path_buf())
+}
+
+pub fn applied_count() -> usize {
+    APPLIED_CHANGES.load(Ordering::Relaxed)
+}
+
+pub fn capture_events() {
+    if let Ok(mut captured) = CAPTURED_EVENTS.lock() {
+        captured.get_or_insert_with(Vec::new);
+    }
+}
+
+pub fn capturing() -> bool {
+    CAPTURED_EVENTS
+        .lock()
+        .map(|captured| captured.is_some())
+        .unwrap_or(false)
+}
+
+pub fn note_event(event: &serde_json::Value) {
+    if let Ok(mut captured) = CAPTURED_EVENTS.lock()
+        && let Some(events) = captured.as_mut()
+    {
+        events.push(event.clone());
+    }
+}
+
+pub fn take_events() -> Vec<serde_json::Value> {
+    CAPTURED_EVENTS
+        .lock()
+        .ok()
+        .and_then(|mut captured| captured.as_mut().map(std::mem::take))
+        .unwrap_or_default()
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WriteRecord {
+    pub before_sha256: Option<String>,
+    pub after_sha256: Option<String>,
+    pub undo_patch: Option<PathBuf>,
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 #[serde(rename_all = "snake_case")]
@@ -34,9 +264,27 @@ pub struct ChangeLogEntry<'a> {
     pub line_summary: &'a str,
     #[serde(rename = "spans", skip_serializing_if = "Option::is_none")]
     pub spans: Option<&'a [LineSpan]>,
+    pub session: &'a str,
+    pub workspace: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub before_sha256: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub after_sha256: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub undo_patch: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub decision: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub run: Option<&'a str>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InvocationRecord {
+    pub timestamp: String,
+    pub invocation: Invocation,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct LoggedEntry {
     pub timestamp: String,
     pub command: String,
@@ -46,6 +294,166 @@ pub struct LoggedEntry {
     pub line_summary: String,
     #[serde(default)]
     pub spans: Vec<LineSpan>,
+    #[serde(default)]
+    pub session: Option<String>,
+    #[serde(default)]
+    pub workspace: Option<String>,
+    #[serde(default)]
+    pub before_sha256: Option<String>,
+    #[serde(default)]
+    pub after_sha256: Option<String>,
+    #[serde(default)]
+    pub undo_patch: Option<String>,
+    #[serde(default)]
+    pub run: Option<String>,
+}
+
+impl LoggedEntry {
+    pub fn parsed_timestamp(&self) -> Option<OffsetDateTime> {
+        OffsetDateTime::parse(&self.timestamp, &Rfc3339).ok()
+    }
+
+    pub fn absolute_path(&self) -> PathBuf {
+        let path = Path::new(&self.path);
+        // Remote files are logged by URL, which no workspace can anchor.
+        if self.path.starts_with("ssh://") {
+            return path.to_path_buf();
+        }
+        match &self.workspace {
+            Some(workspace) if path.is_relative() => Path::new(workspace).join(path),
+            _ => std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf()),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct InvocationSummary {
+    #[serde(flatten)]
+    pub invocation: Invocation,
+    pub timestamp: String,
+    pub files: usize,
+    pub applied: usize,
+}
+
+// Oldest first, one row per recorded invocation. A run whose entries span a
+// rotation is recorded again in the newer file, so repeats are merged.
+pub fn invocations(
+    records: &[InvocationRecord],
+    entries: &[LoggedEntry],
+) -> Vec<InvocationSummary> {
+    let mut summaries: Vec<InvocationSummary> = Vec::new();
+    for record in records {
+        if summaries
+            .iter()
+            .any(|summary| summary.invocation.id == record.invocation.id)
+        {
+            continue;
+        }
+        let entries = entries
+            .iter()
+            .filter(|entry| entry.run.as_deref() == Some(record.invocation.id.as_str()));
+        let (files, applied) = entries.fold((0, 0), |(files, applied), entry| {
+            (
+                files + 1,
+                applied + usize::from(entry.action.starts_with("applied")),
+            )
+        });
+        summaries.push(InvocationSummary {
+            invocation: record.invocation.clone(),
+            timestamp: record.timestamp.clone(),
+            files,
+            applied,
+        });
+    }
+    summaries
+}
+
+#[derive(Debug, Default)]
+pub struct LogFilter {
+    pub path: Option<PathBuf>,
+    pub command: Option<String>,
+    pub action: Option<String>,
+    pub since: Option<OffsetDateTime>,
+    pub until: Option<OffsetDateTime>,
+}
+
+impl LogFilter {
+    pub fn matches(&self, entry: &LoggedEntry) -> bool {
+        if let Some(command) = &self.command
+            && !entry.command.eq_ignore_ascii_case(command)
+        {
+            return false;
+        }
+        if let Some(action) = &self.action
+            && !entry.action.eq_ignore_ascii_case(action)
+        {
+            return false;
+        }
+        if self.since.is_some() || self.until.is_some() {
+            let Some(ts) = entry.parsed_timestamp() else {
+                return false;
+            };
+            if self.since.is_some_and(|since| ts < since)
+                || self.until.is_some_and(|until| ts > until)
+            {
+                return false;
+            }
+        }
+        if let Some(filter) = &self.path {
+            let wanted = std::path::absolute(filter).unwrap_or_else(|_| filter.clone());
+            if !entry.absolute_path().starts_with(&wanted) {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+pub fn note_write(path: &Path, record: WriteRecord) {
+    if let Ok(mut pending) = PENDING_WRITES.lock() {
+        pending.retain(|(existing, _)| existing != path);
+        pending.push((path.to_path_buf(), record));
+    }
+}
+
+pub fn peek_write(path: &Path) -> Option<WriteRecord> {
+    let pending = PENDING_WRITES.lock().ok()?;
+    pending
+        .iter()
+        .find(|(existing, _)| existing == path)
+        .map(|(_, record)| record.clone())
+}
+
+pub fn note_prompt_timeout(path: &Path) {
+    if let Ok(mut timed_out) = TIMED_OUT_PROMPTS.lock() {
+        timed_out.push(path.to_path_buf());
+    }
+}
+
+pub fn prompt_timed_out(path: &Path) -> bool {
+    TIMED_OUT_PROMPTS
+        .lock()
+        .map(|timed_out| timed_out.iter().any(|existing| existing == path))
+        .unwrap_or(false)
+}
+
+fn take_prompt_timeout(path: &Path) -> bool {
+    let Ok(mut timed_out) = TIMED_OUT_PROMPTS.lock() else {
+        return false;
+    };
+    match timed_out.iter().position(|existing| existing == path) {
+        Some(index) => {
+            timed_out.remove(index);
+            true
+        }
+        None => false,
+    }
+}
+
+fn take_write(path: &Path) -> Option<WriteRecord> {
+    let mut pending = PENDING_WRITES.lock().ok()?;
+    let index = pending.iter().position(|(existing, _)| existing == path)?;
+    Some(pending.remove(index).1)
 }
 
 pub fn record_change(
@@ -55,17 +463,40 @@ pub fn record_change(
     line_summary: &str,
     spans: &[LineSpan],
 ) -> Result<()> {
+    if action.starts_with("applied") {
+        APPLIED_CHANGES.fetch_add(1, Ordering::Relaxed);
+    }
     let log_path = ensure_log_file()?;
     let timestamp = OffsetDateTime::now_utc()
         .format(&Rfc3339)
         .unwrap_or_else(|_| "unknown".into());
+    let written = take_write(path).unwrap_or_default();
+    let name = recorded_name(path);
+    if action.starts_with("applied")
+        && (written.before_sha256.is_some() || written.after_sha256.is_some())
+        && name == path
+    {
+        crate::manifest::note_applied(path, written.after_sha256.is_none());
+    }
+    let undo_patch = written
+        .undo_patch
+        .as_ref()
+        .map(|patch| std::path::absolute(patch).unwrap_or_else(|_| patch.clone()))
+        .map(|patch| patch.to_string_lossy().into_owned());
     let entry = ChangeLogEntry {
         timestamp: &timestamp,
         command,
-        path: &path.to_string_lossy(),
+        path: &name.to_string_lossy(),
         action,
         line_summary,
         spans: (!spans.is_empty()).then_some(spans),
+        session: crate::session::current_id(),
+        workspace: &location().workspace.to_string_lossy(),
+        before_sha256: written.before_sha256.as_deref(),
+        after_sha256: written.after_sha256.as_deref(),
+        undo_patch: undo_patch.as_deref(),
+        decision: take_prompt_timeout(path).then_some("prompt-timeout"),
+        run: INVOCATION.get().map(|invocation| invocation.id.as_str()),
     };
     let json = serde_json::to_string(&entry)?;
     let mut file = OpenOptions::new()
@@ -73,81 +504,481 @@ pub fn record_change(
         .create(true)
         .open(&log_path)
         .with_context(|| format!("opening {log_path:?}"))?;
+    if let Some(invocation) = INVOCATION.get()
+        && !INVOCATION_LOGGED.swap(true, Ordering::Relaxed)
+    {
+        let record = InvocationRecord {
+            timestamp: timestamp.clone(),
+            invocation: invocation.clone(),
+        };
+        writeln!(file, "{}", serde_json::to_string(&record)?)?;
+    }
     writeln!(file, "{json}")?;
-    truncate_log(&log_path)?;
+    drop(file);
+    let retention = &location().retention;
+    if rotate_if_needed(&log_path, retention)?.is_some() {
+        INVOCATION_LOGGED.store(false, Ordering::Relaxed);
+        if retention.keep_rotated.is_some() || retention.max_age.is_some() {
+            prune_rotated(&log_path, retention.keep_rotated, retention.max_age)?;
+        }
+    }
     Ok(())
 }
 
-pub fn read_recent(limit: usize) -> Result<Vec<LoggedEntry>> {
-    let path = PathBuf::from(LOG_DIR).join(LOG_FILE);
-    if !path.exists() {
-        return Ok(vec![]);
+pub fn read_all() -> Result<Vec<LoggedEntry>> {
+    read_lines()
+}
+
+pub fn read_invocations() -> Result<Vec<InvocationRecord>> {
+    read_lines()
+}
+
+// Change entries and invocation records share the log; each reader keeps the
+// lines that parse as its own kind.
+fn read_lines<T: DeserializeOwned>() -> Result<Vec<T>> {
+    let path = &location().file;
+    let mut entries = Vec::new();
+    for rotated in rotated_logs(path)? {
+        read_entries(&rotated, &mut entries)?;
+    }
+    if path.exists() {
+        read_entries(path, &mut entries)?;
     }
+    Ok(entries)
+}
+
+fn read_entries<T: DeserializeOwned>(path: &Path, entries: &mut Vec<T>) -> Result<()> {
     let file = OpenOptions::new()
         .read(true)
-        .open(&path)
+        .open(path)
         .with_context(|| format!("reading {path:?}"))?;
     let reader = BufReader::new(file);
-    let mut lines = Vec::new();
     for line in reader.lines() {
         let line = line?;
         if line.trim().is_empty() {
             continue;
         }
-        lines.push(line);
-    }
-    let start = lines.len().saturating_sub(limit);
-    let mut entries = Vec::new();
-    for line in &lines[start..] {
-        if let Ok(entry) = serde_json::from_str::<LoggedEntry>(line) {
+        if let Ok(entry) = serde_json::from_str::<T>(&line) {
             entries.push(entry);
         }
     }
-    Ok(entries)
+    Ok(())
 }
 
-pub fn read_all() -> Result<Vec<LoggedEntry>> {
-    let path = PathBuf::from(LOG_DIR).join(LOG_FILE);
-    if !path.exists() {
-        return Ok(vec![]);
-    }
-    let file = OpenOptions::new()
-        .read(true)
-        .open(&path)
-        .with_context(|| format!("reading {path:?}"))?;
-    let reader = BufReader::new(file);
-    let mut entries = Vec::new();
-    for line in reader.lines() {
-        let line = line?;
-        if line.trim().is_empty() {
+pub fn rotated_logs(log_path: &Path) -> Result<Vec<PathBuf>> {
+    let (stem, ext) = split_log_name(log_path);
+    let parent = log_path
+        .parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .unwrap_or_else(|| Path::new("."));
+    let Ok(listing) = fs::read_dir(parent) else {
+        return Ok(Vec::new());
+    };
+    let mut rotated = Vec::new();
+    for item in listing {
+        let item = item?;
+        let name = item.file_name();
+        let Some(name) = name.to_str() else {
             continue;
+        };
+        let stamp = name
+            .strip_prefix(&stem)
+            .and_then(|rest| rest.strip_prefix('.'))
+            .and_then(|rest| match &ext {
+                Some(ext) => rest.strip_suffix(ext.as_str())?.strip_suffix('.'),
+                None => Some(rest),
+            });
+        if let Some(stamp) = stamp
+            && crate::backup::is_timestamp_stamp(stamp)
+        {
+            let (main, counter) = stamp.split_once('-').unwrap_or((stamp, "0"));
+            let counter: u64 = counter.parse().unwrap_or(0);
+            rotated.push((main.to_string(), counter, item.path()));
         }
-        if let Ok(entry) = serde_json::from_str::<LoggedEntry>(&line) {
-            entries.push(entry);
+    }
+    rotated.sort();
+    Ok(rotated.into_iter().map(|(_, _, path)| path).collect())
+}
+
+fn split_log_name(log_path: &Path) -> (String, Option<String>) {
+    let name = log_path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or(LOG_FILE);
+    match name.rsplit_once('.') {
+        Some((stem, ext)) if !stem.is_empty() => (stem.to_string(), Some(ext.to_string())),
+        _ => (name.to_string(), None),
+    }
+}
+
+fn rotate_if_needed(path: &Path, retention: &LogRetention) -> Result<Option<PathBuf>> {
+    let size = fs::metadata(path)
+        .with_context(|| format!("reading {path:?}"))?
+        .len();
+    let oversized = retention.max_bytes.is_some_and(|max| size > max);
+    if !oversized {
+        let file = OpenOptions::new()
+            .read(true)
+            .open(path)
+            .with_context(|| format!("reading {path:?}"))?;
+        let entries = BufReader::new(file)
+            .lines()
+            .map_while(Result::ok)
+            .filter(|line| !line.trim().is_empty())
+            .count();
+        if entries <= retention.max_entries {
+            return Ok(None);
         }
     }
-    Ok(entries)
+    let (stem, ext) = split_log_name(path);
+    let now = OffsetDateTime::now_utc();
+    let stamp = format!(
+        "{:04}{:02}{:02}T{:02}{:02}{:02}Z",
+        now.year(),
+        u8::from(now.month()),
+        now.day(),
+        now.hour(),
+        now.minute(),
+        now.second()
+    );
+    let mut attempt = 0usize;
+    let target = loop {
+        let stamp = if attempt == 0 {
+            stamp.clone()
+        } else {
+            format!("{stamp}-{attempt}")
+        };
+        let name = match &ext {
+            Some(ext) => format!("{stem}.{stamp}.{ext}"),
+            None => format!("{stem}.{stamp}"),
+        };
+        let candidate = path.with_file_name(name);
+        if !candidate.exists() {
+            break candidate;
+        }
+        attempt += 1;
+    };
+    fs::rename(path, &target).with_context(|| format!("rotating {path:?} to {target:?}"))?;
+    Ok(Some(target))
+}
+
+pub fn prune_rotated(
+    log_path: &Path,
+    keep: Option<usize>,
+    max_age: Option<Duration>,
+) -> Result<Vec<PathBuf>> {
+    let mut removed = Vec::new();
+    for path in prunable_logs(log_path, keep, max_age)? {
+        fs::remove_file(&path).with_context(|| format!("removing {path:?}"))?;
+        removed.push(path);
+    }
+    Ok(removed)
+}
+
+pub fn prunable_logs(
+    log_path: &Path,
+    keep: Option<usize>,
+    max_age: Option<Duration>,
+) -> Result<Vec<PathBuf>> {
+    let rotated = rotated_logs(log_path)?;
+    let excess = keep.map_or(0, |keep| rotated.len().saturating_sub(keep));
+    let cutoff = max_age.and_then(|age| SystemTime::now().checked_sub(age));
+    Ok(rotated
+        .into_iter()
+        .enumerate()
+        .filter(|(index, path)| {
+            *index < excess
+                || cutoff.is_some_and(|cutoff| {
+                    fs::metadata(path)
+                        .and_then(|metadata| metadata.modified())
+                        .is_ok_and(|modified| modified < cutoff)
+                })
+        })
+        .map(|(_, path)| path)
+        .collect())
 }
 
 fn ensure_log_file() -> Result<PathBuf> {
-    let dir = PathBuf::from(LOG_DIR);
-    if !dir.exists() {
-        fs::create_dir_all(&dir).with_context(|| format!("creating {dir:?}"))?;
+    let path = location().file.clone();
+    if let Some(dir) = path.parent()
+        && !dir.as_os_str().is_empty()
+        && !dir.exists()
+    {
+        fs::create_dir_all(dir).with_context(|| format!("creating {dir:?}"))?;
     }
-    Ok(dir.join(LOG_FILE))
+    Ok(path)
 }
 
-fn truncate_log(path: &Path) -> Result<()> {
-    let file = OpenOptions::new()
-        .read(true)
-        .open(path)
-        .with_context(|| format!("reading {path:?}"))?;
-    let reader = BufReader::new(file);
-    let lines: Vec<_> = reader.lines().collect::<Result<_, _>>()?;
-    if lines.len() <= MAX_ENTRIES {
-        return Ok(());
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn logged(command: &str, path: &str, action: &str, timestamp: &str) -> LoggedEntry {
+        LoggedEntry {
+            timestamp: timestamp.into(),
+            command: command.into(),
+            path: path.into(),
+            action: action.into(),
+            line_summary: "L1".into(),
+            spans: Vec::new(),
+            session: None,
+            workspace: None,
+            before_sha256: None,
+            after_sha256: None,
+            undo_patch: None,
+            run: None,
+        }
+    }
+
+    #[test]
+    fn log_filter_combines_criteria() {
+        let entry = logged(
+            "replace",
+            "/work/src/lib.rs",
+            "applied",
+            "2025-11-08T14:00:00Z",
+        );
+        let at = |raw: &str| OffsetDateTime::parse(raw, &Rfc3339).unwrap();
+        assert!(LogFilter::default().matches(&entry));
+        let filter = LogFilter {
+            path: Some(PathBuf::from("/work/src")),
+            command: Some("Replace".into()),
+            action: Some("applied".into()),
+            since: Some(at("2025-11-01T00:00:00Z")),
+            until: Some(at("2025-11-09T00:00:00Z")),
+        };
+        assert!(filter.matches(&entry));
+        let other_file = LogFilter {
+            path: Some(PathBuf::from("/work/src/main.rs")),
+            ..LogFilter::default()
+        };
+        assert!(!other_file.matches(&entry));
+        let skipped_only = LogFilter {
+            action: Some("skipped".into()),
+            ..LogFilter::default()
+        };
+        assert!(!skipped_only.matches(&entry));
+        let later = LogFilter {
+            since: Some(at("2025-11-09T00:00:00Z")),
+            ..LogFilter::default()
+        };
+        assert!(!later.matches(&entry));
+    }
+
+    #[test]
+    fn log_location_prefers_cli_then_env_then_config() {
+        let temp = tempfile::tempdir().unwrap();
+        let root = temp.path().join("project");
+        let nested = root.join("src").join("deep");
+        fs::create_dir_all(&nested).unwrap();
+        fs::create_dir_all(root.join(".git")).unwrap();
+        let home = temp.path().join("home");
+        let config = LogConfig::default();
+
+        let default = resolve_location(None, false, None, &config, &nested, Some(&home));
+        assert_eq!(default.workspace, root);
+        assert_eq!(default.file, root.join(LOG_DIR).join(LOG_FILE));
+
+        let configured = LogConfig {
+            file: Some(PathBuf::from("logs/edits.jsonl")),
+            ..LogConfig::default()
+        };
+        let from_config = resolve_location(None, false, None, &configured, &nested, Some(&home));
+        assert_eq!(from_config.file, root.join("logs/edits.jsonl"));
+
+        let from_env = resolve_location(
+            None,
+            false,
+            Some(PathBuf::from("/var/log/se.jsonl")),
+            &configured,
+            &nested,
+            Some(&home),
+        );
+        assert_eq!(from_env.file, PathBuf::from("/var/log/se.jsonl"));
+
+        let from_cli = resolve_location(
+            Some(PathBuf::from("cli.jsonl")),
+            true,
+            Some(PathBuf::from("/var/log/se.jsonl")),
+            &configured,
+            &nested,
+            Some(&home),
+        );
+        assert_eq!(from_cli.file, nested.join("cli.jsonl"));
+
+        let global = resolve_location(None, true, None, &configured, &nested, Some(&home));
+        assert_eq!(global.file, home.join(LOG_DIR).join(LOG_FILE));
+        assert_eq!(global.workspace, root);
+    }
+
+    #[test]
+    fn log_location_falls_back_to_global_outside_workspace() {
+        let temp = tempfile::tempdir().unwrap();
+        let home = temp.path().join("home");
+        let loose = home.join("projects").join("loose");
+        fs::create_dir_all(&loose).unwrap();
+        fs::create_dir_all(home.join(LOG_DIR)).unwrap();
+        fs::write(home.join(config::CONFIG_FILE), "").unwrap();
+        let location = resolve_location(
+            None,
+            false,
+            None,
+            &LogConfig::default(),
+            &loose,
+            Some(&home),
+        );
+        assert_eq!(location.file, home.join(LOG_DIR).join(LOG_FILE));
+        assert_eq!(location.workspace, loose);
+
+        fs::create_dir_all(home.join(".git")).unwrap();
+        let dotfiles = resolve_location(
+            None,
+            false,
+            None,
+            &LogConfig::default(),
+            &loose,
+            Some(&home),
+        );
+        assert_eq!(dotfiles.workspace, home);
+    }
+
+    #[test]
+    fn full_logs_rotate_to_dated_files_and_prune() {
+        let temp = tempfile::tempdir().unwrap();
+        let log = temp.path().join(LOG_FILE);
+        let retention = LogRetention {
+            max_entries: 2,
+            max_bytes: None,
+            keep_rotated: None,
+            max_age: None,
+        };
+        fs::write(&log, "{}\n{}\n").unwrap();
+        assert!(rotate_if_needed(&log, &retention).unwrap().is_none());
+        let mut rotated = Vec::new();
+        for _ in 0..3 {
+            fs::write(&log, "{}\n{}\n{}\n").unwrap();
+            rotated.push(rotate_if_needed(&log, &retention).unwrap().unwrap());
+        }
+        assert!(!log.exists());
+        let name = rotated[0].file_name().unwrap().to_str().unwrap();
+        assert!(
+            name.starts_with("change_log.") && name.ends_with("Z.jsonl"),
+            "{name}"
+        );
+        assert_eq!(rotated_logs(&log).unwrap(), rotated);
+
+        let removed = prune_rotated(&log, Some(1), None).unwrap();
+        assert_eq!(removed, rotated[..2].to_vec());
+        assert_eq!(rotated_logs(&log).unwrap(), vec![rotated[2].clone()]);
+
+        let by_size = LogRetention {
+            max_entries: 100,
+            max_bytes: Some(4),
+            ..retention
+        };
+        fs::write(&log, "{}\n{}\n{}\n").unwrap();
+        assert!(rotate_if_needed(&log, &by_size).unwrap().is_some());
+    }
+
+    #[test]
+    fn pending_writes_are_consumed_once_per_path() {
+        let path = Path::new("pending/one.txt");
+        let record = WriteRecord {
+            before_sha256: Some("aa".into()),
+            after_sha256: Some("bb".into()),
+            undo_patch: None,
+        };
+        note_write(path, record.clone());
+        assert_eq!(take_write(Path::new("pending/other.txt")), None);
+        assert_eq!(take_write(path), Some(record));
+        assert_eq!(take_write(path), None);
+    }
+
+    #[test]
+    fn prompt_timeouts_are_claimed_once_per_path() {
+        let path = Path::new("timeouts/a.txt");
+        note_prompt_timeout(path);
+        assert!(prompt_timed_out(path));
+        assert!(!prompt_timed_out(Path::new("timeouts/b.txt")));
+        assert!(take_prompt_timeout(path));
+        assert!(!take_prompt_timeout(path));
+    }
+
+    #[test]
+    fn events_are_only_kept_while_capturing() {
+        let event = serde_json::json!({ "path": "captured/one.txt" });
+        note_event(&event);
+        assert!(!take_events().contains(&event));
+        capture_events();
+        note_event(&event);
+        assert!(take_events().contains(&event));
+        assert!(!take_events().contains(&event));
+    }
+
+    #[test]
+    fn invocations_group_entries_by_run() {
+        let record = |id: &str, timestamp: &str| InvocationRecord {
+            timestamp: timestamp.into(),
+            invocation: Invocation {
+                id: id.into(),
+                argv: vec!["replace".into(), "--pattern".into(), "a".into()],
+                cwd: "/w".into(),
+            },
+        };
+        let records = vec![
+            record("one", "2025-11-01T09:00:00Z"),
+            record("two", "2025-11-02T09:00:00Z"),
+            record("one", "2025-11-03T09:00:00Z"),
+        ];
+        let mut entries = vec![
+            logged("replace", "a.rs", "applied", "2025-11-01T09:00:00Z"),
+            logged("replace", "b.rs", "dry-run", "2025-11-01T09:00:00Z"),
+            logged("normalize", "a.rs", "applied", "2025-11-02T09:00:00Z"),
+            logged("replace", "old.rs", "applied", "2025-10-01T09:00:00Z"),
+        ];
+        entries[0].run = Some("one".into());
+        entries[1].run = Some("one".into());
+        entries[2].run = Some("two".into());
+        let summaries = invocations(&records, &entries);
+        assert_eq!(summaries.len(), 2);
+        assert_eq!(summaries[0].invocation.id, "one");
+        assert_eq!(summaries[0].timestamp, "2025-11-01T09:00:00Z");
+        assert_eq!((summaries[0].files, summaries[0].applied), (2, 1));
+        assert_eq!(summaries[1].timestamp, "2025-11-02T09:00:00Z");
+    }
+
+    #[test]
+    fn change_entries_and_invocation_records_read_back_separately() {
+        let temp = tempfile::tempdir().unwrap();
+        let log = temp.path().join(LOG_FILE);
+        let record = InvocationRecord {
+            timestamp: "2025-11-01T09:00:00Z".into(),
+            invocation: Invocation {
+                id: "one".into(),
+                argv: vec!["normalize".into()],
+                cwd: "/w".into(),
+            },
+        };
+        let mut entry = serde_json::to_value(logged(
+            "normalize",
+            "a.rs",
+            "applied",
+            "2025-11-01T09:00:00Z",
+        ))
+        .unwrap();
+        entry["run"] = "one".into();
+        fs::write(
+            &log,
+            format!("{}\n{entry}\n", serde_json::to_string(&record).unwrap()),
+        )
+        .unwrap();
+        let mut entries: Vec<LoggedEntry> = Vec::new();
+        read_entries(&log, &mut entries).unwrap();
+        let mut records: Vec<InvocationRecord> = Vec::new();
+        read_entries(&log, &mut records).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].run.as_deref(), Some("one"));
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].invocation.argv, ["normalize"]);
     }
-    let keep = &lines[lines.len() - MAX_ENTRIES..];
-    fs::write(path, keep.join("\n") + "\n")?;
-    Ok(())
 }
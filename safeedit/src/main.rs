@@ -1,14 +1,16 @@
 use std::borrow::Cow;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fs;
 use std::io::{self, Read, Write};
 use std::path::{Component, Path, PathBuf};
 use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::SystemTime;
 
 use anyhow::{Context, Result, anyhow, bail};
 use arboard::Clipboard;
 use clap::{ArgAction, Args, Parser, Subcommand, ValueEnum, ValueHint, value_parser};
-use diffy::{Patch as DiffPatch, apply as apply_patch};
+use diffy::Patch as DiffPatch;
 use encoding_rs::Encoding;
 use is_terminal::IsTerminal;
 use serde::{Deserialize, Serialize};
@@ -16,21 +18,49 @@ use serde_json::{Map as JsonMap, Value as JsonValue, json};
 use time::{OffsetDateTime, format_description::well_known::Rfc3339};
 use walkdir::WalkDir;
 
+mod archive;
+mod attributes;
+mod backup;
 mod batch;
+mod binpatch;
 mod commands;
+mod config;
+mod decisions;
+mod detect;
 mod diff;
+mod editor;
 mod encoding;
+mod events;
+mod explain;
+mod fetch;
 mod files;
+mod input;
+mod integrity;
+mod journal;
+mod lock;
 mod logging;
+mod manifest;
 mod normalize;
 mod patch;
+mod policy;
+mod remote;
+mod report;
 mod review;
+mod schema;
+mod server;
+mod session;
+mod stats;
+mod template;
+mod timing;
 mod transform;
+mod wizard;
 use commands::{
-    BlockOptions, BlockTarget, RenameOptions, ReplaceOptions, run_block, run_rename, run_replace,
+    BlockOptions, BlockTarget, MatchCounts, RenameOptions, ReplaceOptions, run_block, run_rename,
+    run_replace,
 };
-use encoding::{DecodedText, EncodingStrategy};
+use encoding::{DecodedText, EncodingStrategy, encode_text};
 use files::{FileEntry, FileMetadata};
+use integrity::Precondition;
 use logging::{LineSpan, LineSpanKind, record_change};
 use patch::{FilePatch, PatchKind, load_file_patches};
 use transform::TransformResult;
@@ -49,7 +79,10 @@ impl ColorChoice {
         match self {
             ColorChoice::Always => true,
             ColorChoice::Never => false,
-            ColorChoice::Auto => io::stdout().is_terminal(),
+            ColorChoice::Auto => {
+                std::env::var_os("NO_COLOR").is_none_or(|value| value.is_empty())
+                    && io::stdout().is_terminal()
+            }
         }
     }
 }
@@ -63,6 +96,15 @@ pub enum PagerMode {
     Never,
 }
 
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, ValueEnum, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum DiffFormat {
+    #[default]
+    Inline,
+    Unified,
+    Json,
+}
+
 #[derive(Clone, Copy, Debug, Deserialize, Serialize, ValueEnum, PartialEq, Eq, Default)]
 #[serde(rename_all = "lowercase")]
 enum LineEndingChoice {
@@ -94,11 +136,84 @@ fn system_default_line_ending() -> LineEndingStyle {
 
 fn main() -> Result<()> {
     let cli = Cli::parse();
+    logging::set_invocation(normalize_invocation(std::env::args_os().skip(1)));
     run(cli)
 }
 
-fn run(cli: Cli) -> Result<()> {
-    match cli.command {
+// Flags whose values carry replacement bodies, template variables, or URLs
+// that may embed credentials; the change log keeps only a placeholder.
+const REDACTED_FLAGS: [&str; 5] = ["--with", "--body", "--with-url", "--body-url", "--var"];
+const REDACTED: &str = "<redacted>";
+
+// `--flag=value` is split into two arguments so recorded invocations compare
+// and edit the same way however they were typed.
+fn normalize_invocation(args: impl Iterator<Item = std::ffi::OsString>) -> Vec<String> {
+    let mut normalized: Vec<String> = Vec::new();
+    for arg in args {
+        let arg = arg.to_string_lossy().into_owned();
+        let redact = normalized
+            .last()
+            .is_some_and(|flag| REDACTED_FLAGS.contains(&flag.as_str()));
+        if redact {
+            normalized.push(REDACTED.to_string());
+            continue;
+        }
+        match arg.split_once('=') {
+            Some((flag, value)) if flag.starts_with("--") && !flag.contains(' ') => {
+                normalized.push(flag.to_string());
+                let redact = REDACTED_FLAGS.contains(&flag);
+                normalized.push(if redact { REDACTED } else { value }.to_string());
+            }
+            _ => normalized.push(arg),
+        }
+    }
+    normalized
+}
+
+fn run(mut cli: Cli) -> Result<()> {
+    logging::configure(cli.log_file, cli.global_log)?;
+    let config = config::Config::load()?;
+    diff::set_palette(config.diff_palette()?);
+    diff::set_default_limits(diff::DiffLimits::resolve(
+        config.limits.max_diff_lines,
+        config.limits.max_diff_bytes,
+    ));
+    let prompt_timeout = match cli.prompt_timeout.as_deref() {
+        Some(raw) if raw.trim().chars().all(|ch| ch.is_ascii_digit()) => {
+            Some(parse_age(&format!("{}s", raw.trim()))?)
+        }
+        Some(raw) => Some(parse_age(raw)?),
+        None => None,
+    };
+    input::configure(
+        cli.no_input,
+        prompt_timeout.map(|limit| (limit, cli.timeout_default)),
+    );
+    if cli.timing {
+        timing::enable();
+    }
+    if cli.explain {
+        explain::enable();
+    }
+    if let Some(path) = cli.manifest.clone() {
+        manifest::set_auto(path);
+    }
+    if cli.fresh {
+        decisions::set_fresh();
+    }
+    let _staging = match command_common(&mut cli.command) {
+        Some(common) => stage_targets(common)?,
+        None => None,
+    };
+    let outcome = dispatch(cli.command);
+    if let Some(report) = timing::report(cli.timing_format) {
+        eprint!("{report}");
+    }
+    outcome
+}
+
+fn dispatch(command: Command) -> Result<()> {
+    match command {
         Command::Replace(cmd) => handle_replace(cmd)?,
         Command::Apply(cmd) => handle_apply(cmd)?,
         Command::Block(cmd) => handle_block(cmd)?,
@@ -106,20 +221,298 @@ fn run(cli: Cli) -> Result<()> {
         Command::Review(cmd) => handle_review(cmd)?,
         Command::Normalize(cmd) => handle_normalize(cmd)?,
         Command::Script(cmd) => handle_script(cmd)?,
-        Command::Batch(cmd) => handle_batch(cmd)?,
-        Command::Log(cmd) => handle_log(cmd)?,
+        Command::Batch(cmd) => match cmd.subcommand {
+            Some(BatchAction::Validate(validate)) => handle_batch_validate(validate)?,
+            None => handle_batch(*cmd)?,
+        },
+        Command::Log(cmd) => match cmd.subcommand {
+            Some(LogAction::Prune(prune)) => handle_log_prune(prune)?,
+            None => handle_log(cmd)?,
+        },
         Command::Report(cmd) => handle_report(cmd)?,
         Command::Cleanup(cmd) => handle_cleanup(cmd)?,
+        Command::Recover(cmd) => handle_recover(cmd)?,
+        Command::Verify(cmd) => handle_verify(cmd)?,
+        Command::Restore(cmd) => handle_restore(cmd)?,
+        Command::Undo(cmd) => handle_undo(cmd)?,
+        Command::Session(cmd) => match cmd.action {
+            SessionAction::List(list) => handle_session_list(list)?,
+            SessionAction::Rollback(rollback) => handle_session_rollback(rollback)?,
+        },
+        Command::Patch(cmd) => match cmd.action {
+            PatchAction::Split(split) => handle_patch_split(split)?,
+            PatchAction::Lint(lint) => handle_patch_lint(lint)?,
+        },
         Command::Write(cmd) => handle_write(cmd)?,
+        Command::Compare(cmd) => handle_compare(cmd)?,
+        Command::Stats(cmd) => handle_stats(cmd)?,
+        Command::Detect(cmd) => handle_detect(cmd)?,
+        Command::Filter(cmd) => handle_filter(cmd)?,
+        Command::Wizard(cmd) => handle_wizard(cmd)?,
+        Command::Serve(cmd) => server::serve(&cmd.http, cmd.allow_remote)?,
+        Command::History(cmd) => handle_history(cmd)?,
+        Command::Rerun(cmd) => handle_rerun(cmd)?,
+        Command::Binpatch(cmd) => handle_binpatch(cmd)?,
+        Command::Checksum(cmd) => handle_checksum(cmd)?,
+        Command::Schema(cmd) => {
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&schema::render(cmd.kind))?
+            )
+        }
+    }
+
+    Ok(())
+}
+
+fn command_common(command: &mut Command) -> Option<&mut CommonArgs> {
+    match command {
+        Command::Replace(cmd) => Some(&mut cmd.common),
+        Command::Block(cmd) => Some(&mut cmd.common),
+        Command::Rename(cmd) => Some(&mut cmd.common),
+        Command::Review(cmd) => Some(&mut cmd.common),
+        Command::Normalize(cmd) => Some(&mut cmd.common),
+        _ => None,
+    }
+}
+
+#[derive(PartialEq)]
+enum StagedSource {
+    Archive(archive::MemberRef),
+    Remote(remote::RemotePath),
+}
+
+impl StagedSource {
+    fn parse(raw: &str, remote: Option<&str>) -> Result<Option<Self>> {
+        if let Some(url) = remote::RemotePath::parse_url(raw)? {
+            return Ok(Some(StagedSource::Remote(url)));
+        }
+        if let Some(destination) = remote {
+            return remote::RemotePath::with_remote(destination, raw)
+                .map(|path| Some(StagedSource::Remote(path)));
+        }
+        if Path::new(raw).exists() {
+            return Ok(None);
+        }
+        Ok(archive::MemberRef::parse(raw).map(StagedSource::Archive))
+    }
+
+    // Reads back a name written by recorded_name, so undo, session rollback,
+    // and verify can reach the source again.
+    fn from_recorded(path: &Path) -> Result<Option<Self>> {
+        match path.to_str() {
+            Some(raw) => Self::parse(raw, None),
+            None => Ok(None),
+        }
+    }
+
+    fn read(&self) -> Result<Vec<u8>> {
+        match self {
+            StagedSource::Archive(member) => member.read(),
+            StagedSource::Remote(path) => path.fetch(),
+        }
+    }
+
+    fn recorded_name(&self) -> PathBuf {
+        match self {
+            StagedSource::Archive(member) => {
+                let mut name = std::path::absolute(&member.archive)
+                    .unwrap_or_else(|_| member.archive.clone())
+                    .into_os_string();
+                name.push("!");
+                name.push(&member.member);
+                name.into()
+            }
+            StagedSource::Remote(remote) => remote.url().into(),
+        }
+    }
+
+    fn staging_path(&self, root: &Path) -> PathBuf {
+        let mut path = root.to_path_buf();
+        match self {
+            StagedSource::Archive(member) => {
+                path.extend(
+                    member
+                        .archive
+                        .components()
+                        .filter(|component| matches!(component, Component::Normal(_))),
+                );
+                path.as_mut_os_string().push("!");
+                path.extend(member.member.split('/'));
+            }
+            StagedSource::Remote(remote) => {
+                path.push(remote.destination.replace(['@', ':'], "_"));
+                // `..` and `.` segments would step outside the staging dir.
+                path.extend(
+                    remote
+                        .path
+                        .split('/')
+                        .filter(|part| !matches!(*part, "" | "." | "..")),
+                );
+            }
+        }
+        path
+    }
+
+    fn write_back(
+        &self,
+        data: &[u8],
+        original: &[u8],
+        backup: &backup::BackupOptions,
+        preserve_metadata: bool,
+    ) -> Result<()> {
+        match self {
+            StagedSource::Archive(member) => {
+                let archive = &member.archive;
+                let _lock = lock::lock_path(archive)?;
+                if member.read()? != original {
+                    bail!(
+                        "{member} changed since it was read; not rewriting {}",
+                        archive.display()
+                    );
+                }
+                let rewritten = member.rewrite(data)?;
+                if let Some(bak) = backup::create(archive, backup)? {
+                    println!("backup saved: {} -> {}", archive.display(), bak.display());
+                }
+                write_via_temp(archive, &rewritten, preserve_metadata)
+                    .with_context(|| format!("writing {}", archive.display()))?;
+            }
+            StagedSource::Remote(remote) => {
+                if remote.fetch()? != original {
+                    bail!(
+                        "{remote} changed on the remote host since it was fetched; not uploading"
+                    );
+                }
+                remote.upload(data)?;
+            }
+        }
+        println!("updated {self}");
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for StagedSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StagedSource::Archive(member) => member.fmt(f),
+            StagedSource::Remote(remote) => remote.fmt(f),
+        }
+    }
+}
+
+struct StagedFile {
+    source: StagedSource,
+    staged: PathBuf,
+    // What the source held when last read or written back.
+    original: Vec<u8>,
+}
+
+static STAGED_FILES: Mutex<Vec<StagedFile>> = Mutex::new(Vec::new());
+
+// Archive members (`bundle.zip!docs/readme.md`) and remote files
+// (`ssh://host/etc/app.conf`, or any target with --remote) are copied to a
+// local staging file so the normal pipeline can preview and edit them; each
+// applied edit goes back to its source through write_back_staged. The staging
+// directory is a fresh owner-only temp dir, so on a shared temp dir nobody
+// else can pre-create it or plant symlinks in it; it sits outside the project
+// so hidden-path filtering and globs never see it, and is removed when the
+// returned handle is dropped.
+fn stage_targets(common: &mut CommonArgs) -> Result<Option<tempfile::TempDir>> {
+    let remote = common.remote.clone();
+    if remote.is_some() && !common.globs.is_empty() {
+        bail!("--glob cannot be combined with --remote; list remote files explicitly");
+    }
+    let mut dir: Option<tempfile::TempDir> = None;
+    let mut staged = Vec::new();
+    let mut stage = |raw: &str| -> Result<Option<PathBuf>> {
+        let Some(source) = StagedSource::parse(raw, remote.as_deref())? else {
+            return Ok(None);
+        };
+        if let Some(existing) = staged
+            .iter()
+            .find(|file: &&StagedFile| file.source == source)
+        {
+            return Ok(Some(existing.staged.clone()));
+        }
+        let root = match &dir {
+            Some(dir) => dir.path(),
+            None => dir
+                .insert(
+                    tempfile::Builder::new()
+                        .prefix("safeedit-stage-")
+                        .tempdir()
+                        .context("creating staging directory")?,
+                )
+                .path(),
+        };
+        let path = source.staging_path(root);
+        if staged.iter().any(|file: &StagedFile| file.staged == path) {
+            bail!("{source} stages to the same file as another target; list it only once");
+        }
+        let original = source.read()?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("creating staging directory {}", parent.display()))?;
+        }
+        fs::write(&path, &original).with_context(|| format!("staging {source}"))?;
+        logging::register_staged(&path, source.recorded_name());
+        staged.push(StagedFile {
+            source,
+            staged: path.clone(),
+            original,
+        });
+        Ok(Some(path))
+    };
+    let mut result = Ok(());
+    for target in &mut common.targets {
+        match target.to_str().map(&mut stage).transpose() {
+            Ok(Some(Some(path))) => *target = path,
+            Ok(_) => {}
+            Err(err) => result = Err(err),
+        }
+    }
+    for arg in &mut common.extra_args {
+        if result.is_err() {
+            break;
+        }
+        match stage(arg) {
+            Ok(Some(path)) => *arg = path.display().to_string(),
+            Ok(None) => {}
+            Err(err) => result = Err(err),
+        }
+    }
+    result?;
+    if let Ok(mut files) = STAGED_FILES.lock() {
+        files.extend(staged);
     }
+    Ok(dir)
+}
 
+// Called before a staging copy is overwritten and before anything records the
+// edit, so the log, session, and events never claim a change its source did
+// not get.
+fn write_back_staged(path: &Path, data: &[u8], options: &WriteOptions) -> Result<()> {
+    let mut files = STAGED_FILES
+        .lock()
+        .map_err(|_| anyhow!("staged file registry poisoned"))?;
+    let Some(file) = files.iter_mut().find(|file| file.staged == path) else {
+        return Ok(());
+    };
+    file.source.write_back(
+        data,
+        &file.original,
+        &options.backup,
+        options.preserve_metadata,
+    )?;
+    file.original = data.to_vec();
     Ok(())
 }
 
 fn handle_replace(cmd: ReplaceCommand) -> Result<()> {
     let colorize = cmd.common.color.should_color();
     let diff_config = cmd.common.diff_display_config(colorize);
-    let entries = resolve_entries(&cmd.common)?;
+    let entries = resolve_edit_entries(&cmd.common)?;
     let encoding = resolve_encoding_strategy(&cmd.common)?;
     let literal_mode = cmd.literal || !cmd.regex;
     let pattern = if literal_mode {
@@ -129,13 +522,30 @@ fn handle_replace(cmd: ReplaceCommand) -> Result<()> {
     };
     let (replacement_text, replacement_source) = resolve_replacement_text(&cmd)?;
     let replacement_len = replacement_text.chars().count();
+    let _decisions = decisions::begin("replace", &recorded_replace_fields(&cmd, &replacement_text));
+    let expand = !literal_mode && !cmd.no_expand;
+    if expand {
+        for warning in commands::template_warnings(&pattern, &replacement_text)? {
+            println!("warning: {warning}");
+        }
+    }
+    if cmd.allow_binary_literal
+        && [&cmd.pattern, &replacement_text]
+            .iter()
+            .any(|text| text.contains(['\n', '\r']))
+    {
+        bail!("--allow-binary-literal needs a single-line pattern and replacement");
+    }
     let replace_options = ReplaceOptions {
         pattern,
         replacement: replacement_text.clone(),
-        allow_captures: !literal_mode,
+        allow_captures: expand,
+        case_aware: cmd.case_aware,
         count: cmd.count,
         expect: cmd.expect,
         after_line: cmd.after_line,
+        skip_if_contains: cmd.skip_if_contains.clone(),
+        quiet: false,
     };
     if cmd.diff_only {
         println!("diff-only mode enabled: changes will not be written even with --apply.");
@@ -151,24 +561,176 @@ fn handle_replace(cmd: ReplaceCommand) -> Result<()> {
             format!("replacement_source={replacement_source}"),
             format!("replacement_length={replacement_len} chars"),
             format!("mode={}", if literal_mode { "literal" } else { "regex" }),
+            format!("expand_captures={expand}"),
+            format!("case_aware={}", cmd.case_aware),
+            format!("allow_binary_literal={}", cmd.allow_binary_literal),
             format!("count={:?}", cmd.count),
             format!("expect={:?}", cmd.expect),
+            format!("total_count={:?}", cmd.total_count),
+            format!("expect_total={:?}", cmd.expect_total),
             format!("after_line={:?}", cmd.after_line),
+            format!("skip_if_contains={:?}", cmd.skip_if_contains),
             format!("diff_only={}", cmd.diff_only),
+            format!("if_hash={}", cmd.if_hash.is_some()),
+            format!("if_unchanged_since={:?}", cmd.if_unchanged_since),
         ],
     );
+    let mut write_options = cmd.common.write_options();
+    write_options.precondition =
+        Precondition::from_args(cmd.if_hash.as_deref(), cmd.if_unchanged_since.as_deref())?;
     let mut apply_all = cmd.common.auto_apply && apply_mode;
     let mut stats = CommandStats::default();
-    for entry in &entries {
-        let Some(result) = run_replace(entry, &encoding, &replace_options)? else {
-            stats.no_op += 1;
+    let mut already_done = HashMap::new();
+    let mut expect_mismatches = HashMap::new();
+    let mut match_counts = HashMap::new();
+    let mut over_total = HashSet::new();
+    let mut failures = FileFailures::new(&cmd.common, "replace");
+    let mut binary_edits = HashMap::new();
+    let mut total_replacements = 0usize;
+    let planned = plan_transforms(&entries, &mut failures, |entry| {
+        if let Some(precondition) = &write_options.precondition {
+            precondition.check(&entry.path)?;
+        }
+        // --total-count shares one budget across files, in target order.
+        let remaining = cmd
+            .total_count
+            .map(|limit| limit.saturating_sub(total_replacements));
+        if remaining == Some(0) {
+            over_total.insert(entry.path.clone());
+            return Ok(None);
+        }
+        let options = ReplaceOptions {
+            count: match (cmd.count, remaining) {
+                (Some(count), Some(remaining)) => Some(count.min(remaining)),
+                (count, remaining) => count.or(remaining),
+            },
+            ..replace_options.clone()
+        };
+        if cmd.allow_binary_literal && entry.metadata.is_probably_binary {
+            let data = fs::read(&entry.path)
+                .with_context(|| format!("reading {}", entry.path.display()))?;
+            if let Some(marker) = cmd.skip_if_contains.as_deref()
+                && data
+                    .windows(marker.len().max(1))
+                    .any(|window| window == marker.as_bytes())
+            {
+                already_done.insert(entry.path.clone(), format!("already contains \"{marker}\""));
+                return Ok(None);
+            }
+            let edit = binpatch::replace_literal(
+                data,
+                cmd.pattern.as_bytes(),
+                replacement_text.as_bytes(),
+                options.count,
+            );
+            if let Some(edit) = edit {
+                total_replacements += edit.occurrences;
+                if edit.patched == edit.original {
+                    already_done.insert(
+                        entry.path.clone(),
+                        "replacement reproduces the current bytes".to_string(),
+                    );
+                } else {
+                    binary_edits.insert(entry.path.clone(), edit);
+                }
+            }
+            return Ok(None);
+        }
+        let run = run_replace(entry, &encoding, &options)?;
+        total_replacements += run.counts.occurrences;
+        match_counts.insert(entry.path.clone(), run.counts);
+        if let Some(reason) = run.already_done {
+            already_done.insert(entry.path.clone(), reason);
+        }
+        if let Some(mismatch) = run.expect_mismatch {
+            expect_mismatches.insert(entry.path.clone(), mismatch);
+        }
+        Ok(run.result)
+    })?;
+    if let Some(limit) = cmd.total_count
+        && !over_total.is_empty()
+    {
+        println!(
+            "total-count limit of {limit} reached; {} remaining file(s) left untouched",
+            over_total.len()
+        );
+    }
+    if let Some(expected) = cmd.expect_total
+        && total_replacements != expected
+    {
+        bail!("expected {expected} replacements in total but found {total_replacements}");
+    }
+    print_planned_stat(&entries, &planned, colorize);
+    for (entry, result) in entries.iter().zip(planned) {
+        if failures.failed(&entry.path) {
+            continue;
+        }
+        if let Some(edit) = binary_edits.remove(&entry.path) {
+            let stop = review_binary_replace(
+                &cmd.common,
+                entry,
+                &edit,
+                apply_mode,
+                &mut apply_all,
+                &mut stats,
+                &write_options,
+            );
+            if failures.check(&entry.path, stop)?.unwrap_or(false) {
+                break;
+            }
+            continue;
+        }
+        if let Some(mismatch) = expect_mismatches.get(&entry.path) {
+            let summary = mismatch.describe();
+            println!("expect mismatch: {} ({summary})", entry.path.display());
+            stats.expect_mismatch += 1;
+            let mut extra = status_extra(false, !apply_mode);
+            extra.insert("expected".into(), json!(mismatch.expected));
+            extra.insert("found".into(), json!(mismatch.found));
+            extra.insert(
+                "locations".into(),
+                JsonValue::Array(
+                    mismatch
+                        .locations
+                        .iter()
+                        .map(|(line, column)| json!({ "line": line, "column": column }))
+                        .collect(),
+                ),
+            );
+            log_change(
+                &cmd.common,
+                "replace",
+                &entry.path,
+                "expect-mismatch",
+                &summary,
+                &[],
+                Some(extra),
+            );
+            continue;
+        }
+        let Some(result) = result else {
+            let (status, summary) = match already_done.get(&entry.path) {
+                Some(reason) => {
+                    println!("already done: {} ({reason})", entry.path.display());
+                    stats.already_done += 1;
+                    ("already-done", reason.as_str())
+                }
+                None if over_total.contains(&entry.path) => {
+                    stats.no_op += 1;
+                    ("no-op", "total-count limit reached")
+                }
+                None => {
+                    stats.no_op += 1;
+                    ("no-op", "no matches")
+                }
+            };
             if apply_mode {
                 log_change(
                     &cmd.common,
                     "replace",
                     &entry.path,
-                    "no-op",
-                    "no matches",
+                    status,
+                    summary,
                     &[],
                     Some(status_extra(false, false)),
                 );
@@ -177,8 +739,8 @@ fn handle_replace(cmd: ReplaceCommand) -> Result<()> {
                     &cmd.common,
                     "replace",
                     &entry.path,
-                    "no-op",
-                    "no matches",
+                    status,
+                    summary,
                     &[],
                     Some(status_extra(false, true)),
                 );
@@ -188,8 +750,25 @@ fn handle_replace(cmd: ReplaceCommand) -> Result<()> {
 
         let line_summary = diff::summarize_lines(&result.decoded.text, &result.new_text);
         let line_spans = diff::collect_line_spans(&result.decoded.text, &result.new_text);
-        println!("--- preview: {} ---", entry.path.display());
-        diff::display_diff(&result.decoded.text, &result.new_text, &diff_config)?;
+        let counts = match_counts.get(&entry.path).copied().unwrap_or_default();
+        println!(
+            "--- preview: {} ({}) ---",
+            entry.path.display(),
+            counts.describe()
+        );
+        stage_json_diff(
+            &cmd.common,
+            &entry.path,
+            &result.decoded.text,
+            &result.new_text,
+        );
+        diff::display_diff(
+            &entry.path,
+            &entry.path,
+            &result.decoded.text,
+            &result.new_text,
+            &diff_config,
+        )?;
 
         if !apply_mode {
             stats.dry_run += 1;
@@ -198,7 +777,13 @@ fn handle_replace(cmd: ReplaceCommand) -> Result<()> {
             } else {
                 println!("dry-run: rerun with --apply to write this change.");
             }
-            let mut extra = status_extra(false, true);
+            export_preview(
+                &cmd.common,
+                &entry.path,
+                &result.decoded.text,
+                &result.new_text,
+            )?;
+            let mut extra = with_match_counts(status_extra(false, true), &counts);
             if cmd.diff_only {
                 extra.insert("diff_only".into(), JsonValue::Bool(true));
             }
@@ -217,18 +802,18 @@ fn handle_replace(cmd: ReplaceCommand) -> Result<()> {
         let decision = if apply_all {
             ApprovalDecision::Apply
         } else {
-            prompt_approval(&entry.path)?
+            approve_change(&cmd.common, &entry.path)?
         };
 
         match decision {
             ApprovalDecision::Apply => {
-                apply_transform(
-                    entry,
-                    &result,
-                    None,
-                    cmd.common.undo_log.as_deref(),
-                    cmd.common.no_backup,
-                )?;
+                let Some(()) = failures.check(
+                    &entry.path,
+                    apply_transform(entry, &result, None, &write_options),
+                )?
+                else {
+                    continue;
+                };
                 stats.applied += 1;
                 log_change(
                     &cmd.common,
@@ -237,18 +822,18 @@ fn handle_replace(cmd: ReplaceCommand) -> Result<()> {
                     "applied",
                     &line_summary,
                     &line_spans,
-                    Some(status_extra(true, false)),
+                    Some(with_match_counts(status_extra(true, false), &counts)),
                 );
             }
             ApprovalDecision::ApplyAll => {
                 apply_all = true;
-                apply_transform(
-                    entry,
-                    &result,
-                    None,
-                    cmd.common.undo_log.as_deref(),
-                    cmd.common.no_backup,
-                )?;
+                let Some(()) = failures.check(
+                    &entry.path,
+                    apply_transform(entry, &result, None, &write_options),
+                )?
+                else {
+                    continue;
+                };
                 stats.applied += 1;
                 log_change(
                     &cmd.common,
@@ -257,7 +842,7 @@ fn handle_replace(cmd: ReplaceCommand) -> Result<()> {
                     "applied",
                     &line_summary,
                     &line_spans,
-                    Some(status_extra(true, false)),
+                    Some(with_match_counts(status_extra(true, false), &counts)),
                 );
             }
             ApprovalDecision::Skip => {
@@ -270,7 +855,7 @@ fn handle_replace(cmd: ReplaceCommand) -> Result<()> {
                     "skipped",
                     &line_summary,
                     &line_spans,
-                    Some(status_extra(false, false)),
+                    Some(with_match_counts(status_extra(false, false), &counts)),
                 );
             }
             ApprovalDecision::Quit => {
@@ -281,9 +866,134 @@ fn handle_replace(cmd: ReplaceCommand) -> Result<()> {
         }
     }
     stats.print("replace");
+    record_plan_step(
+        cmd.record.as_ref(),
+        &cmd.common,
+        "replace",
+        recorded_replace_fields(&cmd, &replacement_text),
+    )?;
+    failures.finish()?;
+    if !expect_mismatches.is_empty() {
+        bail!(
+            "--expect mismatched in {} file(s); the other files were processed",
+            expect_mismatches.len()
+        );
+    }
     Ok(())
 }
 
+// Binary files edited by `--allow-binary-literal` skip the text pipeline: the
+// preview is a hex dump and the write goes out byte-for-byte.
+fn review_binary_replace(
+    common: &CommonArgs,
+    entry: &FileEntry,
+    edit: &binpatch::LiteralReplace,
+    apply_mode: bool,
+    apply_all: &mut bool,
+    stats: &mut CommandStats,
+    write_options: &WriteOptions,
+) -> Result<bool> {
+    let summary = format!("{} binary literal replacement(s)", edit.occurrences);
+    println!(
+        "--- preview: {} (binary, {summary}) ---",
+        entry.path.display()
+    );
+    for line in binpatch::preview(
+        &edit.original,
+        &edit.patched,
+        1,
+        common.color.should_color(),
+    ) {
+        println!("{line}");
+    }
+    if !apply_mode {
+        stats.dry_run += 1;
+        println!("dry-run: rerun with --apply to write this change.");
+        log_change(
+            common,
+            "replace",
+            &entry.path,
+            "dry-run",
+            &summary,
+            &[],
+            Some(status_extra(false, true)),
+        );
+        return Ok(false);
+    }
+    let decision = if *apply_all {
+        ApprovalDecision::Apply
+    } else {
+        approve_change(common, &entry.path)?
+    };
+    match decision {
+        ApprovalDecision::Apply | ApprovalDecision::ApplyAll => {
+            *apply_all |= matches!(decision, ApprovalDecision::ApplyAll);
+            write_binary(&entry.path, &edit.original, &edit.patched, write_options)?;
+            stats.applied += 1;
+            log_change(
+                common,
+                "replace",
+                &entry.path,
+                "applied",
+                &summary,
+                &[],
+                Some(status_extra(true, false)),
+            );
+            Ok(false)
+        }
+        ApprovalDecision::Skip => {
+            println!("skipped {}", entry.path.display());
+            stats.skipped += 1;
+            log_change(
+                common,
+                "replace",
+                &entry.path,
+                "skipped",
+                &summary,
+                &[],
+                Some(status_extra(false, false)),
+            );
+            Ok(false)
+        }
+        ApprovalDecision::Quit => {
+            println!("stopping after user request.");
+            stats.skipped += 1;
+            Ok(true)
+        }
+    }
+}
+
+fn recorded_replace_fields(
+    cmd: &ReplaceCommand,
+    replacement_text: &str,
+) -> Vec<(&'static str, JsonValue)> {
+    let mut fields = vec![
+        ("pattern", json!(cmd.pattern)),
+        ("replacement", json!(replacement_text)),
+    ];
+    push_flag(&mut fields, "regex", cmd.regex);
+    push_flag(&mut fields, "literal", cmd.literal);
+    push_flag(&mut fields, "no_expand", cmd.no_expand);
+    push_flag(&mut fields, "case_aware", cmd.case_aware);
+    push_flag(
+        &mut fields,
+        "allow_binary_literal",
+        cmd.allow_binary_literal,
+    );
+    push_flag(&mut fields, "diff_only", cmd.diff_only);
+    push_option(&mut fields, "count", cmd.count);
+    push_option(&mut fields, "expect", cmd.expect);
+    push_option(&mut fields, "total_count", cmd.total_count);
+    push_option(&mut fields, "expect_total", cmd.expect_total);
+    push_option(&mut fields, "after_line", cmd.after_line);
+    push_option(
+        &mut fields,
+        "skip_if_contains",
+        cmd.skip_if_contains.clone(),
+    );
+    fields
+}
+
 fn handle_apply(cmd: ApplyCommand) -> Result<()> {
     let colorize = cmd.common.color.should_color();
     let diff_config = cmd.common.diff_display_config(colorize);
@@ -338,6 +1048,7 @@ fn handle_apply(cmd: ApplyCommand) -> Result<()> {
                     fs::read(&path).with_context(|| format!("reading {}", path.display()))?;
                 let file_len = bytes.len() as u64;
                 let decoded = encoding.decode(&bytes);
+                explain::note_decoded(&path, &decoded);
                 let parsed_patch = DiffPatch::from_str(&work.patch.patch_text).map_err(|err| {
                     anyhow!(
                         "failed to re-parse patch {}#{} during apply: {err}",
@@ -345,15 +1056,20 @@ fn handle_apply(cmd: ApplyCommand) -> Result<()> {
                         work.patch.index
                     )
                 })?;
-                let patched = apply_patch_preserving_newlines(&decoded.text, &parsed_patch)
-                    .map_err(|err| {
-                        anyhow!(
-                            "failed to apply patch {}#{} to {}: {err}",
-                            work.patch.source.display(),
-                            work.patch.index,
-                            path.display()
-                        )
-                    })?;
+                let (patched, offsets) = apply_patch_preserving_newlines(
+                    &decoded.text,
+                    &parsed_patch,
+                    cmd.offset_window,
+                )
+                .map_err(|err| {
+                    anyhow!(
+                        "failed to apply patch {}#{} to {}: {err}",
+                        work.patch.source.display(),
+                        work.patch.index,
+                        path.display()
+                    )
+                })?;
+                report_hunk_offsets(&work, &offsets);
 
                 let entry = FileEntry {
                     path: path.clone(),
@@ -397,10 +1113,18 @@ fn handle_apply(cmd: ApplyCommand) -> Result<()> {
                     continue;
                 }
 
-                diff::display_diff(&decoded.text, &patched, &diff_config)?;
-                let result = TransformResult {
+                stage_json_diff(&cmd.common, &entry.path, &decoded.text, &patched);
+                diff::display_diff(
+                    &entry.path,
+                    &entry.path,
+                    &decoded.text,
+                    &patched,
+                    &diff_config,
+                )?;
+                let result = TransformResult {
                     decoded,
                     new_text: patched,
+                    source_hash: Some(integrity::sha256_hex(&bytes)),
                 };
                 let line_summary = diff::summarize_lines(&result.decoded.text, &result.new_text);
                 let line_spans = diff::collect_line_spans(&result.decoded.text, &result.new_text);
@@ -408,6 +1132,12 @@ fn handle_apply(cmd: ApplyCommand) -> Result<()> {
                 if !apply_mode {
                     stats.dry_run += 1;
                     println!("dry-run: rerun with --apply to write this change.");
+                    export_preview(
+                        &cmd.common,
+                        &entry.path,
+                        &result.decoded.text,
+                        &result.new_text,
+                    )?;
                     log_change(
                         &cmd.common,
                         "apply",
@@ -423,18 +1153,12 @@ fn handle_apply(cmd: ApplyCommand) -> Result<()> {
                 let decision = if apply_all {
                     ApprovalDecision::Apply
                 } else {
-                    prompt_approval(&entry.path)?
+                    approve_change(&cmd.common, &entry.path)?
                 };
 
                 match decision {
                     ApprovalDecision::Apply => {
-                        apply_transform(
-                            &entry,
-                            &result,
-                            None,
-                            cmd.common.undo_log.as_deref(),
-                            cmd.common.no_backup,
-                        )?;
+                        apply_transform(&entry, &result, None, &cmd.common.write_options())?;
                         stats.applied += 1;
                         log_change(
                             &cmd.common,
@@ -448,13 +1172,7 @@ fn handle_apply(cmd: ApplyCommand) -> Result<()> {
                     }
                     ApprovalDecision::ApplyAll => {
                         apply_all = true;
-                        apply_transform(
-                            &entry,
-                            &result,
-                            None,
-                            cmd.common.undo_log.as_deref(),
-                            cmd.common.no_backup,
-                        )?;
+                        apply_transform(&entry, &result, None, &cmd.common.write_options())?;
                         stats.applied += 1;
                         log_change(
                             &cmd.common,
@@ -506,8 +1224,9 @@ fn handle_apply(cmd: ApplyCommand) -> Result<()> {
                     )
                 })?;
                 let base_text = String::new();
-                let new_text =
-                    apply_patch_preserving_newlines(&base_text, &parsed_patch).map_err(|err| {
+                let (new_text, _) =
+                    apply_patch_preserving_newlines(&base_text, &parsed_patch, cmd.offset_window)
+                        .map_err(|err| {
                         anyhow!(
                             "failed to apply patch {}#{} for new file {}: {err}",
                             work.patch.source.display(),
@@ -536,12 +1255,13 @@ fn handle_apply(cmd: ApplyCommand) -> Result<()> {
                     );
                     continue;
                 }
-                diff::display_diff(&base_text, &new_text, &diff_config)?;
+                stage_json_diff(&cmd.common, &path, &base_text, &new_text);
+                diff::display_diff(&path, &path, &base_text, &new_text, &diff_config)?;
                 let decision = if apply_mode {
                     if apply_all {
                         ApprovalDecision::Apply
                     } else {
-                        prompt_approval(&path)?
+                        approve_change(&cmd.common, &path)?
                     }
                 } else {
                     ApprovalDecision::Skip
@@ -564,13 +1284,7 @@ fn handle_apply(cmd: ApplyCommand) -> Result<()> {
                 }
                 match decision {
                     ApprovalDecision::Apply => {
-                        write_new_file(
-                            &path,
-                            &new_text,
-                            &encoding,
-                            cmd.common.undo_log.as_deref(),
-                            cmd.common.no_backup,
-                        )?;
+                        write_new_file(&path, &new_text, &encoding, &cmd.common.write_options())?;
                         stats.applied += 1;
                         log_change(
                             &cmd.common,
@@ -584,13 +1298,7 @@ fn handle_apply(cmd: ApplyCommand) -> Result<()> {
                     }
                     ApprovalDecision::ApplyAll => {
                         apply_all = true;
-                        write_new_file(
-                            &path,
-                            &new_text,
-                            &encoding,
-                            cmd.common.undo_log.as_deref(),
-                            cmd.common.no_backup,
-                        )?;
+                        write_new_file(&path, &new_text, &encoding, &cmd.common.write_options())?;
                         stats.applied += 1;
                         log_change(
                             &cmd.common,
@@ -631,6 +1339,7 @@ fn handle_apply(cmd: ApplyCommand) -> Result<()> {
                 let bytes =
                     fs::read(&path).with_context(|| format!("reading {}", path.display()))?;
                 let decoded = encoding.decode(&bytes);
+                explain::note_decoded(&path, &decoded);
                 let parsed_patch = DiffPatch::from_str(&work.patch.patch_text).map_err(|err| {
                     anyhow!(
                         "failed to re-parse patch {}#{} during apply: {err}",
@@ -638,15 +1347,20 @@ fn handle_apply(cmd: ApplyCommand) -> Result<()> {
                         work.patch.index
                     )
                 })?;
-                let new_text = apply_patch_preserving_newlines(&decoded.text, &parsed_patch)
-                    .map_err(|err| {
-                        anyhow!(
-                            "failed to apply delete patch {}#{} to {}: {err}",
-                            work.patch.source.display(),
-                            work.patch.index,
-                            path.display()
-                        )
-                    })?;
+                let (new_text, offsets) = apply_patch_preserving_newlines(
+                    &decoded.text,
+                    &parsed_patch,
+                    cmd.offset_window,
+                )
+                .map_err(|err| {
+                    anyhow!(
+                        "failed to apply delete patch {}#{} to {}: {err}",
+                        work.patch.source.display(),
+                        work.patch.index,
+                        path.display()
+                    )
+                })?;
+                report_hunk_offsets(&work, &offsets);
                 if !new_text.is_empty() {
                     bail!(
                         "delete patch {}#{} for {} did not result in empty content",
@@ -655,7 +1369,8 @@ fn handle_apply(cmd: ApplyCommand) -> Result<()> {
                         path.display()
                     );
                 }
-                diff::display_diff(&decoded.text, &new_text, &diff_config)?;
+                stage_json_diff(&cmd.common, &path, &decoded.text, &new_text);
+                diff::display_diff(&path, &path, &decoded.text, &new_text, &diff_config)?;
                 let line_summary = diff::summarize_lines(&decoded.text, &new_text);
                 let line_spans = diff::collect_line_spans(&decoded.text, &new_text);
                 if !apply_mode {
@@ -675,16 +1390,11 @@ fn handle_apply(cmd: ApplyCommand) -> Result<()> {
                 let decision = if apply_all {
                     ApprovalDecision::Apply
                 } else {
-                    prompt_approval(&path)?
+                    approve_change(&cmd.common, &path)?
                 };
                 match decision {
                     ApprovalDecision::Apply => {
-                        delete_file_with_undo(
-                            &path,
-                            &decoded.text,
-                            cmd.common.undo_log.as_deref(),
-                            cmd.common.no_backup,
-                        )?;
+                        delete_file_with_undo(&path, &decoded.text, &cmd.common.write_options())?;
                         stats.applied += 1;
                         log_change(
                             &cmd.common,
@@ -698,12 +1408,7 @@ fn handle_apply(cmd: ApplyCommand) -> Result<()> {
                     }
                     ApprovalDecision::ApplyAll => {
                         apply_all = true;
-                        delete_file_with_undo(
-                            &path,
-                            &decoded.text,
-                            cmd.common.undo_log.as_deref(),
-                            cmd.common.no_backup,
-                        )?;
+                        delete_file_with_undo(&path, &decoded.text, &cmd.common.write_options())?;
                         stats.applied += 1;
                         log_change(
                             &cmd.common,
@@ -755,6 +1460,7 @@ fn handle_apply(cmd: ApplyCommand) -> Result<()> {
                 let bytes = fs::read(&old_path)
                     .with_context(|| format!("reading {}", old_path.display()))?;
                 let decoded = encoding.decode(&bytes);
+                explain::note_decoded(&new_path, &decoded);
                 let parsed_patch = DiffPatch::from_str(&work.patch.patch_text).map_err(|err| {
                     anyhow!(
                         "failed to re-parse patch {}#{} during apply: {err}",
@@ -762,19 +1468,31 @@ fn handle_apply(cmd: ApplyCommand) -> Result<()> {
                         work.patch.index
                     )
                 })?;
-                let new_text = apply_patch_preserving_newlines(&decoded.text, &parsed_patch)
-                    .map_err(|err| {
-                        anyhow!(
-                            "failed to apply rename patch {}#{} for {} -> {}: {err}",
-                            work.patch.source.display(),
-                            work.patch.index,
-                            old_path.display(),
-                            new_path.display()
-                        )
-                    })?;
+                let (new_text, offsets) = apply_patch_preserving_newlines(
+                    &decoded.text,
+                    &parsed_patch,
+                    cmd.offset_window,
+                )
+                .map_err(|err| {
+                    anyhow!(
+                        "failed to apply rename patch {}#{} for {} -> {}: {err}",
+                        work.patch.source.display(),
+                        work.patch.index,
+                        old_path.display(),
+                        new_path.display()
+                    )
+                })?;
+                report_hunk_offsets(&work, &offsets);
                 let content_changed = new_text != decoded.text;
                 if content_changed {
-                    diff::display_diff(&decoded.text, &new_text, &diff_config)?;
+                    stage_json_diff(&cmd.common, &new_path, &decoded.text, &new_text);
+                    diff::display_diff(
+                        &old_path,
+                        &new_path,
+                        &decoded.text,
+                        &new_text,
+                        &diff_config,
+                    )?;
                 } else {
                     println!("(rename only; no textual diff)");
                 }
@@ -807,7 +1525,7 @@ fn handle_apply(cmd: ApplyCommand) -> Result<()> {
                 let decision = if apply_all {
                     ApprovalDecision::Apply
                 } else {
-                    prompt_approval(&new_path)?
+                    approve_change(&cmd.common, &new_path)?
                 };
 
                 match decision {
@@ -823,19 +1541,18 @@ fn handle_apply(cmd: ApplyCommand) -> Result<()> {
                         let result = TransformResult {
                             decoded: decoded_for_dest.clone(),
                             new_text: new_text.clone(),
+                            source_hash: None,
                         };
                         apply_transform(
                             &dest_entry,
                             &result,
                             Some(decoded_for_dest.decision.encoding),
-                            cmd.common.undo_log.as_deref(),
-                            cmd.common.no_backup,
+                            &cmd.common.write_options(),
                         )?;
                         delete_file_with_undo(
                             &old_path,
                             &decoded.text,
-                            cmd.common.undo_log.as_deref(),
-                            cmd.common.no_backup,
+                            &cmd.common.write_options(),
                         )?;
                         stats.applied += 1;
                         log_change(
@@ -870,19 +1587,18 @@ fn handle_apply(cmd: ApplyCommand) -> Result<()> {
                         let result = TransformResult {
                             decoded: decoded_for_dest.clone(),
                             new_text: new_text.clone(),
+                            source_hash: None,
                         };
                         apply_transform(
                             &dest_entry,
                             &result,
                             Some(decoded_for_dest.decision.encoding),
-                            cmd.common.undo_log.as_deref(),
-                            cmd.common.no_backup,
+                            &cmd.common.write_options(),
                         )?;
                         delete_file_with_undo(
                             &old_path,
                             &decoded.text,
-                            cmd.common.undo_log.as_deref(),
-                            cmd.common.no_backup,
+                            &cmd.common.write_options(),
                         )?;
                         stats.applied += 1;
                         log_change(
@@ -1021,7 +1737,13 @@ fn resolve_replacement_text(cmd: &ReplaceCommand) -> Result<(String, &'static st
         let text = read_heredoc_input(tag, "replacement")?;
         return Ok((text, "heredoc"));
     }
-    bail!("replacement text required; use --with, --with-stdin, --with-clipboard, or --with-here");
+    if let Some(url) = &cmd.with_url {
+        let text = fetch::fetch_text(url, &cmd.url.options())?;
+        return Ok((text, "url"));
+    }
+    bail!(
+        "replacement text required; use --with, --with-stdin, --with-clipboard, --with-here, or --with-url"
+    );
 }
 
 fn resolve_body_from_sources(
@@ -1030,6 +1752,7 @@ fn resolve_body_from_sources(
     with_stdin: bool,
     with_clipboard: bool,
     heredoc_tag: &Option<String>,
+    body_url: Option<(&str, &UrlFetchArgs)>,
     description: &str,
 ) -> Result<(String, &'static str)> {
     if !literal_lines.is_empty() {
@@ -1053,16 +1776,31 @@ fn resolve_body_from_sources(
         let text = read_replacement_from_clipboard()?;
         return Ok((text, "clipboard"));
     }
-    bail!("{description} required; use --body, --body-file, --with-stdin, or --with-clipboard");
+    if let Some((url, fetch_args)) = body_url {
+        let text = fetch::fetch_text(url, &fetch_args.options())?;
+        return Ok((text, "url"));
+    }
+    bail!(
+        "{description} required; use --body, --body-file, --with-stdin, --with-clipboard, --body-url, or --body-edit"
+    );
 }
 
 fn resolve_block_body(cmd: &BlockCommand) -> Result<(String, &'static str)> {
+    if cmd.body_edit {
+        let target = cmd
+            .common
+            .targets
+            .first()
+            .map_or(Path::new("block"), PathBuf::as_path);
+        return Ok((editor::edit_text(target, "block body")?, "editor"));
+    }
     resolve_body_from_sources(
         &cmd.body,
         &cmd.body_file,
         cmd.with_stdin,
         cmd.with_clipboard,
         &cmd.body_here,
+        cmd.body_url.as_deref().map(|url| (url, &cmd.url)),
         "block body",
     )
 }
@@ -1089,13 +1827,9 @@ fn read_heredoc_input(tag: &str, description: &str) -> Result<String> {
     println!();
     println!("Enter {description} below. Finish with a line containing only {tag}.");
     let mut buf = String::new();
-    let mut line = String::new();
     loop {
-        line.clear();
-        let bytes = io::stdin()
-            .read_line(&mut line)
-            .context("reading heredoc input")?;
-        if bytes == 0 {
+        let line = input::read_line().context("reading heredoc input")?;
+        if line.is_empty() {
             bail!("stdin closed before heredoc terminator '{tag}'");
         }
         let trimmed = line.trim_end_matches(['\r', '\n']);
@@ -1110,13 +1844,14 @@ fn read_heredoc_input(tag: &str, description: &str) -> Result<String> {
 fn handle_block(cmd: BlockCommand) -> Result<()> {
     let colorize = cmd.common.color.should_color();
     let diff_config = cmd.common.diff_display_config(colorize);
-    let entries = resolve_entries(&cmd.common)?;
+    let entries = resolve_edit_entries(&cmd.common)?;
     let encoding = resolve_encoding_strategy(&cmd.common)?;
     let target = cmd.build_target()?;
     let mode = cmd.resolve_mode(&target)?;
     let target_summary = target.describe();
     let expect_blocks = cmd.expect_blocks;
     let (body_text, body_source) = resolve_block_body(&cmd)?;
+    let _decisions = decisions::begin("block", &recorded_block_fields(&cmd, &body_text));
     let mut details = vec![
         format!("target={target_summary}"),
         format!("mode={mode:?}"),
@@ -1140,8 +1875,48 @@ fn handle_block(cmd: BlockCommand) -> Result<()> {
     let apply_mode = cmd.common.apply;
     let mut apply_all = cmd.common.auto_apply && apply_mode;
     let mut stats = CommandStats::default();
-    for entry in &entries {
-        let Some(result) = run_block(entry, &encoding, &options)? else {
+    let mut failures = FileFailures::new(&cmd.common, "block");
+    let mut markers_missing = HashMap::new();
+    let planned = plan_transforms(&entries, &mut failures, |entry| {
+        let run = run_block(entry, &encoding, &options)?;
+        if let Some(reason) = run.markers_missing {
+            markers_missing.insert(entry.path.clone(), reason);
+        }
+        Ok(run.result)
+    })?;
+    // A single file (or a glob where nothing matches) still fails the way it
+    // always has; otherwise marker-less files are only reported.
+    if !entries.is_empty() && markers_missing.len() == entries.len() {
+        let reason = &markers_missing[&entries[0].path];
+        if entries.len() == 1 {
+            bail!("{reason}");
+        }
+        bail!(
+            "none of the {} file(s) contain the block markers ({}: {reason})",
+            entries.len(),
+            entries[0].path.display()
+        );
+    }
+    print_planned_stat(&entries, &planned, colorize);
+    for (entry, result) in entries.iter().zip(planned) {
+        if failures.failed(&entry.path) {
+            continue;
+        }
+        if let Some(reason) = markers_missing.get(&entry.path) {
+            println!("markers missing: {} ({reason})", entry.path.display());
+            stats.markers_missing += 1;
+            log_change(
+                &cmd.common,
+                "block",
+                &entry.path,
+                "markers-missing",
+                reason,
+                &[],
+                Some(status_extra(false, !apply_mode)),
+            );
+            continue;
+        }
+        let Some(result) = result else {
             stats.no_op += 1;
             log_change(
                 &cmd.common,
@@ -1158,11 +1933,29 @@ fn handle_block(cmd: BlockCommand) -> Result<()> {
         let line_summary = diff::summarize_lines(&result.decoded.text, &result.new_text);
         let line_spans = diff::collect_line_spans(&result.decoded.text, &result.new_text);
         println!("--- preview: {} ---", entry.path.display());
-        diff::display_diff(&result.decoded.text, &result.new_text, &diff_config)?;
+        stage_json_diff(
+            &cmd.common,
+            &entry.path,
+            &result.decoded.text,
+            &result.new_text,
+        );
+        diff::display_diff(
+            &entry.path,
+            &entry.path,
+            &result.decoded.text,
+            &result.new_text,
+            &diff_config,
+        )?;
 
         if !apply_mode {
             stats.dry_run += 1;
             println!("dry-run: rerun with --apply to write this change.");
+            export_preview(
+                &cmd.common,
+                &entry.path,
+                &result.decoded.text,
+                &result.new_text,
+            )?;
             log_change(
                 &cmd.common,
                 "block",
@@ -1178,18 +1971,18 @@ fn handle_block(cmd: BlockCommand) -> Result<()> {
         let decision = if apply_all {
             ApprovalDecision::Apply
         } else {
-            prompt_approval(&entry.path)?
+            approve_change(&cmd.common, &entry.path)?
         };
 
         match decision {
             ApprovalDecision::Apply => {
-                apply_transform(
-                    entry,
-                    &result,
-                    None,
-                    cmd.common.undo_log.as_deref(),
-                    cmd.common.no_backup,
-                )?;
+                let Some(()) = failures.check(
+                    &entry.path,
+                    apply_transform(entry, &result, None, &cmd.common.write_options()),
+                )?
+                else {
+                    continue;
+                };
                 stats.applied += 1;
                 log_change(
                     &cmd.common,
@@ -1203,13 +1996,13 @@ fn handle_block(cmd: BlockCommand) -> Result<()> {
             }
             ApprovalDecision::ApplyAll => {
                 apply_all = true;
-                apply_transform(
-                    entry,
-                    &result,
-                    None,
-                    cmd.common.undo_log.as_deref(),
-                    cmd.common.no_backup,
-                )?;
+                let Some(()) = failures.check(
+                    &entry.path,
+                    apply_transform(entry, &result, None, &cmd.common.write_options()),
+                )?
+                else {
+                    continue;
+                };
                 stats.applied += 1;
                 log_change(
                     &cmd.common,
@@ -1242,24 +2035,88 @@ fn handle_block(cmd: BlockCommand) -> Result<()> {
         }
     }
     stats.print("block");
-    Ok(())
+    record_plan_step(
+        cmd.record.as_ref(),
+        &cmd.common,
+        "block",
+        recorded_block_fields(&cmd, &options.body),
+    )?;
+    failures.finish()
+}
+
+fn recorded_block_fields(cmd: &BlockCommand, body: &str) -> Vec<(&'static str, JsonValue)> {
+    let mut fields = Vec::new();
+    push_option(&mut fields, "start_marker", cmd.start_marker.as_ref());
+    push_option(&mut fields, "end_marker", cmd.end_marker.as_ref());
+    push_option(&mut fields, "insert_after", cmd.insert_after.as_ref());
+    push_option(&mut fields, "insert_before", cmd.insert_before.as_ref());
+    push_option(
+        &mut fields,
+        "mode",
+        cmd.mode.map(|mode| format!("{mode:?}").to_lowercase()),
+    );
+    fields.push(("body", json!(body)));
+    push_option(&mut fields, "expect_blocks", cmd.expect_blocks);
+    push_flag(
+        &mut fields,
+        "allow_marker_overlap",
+        cmd.allow_marker_overlap,
+    );
+    fields
 }
 
 fn handle_write(cmd: WriteCommand) -> Result<()> {
     let colorize = cmd.common.color.should_color();
     let diff_config = cmd.common.diff_display_config(colorize);
     let encoding = resolve_encoding_strategy(&cmd.common)?;
-    let (body_text, body_source) = resolve_body_from_sources(
-        &cmd.body,
-        &cmd.body_file,
-        cmd.with_stdin,
-        cmd.with_clipboard,
-        &cmd.body_here,
-        "write body",
-    )?;
+    let (body_text, body_source) = if let Some(template_path) = &cmd.template {
+        let mut vars = BTreeMap::new();
+        for spec in &cmd.vars {
+            let (key, value) = template::parse_var(spec)?;
+            vars.insert(key, value);
+        }
+        let raw = fs::read_to_string(template_path)
+            .with_context(|| format!("reading template {}", template_path.display()))?;
+        let rendered = template::render(&raw, &vars)
+            .with_context(|| format!("rendering template {}", template_path.display()))?;
+        (rendered, "template")
+    } else if cmd.body_edit {
+        (editor::edit_text(&cmd.path, "write body")?, "editor")
+    } else {
+        resolve_body_from_sources(
+            &cmd.body,
+            &cmd.body_file,
+            cmd.with_stdin,
+            cmd.with_clipboard,
+            &cmd.body_here,
+            cmd.body_url.as_deref().map(|url| (url, &cmd.url)),
+            "write body",
+        )?
+    };
     let path = cmd.path.clone();
+    let mut write_options = cmd.common.write_options();
+    write_options.precondition =
+        Precondition::from_args(cmd.if_hash.as_deref(), cmd.if_unchanged_since.as_deref())?;
+    if let Some(precondition) = &write_options.precondition {
+        precondition.check(&path)?;
+    }
     let exists = path.exists();
-    if exists && !cmd.allow_overwrite {
+    if exists && !cmd.allow_overwrite && cmd.mode == WriteMode::Overwrite {
+        if cmd.template.is_some() {
+            let bytes = fs::read(&path).with_context(|| format!("reading {}", path.display()))?;
+            let existing = encoding.decode(&bytes);
+            let style = detect_line_ending_style(&existing.text);
+            let rendered = restore_from_lf(normalize_to_lf(&body_text).into_owned(), style);
+            if existing.text == rendered {
+                println!(
+                    "{} already matches the rendered template; nothing to do.",
+                    path.display()
+                );
+                return Ok(());
+            }
+            println!("--- conflict preview: {} ---", path.display());
+            diff::display_diff(&path, &path, &existing.text, &rendered, &diff_config)?;
+        }
         bail!(
             "{} already exists; use --allow-overwrite to replace it",
             path.display()
@@ -1273,10 +2130,20 @@ fn handle_write(cmd: WriteCommand) -> Result<()> {
             is_probably_binary: false,
         },
     };
+    let mut source_hash = None;
     let existing_decoded = if exists {
         let bytes = fs::read(&path).with_context(|| format!("reading {}", path.display()))?;
         entry.metadata.len = bytes.len() as u64;
-        Some(encoding.decode(&bytes))
+        source_hash = Some(integrity::sha256_hex(&bytes));
+        let decoded = encoding.decode(&bytes);
+        explain::note_decoded(&path, &decoded);
+        if !encoding.accept(&path, &decoded.decision)? {
+            bail!(
+                "refusing to rewrite {} with an uncertain encoding",
+                path.display()
+            );
+        }
+        Some(decoded)
     } else {
         None
     };
@@ -1286,11 +2153,17 @@ fn handle_write(cmd: WriteCommand) -> Result<()> {
             .map(|d| detect_line_ending_style(&d.text)),
     );
     let normalized = normalize_to_lf(&body_text);
-    let new_text = restore_from_lf(normalized.into_owned(), target_line_style);
+    let new_body = restore_from_lf(normalized.into_owned(), target_line_style);
     let old_text = existing_decoded
         .as_ref()
         .map(|d| d.text.clone())
         .unwrap_or_default();
+    let new_text = combine_write_body(
+        &old_text,
+        new_body,
+        cmd.mode,
+        restore_from_lf("\n".to_string(), target_line_style),
+    );
 
     let patch_kind = if exists {
         PatchKind::Modify
@@ -1298,11 +2171,16 @@ fn handle_write(cmd: WriteCommand) -> Result<()> {
         PatchKind::Create
     };
     let mut stats = CommandStats::default();
-    let details = vec![
+    let mut details = vec![
         format!("body_source={body_source}"),
         format!("body_length={} chars", new_text.chars().count()),
         format!("line_ending={:?}", cmd.line_ending),
+        format!("mode={:?}", cmd.mode).to_lowercase(),
     ];
+    if let Some(template_path) = &cmd.template {
+        details.push(format!("template={}", template_path.display()));
+        details.push(format!("vars={}", cmd.vars.len()));
+    }
     print_command_summary("write", &cmd.common, &encoding, &[entry.clone()], &details);
 
     if old_text == new_text {
@@ -1323,12 +2201,14 @@ fn handle_write(cmd: WriteCommand) -> Result<()> {
         return Ok(());
     }
 
-    diff::display_diff(&old_text, &new_text, &diff_config)?;
+    stage_json_diff(&cmd.common, &path, &old_text, &new_text);
+    diff::display_diff(&path, &path, &old_text, &new_text, &diff_config)?;
     let line_summary = diff::summarize_lines(&old_text, &new_text);
     let line_spans = diff::collect_line_spans(&old_text, &new_text);
 
     if !cmd.common.apply {
         println!("dry-run: rerun with --apply to write this file.");
+        export_preview(&cmd.common, &path, &old_text, &new_text)?;
         log_change(
             &cmd.common,
             "write",
@@ -1346,7 +2226,7 @@ fn handle_write(cmd: WriteCommand) -> Result<()> {
     let decision = if cmd.common.auto_apply {
         ApprovalDecision::Apply
     } else {
-        prompt_approval(&path)?
+        approve_change(&cmd.common, &path)?
     };
 
     match decision {
@@ -1356,19 +2236,20 @@ fn handle_write(cmd: WriteCommand) -> Result<()> {
                 DecodedText {
                     text: String::new(),
                     had_errors: false,
+                    bom: false,
                     decision,
                 }
             });
             let result = TransformResult {
                 decoded,
                 new_text: new_text.clone(),
+                source_hash,
             };
             apply_transform(
                 &entry,
                 &result,
                 Some(result.decoded.decision.encoding),
-                cmd.common.undo_log.as_deref(),
-                cmd.common.no_backup,
+                &write_options,
             )?;
             stats.applied += 1;
             log_change(
@@ -1404,10 +2285,46 @@ fn handle_write(cmd: WriteCommand) -> Result<()> {
     Ok(())
 }
 
+#[derive(Clone, Copy, Debug, Deserialize, ValueEnum, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+enum WriteMode {
+    #[default]
+    Overwrite,
+    Append,
+    Prepend,
+}
+
+fn combine_write_body(old_text: &str, body: String, mode: WriteMode, eol: String) -> String {
+    let needle = body.trim_end_matches(['\r', '\n']);
+    if mode != WriteMode::Overwrite && !needle.is_empty() && old_text.contains(needle) {
+        println!("body already present; {mode:?} is a no-op.");
+        return old_text.to_string();
+    }
+    match mode {
+        WriteMode::Overwrite => body,
+        WriteMode::Append => {
+            let mut text = old_text.to_string();
+            if !text.is_empty() && !text.ends_with(['\n', '\r']) {
+                text.push_str(&eol);
+            }
+            text.push_str(&body);
+            text
+        }
+        WriteMode::Prepend => {
+            let mut text = body;
+            if !old_text.is_empty() && !text.is_empty() && !text.ends_with(['\n', '\r']) {
+                text.push_str(&eol);
+            }
+            text.push_str(old_text);
+            text
+        }
+    }
+}
+
 fn handle_rename(cmd: RenameCommand) -> Result<()> {
     let colorize = cmd.common.color.should_color();
     let diff_config = cmd.common.diff_display_config(colorize);
-    let entries = resolve_entries(&cmd.common)?;
+    let entries = resolve_edit_entries(&cmd.common)?;
     let encoding = resolve_encoding_strategy(&cmd.common)?;
     print_command_summary(
         "rename",
@@ -1421,6 +2338,7 @@ fn handle_rename(cmd: RenameCommand) -> Result<()> {
             format!("case_aware={}", cmd.case_aware),
         ],
     );
+    let _decisions = decisions::begin("rename", &recorded_rename_fields(&cmd));
     let options = RenameOptions {
         from: cmd.from.clone(),
         to: cmd.to.clone(),
@@ -1430,8 +2348,19 @@ fn handle_rename(cmd: RenameCommand) -> Result<()> {
     let apply_mode = cmd.common.apply;
     let mut apply_all = cmd.common.auto_apply && apply_mode;
     let mut stats = CommandStats::default();
-    for entry in &entries {
-        let Some(result) = run_rename(entry, &encoding, &options)? else {
+    let mut failures = FileFailures::new(&cmd.common, "rename");
+    let mut match_counts = HashMap::new();
+    let planned = plan_transforms(&entries, &mut failures, |entry| {
+        let (result, counts) = run_rename(entry, &encoding, &options)?;
+        match_counts.insert(entry.path.clone(), counts);
+        Ok(result)
+    })?;
+    print_planned_stat(&entries, &planned, colorize);
+    for (entry, result) in entries.iter().zip(planned) {
+        if failures.failed(&entry.path) {
+            continue;
+        }
+        let Some(result) = result else {
             stats.no_op += 1;
             log_change(
                 &cmd.common,
@@ -1446,12 +2375,35 @@ fn handle_rename(cmd: RenameCommand) -> Result<()> {
         };
         let line_summary = diff::summarize_lines(&result.decoded.text, &result.new_text);
         let line_spans = diff::collect_line_spans(&result.decoded.text, &result.new_text);
-        println!("--- preview: {} ---", entry.path.display());
-        diff::display_diff(&result.decoded.text, &result.new_text, &diff_config)?;
+        let counts = match_counts.get(&entry.path).copied().unwrap_or_default();
+        println!(
+            "--- preview: {} ({}) ---",
+            entry.path.display(),
+            counts.describe()
+        );
+        stage_json_diff(
+            &cmd.common,
+            &entry.path,
+            &result.decoded.text,
+            &result.new_text,
+        );
+        diff::display_diff(
+            &entry.path,
+            &entry.path,
+            &result.decoded.text,
+            &result.new_text,
+            &diff_config,
+        )?;
 
         if !apply_mode {
             stats.dry_run += 1;
             println!("dry-run: rerun with --apply to write this change.");
+            export_preview(
+                &cmd.common,
+                &entry.path,
+                &result.decoded.text,
+                &result.new_text,
+            )?;
             log_change(
                 &cmd.common,
                 "rename",
@@ -1459,7 +2411,7 @@ fn handle_rename(cmd: RenameCommand) -> Result<()> {
                 "dry-run",
                 &line_summary,
                 &line_spans,
-                Some(status_extra(false, true)),
+                Some(with_match_counts(status_extra(false, true), &counts)),
             );
             continue;
         }
@@ -1467,18 +2419,18 @@ fn handle_rename(cmd: RenameCommand) -> Result<()> {
         let decision = if apply_all {
             ApprovalDecision::Apply
         } else {
-            prompt_approval(&entry.path)?
+            approve_change(&cmd.common, &entry.path)?
         };
 
         match decision {
             ApprovalDecision::Apply => {
-                apply_transform(
-                    entry,
-                    &result,
-                    None,
-                    cmd.common.undo_log.as_deref(),
-                    cmd.common.no_backup,
-                )?;
+                let Some(()) = failures.check(
+                    &entry.path,
+                    apply_transform(entry, &result, None, &cmd.common.write_options()),
+                )?
+                else {
+                    continue;
+                };
                 stats.applied += 1;
                 log_change(
                     &cmd.common,
@@ -1487,18 +2439,18 @@ fn handle_rename(cmd: RenameCommand) -> Result<()> {
                     "applied",
                     &line_summary,
                     &line_spans,
-                    Some(status_extra(true, false)),
+                    Some(with_match_counts(status_extra(true, false), &counts)),
                 );
             }
             ApprovalDecision::ApplyAll => {
                 apply_all = true;
-                apply_transform(
-                    entry,
-                    &result,
-                    None,
-                    cmd.common.undo_log.as_deref(),
-                    cmd.common.no_backup,
-                )?;
+                let Some(()) = failures.check(
+                    &entry.path,
+                    apply_transform(entry, &result, None, &cmd.common.write_options()),
+                )?
+                else {
+                    continue;
+                };
                 stats.applied += 1;
                 log_change(
                     &cmd.common,
@@ -1507,7 +2459,7 @@ fn handle_rename(cmd: RenameCommand) -> Result<()> {
                     "applied",
                     &line_summary,
                     &line_spans,
-                    Some(status_extra(true, false)),
+                    Some(with_match_counts(status_extra(true, false), &counts)),
                 );
             }
             ApprovalDecision::Skip => {
@@ -1520,7 +2472,7 @@ fn handle_rename(cmd: RenameCommand) -> Result<()> {
                     "skipped",
                     &line_summary,
                     &line_spans,
-                    Some(status_extra(false, false)),
+                    Some(with_match_counts(status_extra(false, false), &counts)),
                 );
             }
             ApprovalDecision::Quit => {
@@ -1531,7 +2483,20 @@ fn handle_rename(cmd: RenameCommand) -> Result<()> {
         }
     }
     stats.print("rename");
-    Ok(())
+    record_plan_step(
+        cmd.record.as_ref(),
+        &cmd.common,
+        "rename",
+        recorded_rename_fields(&cmd),
+    )?;
+    failures.finish()
+}
+
+fn recorded_rename_fields(cmd: &RenameCommand) -> Vec<(&'static str, JsonValue)> {
+    let mut fields = vec![("from", json!(cmd.from)), ("to", json!(cmd.to))];
+    push_flag(&mut fields, "word_boundary", cmd.word_boundary);
+    push_flag(&mut fields, "case_aware", cmd.case_aware);
+    fields
 }
 
 fn handle_review(cmd: ReviewCommand) -> Result<()> {
@@ -1546,6 +2511,10 @@ fn handle_review(cmd: ReviewCommand) -> Result<()> {
         step: cmd.step,
         search: cmd.search.as_deref(),
         regex: cmd.regex,
+        filter: cmd.filter.as_deref(),
+        step_window: cmd.step_window,
+        report: cmd.report,
+        limits: cmd.common.diff_limits(),
     })?;
     if cmd.follow && entries.len() != 1 {
         bail!("--follow requires exactly one resolved file");
@@ -1562,110 +2531,600 @@ fn handle_review(cmd: ReviewCommand) -> Result<()> {
             format!("around={:?}", cmd.around),
             format!("follow={}", cmd.follow),
             format!("step={}", cmd.step),
+            format!("step_window={:?}", cmd.step_window),
             format!("search={:?}", cmd.search),
             format!("regex={}", cmd.regex),
+            format!("filter={:?}", cmd.filter),
+            format!("report={}", cmd.report),
+            format!("copy={}", cmd.copy),
+            format!("out={:?}", cmd.out),
         ],
     );
     review::run(&entries, &encoding, &review_options)?;
+    if cmd.copy || cmd.out.is_some() {
+        let (excerpt, lines) = review::collect_excerpt(&entries, &encoding, &review_options)?;
+        if let Some(out) = &cmd.out {
+            fs::write(out, &excerpt).with_context(|| format!("writing {}", out.display()))?;
+            println!("saved {lines} line(s) to {}", out.display());
+        }
+        if cmd.copy {
+            let mut clipboard = Clipboard::new().context("opening clipboard")?;
+            clipboard
+                .set_text(excerpt)
+                .context("copying the excerpt to the clipboard")?;
+            println!("copied {lines} line(s) to the clipboard");
+        }
+    }
     Ok(())
 }
 
-fn handle_normalize(cmd: NormalizeCommand) -> Result<()> {
-    let colorize = cmd.common.color.should_color();
-    let diff_config = cmd.common.diff_display_config(colorize);
-    let entries = resolve_entries(&cmd.common)?;
-    let encoding = resolve_encoding_strategy(&cmd.common)?;
-    let report_format = ReportFormat::from_str(&cmd.report_format)?;
-    let convert_encoding = if let Some(label) = cmd.convert_encoding.as_deref() {
-        let trimmed = label.trim();
-        let encoding = Encoding::for_label(trimmed.as_bytes())
-            .ok_or_else(|| anyhow!("unknown convert-encoding '{trimmed}'"))?;
-        Some((encoding, trimmed.to_string()))
-    } else {
-        None
+fn handle_compare(cmd: CompareCommand) -> Result<()> {
+    let encoding = EncodingStrategy::new(cmd.encoding.as_deref())?;
+    let colorize = cmd.color.should_color();
+    let diff_config = diff::DiffDisplayConfig {
+        context: cmd.context,
+        colorize,
+        pager_mode: cmd.pager,
+        interactive: io::stdin().is_terminal() && io::stdout().is_terminal() && !cmd.json,
+        format: cmd.diff_format,
+        line_numbers: cmd.line_numbers,
+        page_lines: cmd.page_lines,
+        limits: diff::DiffLimits::resolve(cmd.max_diff_lines, cmd.max_diff_bytes),
     };
+    let left_bytes =
+        fs::read(&cmd.left).with_context(|| format!("reading {}", cmd.left.display()))?;
+    let right_bytes =
+        fs::read(&cmd.right).with_context(|| format!("reading {}", cmd.right.display()))?;
+    let left = encoding.decode(&left_bytes);
+    let right = encoding.decode(&right_bytes);
+    println!("command: compare");
+    println!("encoding strategy: {}", encoding.describe());
+    for (label, path, decoded) in [("left", &cmd.left, &left), ("right", &cmd.right, &right)] {
+        println!(
+            "{label}: {} ({} bytes, decoded as {} via {}, detection score {:.2}{})",
+            path.display(),
+            decoded.text.len(),
+            decoded.decision.encoding.name(),
+            decoded.decision.source,
+            decoded.decision.score,
+            if decoded.had_errors {
+                ", with errors"
+            } else {
+                ""
+            }
+        );
+    }
+    println!("---");
 
-    let any_scan = cmd.scan_encoding
-        || cmd.scan_zero_width
-        || cmd.scan_control
-        || cmd.scan_trailing_space
-        || cmd.scan_final_newline;
-    let detect_zero_width = if any_scan { cmd.scan_zero_width } else { true };
-    let detect_control = if any_scan { cmd.scan_control } else { true };
-    let detect_trailing_space = if any_scan {
-        cmd.scan_trailing_space
-    } else {
-        true
-    };
-    let detect_final_newline = if any_scan {
-        cmd.scan_final_newline
+    let line_summary = diff::summarize_lines(&left.text, &right.text);
+    let line_spans = diff::collect_line_spans(&left.text, &right.text);
+    if left.text == right.text {
+        println!("files are identical after decoding.");
     } else {
-        true
-    };
-    let detect_encoding = if any_scan { cmd.scan_encoding } else { true };
+        println!("--- {}", cmd.left.display());
+        println!("+++ {}", cmd.right.display());
+        diff::display_diff(&cmd.left, &cmd.right, &left.text, &right.text, &diff_config)?;
+    }
+    if cmd.json {
+        let event = json!({
+            "command": "compare",
+            "left": cmd.left.display().to_string(),
+            "right": cmd.right.display().to_string(),
+            "left_encoding": left.decision.encoding.name(),
+            "right_encoding": right.decision.encoding.name(),
+            "identical": left.text == right.text,
+            "line_summary": line_summary,
+            "spans": spans_to_json(&line_spans),
+        });
+        println!("{event}");
+    }
+    Ok(())
+}
 
-    print_command_summary(
-        "normalize",
-        &cmd.common,
-        &encoding,
-        &entries,
-        &[
-            format!("strip_zero_width={}", cmd.strip_zero_width),
-            format!("strip_control={}", cmd.strip_control),
-            format!("trim_trailing_space={}", cmd.trim_trailing_space),
-            format!("ensure_eol={}", cmd.ensure_eol),
-            format!("report_format={}", cmd.report_format),
-            format!(
-                "convert_encoding={}",
-                convert_encoding
-                    .as_ref()
-                    .map(|(_, l)| l.as_str())
-                    .unwrap_or("none")
-            ),
-        ],
-    );
-    let norm_opts = normalize::NormalizeOptions {
-        strip_zero_width: cmd.strip_zero_width,
-        strip_control: cmd.strip_control,
-        trim_trailing_space: cmd.trim_trailing_space,
-        ensure_eol: cmd.ensure_eol,
-        detect_zero_width,
-        detect_control,
-        detect_trailing_space,
-        detect_final_newline,
-    };
-    let mut apply_all = cmd.common.auto_apply;
-    let mut stats = CommandStats::default();
+fn handle_stats(cmd: StatsCommand) -> Result<()> {
+    let entries = resolve_entries(&cmd.common)?;
+    let encoding = resolve_encoding_strategy(&cmd.common)?;
+    let report_format = ReportFormat::from_str(&cmd.format)?;
+    let mut rows = Vec::with_capacity(entries.len());
     for entry in &entries {
-        if entry.metadata.is_probably_binary {
-            println!("skipping {} (suspected binary file)", entry.path.display());
-            stats.skipped += 1;
-            log_change(
-                &cmd.common,
-                "normalize",
-                &entry.path,
-                "skipped",
-                "suspected binary file",
-                &[],
-                Some(status_extra(false, !cmd.common.apply)),
+        rows.push(stats::collect(entry, &encoding)?);
+    }
+    match report_format {
+        ReportFormat::Table => {
+            println!(
+                "{:>8} {:>10} {:<12} {:<6} {:>8} {:>9} {:<7} path",
+                "lines", "bytes", "encoding", "eol", "longest", "trailing", "binary"
             );
-            continue;
+            for row in &rows {
+                println!(
+                    "{:>8} {:>10} {:<12} {:<6} {:>8} {:>9} {:<7} {}",
+                    format_detection(row.lines),
+                    row.bytes,
+                    row.encoding.as_deref().unwrap_or("n/a"),
+                    row.line_ending_style.unwrap_or("n/a"),
+                    format_detection(row.longest_line),
+                    format_detection(row.trailing_whitespace),
+                    if row.binary { "yes" } else { "no" },
+                    row.path
+                );
+            }
         }
+        ReportFormat::Json => {
+            for row in &rows {
+                println!("{}", serde_json::to_string(row)?);
+            }
+        }
+    }
+    Ok(())
+}
 
-        let bytes = std::fs::read(&entry.path)
-            .with_context(|| format!("reading {}", entry.path.display()))?;
-        let decoded = encoding.decode(&bytes);
-        let outcome = normalize::normalize_text(&decoded.text, &norm_opts);
+fn handle_detect(cmd: DetectCommand) -> Result<()> {
+    let entries = resolve_entries(&cmd.common)?;
+    let encoding = resolve_encoding_strategy(&cmd.common)?;
+    let format = cmd.format.trim().to_ascii_lowercase();
+    if !matches!(format.as_str(), "table" | "json" | "csv") {
+        bail!(
+            "unsupported format '{}' (expected table, json, or csv)",
+            cmd.format
+        );
+    }
+    let mut rows = Vec::with_capacity(entries.len());
+    for entry in &entries {
+        rows.push(detect::collect(entry, &encoding)?);
+    }
+    match format.as_str() {
+        "json" => {
+            for row in &rows {
+                println!("{}", serde_json::to_string(row)?);
+            }
+        }
+        "csv" => {
+            println!(
+                "path,bytes,binary,encoding,source,bom,score,decode_errors,replacement_chars,error_likelihood"
+            );
+            for row in &rows {
+                println!(
+                    "{},{},{},{},{},{},{},{},{},{}",
+                    detect::csv_field(&row.path),
+                    row.bytes,
+                    row.binary,
+                    row.encoding.as_deref().unwrap_or(""),
+                    row.source.as_deref().unwrap_or(""),
+                    row.bom.map(|b| b.to_string()).unwrap_or_default(),
+                    row.score.map(|c| format!("{c:.2}")).unwrap_or_default(),
+                    row.decode_errors.map(|b| b.to_string()).unwrap_or_default(),
+                    row.replacement_chars
+                        .map(|n| n.to_string())
+                        .unwrap_or_default(),
+                    row.error_likelihood
+                );
+            }
+        }
+        _ => {
+            println!(
+                "{:<14} {:<13} {:<4} {:>5} {:<7} path",
+                "encoding", "source", "bom", "score", "risk"
+            );
+            for row in &rows {
+                println!(
+                    "{:<14} {:<13} {:<4} {:>5} {:<7} {}",
+                    if row.binary {
+                        "binary"
+                    } else {
+                        row.encoding.as_deref().unwrap_or("n/a")
+                    },
+                    row.source.as_deref().unwrap_or("n/a"),
+                    format_bool(row.bom),
+                    row.score
+                        .map(|c| format!("{c:.2}"))
+                        .unwrap_or_else(|| "n/a".into()),
+                    row.error_likelihood,
+                    row.path
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+fn handle_filter(cmd: FilterCommand) -> Result<()> {
+    let mut input = Vec::new();
+    io::stdin()
+        .read_to_end(&mut input)
+        .context("reading stdin")?;
+    let (encoding, action) = match &cmd.action {
+        FilterAction::Replace(args) => (args.encoding.as_deref(), "replace"),
+        FilterAction::Normalize(args) => (args.encoding.as_deref(), "normalize"),
+    };
+    let strategy = EncodingStrategy::new(encoding)?;
+    let mut decoded = strategy.decode(&input);
+    if decoded.had_errors {
+        eprintln!(
+            "warning: stdin is not valid {}; output may differ from input",
+            decoded.decision.encoding.name()
+        );
+    }
+    let new_text = match cmd.action {
+        FilterAction::Replace(args) => {
+            let literal_mode = args.literal || !args.regex;
+            let expand = !literal_mode && !args.no_expand;
+            if expand {
+                for warning in commands::template_warnings(&args.pattern, &args.replacement)? {
+                    eprintln!("warning: {warning}");
+                }
+            }
+            let options = ReplaceOptions {
+                pattern: if literal_mode {
+                    regex::escape(&args.pattern)
+                } else {
+                    args.pattern.clone()
+                },
+                replacement: args.replacement,
+                allow_captures: expand,
+                case_aware: false,
+                count: args.count,
+                expect: args.expect,
+                after_line: args.after_line,
+                skip_if_contains: None,
+                quiet: true,
+            };
+            let replaced = commands::apply_replace(&decoded, &options)?;
+            if replaced.is_none()
+                && let Some(expected) = args.expect.filter(|expected| *expected > 0)
+            {
+                bail!("expected {expected} matches but found 0");
+            }
+            replaced
+        }
+        FilterAction::Normalize(args) => {
+            let (text, bom) = filter_normalize(&decoded.text, &args)?;
+            let changed_bom = bom.filter(|wanted| *wanted != decoded.bom);
+            if let Some(wanted) = changed_bom {
+                decoded.bom = wanted;
+            }
+            text.or_else(|| changed_bom.map(|_| decoded.text.clone()))
+        }
+    };
+    let output = match new_text {
+        Some(text) => {
+            let (bytes, unmappable) = encode_text(&text, decoded.decision.encoding, decoded.bom);
+            if unmappable {
+                bail!(
+                    "{action} output contains characters {} cannot represent",
+                    decoded.decision.encoding.name()
+                );
+            }
+            bytes
+        }
+        None => input,
+    };
+    let mut stdout = io::stdout().lock();
+    stdout.write_all(&output)?;
+    stdout.flush()?;
+    Ok(())
+}
+
+// Builds and validates the edits shared by `normalize` and `filter normalize`;
+// the scan/detect switches are left off for the caller to set.
+fn normalize_options(edits: &NormalizeEditArgs) -> Result<normalize::NormalizeOptions> {
+    if edits.max_line_length == Some(0) {
+        bail!("--max-line-length must be at least 1");
+    }
+    if edits.wrap_long_lines && edits.max_line_length.is_none() {
+        bail!("--wrap-long-lines requires --max-line-length");
+    }
+    let indent_conversion = match (edits.tabs_to_spaces, edits.spaces_to_tabs) {
+        (Some(0), _) | (_, Some(0)) => bail!("indentation width must be at least 1"),
+        (Some(_), Some(_)) => bail!("--tabs-to-spaces and --spaces-to-tabs are mutually exclusive"),
+        (Some(width), None) => Some(normalize::IndentConversion::TabsToSpaces(width)),
+        (None, Some(width)) => Some(normalize::IndentConversion::SpacesToTabs(width)),
+        (None, None) => None,
+    };
+    let punctuation_map = edits
+        .map_char
+        .iter()
+        .map(|spec| normalize::parse_char_mapping(spec).map_err(|err| anyhow!(err)))
+        .collect::<Result<Vec<_>>>()?;
+    let strip_classes = if edits.strip_class.is_empty() {
+        Vec::new()
+    } else {
+        let config = config::Config::load()?;
+        edits
+            .strip_class
+            .iter()
+            .map(|name| config.strip_class(name))
+            .collect::<Result<Vec<_>>>()?
+    };
+    Ok(normalize::NormalizeOptions {
+        strip_zero_width: edits.strip_zero_width,
+        strip_control: edits.strip_control,
+        strip_bidi: edits.strip_bidi,
+        map_confusables: edits.map_confusables,
+        ascii_punctuation: edits.ascii_punctuation,
+        punctuation_map,
+        strip_classes,
+        trim_trailing_space: edits.trim_trailing_space,
+        ensure_eol: edits.ensure_eol,
+        indent_conversion,
+        max_blank_lines: edits.max_blank_lines,
+        max_line_length: edits.max_line_length,
+        wrap_long_lines: edits.wrap_long_lines,
+        detect_zero_width: false,
+        detect_control: false,
+        detect_bidi: false,
+        detect_confusables: false,
+        detect_punctuation: false,
+        detect_trailing_space: false,
+        detect_indentation: false,
+        detect_line_endings: false,
+        detect_final_newline: false,
+        record_locations: false,
+        unicode_form: edits.unicode_form,
+    })
+}
+
+fn filter_normalize(
+    text: &str,
+    args: &FilterNormalizeArgs,
+) -> Result<(Option<String>, Option<bool>)> {
+    let options = normalize_options(&args.edits)?;
+    let mut cleaned = normalize::normalize_text(text, &options).cleaned;
+    if let Some(style) = args.edits.convert_eol {
+        let source = cleaned.as_deref().unwrap_or(text);
+        let converted = restore_from_lf(normalize_to_lf(source).into_owned(), style);
+        if converted != text {
+            cleaned = Some(converted);
+        }
+    }
+    let bom = if args.edits.strip_bom {
+        Some(false)
+    } else if args.edits.add_bom {
+        Some(true)
+    } else {
+        None
+    };
+    Ok((cleaned, bom))
+}
+
+fn handle_normalize(cmd: NormalizeCommand) -> Result<()> {
+    let colorize = cmd.common.color.should_color();
+    let diff_config = cmd.common.diff_display_config(colorize);
+    let entries = resolve_edit_entries(&cmd.common)?;
+    let encoding = resolve_encoding_strategy(&cmd.common)?;
+    let report_format = ReportFormat::from_str(&cmd.report_format)?;
+    let _decisions = decisions::begin("normalize", &recorded_normalize_fields(&cmd));
+    let convert_encoding = if let Some(label) = cmd.convert_encoding.as_deref() {
+        let trimmed = label.trim();
+        let encoding = Encoding::for_label(trimmed.as_bytes())
+            .ok_or_else(|| anyhow!("unknown convert-encoding '{trimmed}'"))?;
+        Some((encoding, trimmed.to_string()))
+    } else {
+        None
+    };
+
+    let any_scan = cmd.scan_encoding
+        || cmd.scan_zero_width
+        || cmd.scan_control
+        || cmd.scan_bidi
+        || cmd.scan_confusables
+        || cmd.scan_punctuation
+        || cmd.scan_trailing_space
+        || cmd.scan_indentation
+        || cmd.scan_eol
+        || cmd.scan_final_newline;
+    let detect_zero_width = if any_scan { cmd.scan_zero_width } else { true };
+    let detect_control = if any_scan { cmd.scan_control } else { true };
+    let detect_bidi = if any_scan {
+        cmd.scan_bidi || cmd.fail_on_bidi
+    } else {
+        true
+    };
+    let detect_confusables = if any_scan { cmd.scan_confusables } else { true };
+    let detect_punctuation = if any_scan { cmd.scan_punctuation } else { true };
+    let detect_trailing_space = if any_scan {
+        cmd.scan_trailing_space
+    } else {
+        true
+    };
+    let detect_indentation = if any_scan { cmd.scan_indentation } else { true };
+    let detect_line_endings = if any_scan {
+        cmd.scan_eol || cmd.edits.convert_eol.is_some()
+    } else {
+        true
+    };
+    if cmd.fail_on_long_lines && cmd.edits.max_line_length.is_none() {
+        bail!("--fail-on-long-lines requires --max-line-length");
+    }
+    let edit_options = normalize_options(&cmd.edits)?;
+    let indent_conversion = edit_options.indent_conversion;
+    let detect_final_newline = if any_scan {
+        cmd.scan_final_newline
+    } else {
+        true
+    };
+    let detect_encoding = if any_scan { cmd.scan_encoding } else { true };
+
+    print_command_summary(
+        "normalize",
+        &cmd.common,
+        &encoding,
+        &entries,
+        &[
+            format!("strip_zero_width={}", cmd.edits.strip_zero_width),
+            format!("strip_control={}", cmd.edits.strip_control),
+            format!("strip_bidi={}", cmd.edits.strip_bidi),
+            format!("fail_on_bidi={}", cmd.fail_on_bidi),
+            format!("map_confusables={}", cmd.edits.map_confusables),
+            format!("ascii_punctuation={}", cmd.edits.ascii_punctuation),
+            format!(
+                "strip_class={}",
+                if cmd.edits.strip_class.is_empty() {
+                    "none".to_string()
+                } else {
+                    cmd.edits.strip_class.join(",")
+                }
+            ),
+            format!("trim_trailing_space={}", cmd.edits.trim_trailing_space),
+            format!("ensure_eol={}", cmd.edits.ensure_eol),
+            format!(
+                "max_blank_lines={}",
+                cmd.edits
+                    .max_blank_lines
+                    .map(|max| max.to_string())
+                    .unwrap_or_else(|| "unlimited".to_string())
+            ),
+            format!(
+                "max_line_length={}{}",
+                cmd.edits
+                    .max_line_length
+                    .map(|max| max.to_string())
+                    .unwrap_or_else(|| "unlimited".to_string()),
+                if cmd.edits.wrap_long_lines {
+                    " (wrap)"
+                } else if cmd.fail_on_long_lines {
+                    " (fail)"
+                } else {
+                    ""
+                }
+            ),
+            format!("strip_bom={}", cmd.edits.strip_bom),
+            format!("add_bom={}", cmd.edits.add_bom),
+            format!(
+                "indent={}",
+                match indent_conversion {
+                    Some(normalize::IndentConversion::TabsToSpaces(width)) =>
+                        format!("tabs-to-spaces:{width}"),
+                    Some(normalize::IndentConversion::SpacesToTabs(width)) =>
+                        format!("spaces-to-tabs:{width}"),
+                    None => "unchanged".to_string(),
+                }
+            ),
+            format!(
+                "convert_eol={}",
+                cmd.edits
+                    .convert_eol
+                    .map(line_ending_label)
+                    .unwrap_or("none")
+            ),
+            format!(
+                "unicode_form={}",
+                cmd.edits
+                    .unicode_form
+                    .map(|form| form.label())
+                    .unwrap_or("none")
+            ),
+            format!("report_format={}", cmd.report_format),
+            format!("locations={}", cmd.locations),
+            format!(
+                "convert_encoding={}",
+                convert_encoding
+                    .as_ref()
+                    .map(|(_, l)| l.as_str())
+                    .unwrap_or("none")
+            ),
+        ],
+    );
+    let norm_opts = normalize::NormalizeOptions {
+        detect_zero_width,
+        detect_control,
+        detect_bidi,
+        detect_confusables,
+        detect_punctuation,
+        detect_trailing_space,
+        detect_indentation,
+        detect_line_endings,
+        detect_final_newline,
+        record_locations: cmd.locations,
+        ..edit_options
+    };
+    let mut apply_all = cmd.common.auto_apply;
+    let mut stats = CommandStats::default();
+    let mut failures = FileFailures::new(&cmd.common, "normalize");
+    let mut bidi_files = Vec::new();
+    let mut long_line_files = Vec::new();
+    for entry in &entries {
+        if entry.metadata.is_probably_binary {
+            println!("skipping {} (suspected binary file)", entry.path.display());
+            stats.skipped += 1;
+            log_change(
+                &cmd.common,
+                "normalize",
+                &entry.path,
+                "skipped",
+                "suspected binary file",
+                &[],
+                Some(status_extra(false, !cmd.common.apply)),
+            );
+            continue;
+        }
+
+        let Some(bytes) = failures.check(
+            &entry.path,
+            std::fs::read(&entry.path).with_context(|| format!("reading {}", entry.path.display())),
+        )?
+        else {
+            continue;
+        };
+        let mut decoded = encoding.decode(&bytes);
+        explain::note_decoded(&entry.path, &decoded);
+        if !encoding.accept(&entry.path, &decoded.decision)? {
+            stats.skipped += 1;
+            log_change(
+                &cmd.common,
+                "normalize",
+                &entry.path,
+                "skipped",
+                "low encoding detection score",
+                &[],
+                Some(status_extra(false, !cmd.common.apply)),
+            );
+            continue;
+        }
+        let mut outcome = normalize::normalize_text(&decoded.text, &norm_opts);
+        outcome.report.bom = Some(decoded.bom);
+        let bom_target = if cmd.edits.strip_bom {
+            Some(false)
+        } else if cmd.edits.add_bom {
+            Some(true)
+        } else {
+            None
+        };
+        let bom_change = bom_target.filter(|wanted| *wanted != decoded.bom);
+        if let Some(style) = cmd.edits.convert_eol {
+            let source = outcome.cleaned.as_deref().unwrap_or(&decoded.text);
+            let converted = restore_from_lf(normalize_to_lf(source).into_owned(), style);
+            if converted != decoded.text {
+                outcome.cleaned = Some(converted);
+            }
+        }
         print_normalize_report(
             &entry.path,
             &outcome.report,
-            detect_encoding.then_some(decoded.decision.encoding.name()),
+            detect_encoding.then_some((decoded.decision.encoding.name(), decoded.decision.score)),
             convert_encoding.as_ref().map(|(enc, _)| enc.name()),
+            cmd.edits.unicode_form,
+            cmd.edits.convert_eol,
             report_format,
         )?;
+        if outcome.report.bidi_controls.unwrap_or(0) > 0 {
+            bidi_files.push(entry.path.display().to_string());
+        }
+        if outcome.report.long_lines.unwrap_or(0) > 0 {
+            long_line_files.push(entry.path.display().to_string());
+        }
 
-        let convert_requested = convert_encoding.is_some();
+        let convert_requested = convert_encoding.is_some() || bom_change.is_some();
         let convert_only = outcome.cleaned.is_none() && convert_requested;
+        if let Some(wanted) = bom_change {
+            decoded.bom = wanted;
+        }
+        explain::note_target(
+            &entry.path,
+            convert_encoding
+                .as_ref()
+                .map_or(decoded.decision.encoding, |(enc, _)| *enc),
+            decoded.bom,
+        );
         let new_text = if let Some(text) = outcome.cleaned {
             text
         } else if convert_requested {
@@ -1696,33 +3155,56 @@ fn handle_normalize(cmd: NormalizeCommand) -> Result<()> {
             continue;
         };
 
-        let result = TransformResult { decoded, new_text };
+        let result = TransformResult {
+            decoded,
+            new_text,
+            source_hash: Some(integrity::sha256_hex(&bytes)),
+        };
         let mut line_summary = diff::summarize_lines(&result.decoded.text, &result.new_text);
         let line_spans = diff::collect_line_spans(&result.decoded.text, &result.new_text);
         if convert_only && line_spans.is_empty() {
-            line_summary = format!(
-                "encoding conversion to {}",
-                convert_encoding
-                    .as_ref()
-                    .map(|(_, label)| label.as_str())
-                    .unwrap_or("requested encoding")
-            );
+            let mut rewrites = Vec::new();
+            if let Some((_, label)) = convert_encoding.as_ref() {
+                rewrites.push(format!("encoding conversion to {label}"));
+            }
+            match bom_change {
+                Some(true) => rewrites.push("BOM added".to_string()),
+                Some(false) => rewrites.push("BOM removed".to_string()),
+                None => {}
+            }
+            line_summary = rewrites.join(", ");
             println!(
-                "(no textual diff) {} will be rewritten using {}",
+                "(no textual diff) {} will be rewritten: {}",
                 entry.path.display(),
-                convert_encoding
-                    .as_ref()
-                    .map(|(_, l)| l.as_str())
-                    .unwrap_or("requested encoding")
+                line_summary
             );
+            explain::print(&entry.path, &result.decoded.text, &result.new_text);
         } else {
             println!("--- preview: {} ---", entry.path.display());
-            diff::display_diff(&result.decoded.text, &result.new_text, &diff_config)?;
+            stage_json_diff(
+                &cmd.common,
+                &entry.path,
+                &result.decoded.text,
+                &result.new_text,
+            );
+            diff::display_diff(
+                &entry.path,
+                &entry.path,
+                &result.decoded.text,
+                &result.new_text,
+                &diff_config,
+            )?;
         }
 
         if !cmd.common.apply {
             stats.dry_run += 1;
             println!("dry-run: rerun with --apply to write this change.");
+            export_preview(
+                &cmd.common,
+                &entry.path,
+                &result.decoded.text,
+                &result.new_text,
+            )?;
             log_change(
                 &cmd.common,
                 "normalize",
@@ -1738,18 +3220,23 @@ fn handle_normalize(cmd: NormalizeCommand) -> Result<()> {
         let decision = if apply_all {
             ApprovalDecision::Apply
         } else {
-            prompt_approval(&entry.path)?
+            approve_change(&cmd.common, &entry.path)?
         };
 
         match decision {
             ApprovalDecision::Apply => {
-                apply_transform(
-                    entry,
-                    &result,
-                    convert_encoding.as_ref().map(|(enc, _)| *enc),
-                    cmd.common.undo_log.as_deref(),
-                    cmd.common.no_backup,
-                )?;
+                let Some(()) = failures.check(
+                    &entry.path,
+                    apply_transform(
+                        entry,
+                        &result,
+                        convert_encoding.as_ref().map(|(enc, _)| *enc),
+                        &cmd.common.write_options(),
+                    ),
+                )?
+                else {
+                    continue;
+                };
                 stats.applied += 1;
                 log_change(
                     &cmd.common,
@@ -1763,13 +3250,18 @@ fn handle_normalize(cmd: NormalizeCommand) -> Result<()> {
             }
             ApprovalDecision::ApplyAll => {
                 apply_all = true;
-                apply_transform(
-                    entry,
-                    &result,
-                    convert_encoding.as_ref().map(|(enc, _)| *enc),
-                    cmd.common.undo_log.as_deref(),
-                    cmd.common.no_backup,
-                )?;
+                let Some(()) = failures.check(
+                    &entry.path,
+                    apply_transform(
+                        entry,
+                        &result,
+                        convert_encoding.as_ref().map(|(enc, _)| *enc),
+                        &cmd.common.write_options(),
+                    ),
+                )?
+                else {
+                    continue;
+                };
                 stats.applied += 1;
                 log_change(
                     &cmd.common,
@@ -1802,1420 +3294,6421 @@ fn handle_normalize(cmd: NormalizeCommand) -> Result<()> {
         }
     }
     stats.print("normalize");
+    record_plan_step(
+        cmd.record.as_ref(),
+        &cmd.common,
+        "normalize",
+        recorded_normalize_fields(&cmd),
+    )?;
+    failures.finish()?;
+    if cmd.fail_on_long_lines && !long_line_files.is_empty() {
+        bail!(
+            "lines longer than {} characters found in {} file(s): {}",
+            cmd.edits.max_line_length.unwrap_or_default(),
+            long_line_files.len(),
+            long_line_files.join(", ")
+        );
+    }
+    if cmd.fail_on_bidi && !bidi_files.is_empty() {
+        bail!(
+            "bidi control characters found in {} file(s): {}",
+            bidi_files.len(),
+            bidi_files.join(", ")
+        );
+    }
     Ok(())
 }
 
-fn handle_script(cmd: ScriptCommand) -> Result<()> {
-    let entries = resolve_entries(&cmd.common)?;
-    let encoding = resolve_encoding_strategy(&cmd.common)?;
-    print_command_summary(
-        "script",
-        &cmd.common,
-        &encoding,
-        &entries,
-        &[
-            format!("script={}", cmd.script.display()),
-            format!("args={:?}", cmd.args),
-        ],
+fn recorded_normalize_fields(cmd: &NormalizeCommand) -> Vec<(&'static str, JsonValue)> {
+    let mut fields = Vec::new();
+    push_option(
+        &mut fields,
+        "convert_encoding",
+        cmd.convert_encoding.as_ref(),
     );
-    Ok(())
+    for (name, set) in [
+        ("strip_zero_width", cmd.edits.strip_zero_width),
+        ("strip_control", cmd.edits.strip_control),
+        ("strip_bidi", cmd.edits.strip_bidi),
+        ("fail_on_bidi", cmd.fail_on_bidi),
+        ("map_confusables", cmd.edits.map_confusables),
+        ("ascii_punctuation", cmd.edits.ascii_punctuation),
+        ("trim_trailing_space", cmd.edits.trim_trailing_space),
+        ("ensure_eol", cmd.edits.ensure_eol),
+        ("wrap_long_lines", cmd.edits.wrap_long_lines),
+        ("fail_on_long_lines", cmd.fail_on_long_lines),
+        ("strip_bom", cmd.edits.strip_bom),
+        ("add_bom", cmd.edits.add_bom),
+        ("locations", cmd.locations),
+        ("scan_encoding", cmd.scan_encoding),
+        ("scan_zero_width", cmd.scan_zero_width),
+        ("scan_control", cmd.scan_control),
+        ("scan_bidi", cmd.scan_bidi),
+        ("scan_confusables", cmd.scan_confusables),
+        ("scan_punctuation", cmd.scan_punctuation),
+        ("scan_trailing_space", cmd.scan_trailing_space),
+        ("scan_indentation", cmd.scan_indentation),
+        ("scan_eol", cmd.scan_eol),
+        ("scan_final_newline", cmd.scan_final_newline),
+    ] {
+        push_flag(&mut fields, name, set);
+    }
+    if !cmd.edits.map_char.is_empty() {
+        fields.push(("map_char", json!(cmd.edits.map_char)));
+    }
+    if !cmd.edits.strip_class.is_empty() {
+        fields.push(("strip_class", json!(cmd.edits.strip_class)));
+    }
+    push_option(&mut fields, "tabs_to_spaces", cmd.edits.tabs_to_spaces);
+    push_option(&mut fields, "spaces_to_tabs", cmd.edits.spaces_to_tabs);
+    push_option(&mut fields, "max_blank_lines", cmd.edits.max_blank_lines);
+    push_option(&mut fields, "max_line_length", cmd.edits.max_line_length);
+    push_option(
+        &mut fields,
+        "convert_eol",
+        cmd.edits
+            .convert_eol
+            .map(|style| format!("{style:?}").to_lowercase()),
+    );
+    push_option(&mut fields, "unicode_form", cmd.edits.unicode_form);
+    if cmd.report_format != "table" {
+        fields.push(("report_format", json!(cmd.report_format)));
+    }
+    fields
 }
 
-fn handle_batch(cmd: BatchCommand) -> Result<()> {
-    let BatchCommand { common, plan } = cmd;
-    let encoding = resolve_encoding_strategy(&common)?;
-    let batch_plan = batch::load_plan(&plan)?;
-    if batch_plan.steps.is_empty() {
-        bail!("plan {} does not contain any steps", plan.display());
+fn push_flag(fields: &mut Vec<(&'static str, JsonValue)>, name: &'static str, set: bool) {
+    if set {
+        fields.push((name, JsonValue::Bool(true)));
     }
-    print_command_summary(
-        "batch",
-        &common,
-        &encoding,
-        &[],
-        &[format!(
-            "plan={} ({} steps)",
-            plan.display(),
-            batch_plan.steps.len()
-        )],
+}
+
+fn push_option<T: Serialize>(
+    fields: &mut Vec<(&'static str, JsonValue)>,
+    name: &'static str,
+    value: Option<T>,
+) {
+    if let Some(value) = value {
+        fields.push((name, json!(value)));
+    }
+}
+
+fn handle_wizard(cmd: WizardCommand) -> Result<()> {
+    input::ensure_prompt_allowed("the wizard")?;
+    println!(
+        "safeedit wizard: answer a few questions to build a command (Enter keeps the default)."
     );
-    for (idx, step) in batch_plan.steps.iter().enumerate() {
-        println!(
-            "\n=== Batch Step {}/{}: {} ===",
-            idx + 1,
-            batch_plan.steps.len(),
-            step.kind()
-        );
-        match step {
-            batch::PlanEntry::Replace(step_plan) => {
-                let replace_cmd = build_replace_command(&common, step_plan)?;
-                handle_replace(replace_cmd)?;
-            }
-            batch::PlanEntry::Normalize(step_plan) => {
-                let normalize_cmd = build_normalize_command(&common, step_plan)?;
-                handle_normalize(normalize_cmd)?;
-            }
-            batch::PlanEntry::Block(step_plan) => {
-                let block_cmd = build_block_command(&common, step_plan)?;
-                handle_block(block_cmd)?;
-            }
-            batch::PlanEntry::Rename(step_plan) => {
-                let rename_cmd = build_rename_command(&common, step_plan)?;
-                handle_rename(rename_cmd)?;
+    let mut args = wizard::collect_command()?;
+    println!();
+    println!("command: {}", wizard::command_line(&args));
+    let next = wizard::choose_next_step()?;
+    if next == wizard::NextStep::Quit {
+        return Ok(());
+    }
+    if next == wizard::NextStep::Apply {
+        args.push("--apply".into());
+    }
+    let argv = std::iter::once("safeedit".to_string()).chain(args.iter().cloned());
+    let cli = Cli::try_parse_from(argv).map_err(|err| anyhow!("{err}"))?;
+    if next == wizard::NextStep::Save {
+        let default_plan = cmd
+            .plan
+            .unwrap_or_else(|| PathBuf::from("safeedit-plan.yaml"));
+        let plan = PathBuf::from(wizard::ask(
+            "Plan file",
+            Some(&default_plan.to_string_lossy()),
+        )?);
+        return match cli.command {
+            Command::Replace(cmd) => {
+                let (replacement_text, _) = resolve_replacement_text(&cmd)?;
+                let fields = recorded_replace_fields(&cmd, &replacement_text);
+                record_plan_step(Some(&plan), &cmd.common, "replace", fields)
             }
-        }
+            Command::Rename(cmd) => record_plan_step(
+                Some(&plan),
+                &cmd.common,
+                "rename",
+                recorded_rename_fields(&cmd),
+            ),
+            Command::Normalize(cmd) => record_plan_step(
+                Some(&plan),
+                &cmd.common,
+                "normalize",
+                recorded_normalize_fields(&cmd),
+            ),
+            _ => bail!("the wizard can only save replace, rename, or normalize steps"),
+        };
+    }
+    match cli.command {
+        Command::Replace(cmd) => handle_replace(cmd),
+        Command::Rename(cmd) => handle_rename(cmd),
+        Command::Normalize(cmd) => handle_normalize(cmd),
+        _ => bail!("the wizard can only run replace, rename, or normalize"),
     }
-    Ok(())
 }
 
-fn handle_log(cmd: LogCommand) -> Result<()> {
-    let entries = logging::read_recent(cmd.tail)?;
-    if entries.is_empty() {
-        println!("change log is empty.");
+// Every file's transform runs before the first preview so the diff stat can
+// show the whole run's footprint ahead of any approval prompt.
+fn plan_transforms<F>(
+    entries: &[FileEntry],
+    failures: &mut FileFailures,
+    mut run: F,
+) -> Result<Vec<Option<TransformResult>>>
+where
+    F: FnMut(&FileEntry) -> Result<Option<TransformResult>>,
+{
+    entries
+        .iter()
+        .map(|entry| Ok(failures.check(&entry.path, run(entry))?.flatten()))
+        .collect()
+}
+
+fn print_planned_stat(entries: &[FileEntry], planned: &[Option<TransformResult>], colorize: bool) {
+    let cwd = std::env::current_dir().unwrap_or_default();
+    let files: Vec<(String, usize, usize)> = entries
+        .iter()
+        .zip(planned)
+        .filter_map(|(entry, result)| {
+            let result = result.as_ref()?;
+            let (added, removed) = diff::count_changes(&result.decoded.text, &result.new_text);
+            let label = entry.path.strip_prefix(&cwd).unwrap_or(&entry.path);
+            Some((label.display().to_string(), added, removed))
+        })
+        .collect();
+    if files.len() < 2 {
+        return;
+    }
+    println!("diff stat:");
+    for line in diff::format_diff_stat(&files, colorize) {
+        println!("{line}");
+    }
+    println!("---");
+}
+
+fn record_plan_step(
+    plan: Option<&PathBuf>,
+    common: &CommonArgs,
+    command: &'static str,
+    mut fields: Vec<(&'static str, JsonValue)>,
+) -> Result<()> {
+    let Some(plan) = plan else {
         return Ok(());
+    };
+    fields.insert(0, ("command", json!(command)));
+    let mut recorded = JsonMap::new();
+    let (targets, globs) = common.selection();
+    if !targets.is_empty() {
+        recorded.insert("targets".into(), json!(targets));
     }
-    for entry in entries {
-        println!(
-            "[{}] {:<10} {:<8} {:<12} {}",
-            entry.timestamp, entry.command, entry.action, entry.line_summary, entry.path
-        );
-        if !entry.spans.is_empty() {
-            println!("    spans: {}", describe_spans(&entry.spans));
-        }
+    if !globs.is_empty() {
+        recorded.insert("globs".into(), json!(globs));
+    }
+    if !common.exclude.is_empty() {
+        recorded.insert("exclude".into(), json!(common.exclude));
+    }
+    if let Some(encoding) = &common.encoding {
+        recorded.insert("encoding".into(), json!(encoding));
+    }
+    if common.include_hidden {
+        recorded.insert("include_hidden".into(), JsonValue::Bool(true));
     }
+    if common.strict_encoding {
+        recorded.insert("strict_encoding".into(), JsonValue::Bool(true));
+    }
+    if common.writes.no_backup {
+        recorded.insert("no_backup".into(), JsonValue::Bool(true));
+    }
+    if let Some(dir) = &common.writes.backup_dir {
+        recorded.insert("backup_dir".into(), json!(dir));
+    }
+    if let Some(min) = common.min_detection_score {
+        recorded.insert("min_detection_score".into(), json!(min));
+    }
+    if !recorded.is_empty() {
+        fields.push(("common", JsonValue::Object(recorded)));
+    }
+    batch::append_step(plan, fields)?;
+    println!("recorded {command} step in {}", plan.display());
     Ok(())
 }
 
-fn handle_report(cmd: ReportCommand) -> Result<()> {
-    let entries = logging::read_all()?;
-    if entries.is_empty() {
-        println!("change log is empty.");
-        return Ok(());
+fn positional_or_flag(
+    flag: Option<PathBuf>,
+    common: &mut CommonArgs,
+    name: &str,
+) -> Result<PathBuf> {
+    if let Some(path) = flag {
+        return Ok(path);
+    }
+    if common.extra_args.is_empty() {
+        bail!("missing {name}: pass it as the first argument or via --{name}");
+    }
+    Ok(PathBuf::from(common.extra_args.remove(0)))
+}
+
+fn handle_script(mut cmd: ScriptCommand) -> Result<()> {
+    let script = positional_or_flag(cmd.script.take(), &mut cmd.common, "script")?;
+    let entries = resolve_entries(&cmd.common)?;
+    let encoding = resolve_encoding_strategy(&cmd.common)?;
+    print_command_summary(
+        "script",
+        &cmd.common,
+        &encoding,
+        &entries,
+        &[
+            format!("script={}", script.display()),
+            format!("args={:?}", cmd.args),
+        ],
+    );
+    Ok(())
+}
+
+fn handle_batch(cmd: BatchCommand) -> Result<()> {
+    let BatchCommand {
+        mut common,
+        plan,
+        set,
+        results,
+        resume,
+        review_plan,
+        ..
+    } = cmd;
+    let plan = positional_or_flag(plan, &mut common, "plan")?;
+    let encoding = resolve_encoding_strategy(&common)?;
+    let started = OffsetDateTime::now_utc();
+    if results.is_some() {
+        logging::capture_events();
+    }
+    let overrides = parse_plan_overrides(&set)?;
+    let batch_plan = batch::load_plan(&plan, &overrides)?;
+    if batch_plan.steps.is_empty() {
+        bail!("plan {} does not contain any steps", plan.display());
+    }
+    if review_plan {
+        common.apply = true;
+    }
+    if resume && !common.apply {
+        bail!("--resume only applies to --apply runs; dry runs do not record progress");
+    }
+    let mut progress = batch::BatchProgress::new(&plan, &batch_plan);
+    let state_file = progress.state_file();
+    if resume {
+        progress.resume()?;
+    } else if common.apply {
+        batch::BatchProgress::clear(&state_file)?;
     }
-    let since = if let Some(ref raw) = cmd.since {
-        let parsed = OffsetDateTime::parse(raw, &Rfc3339)
-            .with_context(|| format!("parsing --since '{raw}' as RFC3339 timestamp"))?;
-        Some(parsed)
+    let approved = if review_plan {
+        Some(review_plan_steps(&common, &batch_plan, &progress)?)
     } else {
         None
     };
-    let report_format = ReportFormat::from_str(&cmd.format)?;
-    let mut filtered = Vec::new();
-    for entry in entries {
-        let Ok(ts) = OffsetDateTime::parse(&entry.timestamp, &Rfc3339) else {
+    print_command_summary(
+        "batch",
+        &common,
+        &encoding,
+        &[],
+        &[format!(
+            "plan={} ({} steps)",
+            plan.display(),
+            batch_plan.steps.len()
+        )],
+    );
+    let mut previous_changed = false;
+    let mut outcomes = Vec::new();
+    let mut step_files = Vec::new();
+    let mut aborted = None;
+    for (idx, step) in batch_plan.steps.iter().enumerate() {
+        println!(
+            "\n=== Batch Step {}/{}: {} ===",
+            idx + 1,
+            batch_plan.steps.len(),
+            step.entry.kind()
+        );
+        if progress.is_done(idx) {
+            println!("skipping step: completed in an earlier run");
+            previous_changed = progress.changed(idx);
+            outcomes.push((
+                step.entry.kind(),
+                StepOutcome::Skipped("completed in an earlier run".to_string()),
+            ));
+            step_files.push(Vec::new());
             continue;
-        };
-        if since.is_none_or(|min| ts >= min) {
-            filtered.push(entry);
         }
-    }
-    if filtered.is_empty() {
-        println!("no log entries match the requested window.");
-        return Ok(());
-    }
-    let mut summary: BTreeMap<(String, String), usize> = BTreeMap::new();
-    for entry in &filtered {
-        *summary
-            .entry((entry.command.clone(), entry.action.clone()))
-            .or_default() += 1;
-    }
-    match report_format {
-        ReportFormat::Table => {
-            println!(
-                "Report entries: {} (since {})",
-                filtered.len(),
-                cmd.since.as_deref().unwrap_or("beginning of log")
-            );
-            for ((command, action), count) in summary {
-                println!("{command:<12} {action:<10} {count}");
+        if let Some(approved) = &approved
+            && !approved[idx]
+        {
+            println!("skipping step: not approved during plan review");
+            previous_changed = false;
+            outcomes.push((
+                step.entry.kind(),
+                StepOutcome::Skipped("not approved during plan review".to_string()),
+            ));
+            step_files.push(Vec::new());
+            continue;
+        }
+        if let Some(condition) = &step.when
+            && let Some(reason) = condition.unmet(previous_changed)?
+        {
+            println!("skipping step: {reason}");
+            previous_changed = false;
+            outcomes.push((step.entry.kind(), StepOutcome::Skipped(reason)));
+            step_files.push(Vec::new());
+            if common.apply {
+                progress.mark_done(idx, false, &state_file)?;
             }
+            continue;
         }
-        ReportFormat::Json => {
-            let rows: Vec<_> = summary
-                .into_iter()
-                .map(|((command, action), count)| {
-                    json!({
-                        "command": command,
-                        "action": action,
-                        "count": count
-                    })
-                })
-                .collect();
-            println!("{}", serde_json::to_string(&rows)?);
+        let applied_before = logging::applied_count();
+        let result = run_plan_step(
+            &common,
+            &batch_plan,
+            &step.entry,
+            approved.as_ref().map(|_| true),
+        );
+        step_files.push(logging::take_events());
+        let changed = logging::applied_count() - applied_before;
+        previous_changed = changed > 0;
+        match result {
+            Ok(()) => outcomes.push((step.entry.kind(), StepOutcome::Done(changed))),
+            Err(err) => {
+                println!("step {} failed: {err:#}", idx + 1);
+                let message = format!("{err:#}");
+                match step.on_error {
+                    batch::OnError::Abort => {
+                        outcomes.push((step.entry.kind(), StepOutcome::Failed(message)));
+                        aborted = Some(err);
+                        break;
+                    }
+                    batch::OnError::Skip => {
+                        outcomes.push((step.entry.kind(), StepOutcome::Ignored(message)))
+                    }
+                    batch::OnError::Continue => {
+                        outcomes.push((step.entry.kind(), StepOutcome::Failed(message)))
+                    }
+                }
+            }
+        }
+        if common.apply && !matches!(outcomes.last(), Some((_, StepOutcome::Failed(_)))) {
+            progress.mark_done(idx, previous_changed, &state_file)?;
         }
     }
+    print_batch_summary(&outcomes, batch_plan.steps.len());
+    if let Some(path) = &results {
+        write_batch_results(
+            path,
+            &plan,
+            started,
+            &outcomes,
+            step_files,
+            batch_plan.steps.len(),
+            aborted.is_some(),
+        )?;
+    }
+    let resume_hint = if common.apply {
+        "; rerun with --resume to pick up from there"
+    } else {
+        ""
+    };
+    if let Some(err) = aborted {
+        return Err(err.context(format!(
+            "batch aborted at step {} of {}{resume_hint}",
+            outcomes.len(),
+            batch_plan.steps.len()
+        )));
+    }
+    let failed = outcomes
+        .iter()
+        .filter(|(_, outcome)| matches!(outcome, StepOutcome::Failed(_)))
+        .count();
+    if failed > 0 {
+        bail!("{failed} batch step(s) failed{resume_hint}");
+    }
+    batch::BatchProgress::clear(&state_file)?;
     Ok(())
 }
 
-fn handle_cleanup(cmd: CleanupCommand) -> Result<()> {
-    let root = fs::canonicalize(&cmd.root)
-        .with_context(|| format!("resolving cleanup root {}", cmd.root.display()))?;
-    if !root.is_dir() {
-        bail!("cleanup root {} is not a directory", root.display());
-    }
-    let mut candidates = find_backup_files(&root, cmd.include_hidden)?;
-    candidates.sort();
-    if candidates.is_empty() {
-        println!("no .bak files found under {}", root.display());
-        return Ok(());
+enum StepOutcome {
+    Done(usize),
+    Skipped(String),
+    Ignored(String),
+    Failed(String),
+}
+
+fn print_batch_summary(outcomes: &[(&str, StepOutcome)], total: usize) {
+    println!("\n=== Batch Summary ===");
+    for line in batch_summary_lines(outcomes, total) {
+        println!("{line}");
     }
-    println!("cleanup root: {}", root.display());
-    println!("found {} backup file(s):", candidates.len());
-    for path in &candidates {
-        println!("  - {}", path.display());
+}
+
+fn batch_summary_lines(outcomes: &[(&str, StepOutcome)], total: usize) -> Vec<String> {
+    let mut lines = Vec::new();
+    for (idx, (kind, outcome)) in outcomes.iter().enumerate() {
+        let status = match outcome {
+            StepOutcome::Done(0) => "ok, no changes".to_string(),
+            StepOutcome::Done(changed) => format!("ok, changed {changed} file(s)"),
+            StepOutcome::Skipped(reason) => format!("skipped ({reason})"),
+            StepOutcome::Ignored(err) => format!("failed, ignored by on_error: skip ({err})"),
+            StepOutcome::Failed(err) => format!("FAILED ({err})"),
+        };
+        lines.push(format!("{:>3}. {kind:<10} {status}", idx + 1));
     }
-    if !cmd.apply {
-        println!("dry-run: rerun with --apply to delete these backups.");
-        return Ok(());
+    if outcomes.len() < total {
+        lines.push(format!(
+            "     {} later step(s) not run",
+            total - outcomes.len()
+        ));
     }
+    lines
+}
 
-    let mut stats = CommandStats::default();
-    let mut apply_all = cmd.auto_apply;
-    for path in candidates {
-        let decision = if apply_all {
-            ApprovalDecision::Apply
-        } else {
-            prompt_approval(&path)?
-        };
-        match decision {
-            ApprovalDecision::Apply => {
-                fs::remove_file(&path)
-                    .with_context(|| format!("removing backup {}", path.display()))?;
-                println!("removed {}", path.display());
-                stats.applied += 1;
-            }
-            ApprovalDecision::ApplyAll => {
-                apply_all = true;
-                fs::remove_file(&path)
-                    .with_context(|| format!("removing backup {}", path.display()))?;
-                println!("removed {}", path.display());
-                stats.applied += 1;
-            }
-            ApprovalDecision::Skip => {
-                println!("skipped {}", path.display());
-                stats.skipped += 1;
+fn review_plan_steps(
+    common: &CommonArgs,
+    batch_plan: &batch::BatchPlan,
+    progress: &batch::BatchProgress,
+) -> Result<Vec<bool>> {
+    review_plan_steps_in(common, batch_plan, progress, || {
+        input::ensure_prompt_allowed("--review-plan")?;
+        input::read_line()
+    })
+}
+
+fn review_plan_steps_in(
+    common: &CommonArgs,
+    batch_plan: &batch::BatchPlan,
+    progress: &batch::BatchProgress,
+    mut answer: impl FnMut() -> Result<String>,
+) -> Result<Vec<bool>> {
+    let total = batch_plan.steps.len();
+    let mut pending = Vec::new();
+    println!("\n=== Plan Review: previewing steps, nothing is written yet ===");
+    for (idx, step) in batch_plan.steps.iter().enumerate() {
+        if progress.is_done(idx) {
+            continue;
+        }
+        println!(
+            "\n=== Preview Step {}/{}: {} ===",
+            idx + 1,
+            total,
+            step.entry.kind()
+        );
+        if step.when.is_some() {
+            println!("note: this step's when: clause is checked again before it applies");
+        }
+        if let Err(err) = run_plan_step(common, batch_plan, &step.entry, Some(false)) {
+            println!("preview of step {} failed: {err:#}", idx + 1);
+        }
+        logging::take_events();
+        pending.push(idx);
+    }
+    let mut approved = vec![false; total];
+    if pending.is_empty() {
+        return Ok(approved);
+    }
+    println!(
+        "\nnote: previews show each step against the current files; later steps may differ once earlier ones apply."
+    );
+    loop {
+        print_prompt(&format!(
+            "Apply {} step(s) without further prompts? [y]es/[n]o/[s]elect: ",
+            pending.len()
+        ))?;
+        let input = answer()?;
+        match input.trim().to_lowercase().as_str() {
+            "y" | "yes" => {
+                for idx in &pending {
+                    approved[*idx] = true;
+                }
+                return Ok(approved);
             }
-            ApprovalDecision::Quit => {
-                println!("stopping cleanup after user request.");
-                break;
+            "n" | "no" | "" => return Ok(approved),
+            "s" | "select" => break,
+            other => println!("unrecognized option '{other}'"),
+        }
+    }
+    for idx in pending {
+        loop {
+            print_prompt(&format!(
+                "  apply step {} ({})? [y]es/[n]o: ",
+                idx + 1,
+                batch_plan.steps[idx].entry.kind()
+            ))?;
+            let input = answer()?;
+            match input.trim().to_lowercase().as_str() {
+                "y" | "yes" => approved[idx] = true,
+                "n" | "no" | "" => {}
+                other => {
+                    println!("unrecognized option '{other}'");
+                    continue;
+                }
             }
+            break;
         }
     }
-    stats.print("cleanup");
-    Ok(())
+    Ok(approved)
 }
 
-fn describe_spans(spans: &[logging::LineSpan]) -> String {
-    spans
+fn write_batch_results(
+    path: &Path,
+    plan: &Path,
+    started: OffsetDateTime,
+    outcomes: &[(&str, StepOutcome)],
+    step_files: Vec<Vec<JsonValue>>,
+    total: usize,
+    aborted: bool,
+) -> Result<()> {
+    let steps: Vec<JsonValue> = outcomes
         .iter()
-        .map(|span| {
-            let kind = match span.kind {
-                LineSpanKind::Modified => "M",
-                LineSpanKind::Added => "A",
+        .zip(step_files)
+        .enumerate()
+        .map(|(idx, ((kind, outcome), files))| {
+            let (status, changed, message) = match outcome {
+                StepOutcome::Done(changed) => ("ok", *changed, None),
+                StepOutcome::Skipped(reason) => ("skipped", 0, Some(reason)),
+                StepOutcome::Ignored(err) => ("ignored", 0, Some(err)),
+                StepOutcome::Failed(err) => ("failed", 0, Some(err)),
             };
-            if span.end > span.start {
-                format!("{kind} L{}-L{}", span.start, span.end)
-            } else {
-                format!("{kind} L{}", span.start)
+            let mut step = json!({
+                "step": idx + 1,
+                "kind": kind,
+                "status": status,
+                "changed": changed,
+                "files": files,
+            });
+            if let Some(message) = message {
+                let key = if status == "skipped" {
+                    "reason"
+                } else {
+                    "error"
+                };
+                step[key] = JsonValue::String(message.clone());
             }
+            step
         })
-        .collect::<Vec<_>>()
-        .join(", ")
+        .collect();
+    let failed = outcomes
+        .iter()
+        .any(|(_, outcome)| matches!(outcome, StepOutcome::Failed(_)));
+    let status = if aborted {
+        "aborted"
+    } else if failed {
+        "failed"
+    } else {
+        "ok"
+    };
+    let format_time = |time: OffsetDateTime| time.format(&Rfc3339).unwrap_or_default();
+    let document = json!({
+        "plan": plan.display().to_string(),
+        "status": status,
+        "started": format_time(started),
+        "finished": format_time(OffsetDateTime::now_utc()),
+        "total_steps": total,
+        "steps": steps,
+    });
+    fs::write(path, serde_json::to_string_pretty(&document)? + "\n")
+        .with_context(|| format!("writing batch results {}", path.display()))?;
+    println!("batch results written to {}", path.display());
+    Ok(())
 }
 
-fn find_backup_files(root: &Path, include_hidden: bool) -> Result<Vec<PathBuf>> {
-    let mut files = Vec::new();
-    let walker = WalkDir::new(root).follow_links(false).into_iter();
-    for entry in walker.filter_entry(|e| include_hidden || !has_hidden_component(e.path())) {
-        let entry = entry?;
-        if entry.file_type().is_file() {
-            let path = entry.into_path();
-            if is_backup_file(&path) {
-                files.push(path);
-            }
-        }
-    }
-    files.sort();
-    Ok(files)
+fn parse_plan_overrides(set: &[String]) -> Result<BTreeMap<String, String>> {
+    set.iter().map(|spec| template::parse_var(spec)).collect()
 }
 
-fn has_hidden_component(path: &Path) -> bool {
-    path.components().any(|component| match component {
-        Component::Normal(name) => name
-            .to_str()
-            .map(|segment| segment.starts_with('.'))
-            .unwrap_or(false),
-        _ => false,
-    })
+fn handle_batch_validate(cmd: BatchValidateCommand) -> Result<()> {
+    let BatchValidateCommand {
+        mut common,
+        plan,
+        set,
+    } = cmd;
+    let plan = positional_or_flag(plan, &mut common, "plan")?;
+    let batch_plan = batch::load_plan(&plan, &parse_plan_overrides(&set)?)?;
+    if batch_plan.steps.is_empty() {
+        bail!("plan {} does not contain any steps", plan.display());
+    }
+    println!(
+        "plan {} parsed: {} steps (nothing will be modified)\n",
+        plan.display(),
+        batch_plan.steps.len()
+    );
+    println!(
+        "{:>4}  {:<10} {:<5} {:<9} {:<24} status",
+        "step", "kind", "when", "on_error", "targets"
+    );
+    let mut failed = 0;
+    for (idx, step) in batch_plan.steps.iter().enumerate() {
+        let on_error = match step.on_error {
+            batch::OnError::Abort => "abort",
+            batch::OnError::Skip => "skip",
+            batch::OnError::Continue => "continue",
+        };
+        let (targets, status) = match validate_plan_step(&common, &step.entry).map_err(|err| {
+            format!("{err:#}")
+                .lines()
+                .map(str::trim)
+                .collect::<Vec<_>>()
+                .join(" ")
+        }) {
+            Ok(targets) => (targets, "ok".to_string()),
+            // A gated step may legitimately have nothing to act on yet.
+            Err(err) if step.when.is_some() => ("-".to_string(), format!("warning: {err}")),
+            Err(err) => {
+                failed += 1;
+                ("-".to_string(), format!("error: {err}"))
+            }
+        };
+        println!(
+            "{:>4}  {:<10} {:<5} {:<9} {:<24} {}",
+            idx + 1,
+            step.entry.kind(),
+            if step.when.is_some() { "yes" } else { "-" },
+            on_error,
+            targets,
+            status
+        );
+    }
+    if failed > 0 {
+        bail!("{failed} plan step(s) failed validation");
+    }
+    println!("\nplan is valid.");
+    Ok(())
 }
 
-fn is_backup_file(path: &Path) -> bool {
-    let Some(name) = path.file_name().and_then(|s| s.to_str()) else {
-        return false;
+fn validate_plan_step(common: &CommonArgs, entry: &batch::PlanEntry) -> Result<String> {
+    let describe = |entries: Vec<FileEntry>| format!("{} file(s)", entries.len());
+    let require_file = |path: &Path, what: &str| -> Result<()> {
+        if !path.is_file() {
+            bail!("{what} {} not found", path.display());
+        }
+        Ok(())
     };
-    let lower = name.to_ascii_lowercase();
-    if let Some((_base, suffix)) = lower.rsplit_once(".bak") {
-        return suffix.chars().all(|ch| ch.is_ascii_digit());
+    match entry {
+        batch::PlanEntry::Replace(step_plan) => {
+            let cmd = build_replace_command(common, step_plan)?;
+            if cmd.regex && !cmd.literal {
+                regex::Regex::new(&cmd.pattern)
+                    .with_context(|| format!("invalid regex '{}'", cmd.pattern))?;
+            }
+            resolve_encoding_strategy(&cmd.common)?;
+            Ok(describe(resolve_entries(&cmd.common)?))
+        }
+        batch::PlanEntry::Normalize(step_plan) => {
+            let cmd = build_normalize_command(common, step_plan)?;
+            ReportFormat::from_str(&cmd.report_format)?;
+            if let Some(label) = cmd.convert_encoding.as_deref()
+                && Encoding::for_label(label.trim().as_bytes()).is_none()
+            {
+                bail!("unknown convert-encoding '{}'", label.trim());
+            }
+            resolve_encoding_strategy(&cmd.common)?;
+            Ok(describe(resolve_entries(&cmd.common)?))
+        }
+        batch::PlanEntry::Block(step_plan) => {
+            let cmd = build_block_command(common, step_plan)?;
+            if let Some(path) = &cmd.body_file {
+                require_file(path, "body_file")?;
+            }
+            resolve_encoding_strategy(&cmd.common)?;
+            Ok(describe(resolve_entries(&cmd.common)?))
+        }
+        batch::PlanEntry::Insert(step_plan) => {
+            let cmd = build_insert_command(common, step_plan)?;
+            if let Some(path) = &cmd.body_file {
+                require_file(path, "body_file")?;
+            }
+            resolve_encoding_strategy(&cmd.common)?;
+            Ok(describe(resolve_entries(&cmd.common)?))
+        }
+        batch::PlanEntry::Rename(step_plan) => {
+            let cmd = build_rename_command(common, step_plan)?;
+            resolve_encoding_strategy(&cmd.common)?;
+            Ok(describe(resolve_entries(&cmd.common)?))
+        }
+        batch::PlanEntry::Write(step_plan) => {
+            let cmd = build_write_command(common, step_plan)?;
+            if let Some(path) = &cmd.body_file {
+                require_file(path, "body_file")?;
+            }
+            if let Some(path) = &cmd.template {
+                require_file(path, "template")?;
+            }
+            resolve_encoding_strategy(&cmd.common)?;
+            let state = if cmd.path.exists() { "exists" } else { "new" };
+            Ok(format!("{} ({state})", cmd.path.display()))
+        }
+        batch::PlanEntry::Apply(step_plan) => {
+            let cmd = build_apply_command(common, step_plan)?;
+            resolve_patch_root(cmd.root.as_ref())?;
+            let mut files = 0;
+            for patch_path in &cmd.patch_files {
+                files += load_file_patches(patch_path)
+                    .with_context(|| format!("reading patch {}", patch_path.display()))?
+                    .len();
+            }
+            Ok(format!("{files} file(s)"))
+        }
+        batch::PlanEntry::Script(step_plan) => Err(unsupported_script_step(step_plan)),
+        batch::PlanEntry::Include(step_plan) => {
+            bail!(
+                "include of {} was not expanded when the plan loaded",
+                step_plan.plan.display()
+            )
+        }
     }
-    false
-}
-
-fn log_change(
-    common: &CommonArgs,
-    command: &str,
-    path: &Path,
-    action: &str,
-    line_summary: &str,
-    spans: &[LineSpan],
-    extra: Option<JsonMap<String, JsonValue>>,
-) {
-    let _ = record_change(command, path, action, line_summary, spans);
-    emit_json_diff_event(common, command, path, action, line_summary, spans, extra);
 }
 
-fn emit_json_diff_event(
+fn run_plan_step(
     common: &CommonArgs,
-    command: &str,
-    path: &Path,
-    action: &str,
-    line_summary: &str,
-    spans: &[LineSpan],
-    extra: Option<JsonMap<String, JsonValue>>,
-) {
-    if !common.json {
-        return;
-    }
-    let mut event = JsonMap::new();
-    event.insert("command".into(), JsonValue::String(command.to_string()));
-    event.insert("path".into(), JsonValue::String(path.display().to_string()));
-    event.insert("action".into(), JsonValue::String(action.to_string()));
-    event.insert(
-        "line_summary".into(),
-        JsonValue::String(line_summary.to_string()),
-    );
-    event.insert("spans".into(), spans_to_json(spans));
-    if let Some(extra_map) = extra {
-        for (key, value) in extra_map {
-            event.insert(key, value);
+    batch_plan: &batch::BatchPlan,
+    entry: &batch::PlanEntry,
+    force_apply: Option<bool>,
+) -> Result<()> {
+    match entry {
+        batch::PlanEntry::Replace(step_plan) => {
+            let mut replace_cmd = build_replace_command(common, step_plan)?;
+            force_mode(&mut replace_cmd.common, force_apply);
+            handle_replace(replace_cmd)?;
+        }
+        batch::PlanEntry::Normalize(step_plan) => {
+            let mut normalize_cmd = build_normalize_command(common, step_plan)?;
+            force_mode(&mut normalize_cmd.common, force_apply);
+            handle_normalize(normalize_cmd)?;
+        }
+        batch::PlanEntry::Block(step_plan) => {
+            let mut block_cmd = build_block_command(common, step_plan)?;
+            force_mode(&mut block_cmd.common, force_apply);
+            handle_block(block_cmd)?;
+        }
+        batch::PlanEntry::Rename(step_plan) => {
+            let mut rename_cmd = build_rename_command(common, step_plan)?;
+            force_mode(&mut rename_cmd.common, force_apply);
+            handle_rename(rename_cmd)?;
+        }
+        batch::PlanEntry::Write(step_plan) => {
+            let mut write_cmd = build_write_command(common, step_plan)?;
+            force_mode(&mut write_cmd.common, force_apply);
+            if write_cmd.template.is_some() {
+                let mut vars = batch_plan.vars.clone();
+                vars.extend(step_plan.vars.clone());
+                write_cmd.vars = vars
+                    .into_iter()
+                    .map(|(key, value)| format!("{key}={value}"))
+                    .collect();
+            }
+            handle_write(write_cmd)?;
+        }
+        batch::PlanEntry::Apply(step_plan) => {
+            let mut apply_cmd = build_apply_command(common, step_plan)?;
+            force_mode(&mut apply_cmd.common, force_apply);
+            handle_apply(apply_cmd)?;
+        }
+        batch::PlanEntry::Insert(step_plan) => {
+            let mut insert_cmd = build_insert_command(common, step_plan)?;
+            force_mode(&mut insert_cmd.common, force_apply);
+            handle_block(insert_cmd)?;
+        }
+        batch::PlanEntry::Script(step_plan) => return Err(unsupported_script_step(step_plan)),
+        batch::PlanEntry::Include(step_plan) => {
+            bail!(
+                "include of {} was not expanded when the plan loaded",
+                step_plan.plan.display()
+            );
         }
     }
-    println!("{}", JsonValue::Object(event));
+    Ok(())
 }
 
-fn spans_to_json(spans: &[LineSpan]) -> JsonValue {
-    JsonValue::Array(
-        spans
-            .iter()
-            .map(|span| {
-                json!({
-                    "kind": match span.kind {
-                        LineSpanKind::Modified => "modified",
-                        LineSpanKind::Added => "added",
-                    },
-                    "start": span.start,
-                    "end": span.end
-                })
-            })
-            .collect(),
+// `script` has no transform runner yet, so a script step must not pass for a
+// step that ran and changed nothing (or be marked done for --resume).
+fn unsupported_script_step(step_plan: &batch::ScriptPlan) -> anyhow::Error {
+    anyhow!(
+        "script steps are not supported yet (script {}); remove the step or run the edit another way",
+        step_plan.script.display()
     )
 }
 
-fn status_extra(applied: bool, dry_run: bool) -> JsonMap<String, JsonValue> {
-    let mut map = JsonMap::new();
-    map.insert("applied".into(), JsonValue::Bool(applied));
-    map.insert("dry_run".into(), JsonValue::Bool(dry_run));
-    map
-}
-
-fn status_with_patch(applied: bool, dry_run: bool, kind: PatchKind) -> JsonMap<String, JsonValue> {
-    let mut map = status_extra(applied, dry_run);
-    map.insert(
-        "patch_kind".into(),
-        JsonValue::String(patch_kind_label(kind).to_string()),
-    );
-    map
-}
-
-fn patch_kind_label(kind: PatchKind) -> &'static str {
-    match kind {
-        PatchKind::Modify => "modify",
-        PatchKind::Create => "create",
-        PatchKind::Delete => "delete",
-        PatchKind::Rename => "rename",
+fn force_mode(common: &mut CommonArgs, force_apply: Option<bool>) {
+    if let Some(apply) = force_apply {
+        common.apply = apply;
+        common.auto_apply = apply;
     }
 }
 
-fn print_command_summary(
-    command: &str,
-    common: &CommonArgs,
-    encoding: &EncodingStrategy,
-    entries: &[FileEntry],
-    details: &[String],
-) {
-    println!("command: {command}");
-    println!(
-        "mode: {}{}",
-        if common.apply { "apply" } else { "dry-run" },
-        if common.auto_apply {
-            " (auto-approve)"
-        } else {
-            ""
-        }
-    );
-    if !common.targets.is_empty() {
-        println!("targets:");
-        for target in &common.targets {
-            println!("  - {}", target.display());
-        }
-    } else {
-        println!("targets: (none)");
-    }
-    println!("encoding strategy: {}", encoding.describe());
-    println!("context lines: {}", common.context);
-    println!("pager: {:?}", common.pager);
-    println!("json output: {}", common.json);
-    println!("include hidden: {}", common.include_hidden);
-    if !common.exclude.is_empty() {
-        println!("exclude globs: {:?}", common.exclude);
+fn handle_log(cmd: LogCommand) -> Result<()> {
+    let format = ReportFormat::from_str(&cmd.format)?;
+    let filter = logging::LogFilter {
+        path: cmd.path,
+        command: cmd.command,
+        action: cmd.action,
+        since: parse_log_bound("--since", cmd.since.as_deref())?,
+        until: parse_log_bound("--until", cmd.until.as_deref())?,
+    };
+    let all = logging::read_all()?;
+    let logged = all.len();
+    let mut entries: Vec<_> = all
+        .into_iter()
+        .filter(|entry| filter.matches(entry))
+        .collect();
+    entries.drain(..entries.len().saturating_sub(cmd.tail));
+    if let ReportFormat::Json = format {
+        println!("{}", serde_json::to_string(&entries)?);
+        return Ok(());
     }
-    if common.no_backup {
-        println!("backups disabled");
+    if logged == 0 {
+        println!("change log is empty.");
+        return Ok(());
     }
-    if let Some(log) = &common.undo_log {
-        println!("undo log dir: {}", log.display());
+    if entries.is_empty() {
+        println!("no log entries match the requested filters.");
+        return Ok(());
     }
-    if !common.globs.is_empty() {
-        println!("globs:");
-        for glob in &common.globs {
-            println!("  - {glob}");
+    for entry in entries {
+        println!(
+            "[{}] {:<10} {:<8} {:<12} {}",
+            entry.timestamp, entry.command, entry.action, entry.line_summary, entry.path
+        );
+        if !entry.spans.is_empty() {
+            println!("    spans: {}", describe_spans(&entry.spans));
         }
-    }
-
-    if entries.is_empty() {
-        println!("resolved files: (none)");
-    } else {
-        println!("resolved files ({}):", entries.len());
-        for entry in entries.iter().take(10) {
-            let binary_hint = if entry.metadata.is_probably_binary {
-                ", binary? yes"
-            } else {
-                ""
-            };
+        if entry.before_sha256.is_some() || entry.after_sha256.is_some() {
             println!(
-                "  - {} ({} bytes{})",
-                entry.path.display(),
-                entry.metadata.len,
-                binary_hint
+                "    sha256: {} -> {}",
+                entry.before_sha256.as_deref().unwrap_or("(none)"),
+                entry.after_sha256.as_deref().unwrap_or("(deleted)")
             );
         }
-        if entries.len() > 10 {
-            println!("  ...");
+        if let Some(patch) = &entry.undo_patch {
+            println!("    undo: {patch}");
         }
     }
-    if !common.extra_args.is_empty() {
-        println!("extra args: {:?}", common.extra_args);
+    Ok(())
+}
+
+fn handle_history(cmd: HistoryCommand) -> Result<()> {
+    let format = ReportFormat::from_str(&cmd.format)?;
+    let mut runs = logging::invocations(&logging::read_invocations()?, &logging::read_all()?);
+    runs.drain(..runs.len().saturating_sub(cmd.tail));
+    if let ReportFormat::Json = format {
+        println!("{}", serde_json::to_string(&runs)?);
+        return Ok(());
+    }
+    if runs.is_empty() {
+        println!("no recorded invocations yet.");
+        return Ok(());
     }
-    for detail in details {
-        println!("{detail}");
+    for run in runs.iter().rev() {
+        println!(
+            "{}  [{}] files={} applied={}",
+            run.invocation.id, run.timestamp, run.files, run.applied
+        );
+        println!("    safeedit {}", quote_argv(&run.invocation.argv));
+        println!("    in {}", run.invocation.cwd);
     }
-    println!("---");
+    Ok(())
 }
 
-fn resolve_entries(common: &CommonArgs) -> Result<Vec<FileEntry>> {
-    files::resolve_targets(
-        &common.targets,
-        &common.globs,
-        common.include_hidden,
-        &common.exclude,
-    )
+fn handle_rerun(cmd: RerunCommand) -> Result<()> {
+    let runs = logging::invocations(&logging::read_invocations()?, &logging::read_all()?);
+    let matches: Vec<_> = runs
+        .iter()
+        .filter(|run| run.invocation.id.starts_with(&cmd.id))
+        .collect();
+    let run = match matches.as_slice() {
+        [run] => *run,
+        [] => bail!(
+            "no recorded invocation matches '{}'; see `safeedit history`",
+            cmd.id
+        ),
+        _ => bail!(
+            "'{}' matches {} invocations; use more characters",
+            cmd.id,
+            matches.len()
+        ),
+    };
+    if run.invocation.argv.iter().any(|arg| arg == REDACTED) {
+        bail!(
+            "invocation {} was recorded with its body or URL redacted; repeat it by hand",
+            run.invocation.id
+        );
+    }
+    let mut argv = run.invocation.argv.clone();
+    if cmd.dry_run {
+        argv.retain(|arg| arg != "--apply" && arg != "--yes");
+    }
+    for (wanted, flag) in [(cmd.apply, "--apply"), (cmd.yes, "--yes")] {
+        if wanted && !argv.iter().any(|arg| arg == flag) {
+            argv.push(flag.to_string());
+        }
+    }
+    println!("rerunning {} in {}", run.invocation.id, run.invocation.cwd);
+    println!("safeedit {}", quote_argv(&argv));
+    let exe = std::env::current_exe().context("locating the safeedit executable")?;
+    let status = std::process::Command::new(exe)
+        .args(&argv)
+        .current_dir(&run.invocation.cwd)
+        .status()
+        .with_context(|| format!("re-running invocation {}", run.invocation.id))?;
+    if !status.success() {
+        bail!("rerun of {} exited with {status}", run.invocation.id);
+    }
+    Ok(())
 }
 
-fn resolve_encoding_strategy(common: &CommonArgs) -> Result<EncodingStrategy> {
-    EncodingStrategy::new(common.encoding.as_deref())
+fn quote_argv(argv: &[String]) -> String {
+    argv.iter()
+        .map(|arg| {
+            let plain = !arg.is_empty()
+                && arg
+                    .chars()
+                    .all(|ch| ch.is_ascii_alphanumeric() || "-_./:=@,+%".contains(ch));
+            if plain {
+                arg.clone()
+            } else {
+                format!("'{}'", arg.replace('\'', "'\\''"))
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
 }
 
-#[derive(Debug, Clone, Copy)]
-enum ApprovalDecision {
-    Apply,
-    Skip,
-    ApplyAll,
-    Quit,
+struct RecordedState {
+    hash: Option<String>,
+    timestamp: String,
+    source: String,
 }
 
-fn prompt_approval(path: &Path) -> Result<ApprovalDecision> {
-    loop {
-        print_prompt(&format!(
-            "Apply change to {}? [y]es/[n]o/[a]ll/[q]uit: ",
-            path.display()
-        ))?;
-        let mut input = String::new();
-        io::stdin().read_line(&mut input)?;
-        match input.trim().to_lowercase().as_str() {
-            "y" | "yes" | "" => return Ok(ApprovalDecision::Apply),
-            "n" | "no" => return Ok(ApprovalDecision::Skip),
-            "a" | "all" => return Ok(ApprovalDecision::ApplyAll),
-            "q" | "quit" => return Ok(ApprovalDecision::Quit),
-            _ => {
-                println!("Please enter y, n, a, or q.");
-            }
+fn handle_verify(cmd: VerifyCommand) -> Result<()> {
+    let format = ReportFormat::from_str(&cmd.format)?;
+    let mut recorded: BTreeMap<PathBuf, RecordedState> = BTreeMap::new();
+    match &cmd.session {
+        Some(id) => {
+            for record in session::load(id)? {
+                recorded.insert(
+                    record.path,
+                    RecordedState {
+                        hash: record.new_hash,
+                        timestamp: record.timestamp,
+                        source: format!("session {id}"),
+                    },
+                );
+            }
+        }
+        None => {
+            for entry in logging::read_all()? {
+                if entry.before_sha256.is_none() && entry.after_sha256.is_none() {
+                    continue;
+                }
+                recorded.insert(
+                    entry.absolute_path(),
+                    RecordedState {
+                        hash: entry.after_sha256,
+                        timestamp: entry.timestamp,
+                        source: entry.command,
+                    },
+                );
+            }
         }
     }
-}
-
-fn print_prompt(message: &str) -> Result<()> {
-    print!("{message}");
-    io::stdout().flush()?;
-    Ok(())
-}
-
-#[derive(Clone, Copy)]
-enum ReportFormat {
-    Table,
-    Json,
-}
+    if let Some(filter) = &cmd.path {
+        let wanted = std::path::absolute(filter).unwrap_or_else(|_| filter.clone());
+        recorded.retain(|path, _| path.starts_with(&wanted));
+    }
+    if recorded.is_empty() {
+        bail!("no recorded writes with content hashes to verify against");
+    }
 
-impl ReportFormat {
-    fn from_str(value: &str) -> Result<Self> {
-        match value.to_lowercase().as_str() {
-            "table" => Ok(Self::Table),
-            "json" => Ok(Self::Json),
-            other => Err(anyhow!(
-                "unsupported report-format '{other}' (expected table or json)"
-            )),
+    let mut rows = Vec::new();
+    let mut drifted = 0usize;
+    for (path, state) in &recorded {
+        let (drift, actual) = match StagedSource::from_recorded(path)? {
+            Some(source) => {
+                let actual = integrity::sha256_hex(&source.read()?);
+                (
+                    integrity::drift(state.hash.as_deref(), Some(&actual)),
+                    Some(actual),
+                )
+            }
+            None => integrity::check_recorded(path, state.hash.as_deref())?,
+        };
+        if drift != integrity::Drift::Intact {
+            drifted += 1;
         }
+        rows.push((path, state, drift, actual));
     }
-}
-
-fn print_normalize_report(
-    path: &Path,
-    report: &normalize::NormalizeReport,
-    encoding_name: Option<&str>,
-    convert_encoding: Option<&str>,
-    format: ReportFormat,
-) -> Result<()> {
     match format {
         ReportFormat::Table => {
+            for (path, state, drift, _) in &rows {
+                if *drift == integrity::Drift::Intact && !cmd.show_intact {
+                    continue;
+                }
+                println!(
+                    "{:<10} {} (last recorded {} by {})",
+                    drift.label(),
+                    path.display(),
+                    state.timestamp,
+                    state.source
+                );
+            }
             println!(
-                "{} -> zero-width: {}, control: {}, trailing spaces: {}, missing final newline: {}",
-                path.display(),
-                format_detection(report.zero_width),
-                format_detection(report.control_chars),
-                format_detection(report.trailing_spaces),
-                format_bool(report.missing_final_newline)
+                "verified {} file(s): {} intact, {} changed outside SafeEdit",
+                rows.len(),
+                rows.len() - drifted,
+                drifted
             );
-            match (encoding_name, convert_encoding) {
-                (Some(src), Some(dst)) => println!("    encoding: {src} -> {dst}"),
-                (Some(src), None) => println!("    encoding: {src}"),
-                (None, Some(dst)) => println!("    convert encoding: {dst}"),
-                _ => {}
-            }
         }
         ReportFormat::Json => {
-            let row = NormalizeJsonRow {
-                path: path.display().to_string(),
-                zero_width: report.zero_width,
-                control_chars: report.control_chars,
-                trailing_spaces: report.trailing_spaces,
-                missing_final_newline: report.missing_final_newline,
-                encoding: encoding_name.map(|s| s.to_string()),
-                convert_encoding: convert_encoding.map(|s| s.to_string()),
-            };
-            println!("{}", serde_json::to_string(&row)?);
+            let rows: Vec<_> = rows
+                .iter()
+                .map(|(path, state, drift, actual)| {
+                    json!({
+                        "path": path,
+                        "status": drift.label(),
+                        "expected_sha256": state.hash,
+                        "actual_sha256": actual,
+                        "recorded_at": state.timestamp,
+                        "recorded_by": state.source,
+                    })
+                })
+                .collect();
+            println!("{}", serde_json::to_string(&rows)?);
         }
     }
+    if drifted > 0 {
+        bail!("{drifted} file(s) changed outside SafeEdit since their last recorded write");
+    }
     Ok(())
 }
 
-fn format_detection(value: Option<usize>) -> String {
-    match value {
-        Some(count) => count.to_string(),
-        None => "n/a".into(),
+fn handle_checksum(cmd: ChecksumCommand) -> Result<()> {
+    if let Some(manifest_path) = &cmd.write {
+        let mut entries = resolve_entries(&cmd.common)?;
+        entries.retain(|entry| !skip_in_manifest(manifest_path, &entry.path));
+        if entries.is_empty() {
+            bail!("checksum --write needs at least one target (paths, --target, or --glob)");
+        }
+        let mut manifest = if cmd.update && manifest_path.exists() {
+            manifest::Manifest::load(manifest_path)?
+        } else {
+            manifest::Manifest::default()
+        };
+        for entry in &entries {
+            let bytes = fs::read(&entry.path)
+                .with_context(|| format!("reading {}", entry.path.display()))?;
+            manifest.insert(manifest_path, &entry.path, &bytes);
+        }
+        manifest.save(manifest_path)?;
+        println!(
+            "wrote {} with {} file(s)",
+            manifest_path.display(),
+            manifest.files.len()
+        );
+        return Ok(());
     }
-}
-
-fn format_bool(value: Option<bool>) -> &'static str {
-    match value {
-        Some(true) => "yes",
-        Some(false) => "no",
-        None => "n/a",
+    let Some(manifest_path) = &cmd.verify else {
+        bail!("pass --write MANIFEST or --verify MANIFEST");
+    };
+    let manifest = manifest::Manifest::load(manifest_path)?;
+    let mut rows: Vec<(String, &str, Option<String>, Option<String>)> = manifest
+        .verify(manifest_path)?
+        .into_iter()
+        .map(|checked| {
+            (
+                checked.path,
+                checked.drift.label(),
+                checked.expected,
+                checked.actual,
+            )
+        })
+        .collect();
+    // Selected files the manifest does not know about are reported too, so a
+    // new file cannot slip in unnoticed.
+    let (targets, globs) = cmd.common.selection();
+    if !targets.is_empty() || !globs.is_empty() {
+        for entry in resolve_entries(&cmd.common)? {
+            if skip_in_manifest(manifest_path, &entry.path)
+                || manifest.contains(manifest_path, &entry.path)
+            {
+                continue;
+            }
+            let bytes = fs::read(&entry.path)
+                .with_context(|| format!("reading {}", entry.path.display()))?;
+            rows.push((
+                manifest::key_for(manifest_path, &entry.path),
+                "untracked",
+                None,
+                Some(integrity::sha256_hex(&bytes)),
+            ));
+        }
+    }
+    let failed = rows
+        .iter()
+        .filter(|(_, status, ..)| *status != integrity::Drift::Intact.label())
+        .count();
+    match ReportFormat::from_str(&cmd.format)? {
+        ReportFormat::Table => {
+            for (path, status, ..) in &rows {
+                if *status == integrity::Drift::Intact.label() && !cmd.show_intact {
+                    continue;
+                }
+                println!("{status:<10} {path}");
+            }
+            println!(
+                "checked {} file(s) against {}: {} intact, {failed} mismatched",
+                rows.len(),
+                manifest_path.display(),
+                rows.len() - failed
+            );
+        }
+        ReportFormat::Json => {
+            let rows: Vec<_> = rows
+                .iter()
+                .map(|(path, status, expected, actual)| {
+                    json!({
+                        "path": path,
+                        "status": status,
+                        "expected_sha256": expected,
+                        "actual_sha256": actual,
+                    })
+                })
+                .collect();
+            println!("{}", serde_json::to_string(&rows)?);
+        }
     }
+    if failed > 0 {
+        bail!("{failed} file(s) do not match {}", manifest_path.display());
+    }
+    Ok(())
 }
 
-#[derive(Serialize)]
-struct NormalizeJsonRow {
-    path: String,
-    zero_width: Option<usize>,
-    control_chars: Option<usize>,
-    trailing_spaces: Option<usize>,
-    missing_final_newline: Option<bool>,
-    encoding: Option<String>,
-    convert_encoding: Option<String>,
+// The manifest itself and SafeEdit's own backups never belong in it.
+fn skip_in_manifest(manifest_path: &Path, path: &Path) -> bool {
+    is_backup_file(path)
+        || std::path::absolute(path).ok() == std::path::absolute(manifest_path).ok()
 }
 
-fn apply_transform(
-    entry: &FileEntry,
-    result: &TransformResult,
-    target_encoding: Option<&'static Encoding>,
-    undo_dir: Option<&Path>,
-    no_backup: bool,
-) -> Result<()> {
-    if let Some(dir) = undo_dir {
-        write_undo_patch(dir, entry, &result.decoded.text, &result.new_text)?;
-    }
-    let encoding = target_encoding.unwrap_or(result.decoded.decision.encoding);
-    let (encoded, _, had_errors) = encoding.encode(&result.new_text);
-    if had_errors {
-        println!(
-            "warning: encoding fallback occurred when writing {}; output may be lossy",
-            entry.path.display()
+fn handle_log_prune(cmd: LogPruneCommand) -> Result<()> {
+    let location = logging::location();
+    let keep = cmd.keep.or(location.retention.keep_rotated);
+    let max_age = match cmd.older_than.as_deref() {
+        Some(raw) => Some(parse_age(raw)?),
+        None => location.retention.max_age,
+    };
+    if keep.is_none() && max_age.is_none() {
+        bail!(
+            "nothing to prune: pass --keep N or --older-than AGE (or set [log] keep_rotated / retain_days)"
         );
     }
-    if let Some(parent) = entry.path.parent() {
-        if !parent.as_os_str().is_empty() {
-            fs::create_dir_all(parent)
-                .with_context(|| format!("creating directory {}", parent.display()))?;
-        }
-    }
-    let backup = create_backup_if_needed(&entry.path, no_backup)?;
-    write_via_temp(&entry.path, encoded.as_ref())
-        .with_context(|| format!("writing {}", entry.path.display()))?;
-    if let Some(bak) = backup {
+    let candidates = logging::prunable_logs(&location.file, keep, max_age)?;
+    if candidates.is_empty() {
         println!(
-            "backup saved: {} -> {}",
-            entry.path.display(),
-            bak.display()
+            "no rotated change logs to prune next to {}",
+            location.file.display()
         );
+        return Ok(());
     }
-    println!("applied {}", entry.path.display());
-    Ok(())
-}
-
-fn create_backup_if_needed(path: &Path, no_backup: bool) -> Result<Option<PathBuf>> {
-    if no_backup || !path.exists() {
-        return Ok(None);
+    let mut total_bytes = 0u64;
+    println!("found {} rotated log(s):", candidates.len());
+    for path in &candidates {
+        let size = fs::metadata(path)
+            .map(|metadata| metadata.len())
+            .unwrap_or(0);
+        total_bytes += size;
+        println!("  - {} ({})", path.display(), format_bytes(size));
     }
-
-    let mut attempt = 0usize;
-    loop {
-        let candidate = backup_candidate(path, attempt);
-        if !candidate.exists() {
-            fs::copy(path, &candidate)
-                .with_context(|| format!("creating backup {}", candidate.display()))?;
-            return Ok(Some(candidate));
+    println!("total to reclaim: {}", format_bytes(total_bytes));
+    if !cmd.apply {
+        println!("dry-run: rerun with --apply to delete these logs.");
+        return Ok(());
+    }
+    let mut stats = CommandStats::default();
+    let mut apply_all = cmd.auto_apply;
+    for path in candidates {
+        let decision = if apply_all {
+            ApprovalDecision::Apply
+        } else {
+            prompt_approval(&path, false)?
+        };
+        match decision {
+            ApprovalDecision::Apply | ApprovalDecision::ApplyAll => {
+                apply_all |= matches!(decision, ApprovalDecision::ApplyAll);
+                fs::remove_file(&path).with_context(|| format!("removing {}", path.display()))?;
+                println!("removed {}", path.display());
+                stats.applied += 1;
+            }
+            ApprovalDecision::Skip => stats.skipped += 1,
+            ApprovalDecision::Quit => break,
         }
-        attempt += 1;
     }
+    stats.print("log prune");
+    Ok(())
 }
 
-fn backup_candidate(path: &Path, index: usize) -> PathBuf {
-    let name = path
-        .file_name()
-        .and_then(|name| name.to_str())
-        .unwrap_or("safeedit_file");
-    let suffix = if index == 0 {
-        ".bak".to_string()
-    } else {
-        format!(".bak{index}")
-    };
-    let backup_name = format!("{name}{suffix}");
-    path.with_file_name(backup_name)
+fn parse_log_bound(flag: &str, raw: Option<&str>) -> Result<Option<OffsetDateTime>> {
+    raw.map(|raw| {
+        parse_time_bound(raw, OffsetDateTime::now_utc())
+            .with_context(|| format!("parsing {flag} '{raw}'"))
+    })
+    .transpose()
 }
 
-fn write_via_temp(path: &Path, data: &[u8]) -> Result<()> {
-    let parent = path.parent().filter(|p| !p.as_os_str().is_empty());
-    if let Some(dir) = parent {
-        fs::create_dir_all(dir).with_context(|| format!("creating directory {}", dir.display()))?;
+fn parse_time_bound(raw: &str, now: OffsetDateTime) -> Result<OffsetDateTime> {
+    let trimmed = raw.trim();
+    if let Ok(parsed) = OffsetDateTime::parse(trimmed, &Rfc3339) {
+        return Ok(parsed);
     }
-    let base_dir = parent.unwrap_or_else(|| Path::new("."));
-    let unique = format!(
-        ".safeedit-tmp-{}-{}",
-        std::process::id(),
-        OffsetDateTime::now_utc().unix_timestamp_nanos()
-    );
-    let temp_path = base_dir.join(unique);
-    {
-        let mut file = fs::File::create(&temp_path)
-            .with_context(|| format!("creating temp file {}", temp_path.display()))?;
-        file.write_all(data)
-            .with_context(|| format!("writing temp file {}", temp_path.display()))?;
-        file.sync_all()
-            .with_context(|| format!("syncing temp file {}", temp_path.display()))?;
+    let midnight = now.replace_time(time::Time::MIDNIGHT);
+    match trimmed.to_ascii_lowercase().as_str() {
+        "now" => return Ok(now),
+        "today" => return Ok(midnight),
+        "yesterday" => return Ok(midnight - time::Duration::DAY),
+        _ => {}
     }
-    fs::rename(&temp_path, path).or_else(|err| {
-        let _ = fs::remove_file(&temp_path);
-        Err(err).with_context(|| format!("replacing {}", path.display()))
-    })?;
-    Ok(())
+    if let Some(date) = parse_calendar_date(trimmed) {
+        return Ok(date.midnight().assume_utc());
+    }
+    if trimmed.starts_with(|ch: char| ch.is_ascii_digit()) {
+        let age = parse_age(trimmed.strip_suffix(" ago").unwrap_or(trimmed))?;
+        return Ok(now - age);
+    }
+    bail!(
+        "expected an RFC3339 timestamp, a date (2025-11-08), today/yesterday/now, or an age such as 7d or 12h"
+    )
 }
 
-fn write_new_file(
-    path: &Path,
-    new_text: &str,
-    encoding: &EncodingStrategy,
-    undo_dir: Option<&Path>,
-    no_backup: bool,
-) -> Result<()> {
-    let decision = encoding.decide(b"");
-    let decoded = DecodedText {
-        text: String::new(),
-        had_errors: false,
-        decision: decision.clone(),
-    };
-    let entry = FileEntry {
-        path: path.to_path_buf(),
-        metadata: FileMetadata {
-            len: 0,
-            is_probably_binary: false,
-        },
-    };
-    let result = TransformResult {
-        decoded,
-        new_text: new_text.to_string(),
-    };
-    apply_transform(
-        &entry,
-        &result,
-        Some(decision.encoding),
-        undo_dir,
-        no_backup,
-    )
+fn parse_calendar_date(raw: &str) -> Option<time::Date> {
+    let mut parts = raw.splitn(3, '-');
+    let year: i32 = parts.next()?.parse().ok()?;
+    let month: u8 = parts.next()?.parse().ok()?;
+    let day: u8 = parts.next()?.parse().ok()?;
+    time::Date::from_calendar_date(year, time::Month::try_from(month).ok()?, day).ok()
 }
 
-fn delete_file_with_undo(
-    path: &Path,
-    old_text: &str,
-    undo_dir: Option<&Path>,
-    no_backup: bool,
-) -> Result<()> {
-    let entry = FileEntry {
-        path: path.to_path_buf(),
-        metadata: FileMetadata {
-            len: old_text.len() as u64,
-            is_probably_binary: false,
-        },
-    };
-    if let Some(dir) = undo_dir {
-        write_undo_patch(dir, &entry, old_text, "")?;
+fn handle_report(cmd: ReportCommand) -> Result<()> {
+    let entries = logging::read_all()?;
+    if entries.is_empty() {
+        println!("change log is empty.");
+        return Ok(());
     }
-    if path.exists() {
-        let backup = create_backup_if_needed(path, no_backup)?;
-        if let Some(bak) = backup {
-            println!("backup saved: {} -> {}", path.display(), bak.display());
+    let since = parse_log_bound("--since", cmd.since.as_deref())?;
+    let until = parse_log_bound("--until", cmd.until.as_deref())?;
+    let report_format = report::ReportOutput::from_str(&cmd.format)?;
+    let mut filtered = Vec::new();
+    for entry in entries {
+        let Ok(ts) = OffsetDateTime::parse(&entry.timestamp, &Rfc3339) else {
+            continue;
+        };
+        if since.is_none_or(|min| ts >= min) && until.is_none_or(|max| ts <= max) {
+            filtered.push(entry);
         }
-        fs::remove_file(path).with_context(|| format!("removing {}", path.display()))?;
     }
-    println!("deleted {}", path.display());
+    if filtered.is_empty() {
+        println!("no log entries match the requested window.");
+        return Ok(());
+    }
+    let format_bound =
+        |bound: Option<OffsetDateTime>| bound.and_then(|bound| bound.format(&Rfc3339).ok());
+    let mut window = format_bound(since).unwrap_or_else(|| "beginning of log".to_string());
+    if let Some(until) = format_bound(until) {
+        window.push_str(&format!(" until {until}"));
+    }
+    let data = report::ReportData::build(&filtered, &window);
+    let rendered = report::render(&data, report_format, cmd.group_by, cmd.top)?;
+    match &cmd.out {
+        Some(path) => {
+            fs::write(path, rendered).with_context(|| format!("writing {}", path.display()))?;
+            println!("report written to {}", path.display());
+        }
+        None => print!("{rendered}"),
+    }
     Ok(())
 }
 
-fn write_undo_patch(dir: &Path, entry: &FileEntry, old_text: &str, new_text: &str) -> Result<()> {
-    fs::create_dir_all(dir).with_context(|| format!("creating undo dir {}", dir.display()))?;
-    let timestamp = OffsetDateTime::now_utc()
-        .format(&Rfc3339)
-        .unwrap_or_else(|_| "unknown".into());
-    let sanitized = sanitize_path(&entry.path);
-    let file_name = format!("{timestamp}_{sanitized}.patch");
-    let patch_path = dir.join(file_name);
-    let diff = diff::unified_diff(&entry.path, &entry.path, new_text, old_text, 3);
-    fs::write(&patch_path, diff)
-        .with_context(|| format!("writing undo patch {}", patch_path.display()))?;
-    Ok(())
-}
-
-fn sanitize_path(path: &Path) -> String {
-    path.display()
-        .to_string()
-        .chars()
-        .map(|ch| match ch {
-            '\\' | '/' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
-            _ => ch,
-        })
-        .collect()
-}
+fn handle_cleanup(cmd: CleanupCommand) -> Result<()> {
+    let root = fs::canonicalize(&cmd.root)
+        .with_context(|| format!("resolving cleanup root {}", cmd.root.display()))?;
+    if !root.is_dir() {
+        bail!("cleanup root {} is not a directory", root.display());
+    }
+    let patterns = build_cleanup_patterns(&cmd.patterns)?;
+    let max_age = cmd
+        .older_than
+        .as_deref()
+        .map(parse_age)
+        .transpose()?
+        .map(|age| std::time::SystemTime::now() - age);
+    let min_size = cmd.min_size.as_deref().map(parse_size).transpose()?;
+    let mut found: Vec<(PathBuf, &'static str)> = Vec::new();
+    if !cmd.skip_backups {
+        for path in find_backup_files(&root, cmd.include_hidden, patterns.as_ref())? {
+            found.push((path, "backup"));
+        }
+        if let Some(dir) = &cmd.backup_dir
+            && dir.is_dir()
+        {
+            for path in find_backup_files(dir, true, patterns.as_ref())? {
+                if !found.iter().any(|(existing, _)| existing == &path) {
+                    found.push((path, "backup"));
+                }
+            }
+        }
+    }
+    if cmd.temp_files {
+        for path in journal::find_temp_files(&root)? {
+            found.push((path, "temp"));
+        }
+    }
+    for dir in &cmd.undo_logs {
+        for path in find_undo_patches(dir)? {
+            found.push((path, "undo"));
+        }
+    }
+    let mut candidates = Vec::new();
+    let mut total_bytes = 0u64;
+    for (path, category) in found {
+        let metadata =
+            fs::metadata(&path).with_context(|| format!("reading {}", path.display()))?;
+        if min_size.is_some_and(|min| metadata.len() < min) {
+            continue;
+        }
+        if let Some(cutoff) = max_age
+            && metadata.modified().is_ok_and(|modified| modified > cutoff)
+        {
+            continue;
+        }
+        total_bytes += metadata.len();
+        candidates.push((path, metadata.len(), category));
+    }
+    candidates.sort();
+    if candidates.is_empty() {
+        println!(
+            "no matching cleanup candidates found under {}",
+            root.display()
+        );
+        return Ok(());
+    }
+    println!("cleanup root: {}", root.display());
+    println!("found {} file(s):", candidates.len());
+    for (path, size, category) in &candidates {
+        println!(
+            "  - [{category}] {} ({})",
+            path.display(),
+            format_bytes(*size)
+        );
+    }
+    println!("total to reclaim: {}", format_bytes(total_bytes));
+    if !cmd.apply {
+        println!("dry-run: rerun with --apply to delete these files.");
+        return Ok(());
+    }
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
-enum LineEndingStyle {
-    Lf,
-    Crlf,
-    Cr,
+    let mut stats = CommandStats::default();
+    let mut apply_all = cmd.auto_apply;
+    for (path, _, _) in candidates {
+        let decision = if apply_all {
+            ApprovalDecision::Apply
+        } else {
+            prompt_approval(&path, false)?
+        };
+        match decision {
+            ApprovalDecision::Apply => {
+                fs::remove_file(&path).with_context(|| format!("removing {}", path.display()))?;
+                println!("removed {}", path.display());
+                stats.applied += 1;
+            }
+            ApprovalDecision::ApplyAll => {
+                apply_all = true;
+                fs::remove_file(&path).with_context(|| format!("removing {}", path.display()))?;
+                println!("removed {}", path.display());
+                stats.applied += 1;
+            }
+            ApprovalDecision::Skip => {
+                println!("skipped {}", path.display());
+                stats.skipped += 1;
+            }
+            ApprovalDecision::Quit => {
+                println!("stopping cleanup after user request.");
+                break;
+            }
+        }
+    }
+    for dir in &cmd.undo_logs {
+        if fs::read_dir(dir).is_ok_and(|mut listing| listing.next().is_none()) {
+            fs::remove_dir(dir).with_context(|| format!("removing {}", dir.display()))?;
+            println!("removed empty undo log dir {}", dir.display());
+        }
+    }
+    stats.print("cleanup");
+    Ok(())
 }
 
-fn detect_line_ending_style(text: &str) -> LineEndingStyle {
-    if text.contains("\r\n") {
-        LineEndingStyle::Crlf
-    } else if text.contains('\r') {
-        LineEndingStyle::Cr
-    } else {
-        LineEndingStyle::Lf
+fn find_undo_patches(dir: &Path) -> Result<Vec<PathBuf>> {
+    let Ok(listing) = fs::read_dir(dir) else {
+        return Ok(Vec::new());
+    };
+    let mut patches = Vec::new();
+    for item in listing {
+        let path = item?.path();
+        if path.is_file() && path.extension().is_some_and(|ext| ext == "patch") {
+            patches.push(path);
+        }
     }
+    patches.sort();
+    Ok(patches)
 }
 
-fn normalize_to_lf(text: &str) -> Cow<'_, str> {
-    if !text.contains('\r') {
-        return Cow::Borrowed(text);
+fn handle_recover(cmd: RecoverCommand) -> Result<()> {
+    let root = fs::canonicalize(&cmd.root)
+        .with_context(|| format!("resolving recover root {}", cmd.root.display()))?;
+    let pending = journal::pending()?;
+    let temps = journal::find_temp_files(&root)?;
+    if pending.is_empty() && temps.is_empty() {
+        println!("nothing to recover: no journal entries or leftover temp files.");
+        return Ok(());
     }
-    let mut normalized = String::with_capacity(text.len());
-    let mut chars = text.chars().peekable();
-    while let Some(ch) = chars.next() {
-        match ch {
-            '\r' => {
-                if matches!(chars.peek(), Some('\n')) {
-                    chars.next();
+    println!("found {} interrupted write(s):", pending.len());
+    for write in &pending {
+        println!(
+            "  - {} (started {}, pid {}): {}",
+            write.entry.path.display(),
+            write.entry.started,
+            write.entry.pid,
+            write.state.label()
+        );
+    }
+    println!(
+        "found {} leftover temp file(s) under {}:",
+        temps.len(),
+        root.display()
+    );
+    for path in &temps {
+        println!("  - {}", path.display());
+    }
+    if !cmd.apply {
+        println!("dry-run: rerun with --apply to finish, roll back, or remove these.");
+        return Ok(());
+    }
+    if cmd.auto_apply && cmd.action.is_none() && !pending.is_empty() {
+        bail!("--yes needs --action finish|rollback to resolve interrupted writes");
+    }
+
+    let mut stats = CommandStats::default();
+    for listed in &pending {
+        let path = &listed.entry.path;
+        let _lock = lock::lock_path(path)?;
+        let Some(write) = listed.reload()? else {
+            println!("{} was already resolved", path.display());
+            stats.no_op += 1;
+            continue;
+        };
+        if write.writer_running() {
+            println!(
+                "skipped {} (pid {} is still running)",
+                path.display(),
+                write.entry.pid
+            );
+            stats.skipped += 1;
+            continue;
+        }
+        if write.state == journal::JournalState::Completed {
+            write.discard()?;
+            println!("cleared completed journal entry for {}", path.display());
+            stats.no_op += 1;
+            continue;
+        }
+        let action = match cmd.action {
+            Some(action) if cmd.auto_apply => action,
+            _ => match prompt_recover_action(path, cmd.action)? {
+                Some(action) => action,
+                None => {
+                    println!("skipped {}", path.display());
+                    stats.skipped += 1;
+                    continue;
                 }
-                normalized.push('\n');
+            },
+        };
+        match action {
+            RecoverAction::Finish => {
+                write_via_temp(path, &write.new_bytes()?, true)?;
+                println!("finished interrupted write to {}", path.display());
             }
-            _ => normalized.push(ch),
+            RecoverAction::Rollback => match write.original_bytes()? {
+                Some(bytes) => {
+                    if write.state != journal::JournalState::NotStarted {
+                        write_via_temp(path, &bytes, true)?;
+                    }
+                    println!("rolled back {}", path.display());
+                }
+                None => {
+                    if path.exists() {
+                        fs::remove_file(path)
+                            .with_context(|| format!("removing {}", path.display()))?;
+                    }
+                    println!("rolled back creation of {}", path.display());
+                }
+            },
         }
+        write.discard()?;
+        stats.applied += 1;
     }
-    Cow::Owned(normalized)
-}
 
-fn restore_from_lf(text: String, style: LineEndingStyle) -> String {
-    match style {
-        LineEndingStyle::Lf => text,
-        LineEndingStyle::Crlf => text.replace('\n', "\r\n"),
-        LineEndingStyle::Cr => text.replace('\n', "\r"),
+    let mut apply_all = cmd.auto_apply;
+    for path in temps {
+        let decision = if apply_all {
+            ApprovalDecision::Apply
+        } else {
+            prompt_approval(&path, false)?
+        };
+        match decision {
+            ApprovalDecision::Apply | ApprovalDecision::ApplyAll => {
+                apply_all |= matches!(decision, ApprovalDecision::ApplyAll);
+                fs::remove_file(&path)
+                    .with_context(|| format!("removing temp file {}", path.display()))?;
+                println!("removed {}", path.display());
+                stats.applied += 1;
+            }
+            ApprovalDecision::Skip => {
+                println!("skipped {}", path.display());
+                stats.skipped += 1;
+            }
+            ApprovalDecision::Quit => {
+                println!("stopping recover after user request.");
+                break;
+            }
+        }
     }
+    stats.print("recover");
+    Ok(())
 }
 
-fn apply_patch_preserving_newlines<'a>(
-    text: &str,
-    parsed_patch: &DiffPatch<'a, str>,
-) -> std::result::Result<String, diffy::ApplyError> {
-    let style = detect_line_ending_style(text);
-    let normalized = normalize_to_lf(text);
-    let patched = apply_patch(normalized.as_ref(), parsed_patch)?;
-    Ok(restore_from_lf(patched, style))
-}
-
-#[derive(Default)]
-struct CommandStats {
-    applied: usize,
-    skipped: usize,
-    dry_run: usize,
-    no_op: usize,
-}
-
-impl CommandStats {
-    fn print(&self, label: &str) {
-        let total = self.applied + self.skipped + self.dry_run + self.no_op;
-        if total == 0 {
-            return;
+fn handle_restore(cmd: RestoreCommand) -> Result<()> {
+    let backups = backup::list(&cmd.target, cmd.backup_dir.as_deref())?;
+    let source = match &cmd.from {
+        Some(path) => path.clone(),
+        None => match backups.last() {
+            Some(path) => path.clone(),
+            None => bail!(
+                "no backups found for {}{}",
+                cmd.target.display(),
+                cmd.backup_dir
+                    .as_ref()
+                    .map(|dir| format!(" in {}", dir.display()))
+                    .unwrap_or_default()
+            ),
+        },
+    };
+    if backups.len() > 1 {
+        println!("available backups (oldest first):");
+        for path in &backups {
+            println!("  - {}", path.display());
         }
+    }
+    let restored = backup::read(&source)?;
+    let current = fs::read(&cmd.target).unwrap_or_default();
+    println!("restore {} from {}", cmd.target.display(), source.display());
+    if restored == current {
         println!(
-            "{label} summary: applied={}, skipped={}, dry-run={}, no-op={}",
-            self.applied, self.skipped, self.dry_run, self.no_op
+            "{} already matches the backup; nothing to do.",
+            cmd.target.display()
         );
+        return Ok(());
+    }
+    let strategy = EncodingStrategy::new(None)?;
+    let old_text = strategy.decode(&current).text;
+    let new_text = strategy.decode(&restored).text;
+    let diff_config = diff::DiffDisplayConfig {
+        context: 3,
+        colorize: cmd.color.should_color(),
+        pager_mode: PagerMode::Auto,
+        interactive: io::stdout().is_terminal(),
+        format: DiffFormat::Inline,
+        line_numbers: false,
+        page_lines: None,
+        limits: diff::DiffLimits::resolve(None, None),
+    };
+    diff::display_diff(&cmd.target, &cmd.target, &old_text, &new_text, &diff_config)?;
+    if !cmd.apply {
+        println!("dry-run: rerun with --apply to restore this backup.");
+        return Ok(());
     }
+    let decision = if cmd.auto_apply {
+        ApprovalDecision::Apply
+    } else {
+        prompt_approval(&cmd.target, false)?
+    };
+    match decision {
+        ApprovalDecision::Apply | ApprovalDecision::ApplyAll => {
+            let _lock = lock::lock_path(&cmd.target)?;
+            let options = backup::BackupOptions {
+                dir: cmd.backup_dir.clone(),
+                ..backup::BackupOptions::default()
+            };
+            if let Some(saved) = backup::create(&cmd.target, &options)? {
+                println!(
+                    "backup saved: {} -> {}",
+                    cmd.target.display(),
+                    saved.display()
+                );
+            }
+            let before = fs::read(&cmd.target).ok();
+            write_via_temp(&cmd.target, &restored, true)?;
+            println!("restored {}", cmd.target.display());
+            logging::note_write(
+                &cmd.target,
+                logging::WriteRecord {
+                    before_sha256: before.as_deref().map(integrity::sha256_hex),
+                    after_sha256: Some(integrity::sha256_hex(&restored)),
+                    undo_patch: None,
+                },
+            );
+            let summary = diff::summarize_lines(&old_text, &new_text);
+            let spans = diff::collect_line_spans(&old_text, &new_text);
+            let _ = record_change("restore", &cmd.target, "applied", &summary, &spans);
+        }
+        ApprovalDecision::Skip | ApprovalDecision::Quit => {
+            println!("skipped {}", cmd.target.display());
+        }
+    }
+    Ok(())
 }
 
-fn merge_common(base: &CommonArgs, overrides: &batch::PlanCommon) -> CommonArgs {
-    let mut merged = base.clone();
-    if let Some(targets) = &overrides.targets {
-        merged.targets = targets.clone();
-    }
-    if let Some(globs) = &overrides.globs {
-        merged.globs = globs.clone();
+fn handle_undo(cmd: UndoCommand) -> Result<()> {
+    let staged = StagedSource::from_recorded(&cmd.path)?;
+    let wanted = match &staged {
+        Some(source) => source.recorded_name(),
+        None => std::path::absolute(&cmd.path).unwrap_or_else(|_| cmd.path.clone()),
+    };
+    let logged = logging::read_all()?;
+    let Some(entry) = latest_applied(&logged, &wanted) else {
+        bail!(
+            "no applied change to {} in the change log",
+            cmd.path.display()
+        );
+    };
+    println!(
+        "latest change: [{}] {} {} ({})",
+        entry.timestamp, entry.command, entry.path, entry.line_summary
+    );
+    let current = match &staged {
+        Some(source) => Some(source.read()?),
+        None => fs::read(&cmd.path).ok(),
+    };
+    let current_hash = current.as_deref().map(integrity::sha256_hex);
+    if entry.after_sha256.is_some() && current_hash != entry.after_sha256 {
+        if !cmd.force {
+            bail!(
+                "{} changed after that edit; pass --force to undo it anyway",
+                cmd.path.display()
+            );
+        }
+        println!(
+            "warning: {} changed after that edit; undoing anyway (--force)",
+            cmd.path.display()
+        );
     }
-    if let Some(encoding) = &overrides.encoding {
-        merged.encoding = Some(encoding.clone());
+    let (source, restored) =
+        find_undo_source(&cmd, entry, &wanted, current.as_deref(), staged.is_some())?;
+    println!("undo {} using {source}", cmd.path.display());
+    let current = current.unwrap_or_default();
+    if restored.as_deref() == Some(current.as_slice()) {
+        println!(
+            "{} already matches the state before that change; nothing to do.",
+            cmd.path.display()
+        );
+        return Ok(());
     }
-    if let Some(apply) = overrides.apply {
-        merged.apply = apply;
+    let strategy = EncodingStrategy::new(None)?;
+    let old_text = strategy.decode(&current).text;
+    let new_text = strategy
+        .decode(restored.as_deref().unwrap_or_default())
+        .text;
+    let diff_config = diff::DiffDisplayConfig {
+        context: 3,
+        colorize: cmd.color.should_color(),
+        pager_mode: PagerMode::Auto,
+        interactive: io::stdout().is_terminal(),
+        format: DiffFormat::Inline,
+        line_numbers: false,
+        page_lines: None,
+        limits: diff::DiffLimits::resolve(None, None),
+    };
+    let restored_bytes = restored.as_deref().unwrap_or_default();
+    if current.contains(&0) || restored_bytes.contains(&0) {
+        for line in binpatch::preview(&current, restored_bytes, 2, cmd.color.should_color()) {
+            println!("{line}");
+        }
+    } else {
+        diff::display_diff(&cmd.path, &cmd.path, &old_text, &new_text, &diff_config)?;
     }
-    if let Some(auto) = overrides.auto_apply {
-        merged.auto_apply = auto;
+    if !cmd.apply {
+        println!("dry-run: rerun with --apply to undo this change.");
+        return Ok(());
     }
-    if let Some(no_backup) = overrides.no_backup {
-        merged.no_backup = no_backup;
+    let decision = if cmd.auto_apply {
+        ApprovalDecision::Apply
+    } else {
+        prompt_approval(&cmd.path, false)?
+    };
+    if matches!(decision, ApprovalDecision::Skip | ApprovalDecision::Quit) {
+        println!("skipped {}", cmd.path.display());
+        return Ok(());
     }
-    if let Some(context) = overrides.context {
-        merged.context = context;
+    let summary = diff::summarize_lines(&old_text, &new_text);
+    let spans = diff::collect_line_spans(&old_text, &new_text);
+    let options = backup::BackupOptions {
+        dir: cmd.backup_dir.clone(),
+        ..backup::BackupOptions::default()
+    };
+    if let Some(staged) = &staged {
+        let Some(bytes) = &restored else {
+            bail!("{staged} was created by that change; undo cannot remove it");
+        };
+        staged.write_back(bytes, &current, &options, true)?;
+        logging::note_write(
+            &wanted,
+            logging::WriteRecord {
+                before_sha256: current_hash,
+                after_sha256: Some(integrity::sha256_hex(bytes)),
+                undo_patch: None,
+            },
+        );
+        println!("undid the last change to {staged}");
+        let _ = record_change("undo", &wanted, "applied", &summary, &spans);
+        return Ok(());
     }
-    if let Some(pager) = overrides.pager {
-        merged.pager = pager;
+    let _lock = lock::lock_path(&cmd.path)?;
+    if cmd.path.exists()
+        && let Some(saved) = backup::create(&cmd.path, &options)?
+    {
+        println!(
+            "backup saved: {} -> {}",
+            cmd.path.display(),
+            saved.display()
+        );
     }
-    if let Some(color) = overrides.color {
-        merged.color = color;
-    }
-    if let Some(json) = overrides.json {
-        merged.json = json;
-    }
-    if let Some(include_hidden) = overrides.include_hidden {
-        merged.include_hidden = include_hidden;
-    }
-    if let Some(exclude) = &overrides.exclude {
-        merged.exclude = exclude.clone();
-    }
-    if let Some(undo_log) = &overrides.undo_log {
-        merged.undo_log = Some(undo_log.clone());
+    logging::note_write(
+        &cmd.path,
+        logging::WriteRecord {
+            before_sha256: current_hash,
+            after_sha256: restored.as_deref().map(integrity::sha256_hex),
+            undo_patch: None,
+        },
+    );
+    match &restored {
+        Some(bytes) => {
+            write_via_temp(&cmd.path, bytes, true)?;
+            println!("undid the last change to {}", cmd.path.display());
+        }
+        None => {
+            fs::remove_file(&cmd.path)
+                .with_context(|| format!("removing {}", cmd.path.display()))?;
+            println!(
+                "removed {} (it was created by that change)",
+                cmd.path.display()
+            );
+        }
     }
-    merged
+    let _ = record_change("undo", &cmd.path, "applied", &summary, &spans);
+    Ok(())
 }
 
-fn build_replace_command(
-    base_common: &CommonArgs,
-    step: &batch::ReplacePlan,
-) -> Result<ReplaceCommand> {
-    if step.replacement.is_none() && !step.with_stdin && !step.with_clipboard {
-        bail!("replace step missing replacement text or input source");
-    }
-    Ok(ReplaceCommand {
-        common: merge_common(base_common, &step.common),
-        pattern: step.pattern.clone(),
-        replacement: step.replacement.clone(),
-        with_stdin: step.with_stdin,
-        with_clipboard: step.with_clipboard,
-        with_here: None,
-        regex: step.regex,
-        literal: step.literal,
-        diff_only: step.diff_only,
-        count: step.count,
-        expect: step.expect,
-        after_line: step.after_line,
-    })
+fn latest_applied<'a>(
+    logged: &'a [logging::LoggedEntry],
+    wanted: &Path,
+) -> Option<&'a logging::LoggedEntry> {
+    logged
+        .iter()
+        .rev()
+        .find(|entry| entry.action.starts_with("applied") && entry.absolute_path() == wanted)
 }
 
-fn build_normalize_command(
-    base_common: &CommonArgs,
-    step: &batch::NormalizePlan,
-) -> Result<NormalizeCommand> {
-    Ok(NormalizeCommand {
-        common: merge_common(base_common, &step.common),
-        convert_encoding: step.convert_encoding.clone(),
-        strip_zero_width: step.strip_zero_width.unwrap_or(false),
-        strip_control: step.strip_control.unwrap_or(false),
-        trim_trailing_space: step.trim_trailing_space.unwrap_or(false),
-        ensure_eol: step.ensure_eol.unwrap_or(false),
-        report_format: step
-            .report_format
-            .clone()
-            .unwrap_or_else(|| "table".to_string()),
-        scan_encoding: step.scan_encoding.unwrap_or(false),
-        scan_zero_width: step.scan_zero_width.unwrap_or(false),
-        scan_control: step.scan_control.unwrap_or(false),
-        scan_trailing_space: step.scan_trailing_space.unwrap_or(false),
-        scan_final_newline: step.scan_final_newline.unwrap_or(false),
-    })
+// Session records and log entries name archive members and remote files by
+// their source; those are read through it rather than from disk.
+fn read_recorded(path: &Path) -> Result<Option<Vec<u8>>> {
+    match StagedSource::from_recorded(path)? {
+        Some(source) => source.read().map(Some),
+        None => Ok(fs::read(path).ok()),
+    }
 }
 
-fn build_block_command(base_common: &CommonArgs, step: &batch::BlockPlan) -> Result<BlockCommand> {
-    if step.body.is_none() && step.body_file.is_none() {
-        bail!("block step requires a 'body' string or 'body_file'");
-    }
-    if step.start_marker.is_some() ^ step.end_marker.is_some() {
-        bail!("block step requires both start_marker and end_marker");
+// Returns a description of the source and the content from before the logged
+// change (None when the change created the file). Sources are tried in order
+// of precision: the logged undo patch, the session snapshot, then backups.
+fn find_undo_source(
+    cmd: &UndoCommand,
+    entry: &logging::LoggedEntry,
+    wanted: &Path,
+    current: Option<&[u8]>,
+    staged: bool,
+) -> Result<(String, Option<Vec<u8>>)> {
+    if let Some(patch_path) = entry.undo_patch.as_deref().map(Path::new)
+        && patch_path.exists()
+        && let Some(current) = current
+    {
+        let patches = patch::load_file_patches(patch_path)?;
+        let patch = patches
+            .first()
+            .with_context(|| format!("undo patch {} is empty", patch_path.display()))?;
+        let parsed = DiffPatch::from_str(&patch.patch_text)
+            .map_err(|err| anyhow!("failed to parse {}: {err}", patch_path.display()))?;
+        let decoded = EncodingStrategy::new(None)?.decode(current);
+        let (reverted, _) =
+            apply_patch_preserving_newlines(&decoded.text, &parsed, patch::DEFAULT_OFFSET_WINDOW)
+                .map_err(|err| {
+                anyhow!(
+                    "undo patch {} no longer applies to {}: {err}",
+                    patch_path.display(),
+                    cmd.path.display()
+                )
+            })?;
+        let (bytes, _) = encoding::encode_text(&reverted, decoded.decision.encoding, decoded.bom);
+        return Ok((format!("undo patch {}", patch_path.display()), Some(bytes)));
     }
-    if step.insert_after.is_some() && step.insert_before.is_some() {
-        bail!("block step cannot set both insert_after and insert_before");
+    if let Some(id) = &entry.session
+        && session::has_records(id)
+        && let Some(record) = session::load(id)?.into_iter().rev().find(|record| {
+            record.path == wanted
+                && (entry.after_sha256.is_none() || record.new_hash == entry.after_sha256)
+        })
+    {
+        let snapshot = session::snapshot(id, &record)?;
+        return Ok((format!("session {id} snapshot"), snapshot));
     }
-    if step.start_marker.is_none() && step.insert_after.is_none() && step.insert_before.is_none() {
-        bail!("block step must supply start/end markers or one of insert-after/insert-before");
+    // Archive backups hold the whole archive, not the member.
+    let backups = if staged {
+        Vec::new()
+    } else {
+        backup::list(&cmd.path, cmd.backup_dir.as_deref())?
+    };
+    let mut chosen = None;
+    for path in backups.iter().rev() {
+        let bytes = backup::read(path)?;
+        let matches_before = entry
+            .before_sha256
+            .as_deref()
+            .is_none_or(|hash| integrity::sha256_hex(&bytes) == hash);
+        if matches_before {
+            chosen = Some((path, bytes));
+            break;
+        }
     }
-    let mode = match &step.mode {
-        Some(mode) => Some(
-            BlockMode::from_str(mode)
-                .map_err(|err| anyhow!("invalid block mode '{mode}': {err}"))?,
+    match chosen {
+        Some((path, bytes)) => Ok((format!("backup {}", path.display()), Some(bytes))),
+        None => bail!(
+            "no undo patch, session snapshot, or backup holds the content of {} from before that change",
+            cmd.path.display()
         ),
-        None => None,
-    };
-    Ok(BlockCommand {
-        common: merge_common(base_common, &step.common),
-        start_marker: step.start_marker.clone(),
-        end_marker: step.end_marker.clone(),
-        insert_after: step.insert_after.clone(),
-        insert_before: step.insert_before.clone(),
-        mode,
-        body: step
-            .body
-            .as_ref()
-            .map(|text| vec![text.clone()])
-            .unwrap_or_default(),
-        body_file: step.body_file.clone(),
-        with_stdin: false,
-        with_clipboard: false,
-        body_here: None,
-        expect_blocks: step.expect_blocks,
-        allow_marker_overlap: step.allow_marker_overlap.unwrap_or(false),
-    })
+    }
 }
 
-fn build_rename_command(
-    base_common: &CommonArgs,
-    step: &batch::RenamePlan,
-) -> Result<RenameCommand> {
-    Ok(RenameCommand {
-        common: merge_common(base_common, &step.common),
-        from: step.from.clone(),
-        to: step.to.clone(),
-        word_boundary: step.word_boundary,
-        case_aware: step.case_aware,
-    })
+fn handle_binpatch(cmd: BinpatchCommand) -> Result<()> {
+    let offset = binpatch::parse_offset(&cmd.at)?;
+    let expected = binpatch::parse_hex(&cmd.expect_bytes, "--expect-bytes")?;
+    let replacement = binpatch::parse_hex(&cmd.write_bytes, "--write-bytes")?;
+    if expected.len() != replacement.len() {
+        bail!(
+            "--expect-bytes has {} byte(s) but --write-bytes has {}; binpatch only overwrites in place",
+            expected.len(),
+            replacement.len()
+        );
+    }
+    if expected.len() > binpatch::MAX_PATCH_BYTES {
+        bail!(
+            "binpatch edits at most {} bytes at a time",
+            binpatch::MAX_PATCH_BYTES
+        );
+    }
+    let original =
+        fs::read(&cmd.path).with_context(|| format!("reading {}", cmd.path.display()))?;
+    let patched = binpatch_bytes(&cmd, &original, offset, &expected, &replacement)?;
+    let Some(patched) = patched else {
+        return Ok(());
+    };
+    let summary = format!(
+        "0x{offset:x}: {} -> {}",
+        binpatch::to_hex(&expected),
+        binpatch::to_hex(&replacement)
+    );
+    println!(
+        "--- binpatch: {} @ 0x{offset:x} ({} byte(s)) ---",
+        cmd.path.display(),
+        expected.len()
+    );
+    for line in binpatch::preview(
+        &original,
+        &patched,
+        cmd.context_rows,
+        cmd.color.should_color(),
+    ) {
+        println!("{line}");
+    }
+    if !cmd.apply {
+        println!("dry-run: rerun with --apply to write these bytes.");
+        return Ok(());
+    }
+    let decision = if cmd.auto_apply {
+        ApprovalDecision::Apply
+    } else {
+        prompt_approval(&cmd.path, false)?
+    };
+    if matches!(decision, ApprovalDecision::Skip | ApprovalDecision::Quit) {
+        println!("skipped {}", cmd.path.display());
+        return Ok(());
+    }
+    write_binary(&cmd.path, &original, &patched, &cmd.writes.write_options())?;
+    let _ = record_change("binpatch", &cmd.path, "applied", &summary, &[]);
+    Ok(())
 }
 
-#[derive(Debug, Parser)]
-#[command(name = "safeedit", version, about = "Safe file editing companion")]
-struct Cli {
-    #[command(subcommand)]
-    command: Command,
+// Returns the patched bytes, or None when the file already holds them.
+fn binpatch_bytes(
+    cmd: &BinpatchCommand,
+    original: &[u8],
+    offset: usize,
+    expected: &[u8],
+    replacement: &[u8],
+) -> Result<Option<Vec<u8>>> {
+    let end = offset
+        .checked_add(expected.len())
+        .filter(|end| *end <= original.len())
+        .ok_or_else(|| {
+            anyhow!(
+                "0x{offset:x}+{} is past the end of {} ({} bytes)",
+                expected.len(),
+                cmd.path.display(),
+                original.len()
+            )
+        })?;
+    let actual = &original[offset..end];
+    if actual == replacement && actual != expected {
+        println!(
+            "{} already has {} at 0x{offset:x}; nothing to do.",
+            cmd.path.display(),
+            binpatch::to_hex(replacement)
+        );
+        return Ok(None);
+    }
+    if actual != expected {
+        bail!(
+            "{} has {} at 0x{offset:x}, not the expected {}; refusing to patch",
+            cmd.path.display(),
+            binpatch::to_hex(actual),
+            binpatch::to_hex(expected)
+        );
+    }
+    if expected == replacement {
+        println!("--expect-bytes and --write-bytes are identical; nothing to do.");
+        return Ok(None);
+    }
+    let mut patched = original.to_vec();
+    patched[offset..end].copy_from_slice(replacement);
+    Ok(Some(patched))
 }
 
-#[derive(Debug, Subcommand)]
-enum Command {
-    Replace(ReplaceCommand),
-    Apply(ApplyCommand),
-    Block(BlockCommand),
-    Rename(RenameCommand),
-    Review(ReviewCommand),
-    Normalize(NormalizeCommand),
-    Script(ScriptCommand),
-    Batch(BatchCommand),
-    Log(LogCommand),
-    Report(ReportCommand),
-    Cleanup(CleanupCommand),
-    Write(WriteCommand),
+fn handle_patch_split(cmd: PatchSplitCommand) -> Result<()> {
+    let mut patches = patch::load_file_patches(&cmd.patch)?;
+    let total = patches.len();
+    if !cmd.only.is_empty() {
+        let mut builder = globset::GlobSetBuilder::new();
+        for pattern in &cmd.only {
+            let glob = globset::Glob::new(pattern)
+                .map_err(|err| anyhow!("invalid --only glob '{pattern}': {err}"))?;
+            builder.add(glob);
+        }
+        let only = builder
+            .build()
+            .map_err(|err| anyhow!("unable to build --only globs: {err}"))?;
+        patches.retain(|patch| {
+            [&patch.old_path, &patch.new_path]
+                .into_iter()
+                .flatten()
+                .any(|path| only.is_match(path))
+        });
+    }
+    if patches.is_empty() {
+        bail!(
+            "none of the {total} file segment(s) in {} match --only",
+            cmd.patch.display()
+        );
+    }
+    let pieces = patch::split_pieces(&patches, cmd.by);
+    if !cmd.force
+        && let Some((name, _)) = pieces
+            .iter()
+            .find(|(name, _)| cmd.out_dir.join(name).exists())
+    {
+        bail!(
+            "{} already exists; pass --force to overwrite",
+            cmd.out_dir.join(name).display()
+        );
+    }
+    fs::create_dir_all(&cmd.out_dir)
+        .with_context(|| format!("creating {}", cmd.out_dir.display()))?;
+    for (name, body) in &pieces {
+        let target = cmd.out_dir.join(name);
+        fs::write(&target, body).with_context(|| format!("writing {}", target.display()))?;
+        println!("  - {}", target.display());
+    }
+    println!(
+        "split {} of {total} file segment(s) from {} into {} piece(s) under {}",
+        patches.len(),
+        cmd.patch.display(),
+        pieces.len(),
+        cmd.out_dir.display()
+    );
+    Ok(())
 }
 
-#[derive(Debug, Clone, Args)]
-struct CommonArgs {
-    #[arg(long = "glob", value_name = "GLOB")]
-    globs: Vec<String>,
-    #[arg(long = "target", value_name = "PATH", value_hint = ValueHint::AnyPath)]
-    targets: Vec<PathBuf>,
-    #[arg(long, value_name = "ENCODING")]
-    encoding: Option<String>,
-    #[arg(
-        long,
-        action = ArgAction::SetTrue,
-        help = "Write changes after showing the diff; omit to stay in review-only dry-run mode."
-    )]
-    apply: bool,
-    #[arg(
-        long = "yes",
-        action = ArgAction::SetTrue,
-        help = "Approve every diff without prompting (skips the review step); use only after inspecting output."
-    )]
-    auto_apply: bool,
-    #[arg(long, action = ArgAction::SetTrue)]
-    no_backup: bool,
-    #[arg(long, default_value_t = 3)]
-    context: usize,
-    #[arg(long = "pager", value_enum, default_value = "auto")]
-    pager: PagerMode,
-    #[arg(long = "color", value_enum, default_value = "auto")]
-    color: ColorChoice,
-    #[arg(long, action = ArgAction::SetTrue)]
-    json: bool,
-    #[arg(long = "include-hidden", action = ArgAction::SetTrue)]
-    include_hidden: bool,
-    #[arg(long = "exclude", value_name = "GLOB")]
-    exclude: Vec<String>,
-    #[arg(long = "undo-log", value_name = "DIR", value_hint = ValueHint::DirPath)]
-    undo_log: Option<PathBuf>,
-    #[arg(value_name = "EXTRA", value_parser = value_parser!(String))]
-    extra_args: Vec<String>,
+fn handle_patch_lint(cmd: PatchLintCommand) -> Result<()> {
+    let mut problems = 0usize;
+    for path in &cmd.patches {
+        let raw = fs::read_to_string(path)
+            .with_context(|| format!("reading patch {}", path.display()))?;
+        let report = patch::lint(&raw);
+        for issue in &report.issues {
+            println!("{}:{}: {}", path.display(), issue.line, issue.message);
+        }
+        let verdict = if report.issues.is_empty() {
+            "ok".to_string()
+        } else {
+            format!("{} problem(s)", report.issues.len())
+        };
+        println!(
+            "{}: {verdict} ({} file segment(s), {} hunk(s))",
+            path.display(),
+            report.segments,
+            report.hunks
+        );
+        problems += report.issues.len();
+    }
+    if problems > 0 {
+        bail!("patch lint found {problems} problem(s)");
+    }
+    Ok(())
 }
 
-impl CommonArgs {
-    fn diff_display_config(&self, colorize: bool) -> diff::DiffDisplayConfig {
-        diff::DiffDisplayConfig {
-            context: self.context,
-            colorize,
-            pager_mode: self.pager,
-            interactive: self.allow_interactive_pager(),
+fn handle_session_list(cmd: SessionListCommand) -> Result<()> {
+    let entries = logging::read_all()?;
+    let mut sessions: Vec<(String, Vec<logging::LoggedEntry>)> = Vec::new();
+    for entry in entries {
+        let Some(id) = entry.session.clone() else {
+            continue;
+        };
+        match sessions.iter_mut().find(|(existing, _)| *existing == id) {
+            Some((_, group)) => group.push(entry),
+            None => sessions.push((id, vec![entry])),
         }
     }
-
-    fn allow_interactive_pager(&self) -> bool {
-        io::stdin().is_terminal() && io::stdout().is_terminal() && !self.auto_apply && !self.json
+    if sessions.is_empty() {
+        println!("no sessions recorded in the change log.");
+        return Ok(());
+    }
+    let start = sessions.len().saturating_sub(cmd.limit);
+    println!(
+        "{:<28} {:<32} {:>7} {:>6}  {:<8} commands",
+        "session", "started", "applied", "files", "rollback"
+    );
+    for (id, group) in &sessions[start..] {
+        let applied = group
+            .iter()
+            .filter(|entry| entry.action.starts_with("applied"))
+            .count();
+        let mut files: Vec<&str> = group.iter().map(|entry| entry.path.as_str()).collect();
+        files.sort();
+        files.dedup();
+        let mut commands: Vec<&str> = group.iter().map(|entry| entry.command.as_str()).collect();
+        commands.sort();
+        commands.dedup();
+        println!(
+            "{:<28} {:<32} {:>7} {:>6}  {:<8} {}",
+            id,
+            group[0].timestamp,
+            applied,
+            files.len(),
+            if session::has_records(id) {
+                "yes"
+            } else {
+                "no"
+            },
+            commands.join(",")
+        );
     }
+    Ok(())
 }
 
-#[derive(Debug, Args)]
-struct ReplaceCommand {
-    #[command(flatten)]
-    common: CommonArgs,
-    #[arg(long, value_name = "PATTERN")]
-    pattern: String,
-    #[arg(
-        long = "with",
-        value_name = "TEXT",
-        conflicts_with_all = ["with_stdin", "with_clipboard"],
-        required_unless_present_any = ["with_stdin", "with_clipboard", "with_here"]
-    )]
-    replacement: Option<String>,
+fn handle_session_rollback(cmd: SessionRollbackCommand) -> Result<()> {
+    let records = session::load(&cmd.id)?;
+    if records.is_empty() {
+        bail!("no recorded writes for session {}", cmd.id);
+    }
+    println!(
+        "session {}: {} write(s) to roll back (newest first):",
+        cmd.id,
+        records.len()
+    );
+    let mut plan = Vec::new();
+    for record in records.iter().rev() {
+        let current = read_recorded(&record.path)?.map(|bytes| integrity::sha256_hex(&bytes));
+        let drifted = current != record.new_hash;
+        let action = match &record.original_hash {
+            Some(_) => "restore",
+            None => "delete",
+        };
+        println!(
+            "  - {} {}{}",
+            action,
+            record.path.display(),
+            if drifted {
+                " (changed since the session; needs --force)"
+            } else {
+                ""
+            }
+        );
+        plan.push((record, drifted));
+    }
+    if !cmd.apply {
+        println!("dry-run: rerun with --apply to roll back this session.");
+        return Ok(());
+    }
+    let mut stats = CommandStats::default();
+    let mut apply_all = cmd.auto_apply;
+    for (record, drifted) in plan {
+        if drifted && !cmd.force {
+            println!(
+                "skipped {} (modified after the session; pass --force to overwrite)",
+                record.path.display()
+            );
+            stats.skipped += 1;
+            continue;
+        }
+        let decision = if apply_all {
+            ApprovalDecision::Apply
+        } else {
+            prompt_approval(&record.path, false)?
+        };
+        match decision {
+            ApprovalDecision::Apply | ApprovalDecision::ApplyAll => {
+                apply_all |= matches!(decision, ApprovalDecision::ApplyAll);
+                let staged = StagedSource::from_recorded(&record.path)?;
+                let _lock = match staged {
+                    Some(_) => None,
+                    None => Some(lock::lock_path(&record.path)?),
+                };
+                let before = read_recorded(&record.path)?;
+                let snapshot = session::snapshot(&cmd.id, record)?;
+                logging::note_write(
+                    &record.path,
+                    logging::WriteRecord {
+                        before_sha256: before.as_deref().map(integrity::sha256_hex),
+                        after_sha256: snapshot.as_deref().map(integrity::sha256_hex),
+                        undo_patch: None,
+                    },
+                );
+                match (snapshot, &staged) {
+                    (Some(bytes), Some(source)) => {
+                        source.write_back(
+                            &bytes,
+                            before.as_deref().unwrap_or_default(),
+                            &backup::BackupOptions::default(),
+                            true,
+                        )?;
+                        println!("restored {source}");
+                    }
+                    (None, Some(source)) => {
+                        bail!(
+                            "{source} was created in session {}; rollback cannot remove it",
+                            cmd.id
+                        )
+                    }
+                    (Some(bytes), None) => {
+                        write_via_temp(&record.path, &bytes, true)?;
+                        println!("restored {}", record.path.display());
+                    }
+                    (None, None) => {
+                        if record.path.exists() {
+                            fs::remove_file(&record.path)
+                                .with_context(|| format!("removing {}", record.path.display()))?;
+                        }
+                        println!("removed {}", record.path.display());
+                    }
+                }
+                let _ = record_change(
+                    "session-rollback",
+                    &record.path,
+                    "applied",
+                    &format!("rollback {}", cmd.id),
+                    &[],
+                );
+                stats.applied += 1;
+            }
+            ApprovalDecision::Skip => {
+                println!("skipped {}", record.path.display());
+                stats.skipped += 1;
+            }
+            ApprovalDecision::Quit => {
+                println!("stopping rollback after user request.");
+                break;
+            }
+        }
+    }
+    stats.print("session rollback");
+    Ok(())
+}
+
+fn prompt_recover_action(
+    path: &Path,
+    default: Option<RecoverAction>,
+) -> Result<Option<RecoverAction>> {
+    input::ensure_prompt_allowed(&format!("recovering {}", path.display()))?;
+    loop {
+        print_prompt(&format!(
+            "Recover {}? [f]inish/[r]ollback/[s]kip{}: ",
+            path.display(),
+            match default {
+                Some(RecoverAction::Finish) => " (default finish)",
+                Some(RecoverAction::Rollback) => " (default rollback)",
+                None => "",
+            }
+        ))?;
+        let input = input::read_line()?;
+        match input.trim().to_lowercase().as_str() {
+            "f" | "finish" => return Ok(Some(RecoverAction::Finish)),
+            "r" | "rollback" => return Ok(Some(RecoverAction::Rollback)),
+            "s" | "skip" => return Ok(None),
+            "" if default.is_some() => return Ok(default),
+            _ => println!("Please enter f, r, or s."),
+        }
+    }
+}
+
+fn describe_spans(spans: &[logging::LineSpan]) -> String {
+    spans
+        .iter()
+        .map(|span| {
+            let kind = match span.kind {
+                LineSpanKind::Modified => "M",
+                LineSpanKind::Added => "A",
+            };
+            if span.end > span.start {
+                format!("{kind} L{}-L{}", span.start, span.end)
+            } else {
+                format!("{kind} L{}", span.start)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn find_backup_files(
+    root: &Path,
+    include_hidden: bool,
+    patterns: Option<&globset::GlobSet>,
+) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let walker = WalkDir::new(root).follow_links(false).into_iter();
+    for entry in walker.filter_entry(|e| {
+        include_hidden || !has_hidden_component(e.path().strip_prefix(root).unwrap_or(e.path()))
+    }) {
+        let entry = entry?;
+        if entry.file_type().is_file() {
+            let path = entry.into_path();
+            let custom = patterns.is_some_and(|set| {
+                path.file_name()
+                    .is_some_and(|name| set.is_match(Path::new(name)))
+            });
+            if custom || is_backup_file(&path) {
+                files.push(path);
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+fn build_cleanup_patterns(patterns: &[String]) -> Result<Option<globset::GlobSet>> {
+    if patterns.is_empty() {
+        return Ok(None);
+    }
+    let mut builder = globset::GlobSetBuilder::new();
+    for pattern in patterns {
+        let glob = globset::Glob::new(pattern)
+            .map_err(|err| anyhow!("invalid cleanup pattern '{pattern}': {err}"))?;
+        builder.add(glob);
+    }
+    builder
+        .build()
+        .map(Some)
+        .map_err(|err| anyhow!("unable to build cleanup patterns: {err}"))
+}
+
+fn parse_age(raw: &str) -> Result<std::time::Duration> {
+    let trimmed = raw.trim();
+    let split = trimmed
+        .find(|ch: char| !ch.is_ascii_digit())
+        .unwrap_or(trimmed.len());
+    let (digits, unit) = trimmed.split_at(split);
+    let value: u64 = digits
+        .parse()
+        .map_err(|_| anyhow!("invalid age '{raw}' (expected e.g. 30m, 12h, 7d, 2w)"))?;
+    let seconds = match unit.to_ascii_lowercase().as_str() {
+        "s" => 1,
+        "m" => 60,
+        "h" => 3_600,
+        "" | "d" => 86_400,
+        "w" => 604_800,
+        other => bail!("unknown age unit '{other}' in '{raw}' (use s, m, h, d, or w)"),
+    };
+    Ok(std::time::Duration::from_secs(value * seconds))
+}
+
+fn parse_size(raw: &str) -> Result<u64> {
+    let trimmed = raw.trim();
+    let split = trimmed
+        .find(|ch: char| !ch.is_ascii_digit())
+        .unwrap_or(trimmed.len());
+    let (digits, unit) = trimmed.split_at(split);
+    let value: u64 = digits
+        .parse()
+        .map_err(|_| anyhow!("invalid size '{raw}' (expected e.g. 500, 64K, 10M, 1G)"))?;
+    let multiplier = match unit.to_ascii_lowercase().trim_end_matches('b') {
+        "" => 1,
+        "k" => 1024,
+        "m" => 1024 * 1024,
+        "g" => 1024 * 1024 * 1024,
+        other => bail!("unknown size unit '{other}' in '{raw}' (use K, M, or G)"),
+    };
+    Ok(value * multiplier)
+}
+
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{bytes} B")
+    } else {
+        format!("{value:.1} {}", UNITS[unit])
+    }
+}
+
+fn has_hidden_component(path: &Path) -> bool {
+    path.components().any(|component| match component {
+        Component::Normal(name) => name
+            .to_str()
+            .map(|segment| segment.starts_with('.'))
+            .unwrap_or(false),
+        _ => false,
+    })
+}
+
+fn is_backup_file(path: &Path) -> bool {
+    let Some(name) = path.file_name().and_then(|s| s.to_str()) else {
+        return false;
+    };
+    let lower = name.to_ascii_lowercase();
+    let lower = lower.strip_suffix(".gz").unwrap_or(&lower);
+    if let Some((_base, suffix)) = lower.rsplit_once(".bak") {
+        return suffix.chars().all(|ch| ch.is_ascii_digit());
+    }
+    false
+}
+
+fn log_change(
+    common: &CommonArgs,
+    command: &str,
+    path: &Path,
+    action: &str,
+    line_summary: &str,
+    spans: &[LineSpan],
+    extra: Option<JsonMap<String, JsonValue>>,
+) {
+    emit_json_diff_event(common, command, path, action, line_summary, spans, extra);
+    let _ = record_change(command, path, action, line_summary, spans);
+}
+
+fn stage_json_diff(common: &CommonArgs, path: &Path, old: &str, new: &str) {
+    if common.emits_events() || logging::capturing() {
+        let name = logging::recorded_name(path);
+        events::stage_diff(
+            path,
+            diff::unified_diff(&name, &name, old, new, common.context),
+        );
+    }
+}
+
+fn emit_json_diff_event(
+    common: &CommonArgs,
+    command: &str,
+    path: &Path,
+    action: &str,
+    line_summary: &str,
+    spans: &[LineSpan],
+    extra: Option<JsonMap<String, JsonValue>>,
+) {
+    if !common.emits_events() && !logging::capturing() {
+        return;
+    }
+    let mut event = JsonMap::new();
+    event.insert("schema_version".into(), json!(events::SCHEMA_VERSION));
+    event.insert("command".into(), JsonValue::String(command.to_string()));
+    event.insert(
+        "path".into(),
+        JsonValue::String(logging::recorded_name(path).display().to_string()),
+    );
+    event.insert("action".into(), JsonValue::String(action.to_string()));
+    event.insert(
+        "line_summary".into(),
+        JsonValue::String(line_summary.to_string()),
+    );
+    event.insert("spans".into(), spans_to_json(spans));
+    let (before, after) = match logging::peek_write(path) {
+        Some(written) => (written.before_sha256, written.after_sha256),
+        None => (
+            fs::read(path)
+                .ok()
+                .map(|bytes| integrity::sha256_hex(&bytes)),
+            None,
+        ),
+    };
+    event.insert("before_sha256".into(), json!(before));
+    event.insert("after_sha256".into(), json!(after));
+    event.insert("diff".into(), json!(events::take_diff(path)));
+    if logging::prompt_timed_out(path) {
+        event.insert("decision".into(), json!("prompt-timeout"));
+    }
+    if let Some(extra_map) = extra {
+        for (key, value) in extra_map {
+            event.insert(key, value);
+        }
+    }
+    let event = JsonValue::Object(event);
+    logging::note_event(&event);
+    if common.json {
+        println!("{event}");
+    }
+    if let Some(file) = &common.json_file
+        && let Err(err) = events::write_line(file, &event.to_string())
+    {
+        eprintln!("warning: {err:#}");
+    }
+}
+
+fn spans_to_json(spans: &[LineSpan]) -> JsonValue {
+    JsonValue::Array(
+        spans
+            .iter()
+            .map(|span| {
+                json!({
+                    "kind": match span.kind {
+                        LineSpanKind::Modified => "modified",
+                        LineSpanKind::Added => "added",
+                    },
+                    "start": span.start,
+                    "end": span.end
+                })
+            })
+            .collect(),
+    )
+}
+
+fn status_extra(applied: bool, dry_run: bool) -> JsonMap<String, JsonValue> {
+    let mut map = JsonMap::new();
+    map.insert("applied".into(), JsonValue::Bool(applied));
+    map.insert("dry_run".into(), JsonValue::Bool(dry_run));
+    map
+}
+
+fn with_match_counts(
+    mut map: JsonMap<String, JsonValue>,
+    counts: &MatchCounts,
+) -> JsonMap<String, JsonValue> {
+    map.insert("occurrences".into(), json!(counts.occurrences));
+    map.insert("matched_lines".into(), json!(counts.lines));
+    map
+}
+
+fn status_with_patch(applied: bool, dry_run: bool, kind: PatchKind) -> JsonMap<String, JsonValue> {
+    let mut map = status_extra(applied, dry_run);
+    map.insert(
+        "patch_kind".into(),
+        JsonValue::String(patch_kind_label(kind).to_string()),
+    );
+    map
+}
+
+fn patch_kind_label(kind: PatchKind) -> &'static str {
+    match kind {
+        PatchKind::Modify => "modify",
+        PatchKind::Create => "create",
+        PatchKind::Delete => "delete",
+        PatchKind::Rename => "rename",
+    }
+}
+
+fn print_command_summary(
+    command: &str,
+    common: &CommonArgs,
+    encoding: &EncodingStrategy,
+    entries: &[FileEntry],
+    details: &[String],
+) {
+    println!("command: {command}");
+    println!(
+        "mode: {}{}",
+        if common.apply { "apply" } else { "dry-run" },
+        if common.auto_apply {
+            " (auto-approve)"
+        } else {
+            ""
+        }
+    );
+    let (targets, globs) = common.selection();
+    if !targets.is_empty() {
+        println!("targets:");
+        for target in &targets {
+            println!("  - {}", target.display());
+        }
+    } else {
+        println!("targets: (none)");
+    }
+    println!("encoding strategy: {}", encoding.describe());
+    println!("context lines: {}", common.context);
+    println!("pager: {:?}", common.pager);
+    println!("json output: {}", common.json);
+    println!("include hidden: {}", common.include_hidden);
+    if !common.exclude.is_empty() {
+        println!("exclude globs: {:?}", common.exclude);
+    }
+    if common.writes.no_backup {
+        println!("backups disabled");
+    } else if let Some(dir) = &common.writes.backup_dir {
+        println!("backup dir: {}", dir.display());
+    }
+    if common.writes.backup_keep.is_some() || common.writes.backup_max_age.is_some() {
+        println!(
+            "backup retention: keep={:?}, max_age_days={:?}",
+            common.writes.backup_keep, common.writes.backup_max_age
+        );
+    }
+    if let Some(log) = &common.undo_log {
+        println!("undo log dir: {}", log.display());
+    }
+    if !globs.is_empty() {
+        println!("globs:");
+        for glob in &globs {
+            println!("  - {glob}");
+        }
+    }
+
+    if entries.is_empty() {
+        println!("resolved files: (none)");
+    } else {
+        println!("resolved files ({}):", entries.len());
+        for entry in entries.iter().take(10) {
+            let binary_hint = if entry.metadata.is_probably_binary {
+                ", binary? yes"
+            } else {
+                ""
+            };
+            println!(
+                "  - {} ({} bytes{})",
+                entry.path.display(),
+                entry.metadata.len,
+                binary_hint
+            );
+        }
+        if entries.len() > 10 {
+            println!("  ...");
+        }
+    }
+    for detail in details {
+        println!("{detail}");
+    }
+    println!("---");
+}
+
+fn resolve_entries(common: &CommonArgs) -> Result<Vec<FileEntry>> {
+    let _timer = timing::start(timing::Phase::Resolve, None);
+    let mixed = !common.targets.is_empty() || !common.globs.is_empty();
+    if mixed && !common.extra_args.is_empty() {
+        println!("warning: positional paths are combined with --target/--glob selections");
+    }
+    let (targets, globs) = common.selection();
+    files::resolve_targets(&targets, &globs, common.include_hidden, &common.exclude)
+}
+
+// Like resolve_entries, but drops files over --max-file-size so a broad glob
+// cannot pull a multi-gigabyte artifact into memory for an edit.
+fn resolve_edit_entries(common: &CommonArgs) -> Result<Vec<FileEntry>> {
+    let mut entries = resolve_entries(common)?;
+    if common.max_file_size == 0 || common.force_large {
+        return Ok(entries);
+    }
+    entries.retain(|entry| {
+        if entry.metadata.len <= common.max_file_size {
+            return true;
+        }
+        println!(
+            "skipping {} ({} exceeds --max-file-size {}; pass --force-large to edit it)",
+            entry.path.display(),
+            format_bytes(entry.metadata.len),
+            format_bytes(common.max_file_size)
+        );
+        false
+    });
+    Ok(entries)
+}
+
+fn resolve_encoding_strategy(common: &CommonArgs) -> Result<EncodingStrategy> {
+    let strategy = EncodingStrategy::new(common.encoding.as_deref())?;
+    match common.min_detection_score {
+        Some(min) if !(0.0..=1.0).contains(&min) => {
+            bail!("--min-detection-score must be between 0 and 1")
+        }
+        Some(min) => {
+            let prompt = !common.auto_apply
+                && !common.json
+                && !input::no_input()
+                && io::stdin().is_terminal();
+            Ok(strategy.with_min_score(min, prompt))
+        }
+        None => Ok(strategy),
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum ApprovalDecision {
+    Apply,
+    Skip,
+    ApplyAll,
+    Quit,
+}
+
+fn prompt_approval(path: &Path, json: bool) -> Result<ApprovalDecision> {
+    input::ensure_prompt_allowed(&format!("applying the change to {}", path.display()))?;
+    if json && !io::stdin().is_terminal() {
+        return request_json_approval(path);
+    }
+    loop {
+        print_prompt(&format!(
+            "Apply change to {}? [y]es/[n]o/[a]ll/[q]uit: ",
+            path.display()
+        ))?;
+        let input = match input::read_answer()? {
+            input::Answer::Line(line) => line,
+            input::Answer::TimedOut(fallback) => return Ok(timed_out_approval(path, fallback)),
+        };
+        match input.trim().to_lowercase().as_str() {
+            "y" | "yes" | "" => return Ok(ApprovalDecision::Apply),
+            "n" | "no" => {
+                decisions::remember_rejection(path);
+                return Ok(ApprovalDecision::Skip);
+            }
+            "a" | "all" => return Ok(ApprovalDecision::ApplyAll),
+            "q" | "quit" => return Ok(ApprovalDecision::Quit),
+            _ => {
+                println!("Please enter y, n, a, or q.");
+            }
+        }
+    }
+}
+
+fn timed_out_approval(path: &Path, fallback: input::TimeoutDefault) -> ApprovalDecision {
+    println!();
+    println!(
+        "no answer for {} before --prompt-timeout; defaulting to {}",
+        path.display(),
+        fallback.label()
+    );
+    logging::note_prompt_timeout(path);
+    match fallback {
+        input::TimeoutDefault::Apply => ApprovalDecision::Apply,
+        input::TimeoutDefault::Skip => ApprovalDecision::Skip,
+        input::TimeoutDefault::Quit => ApprovalDecision::Quit,
+    }
+}
+
+fn approve_change(common: &CommonArgs, path: &Path) -> Result<ApprovalDecision> {
+    if let Some(file) = &common.approve_policy {
+        let policy = policy::ApprovalPolicy::load(file)?;
+        let root = std::env::current_dir()?;
+        match policy.decide(path, &root) {
+            (policy::PolicyDecision::Apply, rule) => {
+                println!(
+                    "policy: applying {} ({})",
+                    path.display(),
+                    rule.unwrap_or("default")
+                );
+                return Ok(ApprovalDecision::Apply);
+            }
+            (policy::PolicyDecision::Skip, rule) => {
+                println!(
+                    "policy: skipping {} ({})",
+                    path.display(),
+                    rule.unwrap_or("default")
+                );
+                return Ok(ApprovalDecision::Skip);
+            }
+            (policy::PolicyDecision::Prompt, _) => {}
+        }
+    }
+    if decisions::previously_rejected(path) {
+        println!(
+            "skipping {} (declined in an earlier identical run; pass --fresh to review it again)",
+            path.display()
+        );
+        return Ok(ApprovalDecision::Skip);
+    }
+    prompt_approval(path, common.json)
+}
+
+// Non-TTY `--json` callers get an `approval_request` event carrying the diff
+// and answer with one JSON line such as `{"decision":"apply","path":"..."}`.
+fn request_json_approval(path: &Path) -> Result<ApprovalDecision> {
+    let request = json!({
+        "schema_version": events::SCHEMA_VERSION,
+        "event": "approval_request",
+        "path": path.display().to_string(),
+        "diff": events::peek_diff(path),
+        "decisions": ["apply", "skip", "all", "quit"],
+    });
+    println!("{request}");
+    io::stdout().flush()?;
+    let input = match input::read_answer()? {
+        input::Answer::Line(line) => line,
+        input::Answer::TimedOut(fallback) => return Ok(timed_out_approval(path, fallback)),
+    };
+    if input.is_empty() {
+        bail!(
+            "approval input closed before a decision for {}",
+            path.display()
+        );
+    }
+    let decision = parse_json_approval(&input, path)?;
+    if matches!(decision, ApprovalDecision::Skip) {
+        decisions::remember_rejection(path);
+    }
+    Ok(decision)
+}
+
+fn parse_json_approval(line: &str, path: &Path) -> Result<ApprovalDecision> {
+    let value: JsonValue = serde_json::from_str(line.trim())
+        .with_context(|| format!("invalid approval JSON for {}", path.display()))?;
+    if let Some(answered) = value.get("path").and_then(JsonValue::as_str)
+        && Path::new(answered) != path
+        && std::env::current_dir().is_ok_and(|cwd| cwd.join(answered) != path)
+    {
+        bail!(
+            "approval answered for {answered} but {} is pending",
+            path.display()
+        );
+    }
+    let decision = value
+        .get("decision")
+        .and_then(JsonValue::as_str)
+        .ok_or_else(|| anyhow!("approval JSON for {} has no \"decision\"", path.display()))?;
+    match decision.to_lowercase().as_str() {
+        "apply" | "yes" | "y" => Ok(ApprovalDecision::Apply),
+        "skip" | "no" | "n" => Ok(ApprovalDecision::Skip),
+        "all" | "apply_all" => Ok(ApprovalDecision::ApplyAll),
+        "quit" | "q" => Ok(ApprovalDecision::Quit),
+        other => bail!("unknown approval decision '{other}' (expected apply, skip, all, or quit)"),
+    }
+}
+
+fn print_prompt(message: &str) -> Result<()> {
+    print!("{message}");
+    io::stdout().flush()?;
+    Ok(())
+}
+
+#[derive(Clone, Copy)]
+enum ReportFormat {
+    Table,
+    Json,
+}
+
+impl ReportFormat {
+    fn from_str(value: &str) -> Result<Self> {
+        match value.to_lowercase().as_str() {
+            "table" => Ok(Self::Table),
+            "json" => Ok(Self::Json),
+            other => Err(anyhow!(
+                "unsupported report-format '{other}' (expected table or json)"
+            )),
+        }
+    }
+}
+
+fn print_normalize_report(
+    path: &Path,
+    report: &normalize::NormalizeReport,
+    encoding_name: Option<(&str, f32)>,
+    convert_encoding: Option<&str>,
+    unicode_form: Option<normalize::UnicodeForm>,
+    convert_eol: Option<LineEndingStyle>,
+    format: ReportFormat,
+) -> Result<()> {
+    match format {
+        ReportFormat::Table => {
+            println!(
+                "{} -> zero-width: {}, control: {}, bidi: {}, confusables: {}, trailing spaces: {}, missing final newline: {}",
+                path.display(),
+                format_detection(report.zero_width),
+                format_detection(report.control_chars),
+                format_detection(report.bidi_controls),
+                format_detection(report.confusables),
+                format_detection(report.trailing_spaces),
+                format_bool(report.missing_final_newline)
+            );
+            for finding in &report.findings {
+                match (&finding.codepoint, finding.length) {
+                    (Some(codepoint), _) => println!(
+                        "    {} {codepoint} at {}:{}",
+                        finding.kind, finding.line, finding.column
+                    ),
+                    (None, Some(length)) => println!(
+                        "    {} x{length} at {}:{}",
+                        finding.kind, finding.line, finding.column
+                    ),
+                    (None, None) => {
+                        println!(
+                            "    {} at {}:{}",
+                            finding.kind, finding.line, finding.column
+                        )
+                    }
+                }
+            }
+            for (name, count) in &report.strip_classes {
+                if *count > 0 {
+                    println!("    strip class {name}: {count}");
+                }
+            }
+            if let Some(count) = report.smart_punctuation
+                && count > 0
+            {
+                println!("    smart punctuation / nbsp: {count}");
+            }
+            if let Some(count) = report.long_lines
+                && count > 0
+            {
+                let lines: Vec<String> = report
+                    .long_line_locations
+                    .iter()
+                    .map(|loc| format!("{} ({} chars)", loc.line, loc.length.unwrap_or(0)))
+                    .collect();
+                println!("    long lines: {count} at line(s) {}", lines.join(", "));
+            }
+            if let Some(count) = report.excess_blank_lines
+                && count > 0
+            {
+                println!("    excess blank lines: {count}");
+            }
+            if report.bom == Some(true) {
+                println!("    byte order mark: present");
+            }
+            match (encoding_name, convert_encoding) {
+                (Some((src, score)), Some(dst)) => {
+                    println!("    encoding: {src} (detection score {score:.2}) -> {dst}")
+                }
+                (Some((src, score)), None) => {
+                    println!("    encoding: {src} (detection score {score:.2})")
+                }
+                (None, Some(dst)) => println!("    convert encoding: {dst}"),
+                _ => {}
+            }
+            if report.bidi_controls.unwrap_or(0) > 0 {
+                let positions: Vec<String> = report
+                    .bidi_locations
+                    .iter()
+                    .map(|loc| format!("{} at {}:{}", loc.codepoint, loc.line, loc.column))
+                    .collect();
+                println!("    bidi controls: {}", positions.join(", "));
+            }
+            if report.confusables.unwrap_or(0) > 0 {
+                let positions: Vec<String> = report
+                    .confusable_locations
+                    .iter()
+                    .map(|loc| {
+                        format!(
+                            "{} at {}:{} (looks like '{}')",
+                            loc.codepoint,
+                            loc.line,
+                            loc.column,
+                            loc.ascii.unwrap_or('?')
+                        )
+                    })
+                    .collect();
+                println!("    confusables: {}", positions.join(", "));
+            }
+            if let Some(eol) = report.line_endings
+                && (eol.label() == "mixed" || convert_eol.is_some())
+            {
+                let target = convert_eol
+                    .map(|style| format!(" -> {}", line_ending_label(style)))
+                    .unwrap_or_default();
+                println!(
+                    "    line endings: {} (lf={}, crlf={}, cr={}){target}",
+                    eol.label(),
+                    eol.lf,
+                    eol.crlf,
+                    eol.cr
+                );
+            }
+            if let Some(indent) = report.indentation
+                && (indent.is_inconsistent() || indent.converted > 0)
+            {
+                println!(
+                    "    indentation: {} tab line(s), {} space line(s), {} mixed line(s), {} converted",
+                    indent.tabs, indent.spaces, indent.mixed, indent.converted
+                );
+            }
+            if let (Some(form), Some(lines)) = (unicode_form, report.unicode_form) {
+                println!(
+                    "    unicode {}: {lines} line(s) not in normal form",
+                    form.label()
+                );
+            }
+        }
+        ReportFormat::Json => {
+            let row = NormalizeJsonRow {
+                path: path.display().to_string(),
+                zero_width: report.zero_width,
+                control_chars: report.control_chars,
+                bidi_controls: report.bidi_controls,
+                bidi_locations: report.bidi_locations.clone(),
+                confusables: report.confusables,
+                confusable_locations: report.confusable_locations.clone(),
+                indentation: report.indentation,
+                line_endings: report.line_endings,
+                bom: report.bom,
+                smart_punctuation: report.smart_punctuation,
+                excess_blank_lines: report.excess_blank_lines,
+                findings: report.findings.clone(),
+                strip_classes: report.strip_classes.clone(),
+                long_lines: report.long_lines,
+                long_line_locations: report.long_line_locations.clone(),
+                line_ending_style: report.line_endings.map(|eol| eol.label()),
+                convert_eol: convert_eol.map(line_ending_label),
+                trailing_spaces: report.trailing_spaces,
+                missing_final_newline: report.missing_final_newline,
+                unicode_form: unicode_form.map(|form| form.label()),
+                unicode_form_lines: report.unicode_form,
+                encoding: encoding_name.map(|(name, _)| name.to_string()),
+                encoding_score: encoding_name.map(|(_, score)| score),
+                convert_encoding: convert_encoding.map(|s| s.to_string()),
+            };
+            println!("{}", serde_json::to_string(&row)?);
+        }
+    }
+    Ok(())
+}
+
+fn format_detection(value: Option<usize>) -> String {
+    match value {
+        Some(count) => count.to_string(),
+        None => "n/a".into(),
+    }
+}
+
+fn format_bool(value: Option<bool>) -> &'static str {
+    match value {
+        Some(true) => "yes",
+        Some(false) => "no",
+        None => "n/a",
+    }
+}
+
+#[derive(Serialize)]
+struct NormalizeJsonRow {
+    path: String,
+    zero_width: Option<usize>,
+    control_chars: Option<usize>,
+    bidi_controls: Option<usize>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    bidi_locations: Vec<normalize::CharLocation>,
+    confusables: Option<usize>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    confusable_locations: Vec<normalize::CharLocation>,
+    indentation: Option<normalize::IndentSummary>,
+    line_endings: Option<normalize::LineEndingCounts>,
+    bom: Option<bool>,
+    smart_punctuation: Option<usize>,
+    excess_blank_lines: Option<usize>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    findings: Vec<normalize::Finding>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    strip_classes: BTreeMap<String, usize>,
+    long_lines: Option<usize>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    long_line_locations: Vec<normalize::Finding>,
+    line_ending_style: Option<&'static str>,
+    convert_eol: Option<&'static str>,
+    trailing_spaces: Option<usize>,
+    missing_final_newline: Option<bool>,
+    unicode_form: Option<&'static str>,
+    unicode_form_lines: Option<usize>,
+    encoding: Option<String>,
+    encoding_score: Option<f32>,
+    convert_encoding: Option<String>,
+}
+
+#[derive(Debug, Clone, Default)]
+struct WriteOptions {
+    undo_dir: Option<PathBuf>,
+    applied_patch: Option<PathBuf>,
+    backup: backup::BackupOptions,
+    strict_encoding: bool,
+    verify_write: bool,
+    preserve_metadata: bool,
+    force_readonly: bool,
+    mtime: MtimePolicy,
+    precondition: Option<Precondition>,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+enum MtimePolicy {
+    #[default]
+    Touch,
+    Preserve,
+    Set(SystemTime),
+}
+
+fn parse_size_arg(raw: &str) -> Result<u64, String> {
+    parse_size(raw).map_err(|err| err.to_string())
+}
+
+fn parse_rfc3339(raw: &str) -> Result<OffsetDateTime, String> {
+    OffsetDateTime::parse(raw, &Rfc3339).map_err(|err| format!("expected RFC3339 timestamp: {err}"))
+}
+
+fn apply_mtime(path: &Path, policy: MtimePolicy, original: Option<SystemTime>) -> Result<()> {
+    let stamp = match (policy, original) {
+        (MtimePolicy::Set(stamp), _) => stamp,
+        (MtimePolicy::Preserve, Some(stamp)) => stamp,
+        _ => return Ok(()),
+    };
+    open_for_timestamps(path)
+        .and_then(|file| file.set_modified(stamp))
+        .with_context(|| format!("setting mtime of {}", path.display()))
+}
+
+// Changing timestamps needs no write access to the contents, so this works
+// on files whose read-only bit `write_via_temp` has just put back.
+#[cfg(windows)]
+fn open_for_timestamps(path: &Path) -> io::Result<fs::File> {
+    use std::os::windows::fs::OpenOptionsExt;
+    use windows_sys::Win32::Storage::FileSystem::FILE_WRITE_ATTRIBUTES;
+    fs::File::options()
+        .access_mode(FILE_WRITE_ATTRIBUTES)
+        .open(path)
+}
+
+#[cfg(not(windows))]
+fn open_for_timestamps(path: &Path) -> io::Result<fs::File> {
+    fs::File::open(path)
+}
+
+fn apply_transform(
+    entry: &FileEntry,
+    result: &TransformResult,
+    target_encoding: Option<&'static Encoding>,
+    options: &WriteOptions,
+) -> Result<()> {
+    let _timer = timing::start(timing::Phase::Write, Some(&entry.path));
+    let _lock = lock::lock_path(&entry.path)?;
+    if let Some(precondition) = &options.precondition {
+        precondition.check(&entry.path)?;
+    }
+    ensure_preview_current(entry, result)?;
+    ensure_writable(&entry.path, options.force_readonly)?;
+    let encoding = target_encoding.unwrap_or(result.decoded.decision.encoding);
+    let (encoded, had_errors) = encode_text(&result.new_text, encoding, result.decoded.bom);
+    if had_errors && options.strict_encoding {
+        let unencodable = encoding::unencodable_chars(&result.new_text, encoding);
+        let listed: Vec<String> = unencodable
+            .iter()
+            .take(20)
+            .map(|(line, column, ch)| format!("U+{:04X} '{ch}' at {line}:{column}", *ch as u32))
+            .collect();
+        let more = unencodable.len().saturating_sub(listed.len());
+        bail!(
+            "refusing lossy write of {}: {} character(s) cannot be represented in {}: {}{}",
+            entry.path.display(),
+            unencodable.len(),
+            encoding.name(),
+            listed.join(", "),
+            if more > 0 {
+                format!(" (+{more} more)")
+            } else {
+                String::new()
+            }
+        );
+    }
+    let mut undo_patch = None;
+    if let Some(dir) = options.undo_dir.as_deref() {
+        undo_patch = Some(write_undo_patch(
+            dir,
+            entry,
+            &result.decoded.text,
+            &result.new_text,
+        )?);
+    }
+    if had_errors {
+        println!(
+            "warning: encoding fallback occurred when writing {}; output may be lossy",
+            entry.path.display()
+        );
+    }
+    if let Some(parent) = entry.path.parent()
+        && !parent.as_os_str().is_empty()
+    {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("creating directory {}", parent.display()))?;
+    }
+    let original_mtime = fs::metadata(&entry.path)
+        .and_then(|metadata| metadata.modified())
+        .ok();
+    // A staging copy is thrown away; its source gets the backup on write-back.
+    let staged = logging::staged_name(&entry.path).is_some();
+    let backup = if staged {
+        None
+    } else {
+        backup::create(&entry.path, &options.backup)?
+    };
+    let original = if entry.path.exists() {
+        Some(fs::read(&entry.path).with_context(|| format!("reading {}", entry.path.display()))?)
+    } else {
+        None
+    };
+    let journal = if staged {
+        write_back_staged(&entry.path, &encoded, options)?;
+        None
+    } else {
+        Some(journal::begin(&entry.path, original.as_deref(), &encoded)?)
+    };
+    write_via_temp(&entry.path, &encoded, options.preserve_metadata)
+        .with_context(|| format!("writing {}", entry.path.display()))?;
+    if let Some(bak) = &backup {
+        println!(
+            "backup saved: {} -> {}",
+            entry.path.display(),
+            bak.display()
+        );
+    }
+    if options.verify_write {
+        verify_written(&entry.path, encoding, &result.new_text, original.as_deref())?;
+    }
+    apply_mtime(&entry.path, options.mtime, original_mtime)?;
+    session::record(&entry.path, original.as_deref(), Some(&encoded))?;
+    if let Some(file) = &options.applied_patch {
+        let old = original.as_ref().map(|_| result.decoded.text.as_str());
+        save_applied_patch(file, &entry.path, old, Some(&result.new_text))?;
+    }
+    logging::note_write(
+        &entry.path,
+        logging::WriteRecord {
+            before_sha256: original.as_deref().map(integrity::sha256_hex),
+            after_sha256: Some(integrity::sha256_hex(&encoded)),
+            undo_patch,
+        },
+    );
+    drop(journal);
+    println!("applied {}", logging::recorded_name(&entry.path).display());
+    Ok(())
+}
+
+// The byte-level counterpart of apply_transform, for edits that never went
+// through a decode.
+fn write_binary(
+    path: &Path,
+    original: &[u8],
+    patched: &[u8],
+    options: &WriteOptions,
+) -> Result<()> {
+    let _timer = timing::start(timing::Phase::Write, Some(path));
+    let _lock = lock::lock_path(path)?;
+    if let Some(precondition) = &options.precondition {
+        precondition.check(path)?;
+    }
+    let current = fs::read(path).with_context(|| format!("reading {}", path.display()))?;
+    if current != original {
+        bail!(
+            "{} changed since the preview; rerun to see the current diff",
+            path.display()
+        );
+    }
+    ensure_writable(path, options.force_readonly)?;
+    let original_mtime = fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok();
+    let staged = logging::staged_name(path).is_some();
+    let backup = if staged {
+        None
+    } else {
+        backup::create(path, &options.backup)?
+    };
+    let journal = if staged {
+        write_back_staged(path, patched, options)?;
+        None
+    } else {
+        Some(journal::begin(path, Some(original), patched)?)
+    };
+    write_via_temp(path, patched, options.preserve_metadata)
+        .with_context(|| format!("writing {}", path.display()))?;
+    if let Some(bak) = &backup {
+        println!("backup saved: {} -> {}", path.display(), bak.display());
+    }
+    if options.verify_write {
+        verify_written_bytes(path, patched, original)?;
+    }
+    apply_mtime(path, options.mtime, original_mtime)?;
+    session::record(path, Some(original), Some(patched))?;
+    logging::note_write(
+        path,
+        logging::WriteRecord {
+            before_sha256: Some(integrity::sha256_hex(original)),
+            after_sha256: Some(integrity::sha256_hex(patched)),
+            undo_patch: None,
+        },
+    );
+    drop(journal);
+    println!("applied {}", logging::recorded_name(path).display());
+    Ok(())
+}
+
+fn verify_written(
+    path: &Path,
+    encoding: &'static Encoding,
+    expected: &str,
+    original: Option<&[u8]>,
+) -> Result<()> {
+    let written = fs::read(path).with_context(|| format!("re-reading {}", path.display()))?;
+    let (decoded, _, _) = encoding.decode(&written);
+    if decoded == expected {
+        println!("verified {} ({} bytes)", path.display(), written.len());
+        return Ok(());
+    }
+    let first_diff = decoded
+        .lines()
+        .zip(expected.lines())
+        .position(|(actual, wanted)| actual != wanted)
+        .map(|idx| idx + 1)
+        .unwrap_or_else(|| decoded.lines().count().min(expected.lines().count()) + 1);
+    match original {
+        Some(bytes) => write_via_temp(path, bytes, true)
+            .with_context(|| format!("restoring {}", path.display()))?,
+        None => fs::remove_file(path).with_context(|| format!("removing {}", path.display()))?,
+    }
+    bail!(
+        "round-trip verification failed for {} (first mismatch at line {first_diff}); original content restored",
+        path.display()
+    );
+}
+
+fn verify_written_bytes(path: &Path, expected: &[u8], original: &[u8]) -> Result<()> {
+    let written = fs::read(path).with_context(|| format!("re-reading {}", path.display()))?;
+    if written == expected {
+        println!("verified {} ({} bytes)", path.display(), written.len());
+        return Ok(());
+    }
+    let first_diff = written
+        .iter()
+        .zip(expected)
+        .position(|(actual, wanted)| actual != wanted)
+        .unwrap_or_else(|| written.len().min(expected.len()));
+    write_via_temp(path, original, true)
+        .with_context(|| format!("restoring {}", path.display()))?;
+    bail!(
+        "round-trip verification failed for {} (first mismatch at byte 0x{first_diff:x}); original content restored",
+        path.display()
+    );
+}
+
+fn write_via_temp(path: &Path, data: &[u8], preserve_metadata: bool) -> Result<()> {
+    let parent = path.parent().filter(|p| !p.as_os_str().is_empty());
+    if let Some(dir) = parent {
+        fs::create_dir_all(dir).with_context(|| format!("creating directory {}", dir.display()))?;
+    }
+    let base_dir = parent.unwrap_or_else(|| Path::new("."));
+    let original_attributes = attributes::FileAttributes::read(path);
+    let unique = format!(
+        "{}{}-{}",
+        journal::TEMP_PREFIX,
+        std::process::id(),
+        OffsetDateTime::now_utc().unix_timestamp_nanos()
+    );
+    let temp_path = base_dir.join(unique);
+    {
+        let mut file = fs::File::create(&temp_path)
+            .with_context(|| format!("creating temp file {}", temp_path.display()))?;
+        file.write_all(data)
+            .with_context(|| format!("writing temp file {}", temp_path.display()))?;
+        file.sync_all()
+            .with_context(|| format!("syncing temp file {}", temp_path.display()))?;
+    }
+    if preserve_metadata && let Err(err) = copy_file_metadata(path, &temp_path) {
+        let _ = fs::remove_file(&temp_path);
+        return Err(err);
+    }
+    let readonly = original_attributes.is_some_and(|attrs| attrs.readonly);
+    if readonly {
+        attributes::set_readonly(path, false)?;
+    }
+    let replaced = match fs::rename(&temp_path, path) {
+        Ok(()) => Ok(()),
+        Err(err) if is_cross_device(&err) => {
+            println!(
+                "note: cannot rename over {} ({err}); copying in place instead",
+                path.display()
+            );
+            copy_in_place(&temp_path, path)
+        }
+        Err(err) => Err(err).with_context(|| format!("replacing {}", path.display())),
+    };
+    if let Err(err) = replaced {
+        let _ = fs::remove_file(&temp_path);
+        if readonly {
+            let _ = attributes::set_readonly(path, true);
+        }
+        return Err(err);
+    }
+    let _ = fs::remove_file(&temp_path);
+    if let Err(err) = sync_dir(base_dir) {
+        println!(
+            "warning: could not sync directory {}: {err}",
+            base_dir.display()
+        );
+    }
+    if let Some(attrs) = original_attributes
+        && (preserve_metadata || attrs.readonly)
+    {
+        attributes::restore(path, attrs)?;
+    }
+    Ok(())
+}
+
+fn is_cross_device(err: &io::Error) -> bool {
+    matches!(
+        err.kind(),
+        io::ErrorKind::CrossesDevices | io::ErrorKind::ResourceBusy
+    )
+}
+
+fn copy_in_place(source: &Path, target: &Path) -> Result<()> {
+    let data = fs::read(source).with_context(|| format!("reading {}", source.display()))?;
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .truncate(true)
+        .create(true)
+        .open(target)
+        .with_context(|| format!("opening {}", target.display()))?;
+    file.write_all(&data)
+        .with_context(|| format!("writing {}", target.display()))?;
+    file.sync_all()
+        .with_context(|| format!("syncing {}", target.display()))
+}
+
+#[cfg(unix)]
+fn sync_dir(dir: &Path) -> io::Result<()> {
+    fs::File::open(dir)?.sync_all()
+}
+
+#[cfg(not(unix))]
+fn sync_dir(_dir: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+fn copy_file_metadata(original: &Path, replacement: &Path) -> Result<()> {
+    let Ok(metadata) = fs::metadata(original) else {
+        return Ok(());
+    };
+    fs::set_permissions(replacement, metadata.permissions())
+        .with_context(|| format!("copying permissions from {}", original.display()))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        let current = fs::metadata(replacement)
+            .with_context(|| format!("reading metadata of {}", replacement.display()))?;
+        if (current.uid(), current.gid()) != (metadata.uid(), metadata.gid())
+            && let Err(err) =
+                std::os::unix::fs::chown(replacement, Some(metadata.uid()), Some(metadata.gid()))
+        {
+            println!(
+                "warning: could not preserve ownership of {}: {err}",
+                original.display()
+            );
+        }
+        if let Ok(names) = xattr::list(original) {
+            for name in names {
+                if let Ok(Some(value)) = xattr::get(original, &name)
+                    && let Err(err) = xattr::set(replacement, &name, &value)
+                {
+                    println!(
+                        "warning: could not preserve extended attribute {} on {}: {err}",
+                        name.to_string_lossy(),
+                        original.display()
+                    );
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn ensure_writable(path: &Path, force_readonly: bool) -> Result<()> {
+    let Some(attributes) = attributes::FileAttributes::read(path) else {
+        return Ok(());
+    };
+    // Only the Windows read-only attribute is a lock worth refusing; on Unix a
+    // 0444 file has always been replaced by the temp-file rename.
+    if cfg!(windows) && attributes.readonly && !force_readonly {
+        bail!(
+            "{} is read-only; pass --force-readonly to clear the attribute for this write (it is restored afterwards)",
+            path.display()
+        );
+    }
+    let labels = attributes.labels();
+    if !labels.is_empty() {
+        println!(
+            "note: {} has {} attribute(s); they will be restored after the write",
+            path.display(),
+            labels.join(", ")
+        );
+    }
+    Ok(())
+}
+
+fn ensure_preview_current(entry: &FileEntry, result: &TransformResult) -> Result<()> {
+    let Some(expected) = &result.source_hash else {
+        return Ok(());
+    };
+    let current = fs::read(&entry.path)
+        .ok()
+        .map(|bytes| integrity::sha256_hex(&bytes));
+    if current.as_ref() != Some(expected) {
+        bail!(
+            "{} changed on disk after the preview was generated; rerun to review a fresh diff",
+            entry.path.display()
+        );
+    }
+    Ok(())
+}
+
+fn write_new_file(
+    path: &Path,
+    new_text: &str,
+    encoding: &EncodingStrategy,
+    options: &WriteOptions,
+) -> Result<()> {
+    let decision = encoding.decide(b"");
+    let decoded = DecodedText {
+        text: String::new(),
+        had_errors: false,
+        bom: false,
+        decision: decision.clone(),
+    };
+    let entry = FileEntry {
+        path: path.to_path_buf(),
+        metadata: FileMetadata {
+            len: 0,
+            is_probably_binary: false,
+        },
+    };
+    let result = TransformResult {
+        decoded,
+        new_text: new_text.to_string(),
+        source_hash: None,
+    };
+    apply_transform(&entry, &result, Some(decision.encoding), options)
+}
+
+fn delete_file_with_undo(path: &Path, old_text: &str, options: &WriteOptions) -> Result<()> {
+    let entry = FileEntry {
+        path: path.to_path_buf(),
+        metadata: FileMetadata {
+            len: old_text.len() as u64,
+            is_probably_binary: false,
+        },
+    };
+    let _lock = lock::lock_path(path)?;
+    let undo_patch = match options.undo_dir.as_deref() {
+        Some(dir) => Some(write_undo_patch(dir, &entry, old_text, "")?),
+        None => None,
+    };
+    if path.exists() {
+        let backup = backup::create(path, &options.backup)?;
+        if let Some(bak) = backup {
+            println!("backup saved: {} -> {}", path.display(), bak.display());
+        }
+        let original = fs::read(path).with_context(|| format!("reading {}", path.display()))?;
+        fs::remove_file(path).with_context(|| format!("removing {}", path.display()))?;
+        session::record(path, Some(&original), None)?;
+        if let Some(file) = &options.applied_patch {
+            save_applied_patch(file, path, Some(old_text), None)?;
+        }
+        logging::note_write(
+            path,
+            logging::WriteRecord {
+                before_sha256: Some(integrity::sha256_hex(&original)),
+                after_sha256: None,
+                undo_patch,
+            },
+        );
+    }
+    println!("deleted {}", path.display());
+    Ok(())
+}
+
+// Created and deleted files use /dev/null on the missing side so the combined
+// patch applies (or reverts) with `git apply` / `patch -p0`.
+fn save_applied_patch(
+    file: &Path,
+    path: &Path,
+    old: Option<&str>,
+    new: Option<&str>,
+) -> Result<()> {
+    let null = Path::new("/dev/null");
+    let name = logging::recorded_name(path);
+    let path = std::env::current_dir()
+        .ok()
+        .and_then(|cwd| name.strip_prefix(cwd).ok())
+        .unwrap_or(&name);
+    let diff = diff::unified_diff(
+        if old.is_some() { path } else { null },
+        if new.is_some() { path } else { null },
+        old.unwrap_or(""),
+        new.unwrap_or(""),
+        3,
+    );
+    events::append_applied_patch(file, &diff)
+}
+
+fn write_undo_patch(
+    dir: &Path,
+    entry: &FileEntry,
+    old_text: &str,
+    new_text: &str,
+) -> Result<PathBuf> {
+    fs::create_dir_all(dir).with_context(|| format!("creating undo dir {}", dir.display()))?;
+    let timestamp = OffsetDateTime::now_utc()
+        .format(&Rfc3339)
+        .unwrap_or_else(|_| "unknown".into());
+    let path = logging::recorded_name(&entry.path);
+    let sanitized = sanitize_path(&path);
+    let file_name = format!("{timestamp}_{sanitized}.patch");
+    let patch_path = dir.join(file_name);
+    let diff = diff::unified_diff(&path, &path, new_text, old_text, 3);
+    let diff = format!("# safeedit session: {}\n{diff}", session::current_id());
+    fs::write(&patch_path, diff)
+        .with_context(|| format!("writing undo patch {}", patch_path.display()))?;
+    Ok(patch_path)
+}
+
+// --preview-dir: each dry-run diff also lands in DIR as
+// `<escaped-path>.patch` (cwd-relative paths, 3 lines of context) so the
+// review can be split up before the --apply run.
+fn export_preview(common: &CommonArgs, path: &Path, old: &str, new: &str) -> Result<()> {
+    let Some(dir) = &common.preview_dir else {
+        return Ok(());
+    };
+    if old == new {
+        return Ok(());
+    }
+    let relative = std::env::current_dir()
+        .ok()
+        .and_then(|cwd| path.strip_prefix(cwd).ok())
+        .unwrap_or(path);
+    fs::create_dir_all(dir).with_context(|| format!("creating preview dir {}", dir.display()))?;
+    let patch_path = dir.join(preview_file_name(relative));
+    fs::write(
+        &patch_path,
+        diff::unified_diff(relative, relative, old, new, 3),
+    )
+    .with_context(|| format!("writing preview {}", patch_path.display()))?;
+    println!("preview saved: {}", patch_path.display());
+    Ok(())
+}
+
+// Percent-escapes `%` along with the separators and reserved characters, so
+// distinct paths (`a/b.txt`, `a_b.txt`) never share a preview file.
+fn preview_file_name(path: &Path) -> String {
+    let mut name = String::new();
+    for ch in path.display().to_string().chars() {
+        match ch {
+            '%' | '\\' | '/' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => {
+                name.push_str(&format!("%{:02X}", ch as u32));
+            }
+            _ => name.push(ch),
+        }
+    }
+    name.push_str(".patch");
+    name
+}
+
+fn sanitize_path(path: &Path) -> String {
+    path.display()
+        .to_string()
+        .chars()
+        .map(|ch| match ch {
+            '\\' | '/' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            _ => ch,
+        })
+        .collect()
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, ValueEnum, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum LineEndingStyle {
+    Lf,
+    Crlf,
+    Cr,
+}
+
+fn line_ending_label(style: LineEndingStyle) -> &'static str {
+    match style {
+        LineEndingStyle::Lf => "lf",
+        LineEndingStyle::Crlf => "crlf",
+        LineEndingStyle::Cr => "cr",
+    }
+}
+
+fn detect_line_ending_style(text: &str) -> LineEndingStyle {
+    if text.contains("\r\n") {
+        LineEndingStyle::Crlf
+    } else if text.contains('\r') {
+        LineEndingStyle::Cr
+    } else {
+        LineEndingStyle::Lf
+    }
+}
+
+fn normalize_to_lf(text: &str) -> Cow<'_, str> {
+    if !text.contains('\r') {
+        return Cow::Borrowed(text);
+    }
+    let mut normalized = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '\r' => {
+                if matches!(chars.peek(), Some('\n')) {
+                    chars.next();
+                }
+                normalized.push('\n');
+            }
+            _ => normalized.push(ch),
+        }
+    }
+    Cow::Owned(normalized)
+}
+
+fn restore_from_lf(text: String, style: LineEndingStyle) -> String {
+    match style {
+        LineEndingStyle::Lf => text,
+        LineEndingStyle::Crlf => text.replace('\n', "\r\n"),
+        LineEndingStyle::Cr => text.replace('\n', "\r"),
+    }
+}
+
+fn apply_patch_preserving_newlines<'a>(
+    text: &str,
+    parsed_patch: &DiffPatch<'a, str>,
+    window: usize,
+) -> std::result::Result<(String, Vec<patch::HunkOffset>), patch::HunkMismatch> {
+    let style = detect_line_ending_style(text);
+    let normalized = normalize_to_lf(text);
+    let (patched, offsets) = patch::apply_with_offsets(normalized.as_ref(), parsed_patch, window)?;
+    Ok((restore_from_lf(patched, style), offsets))
+}
+
+fn report_hunk_offsets(work: &PatchWork, offsets: &[patch::HunkOffset]) {
+    for offset in offsets {
+        println!(
+            "patch {}#{}: hunk {} applied at line {} (offset {:+} line(s))",
+            work.patch.source.display(),
+            work.patch.index,
+            offset.hunk,
+            offset.line,
+            offset.offset
+        );
+    }
+}
+
+// --keep-going: a file that cannot be read or written is reported and left
+// behind while the rest of the run carries on; the command still fails at the
+// end, listing every such file.
+struct FileFailures<'a> {
+    common: &'a CommonArgs,
+    command: &'static str,
+    failed: Vec<(PathBuf, String)>,
+}
+
+impl<'a> FileFailures<'a> {
+    fn new(common: &'a CommonArgs, command: &'static str) -> Self {
+        Self {
+            common,
+            command,
+            failed: Vec::new(),
+        }
+    }
+
+    fn check<T>(&mut self, path: &Path, result: Result<T>) -> Result<Option<T>> {
+        match result {
+            Ok(value) => Ok(Some(value)),
+            Err(err) if self.common.keep_going => {
+                let message = format!("{err:#}");
+                println!("error: {}: {message}", path.display());
+                log_change(
+                    self.common,
+                    self.command,
+                    path,
+                    "failed",
+                    &message,
+                    &[],
+                    Some(status_extra(false, !self.common.apply)),
+                );
+                self.failed.push((path.to_path_buf(), message));
+                Ok(None)
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    fn failed(&self, path: &Path) -> bool {
+        self.failed.iter().any(|(failed, _)| failed == path)
+    }
+
+    fn finish(&self) -> Result<()> {
+        if self.failed.is_empty() {
+            return Ok(());
+        }
+        println!("{} failed for {} file(s):", self.command, self.failed.len());
+        for (path, message) in &self.failed {
+            println!("  - {}: {message}", path.display());
+        }
+        bail!(
+            "{} failed for {} file(s); the other files were processed",
+            self.command,
+            self.failed.len()
+        );
+    }
+}
+
+#[derive(Default)]
+struct CommandStats {
+    applied: usize,
+    skipped: usize,
+    dry_run: usize,
+    no_op: usize,
+    already_done: usize,
+    markers_missing: usize,
+    expect_mismatch: usize,
+}
+
+impl CommandStats {
+    fn print(&self, label: &str) {
+        let total = self.applied
+            + self.skipped
+            + self.dry_run
+            + self.no_op
+            + self.already_done
+            + self.markers_missing
+            + self.expect_mismatch;
+        if total == 0 {
+            return;
+        }
+        let mut extra = String::new();
+        if self.already_done > 0 {
+            extra.push_str(&format!(", already-done={}", self.already_done));
+        }
+        if self.markers_missing > 0 {
+            extra.push_str(&format!(", markers-missing={}", self.markers_missing));
+        }
+        if self.expect_mismatch > 0 {
+            extra.push_str(&format!(", expect-mismatch={}", self.expect_mismatch));
+        }
+        println!(
+            "{label} summary: applied={}, skipped={}, dry-run={}, no-op={}{extra}",
+            self.applied, self.skipped, self.dry_run, self.no_op
+        );
+    }
+}
+
+fn merge_common(base: &CommonArgs, overrides: &batch::PlanCommon) -> CommonArgs {
+    let mut merged = base.clone();
+    if let Some(targets) = &overrides.targets {
+        merged.targets = targets.clone();
+    }
+    if let Some(globs) = &overrides.globs {
+        merged.globs = globs.clone();
+    }
+    if overrides.targets.is_some() || overrides.globs.is_some() {
+        merged.extra_args.clear();
+    }
+    if let Some(encoding) = &overrides.encoding {
+        merged.encoding = Some(encoding.clone());
+    }
+    if let Some(apply) = overrides.apply {
+        merged.apply = apply;
+    }
+    if let Some(auto) = overrides.auto_apply {
+        merged.auto_apply = auto;
+    }
+    if let Some(policy) = &overrides.approve_policy {
+        merged.approve_policy = Some(policy.clone());
+    }
+    if let Some(no_backup) = overrides.no_backup {
+        merged.writes.no_backup = no_backup;
+    }
+    if let Some(dir) = &overrides.backup_dir {
+        merged.writes.backup_dir = Some(dir.clone());
+    }
+    if let Some(naming) = overrides.backup_naming {
+        merged.writes.backup_naming = naming;
+    }
+    if let Some(keep) = overrides.backup_keep {
+        merged.writes.backup_keep = Some(keep);
+    }
+    if let Some(days) = overrides.backup_max_age {
+        merged.writes.backup_max_age = Some(days);
+    }
+    if let Some(compress) = overrides.backup_compress {
+        merged.writes.backup_compress = compress;
+    }
+    if let Some(threshold) = overrides.backup_compress_threshold {
+        merged.writes.backup_compress_threshold = threshold;
+    }
+    if let Some(context) = overrides.context {
+        merged.context = context;
+    }
+    if let Some(pager) = overrides.pager {
+        merged.pager = pager;
+    }
+    if let Some(format) = overrides.diff_format {
+        merged.diff_format = format;
+    }
+    if let Some(line_numbers) = overrides.line_numbers {
+        merged.line_numbers = line_numbers;
+    }
+    if let Some(page_lines) = overrides.page_lines {
+        merged.page_lines = Some(page_lines);
+    }
+    if let Some(lines) = overrides.max_diff_lines {
+        merged.max_diff_lines = Some(lines);
+    }
+    if let Some(bytes) = overrides.max_diff_bytes {
+        merged.max_diff_bytes = Some(bytes);
+    }
+    if let Some(color) = overrides.color {
+        merged.color = color;
+    }
+    if let Some(json) = overrides.json {
+        merged.json = json;
+    }
+    if let Some(path) = &overrides.json_file {
+        merged.json_file = Some(path.clone());
+    }
+    if let Some(include_hidden) = overrides.include_hidden {
+        merged.include_hidden = include_hidden;
+    }
+    if let Some(exclude) = &overrides.exclude {
+        merged.exclude = exclude.clone();
+    }
+    if let Some(undo_log) = &overrides.undo_log {
+        merged.undo_log = Some(undo_log.clone());
+    }
+    if let Some(path) = &overrides.save_applied_patch {
+        merged.save_applied_patch = Some(path.clone());
+    }
+    if let Some(dir) = &overrides.preview_dir {
+        merged.preview_dir = Some(dir.clone());
+    }
+    if let Some(strict) = overrides.strict_encoding {
+        merged.strict_encoding = strict;
+    }
+    if let Some(verify) = overrides.verify_write {
+        merged.writes.verify_write = verify;
+    }
+    if let Some(skip) = overrides.no_preserve_metadata {
+        merged.writes.no_preserve_metadata = skip;
+    }
+    if let Some(force) = overrides.force_readonly {
+        merged.writes.force_readonly = force;
+    }
+    if let Some(limit) = overrides.max_file_size {
+        merged.max_file_size = limit;
+    }
+    if let Some(force) = overrides.force_large {
+        merged.force_large = force;
+    }
+    if let Some(keep_going) = overrides.keep_going {
+        merged.keep_going = keep_going;
+    }
+    if let Some(preserve) = overrides.preserve_mtime {
+        merged.writes.preserve_mtime = preserve;
+        if preserve {
+            merged.writes.set_mtime = None;
+        }
+    }
+    if let Some(stamp) = overrides.set_mtime {
+        merged.writes.set_mtime = Some(stamp);
+        merged.writes.preserve_mtime = false;
+    }
+    if let Some(min) = overrides.min_detection_score {
+        merged.min_detection_score = Some(min);
+    }
+    merged
+}
+
+fn build_replace_command(
+    base_common: &CommonArgs,
+    step: &batch::ReplacePlan,
+) -> Result<ReplaceCommand> {
+    if step.replacement.is_none()
+        && step.with_url.is_none()
+        && !step.with_stdin
+        && !step.with_clipboard
+    {
+        bail!("replace step missing replacement text or input source");
+    }
+    Ok(ReplaceCommand {
+        common: merge_common(base_common, &step.common),
+        pattern: step.pattern.clone(),
+        replacement: step.replacement.clone(),
+        with_stdin: step.with_stdin,
+        with_clipboard: step.with_clipboard,
+        with_here: None,
+        with_url: step.with_url.clone(),
+        url: UrlFetchArgs::from_plan(&step.url_sha256),
+        regex: step.regex,
+        literal: step.literal,
+        no_expand: step.no_expand,
+        case_aware: step.case_aware,
+        allow_binary_literal: step.allow_binary_literal,
+        diff_only: step.diff_only,
+        count: step.count,
+        expect: step.expect,
+        total_count: step.total_count,
+        expect_total: step.expect_total,
+        after_line: step.after_line,
+        skip_if_contains: step.skip_if_contains.clone(),
+        if_hash: step.if_hash.clone(),
+        if_unchanged_since: step.if_unchanged_since.clone(),
+        record: None,
+    })
+}
+
+fn build_normalize_command(
+    base_common: &CommonArgs,
+    step: &batch::NormalizePlan,
+) -> Result<NormalizeCommand> {
+    Ok(NormalizeCommand {
+        common: merge_common(base_common, &step.common),
+        edits: NormalizeEditArgs {
+            strip_zero_width: step.strip_zero_width.unwrap_or(false),
+            strip_control: step.strip_control.unwrap_or(false),
+            strip_bidi: step.strip_bidi.unwrap_or(false),
+            map_confusables: step.map_confusables.unwrap_or(false),
+            ascii_punctuation: step.ascii_punctuation.unwrap_or(false),
+            map_char: step.map_char.clone(),
+            strip_class: step.strip_class.clone(),
+            trim_trailing_space: step.trim_trailing_space.unwrap_or(false),
+            ensure_eol: step.ensure_eol.unwrap_or(false),
+            tabs_to_spaces: step.tabs_to_spaces,
+            spaces_to_tabs: step.spaces_to_tabs,
+            max_blank_lines: step.max_blank_lines,
+            max_line_length: step.max_line_length,
+            wrap_long_lines: step.wrap_long_lines.unwrap_or(false),
+            convert_eol: step.convert_eol,
+            strip_bom: step.strip_bom.unwrap_or(false),
+            add_bom: step.add_bom.unwrap_or(false),
+            unicode_form: step.unicode_form,
+        },
+        convert_encoding: step.convert_encoding.clone(),
+        fail_on_bidi: step.fail_on_bidi.unwrap_or(false),
+        fail_on_long_lines: step.fail_on_long_lines.unwrap_or(false),
+        report_format: step
+            .report_format
+            .clone()
+            .unwrap_or_else(|| "table".to_string()),
+        locations: step.locations.unwrap_or(false),
+        scan_encoding: step.scan_encoding.unwrap_or(false),
+        scan_zero_width: step.scan_zero_width.unwrap_or(false),
+        scan_control: step.scan_control.unwrap_or(false),
+        scan_bidi: step.scan_bidi.unwrap_or(false),
+        scan_confusables: step.scan_confusables.unwrap_or(false),
+        scan_punctuation: step.scan_punctuation.unwrap_or(false),
+        scan_trailing_space: step.scan_trailing_space.unwrap_or(false),
+        scan_indentation: step.scan_indentation.unwrap_or(false),
+        scan_eol: step.scan_eol.unwrap_or(false),
+        scan_final_newline: step.scan_final_newline.unwrap_or(false),
+        record: None,
+    })
+}
+
+fn build_block_command(base_common: &CommonArgs, step: &batch::BlockPlan) -> Result<BlockCommand> {
+    if step.body.is_none() && step.body_file.is_none() && step.body_url.is_none() {
+        bail!("block step requires a 'body' string, 'body_file', or 'body_url'");
+    }
+    if step.start_marker.is_some() ^ step.end_marker.is_some() {
+        bail!("block step requires both start_marker and end_marker");
+    }
+    if step.insert_after.is_some() && step.insert_before.is_some() {
+        bail!("block step cannot set both insert_after and insert_before");
+    }
+    if step.start_marker.is_none() && step.insert_after.is_none() && step.insert_before.is_none() {
+        bail!("block step must supply start/end markers or one of insert-after/insert-before");
+    }
+    let mode = match &step.mode {
+        Some(mode) => Some(
+            BlockMode::from_str(mode)
+                .map_err(|err| anyhow!("invalid block mode '{mode}': {err}"))?,
+        ),
+        None => None,
+    };
+    Ok(BlockCommand {
+        common: merge_common(base_common, &step.common),
+        start_marker: step.start_marker.clone(),
+        end_marker: step.end_marker.clone(),
+        insert_after: step.insert_after.clone(),
+        insert_before: step.insert_before.clone(),
+        mode,
+        body: step
+            .body
+            .as_ref()
+            .map(|text| vec![text.clone()])
+            .unwrap_or_default(),
+        body_file: step.body_file.clone(),
+        with_stdin: false,
+        with_clipboard: false,
+        body_here: None,
+        body_url: step.body_url.clone(),
+        body_edit: false,
+        url: UrlFetchArgs::from_plan(&step.url_sha256),
+        expect_blocks: step.expect_blocks,
+        allow_marker_overlap: step.allow_marker_overlap.unwrap_or(false),
+        record: None,
+    })
+}
+
+fn build_rename_command(
+    base_common: &CommonArgs,
+    step: &batch::RenamePlan,
+) -> Result<RenameCommand> {
+    Ok(RenameCommand {
+        common: merge_common(base_common, &step.common),
+        from: step.from.clone(),
+        to: step.to.clone(),
+        word_boundary: step.word_boundary,
+        case_aware: step.case_aware,
+        record: None,
+    })
+}
+
+fn build_write_command(base_common: &CommonArgs, step: &batch::WritePlan) -> Result<WriteCommand> {
+    let sources = [
+        step.body.is_some(),
+        step.body_file.is_some(),
+        step.template.is_some(),
+        step.body_url.is_some(),
+    ];
+    if sources.iter().filter(|set| **set).count() != 1 {
+        bail!("write step requires exactly one of 'body', 'body_file', 'template', or 'body_url'");
+    }
+    if !step.vars.is_empty() && step.template.is_none() {
+        bail!("write step 'vars' only apply together with 'template'");
+    }
+    Ok(WriteCommand {
+        common: merge_common(base_common, &step.common),
+        path: step.path.clone(),
+        body: step
+            .body
+            .as_ref()
+            .map(|text| vec![text.clone()])
+            .unwrap_or_default(),
+        body_file: step.body_file.clone(),
+        with_stdin: false,
+        with_clipboard: false,
+        body_here: None,
+        template: step.template.clone(),
+        body_url: step.body_url.clone(),
+        body_edit: false,
+        url: UrlFetchArgs::from_plan(&step.url_sha256),
+        vars: step
+            .vars
+            .iter()
+            .map(|(key, value)| format!("{key}={value}"))
+            .collect(),
+        allow_overwrite: step.allow_overwrite,
+        mode: step.mode.unwrap_or_default(),
+        if_hash: step.if_hash.clone(),
+        if_unchanged_since: step.if_unchanged_since.clone(),
+        line_ending: step.line_ending.unwrap_or_default(),
+    })
+}
+
+fn build_apply_command(base_common: &CommonArgs, step: &batch::ApplyPlan) -> Result<ApplyCommand> {
+    if step.patches.is_empty() {
+        bail!("apply step requires at least one entry in 'patches'");
+    }
+    Ok(ApplyCommand {
+        common: merge_common(base_common, &step.common),
+        patch_files: step.patches.clone(),
+        root: step.root.clone(),
+        offset_window: step.offset_window.unwrap_or(patch::DEFAULT_OFFSET_WINDOW),
+    })
+}
+
+fn build_insert_command(
+    base_common: &CommonArgs,
+    step: &batch::InsertPlan,
+) -> Result<BlockCommand> {
+    if step.after.is_some() == step.before.is_some() {
+        bail!("insert step requires exactly one of 'after' or 'before'");
+    }
+    build_block_command(
+        base_common,
+        &batch::BlockPlan {
+            common: step.common.clone(),
+            body: step.body.clone(),
+            body_file: step.body_file.clone(),
+            insert_after: step.after.clone(),
+            insert_before: step.before.clone(),
+            ..batch::BlockPlan::default()
+        },
+    )
+}
+
+#[derive(Debug, Parser)]
+#[command(name = "safeedit", version, about = "Safe file editing companion")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+    #[arg(
+        long = "log-file",
+        value_name = "PATH",
+        global = true,
+        help = "Append to (and read) this change log instead of the workspace default."
+    )]
+    log_file: Option<PathBuf>,
+    #[arg(
+        long = "global-log",
+        global = true,
+        conflicts_with = "log_file",
+        help = "Use the per-user log in ~/.safeedit instead of the workspace log."
+    )]
+    global_log: bool,
+    #[arg(
+        long = "no-input",
+        global = true,
+        help = "Fail instead of waiting when a confirmation prompt would read stdin (for CI and agents)."
+    )]
+    no_input: bool,
+    #[arg(
+        long = "prompt-timeout",
+        value_name = "DURATION",
+        global = true,
+        help = "Stop waiting for an approval answer after DURATION (e.g. 30s, 5m; bare numbers are seconds)."
+    )]
+    prompt_timeout: Option<String>,
+    #[arg(
+        long = "timeout-default",
+        value_enum,
+        global = true,
+        default_value = "skip",
+        help = "Decision used when --prompt-timeout expires."
+    )]
+    timeout_default: input::TimeoutDefault,
+    #[arg(
+        long = "manifest",
+        value_name = "FILE",
+        global = true,
+        value_hint = ValueHint::FilePath,
+        help = "Record the SHA-256 of every file this run writes in a checksum manifest (created or updated); check it later with `safeedit checksum --verify FILE`."
+    )]
+    manifest: Option<PathBuf>,
+    #[arg(
+        long = "fresh",
+        global = true,
+        help = "Ignore files declined in earlier identical runs (.safeedit/decisions.json) and ask about them again."
+    )]
+    fresh: bool,
+    #[arg(
+        long = "explain",
+        global = true,
+        help = "After each diff, print a plain-English summary of the change: line counts, affected regions, and encoding/line-ending effects."
+    )]
+    explain: bool,
+    #[arg(
+        long = "timing",
+        global = true,
+        help = "Report time spent resolving, decoding, transforming, diffing, and writing each file (on stderr)."
+    )]
+    timing: bool,
+    #[arg(
+        long = "timing-format",
+        value_enum,
+        global = true,
+        default_value = "table",
+        help = "Layout of the --timing report."
+    )]
+    timing_format: timing::TimingFormat,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    Replace(ReplaceCommand),
+    Apply(ApplyCommand),
+    Block(BlockCommand),
+    Rename(RenameCommand),
+    Review(ReviewCommand),
+    Normalize(NormalizeCommand),
+    Script(ScriptCommand),
+    Batch(Box<BatchCommand>),
+    Log(LogCommand),
+    Verify(VerifyCommand),
+    Report(ReportCommand),
+    Cleanup(CleanupCommand),
+    Recover(RecoverCommand),
+    Restore(RestoreCommand),
+    Undo(UndoCommand),
+    Session(SessionCommand),
+    Patch(PatchCommand),
+    Write(WriteCommand),
+    Compare(CompareCommand),
+    Stats(StatsCommand),
+    Detect(DetectCommand),
+    Filter(FilterCommand),
+    Wizard(WizardCommand),
+    Serve(ServeCommand),
+    History(HistoryCommand),
+    Rerun(RerunCommand),
+    Schema(SchemaCommand),
+    Binpatch(BinpatchCommand),
+    Checksum(ChecksumCommand),
+}
+
+#[derive(Debug, Clone, Args)]
+struct CommonArgs {
+    #[arg(long = "glob", value_name = "GLOB")]
+    globs: Vec<String>,
+    #[arg(long = "target", value_name = "PATH", value_hint = ValueHint::AnyPath)]
+    targets: Vec<PathBuf>,
+    #[arg(long, value_name = "ENCODING")]
+    encoding: Option<String>,
+    #[arg(
+        long,
+        action = ArgAction::SetTrue,
+        help = "Write changes after showing the diff; omit to stay in review-only dry-run mode."
+    )]
+    apply: bool,
+    #[arg(
+        long = "yes",
+        action = ArgAction::SetTrue,
+        help = "Approve every diff without prompting (skips the review step); use only after inspecting output."
+    )]
+    auto_apply: bool,
+    #[arg(
+        long = "approve-policy",
+        value_name = "FILE",
+        value_hint = ValueHint::FilePath,
+        help = "TOML file mapping globs to apply/skip/prompt decisions, checked before each approval prompt."
+    )]
+    approve_policy: Option<PathBuf>,
+    #[command(flatten)]
+    writes: WriteArgs,
+    #[arg(long, default_value_t = 3)]
+    context: usize,
+    #[arg(long = "pager", value_enum, default_value = "auto")]
+    pager: PagerMode,
+    #[arg(
+        long = "page-lines",
+        value_name = "N",
+        help = "Lines per pager page (default: terminal height)."
+    )]
+    page_lines: Option<usize>,
+    #[arg(
+        long = "max-diff-lines",
+        value_name = "N",
+        help = "Truncate diff previews and review output after N lines (0 = unlimited; default 5000)."
+    )]
+    max_diff_lines: Option<usize>,
+    #[arg(
+        long = "max-diff-bytes",
+        value_name = "BYTES",
+        help = "Truncate diff previews and review output after BYTES bytes (0 = unlimited; default 5 MiB)."
+    )]
+    max_diff_bytes: Option<usize>,
+    #[arg(
+        long = "diff-format",
+        value_enum,
+        default_value = "inline",
+        help = "Preview style: inline +/- view, standard unified diff, or structured hunk JSON."
+    )]
+    diff_format: DiffFormat,
+    #[arg(
+        long = "line-numbers",
+        action = ArgAction::SetTrue,
+        help = "Show old/new line numbers in a gutter beside inline diff previews."
+    )]
+    line_numbers: bool,
+    #[arg(long = "color", value_enum, default_value = "auto")]
+    color: ColorChoice,
+    #[arg(long, action = ArgAction::SetTrue)]
+    json: bool,
+    #[arg(
+        long = "json-file",
+        value_name = "PATH",
+        help = "Write JSON change events (NDJSON) to PATH, separate from the human-readable output."
+    )]
+    json_file: Option<PathBuf>,
+    #[arg(long = "include-hidden", action = ArgAction::SetTrue)]
+    include_hidden: bool,
+    #[arg(long = "exclude", value_name = "GLOB")]
+    exclude: Vec<String>,
+    #[arg(long = "undo-log", value_name = "DIR", value_hint = ValueHint::DirPath)]
+    undo_log: Option<PathBuf>,
+    #[arg(
+        long = "remote",
+        value_name = "USER@HOST",
+        help = "Treat every target path as a file on USER@HOST[:PORT], fetched and written back over ssh."
+    )]
+    remote: Option<String>,
+    #[arg(
+        long = "save-applied-patch",
+        value_name = "FILE",
+        value_hint = ValueHint::FilePath,
+        help = "Collect every change applied in this run into one unified patch FILE."
+    )]
+    save_applied_patch: Option<PathBuf>,
+    #[arg(
+        long = "preview-dir",
+        value_name = "DIR",
+        value_hint = ValueHint::DirPath,
+        help = "In dry runs, also write each file's pending diff to DIR as <escaped-path>.patch."
+    )]
+    preview_dir: Option<PathBuf>,
+    #[arg(long = "strict-encoding", action = ArgAction::SetTrue)]
+    strict_encoding: bool,
+    #[arg(
+        long = "max-file-size",
+        value_name = "SIZE",
+        default_value = "50M",
+        value_parser = parse_size_arg,
+        help = "Skip files larger than SIZE (e.g. 500K, 50M, 2G; 0 = unlimited) when editing."
+    )]
+    max_file_size: u64,
+    #[arg(
+        long = "force-large",
+        action = ArgAction::SetTrue,
+        help = "Edit files even when they exceed --max-file-size."
+    )]
+    force_large: bool,
+    #[arg(
+        long = "keep-going",
+        action = ArgAction::SetTrue,
+        help = "Report files that fail to read or write and continue with the rest; exits non-zero at the end if any failed."
+    )]
+    keep_going: bool,
+    #[arg(
+        long = "min-detection-score",
+        value_name = "0..1",
+        help = "Prompt (or skip, when non-interactive) before editing files whose heuristic encoding detection score is below this."
+    )]
+    min_detection_score: Option<f32>,
+    #[arg(
+        value_name = "PATHS",
+        value_parser = value_parser!(String),
+        help = "Files, directories, or glob patterns to edit (same as --target/--glob)."
+    )]
+    extra_args: Vec<String>,
+}
+
+impl CommonArgs {
+    fn diff_display_config(&self, colorize: bool) -> diff::DiffDisplayConfig {
+        diff::DiffDisplayConfig {
+            context: self.context,
+            colorize,
+            pager_mode: self.pager,
+            interactive: self.allow_interactive_pager(),
+            format: self.diff_format,
+            line_numbers: self.line_numbers,
+            page_lines: self.page_lines,
+            limits: self.diff_limits(),
+        }
+    }
+
+    fn diff_limits(&self) -> diff::DiffLimits {
+        diff::DiffLimits::resolve(self.max_diff_lines, self.max_diff_bytes)
+    }
+
+    fn write_options(&self) -> WriteOptions {
+        WriteOptions {
+            undo_dir: self.undo_log.clone(),
+            applied_patch: self.save_applied_patch.clone(),
+            strict_encoding: self.strict_encoding,
+            ..self.writes.write_options()
+        }
+    }
+
+    fn emits_events(&self) -> bool {
+        self.json || self.json_file.is_some()
+    }
+
+    fn selection(&self) -> (Vec<PathBuf>, Vec<String>) {
+        let mut targets = self.targets.clone();
+        let mut globs = self.globs.clone();
+        for arg in &self.extra_args {
+            if !Path::new(arg).exists() && arg.contains(['*', '?', '[', '{']) {
+                globs.push(arg.clone());
+            } else {
+                targets.push(PathBuf::from(arg));
+            }
+        }
+        (targets, globs)
+    }
+
+    fn allow_interactive_pager(&self) -> bool {
+        io::stdin().is_terminal() && io::stdout().is_terminal() && !self.auto_apply && !self.json
+    }
+}
+
+#[derive(Debug, Args, Clone)]
+struct UrlFetchArgs {
+    #[arg(
+        long = "url-max-size",
+        value_name = "SIZE",
+        default_value = "10M",
+        value_parser = parse_size_arg,
+        help = "Largest download accepted from --with-url/--body-url (0 disables the limit)."
+    )]
+    url_max_size: u64,
+    #[arg(
+        long = "url-sha256",
+        value_name = "HEX",
+        help = "Refuse the download unless its SHA-256 digest matches HEX."
+    )]
+    url_sha256: Option<String>,
+}
+
+impl UrlFetchArgs {
+    fn from_plan(sha256: &Option<String>) -> Self {
+        UrlFetchArgs {
+            url_max_size: fetch::DEFAULT_MAX_BYTES,
+            url_sha256: sha256.clone(),
+        }
+    }
+
+    fn options(&self) -> fetch::FetchOptions {
+        fetch::FetchOptions {
+            max_bytes: self.url_max_size,
+            sha256: self.url_sha256.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Args)]
+struct ReplaceCommand {
+    #[command(flatten)]
+    common: CommonArgs,
+    #[arg(long, value_name = "PATTERN")]
+    pattern: String,
+    #[arg(
+        long = "with",
+        value_name = "TEXT",
+        conflicts_with_all = ["with_stdin", "with_clipboard", "with_url"],
+        required_unless_present_any = ["with_stdin", "with_clipboard", "with_here", "with_url"]
+    )]
+    replacement: Option<String>,
     #[arg(long = "with-stdin", action = ArgAction::SetTrue, conflicts_with = "with_clipboard")]
     with_stdin: bool,
-    #[arg(long = "with-clipboard", action = ArgAction::SetTrue, conflicts_with = "with_stdin")]
+    #[arg(long = "with-clipboard", action = ArgAction::SetTrue, conflicts_with = "with_stdin")]
+    with_clipboard: bool,
+    #[arg(long = "with-here", value_name = "TAG", conflicts_with_all = ["replacement", "with_stdin", "with_clipboard"])]
+    with_here: Option<String>,
+    #[arg(
+        long = "with-url",
+        value_name = "URL",
+        conflicts_with_all = ["replacement", "with_stdin", "with_clipboard", "with_here"],
+        help = "Download the replacement text from an https:// URL."
+    )]
+    with_url: Option<String>,
+    #[command(flatten)]
+    url: UrlFetchArgs,
+    #[arg(long, action = ArgAction::SetTrue)]
+    regex: bool,
+    #[arg(long, action = ArgAction::SetTrue)]
+    literal: bool,
+    #[arg(
+        long = "no-expand",
+        action = ArgAction::SetTrue,
+        help = "Insert the replacement verbatim in --regex mode instead of expanding $1/${name} capture references."
+    )]
+    no_expand: bool,
+    #[arg(
+        long = "case-aware",
+        action = ArgAction::SetTrue,
+        help = "Match any casing and re-case the replacement to follow each match (color->colour, Color->Colour, COLOR->COLOUR)."
+    )]
+    case_aware: bool,
+    #[arg(
+        long = "allow-binary-literal",
+        action = ArgAction::SetTrue,
+        conflicts_with_all = ["regex", "no_expand", "case_aware", "after_line"],
+        help = "Edit files flagged as binary with a byte-level literal substitution (single-line pattern and replacement) instead of skipping them."
+    )]
+    allow_binary_literal: bool,
+    #[arg(long = "diff-only", action = ArgAction::SetTrue)]
+    diff_only: bool,
+    #[arg(long, value_name = "N")]
+    count: Option<usize>,
+    #[arg(long, value_name = "N")]
+    expect: Option<usize>,
+    #[arg(
+        long = "total-count",
+        value_name = "N",
+        help = "Stop after N replacements across all files (--count still caps each file)."
+    )]
+    total_count: Option<usize>,
+    #[arg(
+        long = "expect-total",
+        value_name = "N",
+        help = "Fail before writing anything unless the run makes exactly N replacements in total."
+    )]
+    expect_total: Option<usize>,
+    #[arg(long = "after-line", value_name = "LINE")]
+    after_line: Option<usize>,
+    #[arg(
+        long = "skip-if-contains",
+        value_name = "TEXT",
+        help = "Leave files that already contain TEXT untouched and report them as already done."
+    )]
+    skip_if_contains: Option<String>,
+    #[arg(long = "if-hash", value_name = "SHA256")]
+    if_hash: Option<String>,
+    #[arg(long = "if-unchanged-since", value_name = "RFC3339")]
+    if_unchanged_since: Option<String>,
+    #[arg(
+        long = "record",
+        value_name = "PLAN",
+        value_hint = ValueHint::FilePath,
+        help = "Append an equivalent batch plan step to PLAN (YAML or JSON) after the run."
+    )]
+    record: Option<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+struct ApplyCommand {
+    #[command(flatten)]
+    common: CommonArgs,
+    #[arg(
+        long = "patch",
+        value_name = "FILE",
+        value_hint = ValueHint::FilePath,
+        required = true,
+        action = ArgAction::Append
+    )]
+    patch_files: Vec<PathBuf>,
+    #[arg(long = "root", value_name = "DIR", value_hint = ValueHint::DirPath)]
+    root: Option<PathBuf>,
+    #[arg(
+        long = "offset-window",
+        value_name = "N",
+        default_value_t = patch::DEFAULT_OFFSET_WINDOW,
+        help = "Search up to N lines either side of each hunk's header line when the file has drifted (0 = exact position only)."
+    )]
+    offset_window: usize,
+}
+
+#[derive(Debug, Args)]
+struct BlockCommand {
+    #[command(flatten)]
+    common: CommonArgs,
+    #[arg(
+        long = "start-marker",
+        value_name = "TEXT",
+        conflicts_with_all = ["insert_after", "insert_before"],
+        required_unless_present_any = ["insert_after", "insert_before"]
+    )]
+    start_marker: Option<String>,
+    #[arg(
+        long = "end-marker",
+        value_name = "TEXT",
+        requires = "start_marker",
+        conflicts_with_all = ["insert_after", "insert_before"],
+        required_unless_present_any = ["insert_after", "insert_before"]
+    )]
+    end_marker: Option<String>,
+    #[arg(
+        long = "insert-after",
+        value_name = "TEXT",
+        conflicts_with_all = ["start_marker", "end_marker", "insert_before"]
+    )]
+    insert_after: Option<String>,
+    #[arg(
+        long = "insert-before",
+        value_name = "TEXT",
+        conflicts_with_all = ["start_marker", "end_marker", "insert_after"]
+    )]
+    insert_before: Option<String>,
+    #[arg(long, value_name = "MODE")]
+    mode: Option<BlockMode>,
+    #[arg(
+        long = "body",
+        value_name = "TEXT",
+        action = ArgAction::Append,
+        conflicts_with_all = ["body_file", "with_stdin", "with_clipboard", "body_here", "body_url", "body_edit"],
+        required_unless_present_any = ["body_file", "with_stdin", "with_clipboard", "body_here", "body_url", "body_edit"]
+    )]
+    body: Vec<String>,
+    #[arg(
+        long = "body-file",
+        value_name = "FILE",
+        value_hint = ValueHint::FilePath,
+        conflicts_with_all = ["body", "with_stdin", "with_clipboard", "body_here"]
+    )]
+    body_file: Option<PathBuf>,
+    #[arg(long = "with-stdin", action = ArgAction::SetTrue, conflicts_with_all = ["body", "body_file", "with_clipboard", "body_here"])]
+    with_stdin: bool,
+    #[arg(long = "with-clipboard", action = ArgAction::SetTrue, conflicts_with_all = ["body", "body_file", "with_stdin", "body_here"])]
     with_clipboard: bool,
-    #[arg(long = "with-here", value_name = "TAG", conflicts_with_all = ["replacement", "with_stdin", "with_clipboard"])]
-    with_here: Option<String>,
+    #[arg(long = "body-here", value_name = "TAG", conflicts_with_all = ["body", "body_file", "with_stdin", "with_clipboard"])]
+    body_here: Option<String>,
+    #[arg(
+        long = "body-url",
+        value_name = "URL",
+        conflicts_with_all = ["body", "body_file", "with_stdin", "with_clipboard", "body_here"],
+        help = "Download the block body from an https:// URL."
+    )]
+    body_url: Option<String>,
+    #[arg(
+        long = "body-edit",
+        action = ArgAction::SetTrue,
+        conflicts_with_all = ["body", "body_file", "with_stdin", "with_clipboard", "body_here", "body_url"],
+        help = "Type the block body in $EDITOR; saving an empty file aborts."
+    )]
+    body_edit: bool,
+    #[command(flatten)]
+    url: UrlFetchArgs,
+    #[arg(long = "expect-blocks", value_name = "N")]
+    expect_blocks: Option<usize>,
+    #[arg(long = "allow-marker-overlap", action = ArgAction::SetTrue)]
+    allow_marker_overlap: bool,
+    #[arg(
+        long = "record",
+        value_name = "PLAN",
+        value_hint = ValueHint::FilePath,
+        help = "Append an equivalent batch plan step to PLAN (YAML or JSON) after the run."
+    )]
+    record: Option<PathBuf>,
+}
+
+impl BlockCommand {
+    fn build_target(&self) -> Result<BlockTarget> {
+        match (
+            &self.start_marker,
+            &self.end_marker,
+            &self.insert_after,
+            &self.insert_before,
+        ) {
+            (Some(start), Some(end), None, None) => Ok(BlockTarget::Range {
+                start: start.clone(),
+                end: end.clone(),
+            }),
+            (None, None, Some(marker), None) => Ok(BlockTarget::InsertAfter {
+                marker: marker.clone(),
+            }),
+            (None, None, None, Some(marker)) => Ok(BlockTarget::InsertBefore {
+                marker: marker.clone(),
+            }),
+            _ => bail!(
+                "provide --start-marker/--end-marker or exactly one of --insert-after/--insert-before"
+            ),
+        }
+    }
+
+    fn resolve_mode(&self, target: &BlockTarget) -> Result<BlockMode> {
+        let requested = self.mode.unwrap_or(BlockMode::Replace);
+        if matches!(
+            target,
+            BlockTarget::InsertAfter { .. } | BlockTarget::InsertBefore { .. }
+        ) {
+            if matches!(self.mode, Some(BlockMode::Replace)) {
+                bail!("--insert-after/--insert-before only support insert mode");
+            }
+            return Ok(BlockMode::Insert);
+        }
+        Ok(requested)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockMode {
+    Insert,
+    Replace,
+}
+
+impl std::str::FromStr for BlockMode {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "insert" | "after" | "before" => Ok(Self::Insert),
+            "replace" => Ok(Self::Replace),
+            _ => Err("expected 'insert', 'replace', 'after', or 'before'"),
+        }
+    }
+}
+
+#[derive(Debug, Args)]
+struct WriteCommand {
+    #[command(flatten)]
+    common: CommonArgs,
+    #[arg(long = "path", value_name = "FILE", value_hint = ValueHint::FilePath)]
+    path: PathBuf,
+    #[arg(
+        long = "body",
+        value_name = "TEXT",
+        action = ArgAction::Append,
+        conflicts_with_all = ["body_file", "with_stdin", "with_clipboard", "body_here", "template", "body_url", "body_edit"],
+        required_unless_present_any = ["body_file", "with_stdin", "with_clipboard", "body_here", "template", "body_url", "body_edit"]
+    )]
+    body: Vec<String>,
+    #[arg(
+        long = "body-file",
+        value_name = "FILE",
+        value_hint = ValueHint::FilePath,
+        conflicts_with_all = ["body", "with_stdin", "with_clipboard", "body_here", "template"]
+    )]
+    body_file: Option<PathBuf>,
+    #[arg(
+        long = "with-stdin",
+        action = ArgAction::SetTrue,
+        conflicts_with_all = ["body", "body_file", "with_clipboard", "body_here", "template"]
+    )]
+    with_stdin: bool,
+    #[arg(
+        long = "with-clipboard",
+        action = ArgAction::SetTrue,
+        conflicts_with_all = ["body", "body_file", "with_stdin", "body_here", "template"]
+    )]
+    with_clipboard: bool,
+    #[arg(
+        long = "body-here",
+        value_name = "TAG",
+        conflicts_with_all = ["body", "body_file", "with_stdin", "with_clipboard", "template"]
+    )]
+    body_here: Option<String>,
+    #[arg(
+        long = "template",
+        value_name = "FILE",
+        value_hint = ValueHint::FilePath,
+        conflicts_with_all = ["body", "body_file", "with_stdin", "with_clipboard", "body_here"]
+    )]
+    template: Option<PathBuf>,
+    #[arg(
+        long = "body-url",
+        value_name = "URL",
+        conflicts_with_all = ["body", "body_file", "with_stdin", "with_clipboard", "body_here", "template"],
+        help = "Download the file contents from an https:// URL."
+    )]
+    body_url: Option<String>,
+    #[arg(
+        long = "body-edit",
+        action = ArgAction::SetTrue,
+        conflicts_with_all = ["body", "body_file", "with_stdin", "with_clipboard", "body_here", "template", "body_url"],
+        help = "Type the file contents in $EDITOR; saving an empty file aborts."
+    )]
+    body_edit: bool,
+    #[command(flatten)]
+    url: UrlFetchArgs,
+    #[arg(long = "var", value_name = "KEY=VALUE", requires = "template")]
+    vars: Vec<String>,
+    #[arg(long = "allow-overwrite", action = ArgAction::SetTrue)]
+    allow_overwrite: bool,
+    #[arg(long = "mode", value_enum, default_value = "overwrite")]
+    mode: WriteMode,
+    #[arg(long = "if-hash", value_name = "SHA256")]
+    if_hash: Option<String>,
+    #[arg(long = "if-unchanged-since", value_name = "RFC3339")]
+    if_unchanged_since: Option<String>,
+    #[arg(long = "line-ending", value_enum, default_value = "auto")]
+    line_ending: LineEndingChoice,
+}
+
+#[derive(Debug, Args)]
+struct StatsCommand {
+    #[command(flatten)]
+    common: CommonArgs,
+    #[arg(long = "format", default_value = "table")]
+    format: String,
+}
+
+#[derive(Debug, Args)]
+struct DetectCommand {
+    #[command(flatten)]
+    common: CommonArgs,
+    #[arg(long = "format", default_value = "table")]
+    format: String,
+}
+
+#[derive(Debug, Args)]
+#[command(about = "Transform stdin and write the result to stdout without touching files.")]
+struct FilterCommand {
+    #[command(subcommand)]
+    action: FilterAction,
+}
+
+#[derive(Debug, Subcommand)]
+enum FilterAction {
+    Replace(FilterReplaceArgs),
+    Normalize(FilterNormalizeArgs),
+}
+
+#[derive(Debug, Args)]
+struct FilterReplaceArgs {
+    #[arg(long, value_name = "PATTERN")]
+    pattern: String,
+    #[arg(long = "with", value_name = "TEXT")]
+    replacement: String,
     #[arg(long, action = ArgAction::SetTrue)]
     regex: bool,
     #[arg(long, action = ArgAction::SetTrue)]
-    literal: bool,
-    #[arg(long = "diff-only", action = ArgAction::SetTrue)]
-    diff_only: bool,
-    #[arg(long, value_name = "N")]
-    count: Option<usize>,
-    #[arg(long, value_name = "N")]
-    expect: Option<usize>,
-    #[arg(long = "after-line", value_name = "LINE")]
-    after_line: Option<usize>,
+    literal: bool,
+    #[arg(
+        long = "no-expand",
+        action = ArgAction::SetTrue,
+        help = "Insert the replacement verbatim in --regex mode instead of expanding $1/${name} capture references."
+    )]
+    no_expand: bool,
+    #[arg(long, value_name = "N")]
+    count: Option<usize>,
+    #[arg(long, value_name = "N")]
+    expect: Option<usize>,
+    #[arg(long = "after-line", value_name = "LINE")]
+    after_line: Option<usize>,
+    #[arg(long, value_name = "ENCODING")]
+    encoding: Option<String>,
+}
+
+#[derive(Debug, Args)]
+struct FilterNormalizeArgs {
+    #[command(flatten)]
+    edits: NormalizeEditArgs,
+    #[arg(long, value_name = "ENCODING")]
+    encoding: Option<String>,
+}
+
+#[derive(Debug, Args)]
+struct WizardCommand {
+    #[arg(
+        long = "plan",
+        value_name = "PLAN",
+        value_hint = ValueHint::FilePath,
+        help = "Plan file offered by default when saving the command as a batch step."
+    )]
+    plan: Option<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+struct ServeCommand {
+    #[arg(
+        long = "http",
+        value_name = "ADDR",
+        default_value = "127.0.0.1:8787",
+        help = "Address to listen on for the JSON preview/apply API."
+    )]
+    http: String,
+    #[arg(
+        long = "allow-remote",
+        action = ArgAction::SetTrue,
+        help = "Allow listening on a non-loopback address."
+    )]
+    allow_remote: bool,
+}
+
+#[derive(Debug, Args)]
+struct CompareCommand {
+    #[arg(value_name = "LEFT", value_hint = ValueHint::FilePath)]
+    left: PathBuf,
+    #[arg(value_name = "RIGHT", value_hint = ValueHint::FilePath)]
+    right: PathBuf,
+    #[arg(long, value_name = "ENCODING")]
+    encoding: Option<String>,
+    #[arg(long, default_value_t = 3)]
+    context: usize,
+    #[arg(long = "pager", value_enum, default_value = "auto")]
+    pager: PagerMode,
+    #[arg(
+        long = "page-lines",
+        value_name = "N",
+        help = "Lines per pager page (default: terminal height)."
+    )]
+    page_lines: Option<usize>,
+    #[arg(
+        long = "max-diff-lines",
+        value_name = "N",
+        help = "Truncate diff previews and review output after N lines (0 = unlimited; default 5000)."
+    )]
+    max_diff_lines: Option<usize>,
+    #[arg(
+        long = "max-diff-bytes",
+        value_name = "BYTES",
+        help = "Truncate diff previews and review output after BYTES bytes (0 = unlimited; default 5 MiB)."
+    )]
+    max_diff_bytes: Option<usize>,
+    #[arg(
+        long = "diff-format",
+        value_enum,
+        default_value = "inline",
+        help = "Preview style: inline +/- view, standard unified diff, or structured hunk JSON."
+    )]
+    diff_format: DiffFormat,
+    #[arg(
+        long = "line-numbers",
+        action = ArgAction::SetTrue,
+        help = "Show old/new line numbers in a gutter beside inline diff previews."
+    )]
+    line_numbers: bool,
+    #[arg(long = "color", value_enum, default_value = "auto")]
+    color: ColorChoice,
+    #[arg(long, action = ArgAction::SetTrue)]
+    json: bool,
 }
 
 #[derive(Debug, Args)]
-struct ApplyCommand {
+struct RenameCommand {
     #[command(flatten)]
     common: CommonArgs,
+    #[arg(long, value_name = "OLD")]
+    from: String,
+    #[arg(long, value_name = "NEW")]
+    to: String,
+    #[arg(long = "word-boundary", action = ArgAction::SetTrue)]
+    word_boundary: bool,
+    #[arg(long = "case-aware", action = ArgAction::SetTrue)]
+    case_aware: bool,
     #[arg(
-        long = "patch",
-        value_name = "FILE",
+        long = "record",
+        value_name = "PLAN",
         value_hint = ValueHint::FilePath,
-        required = true,
-        action = ArgAction::Append
+        help = "Append an equivalent batch plan step to PLAN (YAML or JSON) after the run."
     )]
-    patch_files: Vec<PathBuf>,
-    #[arg(long = "root", value_name = "DIR", value_hint = ValueHint::DirPath)]
-    root: Option<PathBuf>,
+    record: Option<PathBuf>,
 }
 
 #[derive(Debug, Args)]
-struct BlockCommand {
+struct ReviewCommand {
     #[command(flatten)]
     common: CommonArgs,
+    #[arg(long, value_name = "N")]
+    head: Option<usize>,
+    #[arg(long, value_name = "N")]
+    tail: Option<usize>,
+    #[arg(long, value_name = "START:END")]
+    lines: Option<String>,
+    #[arg(long, value_name = "LINE:CONTEXT")]
+    around: Option<String>,
+    #[arg(long, action = ArgAction::SetTrue)]
+    follow: bool,
+    #[arg(long, value_name = "PATTERN")]
+    search: Option<String>,
+    #[arg(long, action = ArgAction::SetTrue)]
+    regex: bool,
+    #[arg(long, action = ArgAction::SetTrue)]
+    step: bool,
+    #[arg(long = "step-window", value_name = "N", requires = "step")]
+    step_window: Option<usize>,
+    #[arg(long, value_name = "REGEX", requires = "follow")]
+    filter: Option<String>,
+    #[arg(long, action = ArgAction::SetTrue, conflicts_with_all = ["follow", "step"])]
+    copy: bool,
+    #[arg(long, action = ArgAction::SetTrue, conflicts_with = "follow")]
+    report: bool,
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["follow", "step"])]
+    out: Option<PathBuf>,
+}
+
+// Backup, verification and metadata settings shared by every command that
+// writes files, including `binpatch`.
+#[derive(Debug, Clone, Args)]
+struct WriteArgs {
+    #[arg(long, action = ArgAction::SetTrue)]
+    no_backup: bool,
+    #[arg(long = "backup-dir", value_name = "DIR", value_hint = ValueHint::DirPath, conflicts_with = "no_backup")]
+    backup_dir: Option<PathBuf>,
+    #[arg(long = "backup-naming", value_enum, default_value = "index")]
+    backup_naming: backup::BackupNaming,
+    #[arg(long = "backup-keep", value_name = "N")]
+    backup_keep: Option<usize>,
+    #[arg(long = "backup-max-age", value_name = "DAYS")]
+    backup_max_age: Option<u64>,
+    #[arg(long = "backup-compress", action = ArgAction::SetTrue)]
+    backup_compress: bool,
+    #[arg(long = "backup-compress-threshold", value_name = "BYTES", default_value_t = 1024 * 1024)]
+    backup_compress_threshold: u64,
+    #[arg(long = "verify-write", action = ArgAction::SetTrue)]
+    verify_write: bool,
+    #[arg(long = "no-preserve-metadata", action = ArgAction::SetTrue)]
+    no_preserve_metadata: bool,
+    #[arg(long = "force-readonly", action = ArgAction::SetTrue)]
+    force_readonly: bool,
+    #[arg(long = "preserve-mtime", action = ArgAction::SetTrue, conflicts_with = "set_mtime")]
+    preserve_mtime: bool,
+    #[arg(long = "set-mtime", value_name = "RFC3339", value_parser = parse_rfc3339)]
+    set_mtime: Option<OffsetDateTime>,
+}
+
+impl WriteArgs {
+    fn write_options(&self) -> WriteOptions {
+        WriteOptions {
+            backup: backup::BackupOptions {
+                disabled: self.no_backup,
+                dir: self.backup_dir.clone(),
+                naming: self.backup_naming,
+                keep: self.backup_keep,
+                max_age_days: self.backup_max_age,
+                compress_over: self
+                    .backup_compress
+                    .then_some(self.backup_compress_threshold),
+            },
+            verify_write: self.verify_write,
+            preserve_metadata: !self.no_preserve_metadata,
+            force_readonly: self.force_readonly,
+            mtime: if self.preserve_mtime {
+                MtimePolicy::Preserve
+            } else if let Some(stamp) = self.set_mtime {
+                MtimePolicy::Set(stamp.into())
+            } else {
+                MtimePolicy::Touch
+            },
+            ..WriteOptions::default()
+        }
+    }
+}
+
+// Text edits shared by `normalize` and `filter normalize`.
+#[derive(Debug, Args)]
+struct NormalizeEditArgs {
+    #[arg(long = "strip-zero-width", action = ArgAction::SetTrue)]
+    strip_zero_width: bool,
+    #[arg(long = "strip-control", action = ArgAction::SetTrue)]
+    strip_control: bool,
+    #[arg(long = "strip-bidi", action = ArgAction::SetTrue)]
+    strip_bidi: bool,
+    #[arg(long = "map-confusables", action = ArgAction::SetTrue)]
+    map_confusables: bool,
+    #[arg(long = "ascii-punctuation", action = ArgAction::SetTrue)]
+    ascii_punctuation: bool,
+    #[arg(long = "map-char", value_name = "CHAR=REPLACEMENT")]
+    map_char: Vec<String>,
+    #[arg(long = "strip-class", value_name = "NAME")]
+    strip_class: Vec<String>,
+    #[arg(long = "trim-trailing-space", action = ArgAction::SetTrue)]
+    trim_trailing_space: bool,
+    #[arg(long = "ensure-eol", action = ArgAction::SetTrue)]
+    ensure_eol: bool,
     #[arg(
-        long = "start-marker",
-        value_name = "TEXT",
-        conflicts_with_all = ["insert_after", "insert_before"],
-        required_unless_present_any = ["insert_after", "insert_before"]
+        long = "tabs-to-spaces",
+        value_name = "N",
+        conflicts_with = "spaces_to_tabs"
     )]
-    start_marker: Option<String>,
+    tabs_to_spaces: Option<usize>,
+    #[arg(long = "spaces-to-tabs", value_name = "N")]
+    spaces_to_tabs: Option<usize>,
+    #[arg(long = "max-blank-lines", value_name = "N")]
+    max_blank_lines: Option<usize>,
+    #[arg(long = "max-line-length", value_name = "N")]
+    max_line_length: Option<usize>,
+    #[arg(long = "wrap-long-lines", action = ArgAction::SetTrue)]
+    wrap_long_lines: bool,
+    #[arg(long = "convert-eol", value_enum, value_name = "STYLE")]
+    convert_eol: Option<LineEndingStyle>,
+    #[arg(long = "strip-bom", action = ArgAction::SetTrue, conflicts_with = "add_bom")]
+    strip_bom: bool,
+    #[arg(long = "add-bom", action = ArgAction::SetTrue)]
+    add_bom: bool,
+    #[arg(long = "unicode-form", value_enum, value_name = "FORM")]
+    unicode_form: Option<normalize::UnicodeForm>,
+}
+
+#[derive(Debug, Args)]
+struct NormalizeCommand {
+    #[command(flatten)]
+    common: CommonArgs,
+    #[command(flatten)]
+    edits: NormalizeEditArgs,
+    #[arg(long = "convert-encoding", value_name = "ENCODING")]
+    convert_encoding: Option<String>,
+    #[arg(long = "fail-on-bidi", action = ArgAction::SetTrue)]
+    fail_on_bidi: bool,
     #[arg(
-        long = "end-marker",
-        value_name = "TEXT",
-        requires = "start_marker",
-        conflicts_with_all = ["insert_after", "insert_before"],
-        required_unless_present_any = ["insert_after", "insert_before"]
+        long = "fail-on-long-lines",
+        action = ArgAction::SetTrue,
+        conflicts_with = "wrap_long_lines"
     )]
-    end_marker: Option<String>,
+    fail_on_long_lines: bool,
+    #[arg(long = "report-format", default_value = "table")]
+    report_format: String,
+    #[arg(long = "locations", action = ArgAction::SetTrue)]
+    locations: bool,
+    #[arg(long = "scan-encoding", action = ArgAction::SetTrue)]
+    scan_encoding: bool,
+    #[arg(long = "scan-zero-width", action = ArgAction::SetTrue)]
+    scan_zero_width: bool,
+    #[arg(long = "scan-control", action = ArgAction::SetTrue)]
+    scan_control: bool,
+    #[arg(long = "scan-bidi", action = ArgAction::SetTrue)]
+    scan_bidi: bool,
+    #[arg(long = "scan-confusables", action = ArgAction::SetTrue)]
+    scan_confusables: bool,
+    #[arg(long = "scan-punctuation", action = ArgAction::SetTrue)]
+    scan_punctuation: bool,
+    #[arg(long = "scan-trailing-space", action = ArgAction::SetTrue)]
+    scan_trailing_space: bool,
+    #[arg(long = "scan-indentation", action = ArgAction::SetTrue)]
+    scan_indentation: bool,
+    #[arg(long = "scan-eol", action = ArgAction::SetTrue)]
+    scan_eol: bool,
+    #[arg(long = "scan-final-newline", action = ArgAction::SetTrue)]
+    scan_final_newline: bool,
     #[arg(
-        long = "insert-after",
-        value_name = "TEXT",
-        conflicts_with_all = ["start_marker", "end_marker", "insert_before"]
+        long = "record",
+        value_name = "PLAN",
+        value_hint = ValueHint::FilePath,
+        help = "Append an equivalent batch plan step to PLAN (YAML or JSON) after the run."
     )]
-    insert_after: Option<String>,
+    record: Option<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+struct ScriptCommand {
+    #[command(flatten)]
+    common: CommonArgs,
+    #[arg(long = "script", value_name = "SCRIPT", value_hint = ValueHint::FilePath)]
+    script: Option<PathBuf>,
+    #[arg(long = "arg", value_name = "VALUE")]
+    args: Vec<String>,
+}
+
+#[derive(Debug, Args)]
+struct BatchCommand {
+    #[command(subcommand)]
+    subcommand: Option<BatchAction>,
+    #[command(flatten)]
+    common: CommonArgs,
+    #[arg(long = "plan", value_name = "PLAN", value_hint = ValueHint::FilePath)]
+    plan: Option<PathBuf>,
+    #[arg(long = "set", value_name = "KEY=VALUE")]
+    set: Vec<String>,
     #[arg(
-        long = "insert-before",
-        value_name = "TEXT",
-        conflicts_with_all = ["start_marker", "end_marker", "insert_after"]
+        long = "results",
+        value_name = "PATH",
+        help = "Write per-step, per-file outcomes as JSON to PATH when the run ends."
     )]
-    insert_before: Option<String>,
-    #[arg(long, value_name = "MODE")]
-    mode: Option<BlockMode>,
+    results: Option<PathBuf>,
     #[arg(
-        long = "body",
-        value_name = "TEXT",
-        action = ArgAction::Append,
-        conflicts_with_all = ["body_file", "with_stdin", "with_clipboard", "body_here"],
-        required_unless_present_any = ["body_file", "with_stdin", "with_clipboard", "body_here"]
+        long = "resume",
+        help = "Skip steps an interrupted --apply run of this plan already finished."
     )]
-    body: Vec<String>,
+    resume: bool,
     #[arg(
-        long = "body-file",
-        value_name = "FILE",
-        value_hint = ValueHint::FilePath,
-        conflicts_with_all = ["body", "with_stdin", "with_clipboard", "body_here"]
+        long = "review-plan",
+        help = "Preview every step first, approve once (or pick steps), then apply unattended."
     )]
-    body_file: Option<PathBuf>,
-    #[arg(long = "with-stdin", action = ArgAction::SetTrue, conflicts_with_all = ["body", "body_file", "with_clipboard", "body_here"])]
-    with_stdin: bool,
-    #[arg(long = "with-clipboard", action = ArgAction::SetTrue, conflicts_with_all = ["body", "body_file", "with_stdin", "body_here"])]
-    with_clipboard: bool,
-    #[arg(long = "body-here", value_name = "TAG", conflicts_with_all = ["body", "body_file", "with_stdin", "with_clipboard"])]
-    body_here: Option<String>,
-    #[arg(long = "expect-blocks", value_name = "N")]
-    expect_blocks: Option<usize>,
-    #[arg(long = "allow-marker-overlap", action = ArgAction::SetTrue)]
-    allow_marker_overlap: bool,
+    review_plan: bool,
 }
 
-impl BlockCommand {
-    fn build_target(&self) -> Result<BlockTarget> {
-        match (
-            &self.start_marker,
-            &self.end_marker,
-            &self.insert_after,
-            &self.insert_before,
-        ) {
-            (Some(start), Some(end), None, None) => Ok(BlockTarget::Range {
-                start: start.clone(),
-                end: end.clone(),
-            }),
-            (None, None, Some(marker), None) => Ok(BlockTarget::InsertAfter {
-                marker: marker.clone(),
-            }),
-            (None, None, None, Some(marker)) => Ok(BlockTarget::InsertBefore {
-                marker: marker.clone(),
-            }),
-            _ => bail!(
-                "provide --start-marker/--end-marker or exactly one of --insert-after/--insert-before"
-            ),
-        }
-    }
+#[derive(Debug, Subcommand)]
+enum BatchAction {
+    #[command(about = "Check a plan and list what each step would touch without editing.")]
+    Validate(BatchValidateCommand),
+}
 
-    fn resolve_mode(&self, target: &BlockTarget) -> Result<BlockMode> {
-        let requested = self.mode.unwrap_or(BlockMode::Replace);
-        if matches!(
-            target,
-            BlockTarget::InsertAfter { .. } | BlockTarget::InsertBefore { .. }
-        ) {
-            if matches!(self.mode, Some(BlockMode::Replace)) {
-                bail!("--insert-after/--insert-before only support insert mode");
-            }
-            return Ok(BlockMode::Insert);
-        }
-        Ok(requested)
-    }
+#[derive(Debug, Args)]
+struct BatchValidateCommand {
+    #[command(flatten)]
+    common: CommonArgs,
+    #[arg(long = "plan", value_name = "PLAN", value_hint = ValueHint::FilePath)]
+    plan: Option<PathBuf>,
+    #[arg(long = "set", value_name = "KEY=VALUE")]
+    set: Vec<String>,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum BlockMode {
-    Insert,
-    Replace,
+#[derive(Debug, Args)]
+struct LogCommand {
+    #[command(subcommand)]
+    subcommand: Option<LogAction>,
+    #[arg(long = "tail", default_value_t = 20)]
+    tail: usize,
+    #[arg(long = "path", value_name = "PATH")]
+    path: Option<PathBuf>,
+    #[arg(long = "command", value_name = "NAME")]
+    command: Option<String>,
+    #[arg(long = "action", value_name = "ACTION")]
+    action: Option<String>,
+    #[arg(long = "since", value_name = "WHEN")]
+    since: Option<String>,
+    #[arg(long = "until", value_name = "WHEN")]
+    until: Option<String>,
+    #[arg(long = "format", default_value = "table")]
+    format: String,
 }
 
-impl std::str::FromStr for BlockMode {
-    type Err = &'static str;
+#[derive(Debug, Args)]
+struct HistoryCommand {
+    #[arg(
+        long = "tail",
+        default_value_t = 20,
+        help = "Show at most the N most recent invocations."
+    )]
+    tail: usize,
+    #[arg(long = "format", default_value = "table")]
+    format: String,
+}
 
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match s.to_lowercase().as_str() {
-            "insert" | "after" | "before" => Ok(Self::Insert),
-            "replace" => Ok(Self::Replace),
-            _ => Err("expected 'insert', 'replace', 'after', or 'before'"),
-        }
-    }
+#[derive(Debug, Args)]
+struct RerunCommand {
+    #[arg(
+        value_name = "ID",
+        help = "Invocation id (or unique prefix) from `safeedit history`."
+    )]
+    id: String,
+    #[arg(
+        long,
+        action = ArgAction::SetTrue,
+        conflicts_with = "dry_run",
+        help = "Add --apply when repeating a dry run."
+    )]
+    apply: bool,
+    #[arg(
+        long,
+        action = ArgAction::SetTrue,
+        help = "Add --yes so the repeated run applies without prompting."
+    )]
+    yes: bool,
+    #[arg(
+        long = "dry-run",
+        action = ArgAction::SetTrue,
+        help = "Drop --apply/--yes so the repeated run only previews."
+    )]
+    dry_run: bool,
 }
 
 #[derive(Debug, Args)]
-struct WriteCommand {
-    #[command(flatten)]
-    common: CommonArgs,
-    #[arg(long = "path", value_name = "FILE", value_hint = ValueHint::FilePath)]
-    path: PathBuf,
+struct SchemaCommand {
     #[arg(
-        long = "body",
-        value_name = "TEXT",
-        action = ArgAction::Append,
-        conflicts_with_all = ["body_file", "with_stdin", "with_clipboard", "body_here"],
-        required_unless_present_any = ["body_file", "with_stdin", "with_clipboard", "body_here"]
+        value_enum,
+        value_name = "KIND",
+        help = "Print the JSON Schema for batch plan files or NDJSON events."
     )]
-    body: Vec<String>,
+    kind: schema::SchemaKind,
+}
+
+#[derive(Debug, Args)]
+struct ChecksumCommand {
+    #[command(flatten)]
+    common: CommonArgs,
     #[arg(
-        long = "body-file",
-        value_name = "FILE",
+        long = "write",
+        value_name = "MANIFEST",
         value_hint = ValueHint::FilePath,
-        conflicts_with_all = ["body", "with_stdin", "with_clipboard", "body_here"]
+        conflicts_with = "verify",
+        help = "Record the SHA-256 of every resolved target in MANIFEST."
     )]
-    body_file: Option<PathBuf>,
+    write: Option<PathBuf>,
     #[arg(
-        long = "with-stdin",
-        action = ArgAction::SetTrue,
-        conflicts_with_all = ["body", "body_file", "with_clipboard", "body_here"]
+        long = "verify",
+        value_name = "MANIFEST",
+        value_hint = ValueHint::FilePath,
+        help = "Check the files listed in MANIFEST; selected targets it does not list are reported as untracked."
     )]
-    with_stdin: bool,
+    verify: Option<PathBuf>,
     #[arg(
-        long = "with-clipboard",
+        long = "update",
         action = ArgAction::SetTrue,
-        conflicts_with_all = ["body", "body_file", "with_stdin", "body_here"]
-    )]
-    with_clipboard: bool,
-    #[arg(
-        long = "body-here",
-        value_name = "TAG",
-        conflicts_with_all = ["body", "body_file", "with_stdin", "with_clipboard"]
+        requires = "write",
+        help = "Keep existing manifest entries and refresh only the resolved targets."
     )]
-    body_here: Option<String>,
-    #[arg(long = "allow-overwrite", action = ArgAction::SetTrue)]
-    allow_overwrite: bool,
-    #[arg(long = "line-ending", value_enum, default_value = "auto")]
-    line_ending: LineEndingChoice,
+    update: bool,
+    #[arg(long = "all", action = ArgAction::SetTrue)]
+    show_intact: bool,
+    #[arg(long = "format", default_value = "table")]
+    format: String,
 }
 
 #[derive(Debug, Args)]
-struct RenameCommand {
-    #[command(flatten)]
-    common: CommonArgs,
-    #[arg(long, value_name = "OLD")]
-    from: String,
-    #[arg(long, value_name = "NEW")]
-    to: String,
-    #[arg(long = "word-boundary", action = ArgAction::SetTrue)]
-    word_boundary: bool,
-    #[arg(long = "case-aware", action = ArgAction::SetTrue)]
-    case_aware: bool,
+struct VerifyCommand {
+    #[arg(long = "path", value_name = "PATH")]
+    path: Option<PathBuf>,
+    #[arg(long = "session", value_name = "ID")]
+    session: Option<String>,
+    #[arg(long = "all", action = ArgAction::SetTrue)]
+    show_intact: bool,
+    #[arg(long = "format", default_value = "table")]
+    format: String,
+}
+
+#[derive(Debug, Subcommand)]
+enum LogAction {
+    Prune(LogPruneCommand),
 }
 
 #[derive(Debug, Args)]
-struct ReviewCommand {
-    #[command(flatten)]
-    common: CommonArgs,
-    #[arg(long, value_name = "N")]
-    head: Option<usize>,
-    #[arg(long, value_name = "N")]
-    tail: Option<usize>,
-    #[arg(long, value_name = "START:END")]
-    lines: Option<String>,
-    #[arg(long, value_name = "LINE:CONTEXT")]
-    around: Option<String>,
-    #[arg(long, action = ArgAction::SetTrue)]
-    follow: bool,
-    #[arg(long, value_name = "PATTERN")]
-    search: Option<String>,
-    #[arg(long, action = ArgAction::SetTrue)]
-    regex: bool,
+struct LogPruneCommand {
+    #[arg(long = "keep", value_name = "N")]
+    keep: Option<usize>,
+    #[arg(long = "older-than", value_name = "AGE")]
+    older_than: Option<String>,
     #[arg(long, action = ArgAction::SetTrue)]
-    step: bool,
+    apply: bool,
+    #[arg(long = "yes", action = ArgAction::SetTrue)]
+    auto_apply: bool,
 }
 
 #[derive(Debug, Args)]
-struct NormalizeCommand {
-    #[command(flatten)]
-    common: CommonArgs,
-    #[arg(long = "convert-encoding", value_name = "ENCODING")]
-    convert_encoding: Option<String>,
-    #[arg(long = "strip-zero-width", action = ArgAction::SetTrue)]
-    strip_zero_width: bool,
-    #[arg(long = "strip-control", action = ArgAction::SetTrue)]
-    strip_control: bool,
-    #[arg(long = "trim-trailing-space", action = ArgAction::SetTrue)]
-    trim_trailing_space: bool,
-    #[arg(long = "ensure-eol", action = ArgAction::SetTrue)]
-    ensure_eol: bool,
-    #[arg(long = "report-format", default_value = "table")]
-    report_format: String,
-    #[arg(long = "scan-encoding", action = ArgAction::SetTrue)]
-    scan_encoding: bool,
-    #[arg(long = "scan-zero-width", action = ArgAction::SetTrue)]
-    scan_zero_width: bool,
-    #[arg(long = "scan-control", action = ArgAction::SetTrue)]
-    scan_control: bool,
-    #[arg(long = "scan-trailing-space", action = ArgAction::SetTrue)]
-    scan_trailing_space: bool,
-    #[arg(long = "scan-final-newline", action = ArgAction::SetTrue)]
-    scan_final_newline: bool,
+struct ReportCommand {
+    #[arg(long = "since", value_name = "WHEN")]
+    since: Option<String>,
+    #[arg(long = "until", value_name = "WHEN")]
+    until: Option<String>,
+    #[arg(long = "format", default_value = "table")]
+    format: String,
+    #[arg(long = "group-by", value_enum, default_value = "command")]
+    group_by: report::GroupBy,
+    #[arg(long = "top", value_name = "N", default_value_t = 10)]
+    top: usize,
+    #[arg(long = "out", value_name = "FILE")]
+    out: Option<PathBuf>,
+}
+
+#[cfg(test)]
+mod patch_line_ending_tests {
+    use super::{DiffPatch, LineEndingChoice, LineEndingStyle, apply_patch_preserving_newlines};
+
+    #[test]
+    fn apply_patch_respects_crlf_inputs() {
+        let original = "alpha\r\nbeta\r\n";
+        let patch_text = "\
+--- a/file.txt
++++ b/file.txt
+@@ -1,2 +1,2 @@
+ alpha
+-beta
++beta2
+";
+        let patch = DiffPatch::from_str(patch_text).expect("patch parses");
+        let (patched, _) =
+            apply_patch_preserving_newlines(original, &patch, 0).expect("patch applies cleanly");
+        assert_eq!(patched, "alpha\r\nbeta2\r\n");
+    }
+    #[test]
+    fn line_ending_choice_prefers_existing_style() {
+        assert_eq!(
+            LineEndingChoice::Auto.resolve(Some(LineEndingStyle::Cr)),
+            LineEndingStyle::Cr
+        );
+    }
+
+    #[test]
+    fn line_ending_choice_specific_variants() {
+        assert_eq!(
+            LineEndingChoice::Lf.resolve(Some(LineEndingStyle::Crlf)),
+            LineEndingStyle::Lf
+        );
+        assert_eq!(
+            LineEndingChoice::Crlf.resolve(Some(LineEndingStyle::Lf)),
+            LineEndingStyle::Crlf
+        );
+        assert_eq!(
+            LineEndingChoice::Cr.resolve(Some(LineEndingStyle::Lf)),
+            LineEndingStyle::Cr
+        );
+    }
+
+    #[test]
+    fn line_ending_choice_auto_uses_platform_default() {
+        let expected = if cfg!(windows) {
+            LineEndingStyle::Crlf
+        } else {
+            LineEndingStyle::Lf
+        };
+        assert_eq!(LineEndingChoice::Auto.resolve(None), expected);
+    }
+}
+
+#[cfg(test)]
+mod time_bound_tests {
+    use super::parse_time_bound;
+    use time::{OffsetDateTime, format_description::well_known::Rfc3339};
+
+    #[test]
+    fn relative_bounds_resolve_against_now() {
+        let now = OffsetDateTime::parse("2025-11-08T14:30:00Z", &Rfc3339).unwrap();
+        let at = |raw: &str| OffsetDateTime::parse(raw, &Rfc3339).unwrap();
+        assert_eq!(
+            parse_time_bound("7d", now).unwrap(),
+            at("2025-11-01T14:30:00Z")
+        );
+        assert_eq!(
+            parse_time_bound("12h ago", now).unwrap(),
+            at("2025-11-08T02:30:00Z")
+        );
+        assert_eq!(
+            parse_time_bound("yesterday", now).unwrap(),
+            at("2025-11-07T00:00:00Z")
+        );
+        assert_eq!(
+            parse_time_bound("Today", now).unwrap(),
+            at("2025-11-08T00:00:00Z")
+        );
+        assert_eq!(
+            parse_time_bound("2025-10-31", now).unwrap(),
+            at("2025-10-31T00:00:00Z")
+        );
+        assert_eq!(
+            parse_time_bound("2025-11-08T14:00:00-07:00", now).unwrap(),
+            at("2025-11-08T21:00:00Z")
+        );
+        assert!(parse_time_bound("last tuesday", now).is_err());
+        assert!(parse_time_bound("2025-13-01", now).is_err());
+    }
+}
+
+#[cfg(test)]
+mod cleanup_tests {
+    use super::{
+        build_cleanup_patterns, find_backup_files, find_undo_patches, format_bytes, is_backup_file,
+        parse_age, parse_size,
+    };
+    use std::path::Path;
+
+    #[test]
+    fn backup_detector_flags_basic_suffix() {
+        assert!(is_backup_file(Path::new("foo.txt.bak")));
+    }
+
+    #[test]
+    fn backup_detector_flags_incremental_suffix() {
+        assert!(is_backup_file(Path::new("docs/plan.md.bak12")));
+        assert!(is_backup_file(Path::new("out/bundle.js.bak3.gz")));
+    }
+
+    #[test]
+    fn backup_detector_ignores_other_files() {
+        assert!(!is_backup_file(Path::new("notes/backup-plan.md")));
+        assert!(!is_backup_file(Path::new("README.md")));
+        assert!(!is_backup_file(Path::new("file.bakup")));
+    }
+
+    #[test]
+    fn cleanup_filters_parse_units() {
+        assert_eq!(parse_age("7d").unwrap().as_secs(), 7 * 86_400);
+        assert_eq!(parse_age("90m").unwrap().as_secs(), 5_400);
+        assert!(parse_age("3y").is_err());
+        assert_eq!(parse_size("64K").unwrap(), 65_536);
+        assert_eq!(parse_size("2mb").unwrap(), 2 * 1024 * 1024);
+        assert_eq!(parse_size("500").unwrap(), 500);
+        assert_eq!(format_bytes(1536), "1.5 KiB");
+        assert_eq!(format_bytes(12), "12 B");
+    }
+
+    #[test]
+    fn custom_patterns_extend_backup_detection() {
+        let temp = tempfile::tempdir().expect("temp dir");
+        std::fs::write(temp.path().join("a.txt.orig"), "x").expect("write");
+        std::fs::write(temp.path().join("b.txt.bak"), "x").expect("write");
+        std::fs::write(temp.path().join("c.txt"), "x").expect("write");
+        let patterns = build_cleanup_patterns(&["*.orig".to_string()]).expect("patterns");
+        let found = find_backup_files(temp.path(), false, patterns.as_ref()).expect("scan");
+        assert_eq!(found.len(), 2);
+        let default = find_backup_files(temp.path(), false, None).expect("scan");
+        assert_eq!(default, vec![temp.path().join("b.txt.bak")]);
+    }
+
+    #[test]
+    fn undo_patch_scan_only_picks_patch_files() {
+        let temp = tempfile::tempdir().expect("temp dir");
+        std::fs::write(temp.path().join("2025-01-01_src_lib.rs.patch"), "x").expect("write");
+        std::fs::write(temp.path().join("notes.txt"), "x").expect("write");
+        let found = find_undo_patches(temp.path()).expect("scan");
+        assert_eq!(found, vec![temp.path().join("2025-01-01_src_lib.rs.patch")]);
+        assert!(
+            find_undo_patches(&temp.path().join("missing"))
+                .expect("scan")
+                .is_empty()
+        );
+    }
+}
+
+#[cfg(test)]
+mod write_tests {
+    use super::{
+        EncodingStrategy, FileEntry, FileMetadata, MtimePolicy, TransformResult, apply_mtime,
+        ensure_preview_current, integrity, verify_written, verify_written_bytes,
+    };
+    use encoding_rs::UTF_8;
+    use std::fs;
+    use std::time::{Duration, SystemTime};
+    use tempfile::tempdir;
+
+    #[test]
+    fn verify_written_restores_original_on_mismatch() {
+        let temp = tempdir().expect("temp dir");
+        let path = temp.path().join("target.txt");
+        fs::write(&path, "written\n").expect("write");
+        assert!(verify_written(&path, UTF_8, "written\n", Some(b"before\n")).is_ok());
+
+        let err = verify_written(&path, UTF_8, "expected\n", Some(b"before\n"))
+            .expect_err("mismatch should fail");
+        assert!(err.to_string().contains("line 1"));
+        assert_eq!(fs::read_to_string(&path).expect("read"), "before\n");
+
+        let fresh = temp.path().join("fresh.txt");
+        fs::write(&fresh, "oops").expect("write");
+        assert!(verify_written(&fresh, UTF_8, "intended", None).is_err());
+        assert!(!fresh.exists());
+    }
+
+    #[test]
+    fn verify_written_bytes_restores_original_on_mismatch() {
+        let temp = tempdir().expect("temp dir");
+        let path = temp.path().join("fw.bin");
+        fs::write(&path, [1u8, 2, 9]).expect("write");
+        assert!(verify_written_bytes(&path, &[1, 2, 9], &[1, 2, 3]).is_ok());
+
+        let err =
+            verify_written_bytes(&path, &[1, 2, 4], &[1, 2, 3]).expect_err("mismatch should fail");
+        assert!(err.to_string().contains("byte 0x2"), "{err}");
+        assert_eq!(fs::read(&path).expect("read"), [1, 2, 3]);
+    }
+
+    #[test]
+    fn stale_preview_is_rejected() {
+        let temp = tempdir().expect("temp dir");
+        let path = temp.path().join("target.txt");
+        fs::write(&path, "before\n").expect("write");
+        let entry = FileEntry {
+            path: path.clone(),
+            metadata: FileMetadata {
+                len: 7,
+                is_probably_binary: false,
+            },
+        };
+        let result = TransformResult {
+            decoded: EncodingStrategy::new(None)
+                .expect("strategy")
+                .decode(b"before\n"),
+            new_text: "after\n".into(),
+            source_hash: Some(integrity::sha256_hex(b"before\n")),
+        };
+        assert!(ensure_preview_current(&entry, &result).is_ok());
+        fs::write(&path, "edited elsewhere\n").expect("write");
+        assert!(ensure_preview_current(&entry, &result).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn unix_read_only_modes_do_not_block_writes() {
+        use super::{ensure_writable, write_via_temp};
+        use std::os::unix::fs::PermissionsExt;
+        let temp = tempdir().expect("temp dir");
+        let path = temp.path().join("locked.txt");
+        fs::write(&path, "before\n").expect("write");
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o444)).expect("chmod");
+        ensure_writable(&path, false).expect("0444 is not refused on unix");
+        write_via_temp(&path, b"after\n", true).expect("rewrite");
+        assert_eq!(fs::read_to_string(&path).expect("read"), "after\n");
+        let mode = fs::metadata(&path).expect("metadata").permissions().mode();
+        assert_eq!(mode & 0o777, 0o444);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn rewrite_keeps_permissions_unless_disabled() {
+        use super::write_via_temp;
+        use std::os::unix::fs::PermissionsExt;
+        let temp = tempdir().expect("temp dir");
+        let path = temp.path().join("script.sh");
+        fs::write(&path, "echo hi\n").expect("write");
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o750)).expect("chmod");
+        write_via_temp(&path, b"echo bye\n", true).expect("rewrite");
+        let mode = fs::metadata(&path).expect("metadata").permissions().mode();
+        assert_eq!(mode & 0o777, 0o750);
+        write_via_temp(&path, b"echo again\n", false).expect("rewrite");
+        let mode = fs::metadata(&path).expect("metadata").permissions().mode();
+        assert_ne!(mode & 0o777, 0o750);
+    }
+
+    #[test]
+    fn copy_in_place_overwrites_existing_inode() {
+        use super::copy_in_place;
+        let temp = tempdir().expect("temp dir");
+        let source = temp.path().join("staged");
+        let target = temp.path().join("target.txt");
+        fs::write(&source, "new contents\n").expect("write");
+        fs::write(&target, "old contents that are longer\n").expect("write");
+        copy_in_place(&source, &target).expect("copy");
+        assert_eq!(fs::read_to_string(&target).expect("read"), "new contents\n");
+    }
+
+    #[test]
+    fn mtime_policy_preserves_or_sets_timestamp() {
+        let temp = tempdir().expect("temp dir");
+        let path = temp.path().join("stamp.txt");
+        fs::write(&path, "x").expect("write");
+        let old = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+        apply_mtime(&path, MtimePolicy::Preserve, Some(old)).expect("preserve");
+        assert_eq!(fs::metadata(&path).unwrap().modified().unwrap(), old);
+        let set = SystemTime::UNIX_EPOCH + Duration::from_secs(1_500_000_000);
+        apply_mtime(&path, MtimePolicy::Set(set), Some(old)).expect("set");
+        assert_eq!(fs::metadata(&path).unwrap().modified().unwrap(), set);
+        apply_mtime(&path, MtimePolicy::Touch, Some(old)).expect("touch");
+        assert_eq!(fs::metadata(&path).unwrap().modified().unwrap(), set);
+
+        crate::attributes::set_readonly(&path, true).expect("readonly");
+        apply_mtime(&path, MtimePolicy::Preserve, Some(old)).expect("preserve readonly");
+        assert_eq!(fs::metadata(&path).unwrap().modified().unwrap(), old);
+        assert!(fs::metadata(&path).unwrap().permissions().readonly());
+    }
+}
+
+#[cfg(test)]
+mod write_mode_tests {
+    use super::{WriteMode, combine_write_body};
+
+    #[test]
+    fn append_and_prepend_are_idempotent() {
+        let eol = "\n".to_string();
+        let appended = combine_write_body("a\nb", "c\n".into(), WriteMode::Append, eol.clone());
+        assert_eq!(appended, "a\nb\nc\n");
+        assert_eq!(
+            combine_write_body(&appended, "c\n".into(), WriteMode::Append, eol.clone()),
+            appended
+        );
+        assert_eq!(
+            combine_write_body("body\n", "# header".into(), WriteMode::Prepend, eol.clone()),
+            "# header\nbody\n"
+        );
+        assert_eq!(
+            combine_write_body("old\n", "new\n".into(), WriteMode::Overwrite, eol),
+            "new\n"
+        );
+    }
+}
+
+#[cfg(test)]
+mod positional_tests {
+    use super::{Cli, Command};
+    use clap::Parser;
+    use std::path::PathBuf;
+
+    #[test]
+    fn positional_arguments_become_targets_and_globs() {
+        let cli = Cli::parse_from([
+            "safeedit",
+            "replace",
+            "notes.txt",
+            "src/**/*.rs",
+            "--target",
+            "docs",
+            "--pattern",
+            "a",
+            "--with",
+            "b",
+        ]);
+        let Command::Replace(cmd) = cli.command else {
+            unreachable!()
+        };
+        let (targets, globs) = cmd.common.selection();
+        assert_eq!(
+            targets,
+            vec![PathBuf::from("docs"), PathBuf::from("notes.txt")]
+        );
+        assert_eq!(globs, vec!["src/**/*.rs".to_string()]);
+    }
+
+    #[test]
+    fn max_file_size_defaults_to_50_mib_and_accepts_units() {
+        let parse = |extra: &[&str]| {
+            let mut argv = vec!["safeedit", "normalize", "notes.txt"];
+            argv.extend_from_slice(extra);
+            let Command::Normalize(cmd) = Cli::parse_from(argv).command else {
+                unreachable!()
+            };
+            cmd.common.max_file_size
+        };
+        assert_eq!(parse(&[]), 50 * 1024 * 1024);
+        assert_eq!(parse(&["--max-file-size", "2G"]), 2 * 1024 * 1024 * 1024);
+        assert_eq!(parse(&["--max-file-size", "0"]), 0);
+        assert!(Cli::try_parse_from(["safeedit", "normalize", "--max-file-size", "lots"]).is_err());
+    }
 }
 
-#[derive(Debug, Args)]
-struct ScriptCommand {
-    #[command(flatten)]
-    common: CommonArgs,
-    #[arg(value_name = "SCRIPT", value_hint = ValueHint::FilePath)]
-    script: PathBuf,
-    #[arg(long = "arg", value_name = "VALUE")]
-    args: Vec<String>,
-}
+#[cfg(test)]
+mod staging_tests {
+    use super::{Cli, Command, StagedSource, backup, handle_normalize, stage_targets};
+    use crate::archive::tests::tar_entry;
+    use clap::Parser;
+    use std::fs;
+    use std::path::{Component, Path};
+
+    #[test]
+    fn recorded_names_lead_back_to_the_source() {
+        let temp = tempfile::tempdir().unwrap();
+        let raw = format!("{}!d/a.txt", temp.path().join("b.tar").display());
+        let member = StagedSource::parse(&raw, None).unwrap().unwrap();
+        let name = member.recorded_name();
+        assert_eq!(name.to_str(), Some(raw.as_str()));
+        assert!(StagedSource::from_recorded(&name).unwrap() == Some(member));
+
+        let remote = StagedSource::parse("/etc/app.conf", Some("deploy@web1:2222"))
+            .unwrap()
+            .unwrap();
+        let name = remote.recorded_name();
+        assert_eq!(name.to_str(), Some("ssh://deploy@web1:2222/etc/app.conf"));
+        assert!(StagedSource::from_recorded(&name).unwrap() == Some(remote));
+
+        // Relative remote paths start at the remote home, as ssh does.
+        let relative = StagedSource::parse("notes.txt", Some("web1"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            relative.recorded_name().to_str(),
+            Some("ssh://web1/~/notes.txt")
+        );
+    }
+
+    #[test]
+    fn remote_parent_segments_stay_inside_the_staging_dir() {
+        let remote = StagedSource::parse("ssh://host/etc/../../../../home/me/.bashrc", None)
+            .unwrap()
+            .unwrap();
+        let path = remote.staging_path(Path::new("/stage"));
+        assert!(path.starts_with("/stage"), "{}", path.display());
+        assert!(
+            !path
+                .components()
+                .any(|component| matches!(component, Component::ParentDir)),
+            "{}",
+            path.display()
+        );
+    }
 
-#[derive(Debug, Args)]
-struct BatchCommand {
-    #[command(flatten)]
-    common: CommonArgs,
-    #[arg(value_name = "PLAN", value_hint = ValueHint::FilePath)]
-    plan: PathBuf,
+    #[test]
+    fn staged_edits_reach_the_source_even_when_the_run_fails_later() {
+        // The default `.tmp` prefix would be skipped as a hidden directory.
+        let temp = tempfile::Builder::new().prefix("staged").tempdir().unwrap();
+        let archive = temp.path().join("b.tar");
+        let mut raw = tar_entry("a.txt", b"short  \nfar too long a line\n");
+        raw.extend([0u8; 1024]);
+        fs::write(&archive, raw).unwrap();
+        let member = format!("{}!a.txt", archive.display());
+        let cli = Cli::parse_from([
+            "safeedit",
+            "normalize",
+            "--trim-trailing-space",
+            "--max-line-length",
+            "10",
+            "--fail-on-long-lines",
+            "--apply",
+            "--yes",
+            &member,
+        ]);
+        let Command::Normalize(mut cmd) = cli.command else {
+            unreachable!()
+        };
+        let _staging = stage_targets(&mut cmd.common).unwrap();
+        let err = handle_normalize(cmd).expect_err("the long line fails the run");
+        assert!(
+            format!("{err:#}").contains("lines longer than 10"),
+            "{err:#}"
+        );
+        let source = StagedSource::parse(&member, None).unwrap().unwrap();
+        assert_eq!(source.read().unwrap(), b"short\nfar too long a line\n");
+    }
+
+    #[test]
+    fn archive_write_back_refuses_a_changed_member() {
+        let temp = tempfile::tempdir().unwrap();
+        let archive = temp.path().join("b.tar");
+        let mut raw = tar_entry("d/a.txt", b"old\n");
+        raw.extend([0u8; 1024]);
+        fs::write(&archive, raw).unwrap();
+        let source = StagedSource::parse(&format!("{}!d/a.txt", archive.display()), None)
+            .unwrap()
+            .unwrap();
+        let options = backup::BackupOptions::default();
+
+        let err = source
+            .write_back(b"new\n", b"stale\n", &options, true)
+            .unwrap_err();
+        assert!(
+            err.to_string().contains("changed since it was read"),
+            "{err}"
+        );
+        assert_eq!(source.read().unwrap(), b"old\n");
+
+        source
+            .write_back(b"new\n", b"old\n", &options, true)
+            .unwrap();
+        assert_eq!(source.read().unwrap(), b"new\n");
+    }
 }
 
-#[derive(Debug, Args)]
-struct LogCommand {
-    #[arg(long = "tail", default_value_t = 20)]
-    tail: usize,
+#[cfg(test)]
+mod preview_tests {
+    use super::preview_file_name;
+    use std::path::Path;
+
+    #[test]
+    fn preview_names_do_not_collide() {
+        let nested = preview_file_name(Path::new("a/b.txt"));
+        let flat = preview_file_name(Path::new("a_b.txt"));
+        assert_eq!(nested, "a%2Fb.txt.patch");
+        assert_eq!(flat, "a_b.txt.patch");
+        assert_ne!(
+            preview_file_name(Path::new("a%2Fb.txt")),
+            nested,
+            "an escaped-looking name must not match the nested path"
+        );
+    }
 }
 
-#[derive(Debug, Args)]
-struct ReportCommand {
-    #[arg(long = "since", value_name = "RFC3339")]
-    since: Option<String>,
-    #[arg(long = "format", default_value = "table")]
-    format: String,
+#[cfg(test)]
+mod keep_going_tests {
+    use super::{Cli, Command, handle_normalize};
+    use clap::Parser;
+    use std::fs;
+
+    #[test]
+    fn normalize_keep_going_finishes_other_files_then_fails() {
+        // The default `.tmp` prefix would be skipped as a hidden directory.
+        let temp = tempfile::Builder::new()
+            .prefix("keep-going")
+            .tempdir()
+            .expect("temp dir");
+        // U+2713 has no Latin-1 form, so --strict-encoding fails that file.
+        let wide = temp.path().join("wide.txt");
+        let open = temp.path().join("open.txt");
+        fs::write(&wide, "a  \u{2713}\n").unwrap();
+        fs::write(&open, "b  \n").unwrap();
+
+        let cli = Cli::parse_from([
+            "safeedit",
+            "normalize",
+            "--trim-trailing-space",
+            "--convert-encoding",
+            "latin1",
+            "--strict-encoding",
+            "--apply",
+            "--yes",
+            "--keep-going",
+            wide.to_str().unwrap(),
+            open.to_str().unwrap(),
+        ]);
+        let Command::Normalize(cmd) = cli.command else {
+            unreachable!()
+        };
+        let err = handle_normalize(cmd).expect_err("the read-only file fails the run");
+        assert!(
+            format!("{err:#}").contains("normalize failed for 1 file(s)"),
+            "{err:#}"
+        );
+        assert_eq!(fs::read_to_string(&open).unwrap(), "b\n");
+        assert_eq!(fs::read_to_string(&wide).unwrap(), "a  \u{2713}\n");
+    }
 }
 
 #[cfg(test)]
-mod patch_line_ending_tests {
-    use super::{DiffPatch, LineEndingChoice, LineEndingStyle, apply_patch_preserving_newlines};
+mod batch_run_tests {
+    use super::{
+        Cli, Command, StepOutcome, batch, batch_summary_lines, handle_batch, review_plan_steps_in,
+    };
+    use clap::Parser;
+    use std::collections::{BTreeMap, VecDeque};
+    use std::fs;
+    use std::path::{Path, PathBuf};
+
+    // A failing step (its target does not exist) followed by one that edits
+    // `app.txt`. The default `.tmp` prefix would be skipped as hidden.
+    fn failing_plan(on_error: &str) -> (tempfile::TempDir, PathBuf, PathBuf) {
+        let temp = tempfile::Builder::new()
+            .prefix("batch")
+            .tempdir()
+            .expect("temp dir");
+        let file = temp.path().join("app.txt");
+        fs::write(&file, "alpha beta\n").unwrap();
+        let plan = temp.path().join("plan.yaml");
+        fs::write(
+            &plan,
+            format!(
+                "steps:\n  - command: replace\n    pattern: alpha\n    replacement: ALPHA\n    on_error: {on_error}\n    common:\n      targets:\n        - {missing}\n  - command: replace\n    pattern: beta\n    replacement: BETA\n    common:\n      targets:\n        - {file}\n",
+                missing = temp.path().join("missing.txt").display(),
+                file = file.display()
+            ),
+        )
+        .unwrap();
+        (temp, plan, file)
+    }
+
+    fn run_batch(plan: &Path, extra: &[&str]) -> anyhow::Result<()> {
+        let mut args = vec!["safeedit", "batch", plan.to_str().unwrap()];
+        args.extend(extra);
+        let Command::Batch(cmd) = Cli::parse_from(args).command else {
+            unreachable!()
+        };
+        handle_batch(*cmd)
+    }
 
     #[test]
-    fn apply_patch_respects_crlf_inputs() {
-        let original = "alpha\r\nbeta\r\n";
-        let patch_text = "\
---- a/file.txt
-+++ b/file.txt
-@@ -1,2 +1,2 @@
- alpha
--beta
-+beta2
-";
-        let patch = DiffPatch::from_str(patch_text).expect("patch parses");
-        let patched =
-            apply_patch_preserving_newlines(original, &patch).expect("patch applies cleanly");
-        assert_eq!(patched, "alpha\r\nbeta2\r\n");
+    fn abort_stops_at_the_failing_step() {
+        let (_temp, plan, file) = failing_plan("abort");
+        let err = run_batch(&plan, &["--apply", "--yes"]).expect_err("abort fails the run");
+        assert!(
+            format!("{err:#}").contains("batch aborted at step 1 of 2"),
+            "{err:#}"
+        );
+        assert_eq!(fs::read_to_string(&file).unwrap(), "alpha beta\n");
     }
+
     #[test]
-    fn line_ending_choice_prefers_existing_style() {
-        assert_eq!(
-            LineEndingChoice::Auto.resolve(Some(LineEndingStyle::Cr)),
-            LineEndingStyle::Cr
+    fn skip_ignores_the_failure_and_succeeds() {
+        let (_temp, plan, file) = failing_plan("skip");
+        run_batch(&plan, &["--apply", "--yes"]).expect("skip keeps the run green");
+        assert_eq!(fs::read_to_string(&file).unwrap(), "alpha BETA\n");
+    }
+
+    #[test]
+    fn continue_runs_later_steps_then_fails() {
+        let (_temp, plan, file) = failing_plan("continue");
+        let err = run_batch(&plan, &["--apply", "--yes"]).expect_err("continue still fails");
+        assert!(
+            format!("{err:#}").contains("1 batch step(s) failed"),
+            "{err:#}"
         );
+        assert_eq!(fs::read_to_string(&file).unwrap(), "alpha BETA\n");
     }
 
     #[test]
-    fn line_ending_choice_specific_variants() {
-        assert_eq!(
-            LineEndingChoice::Lf.resolve(Some(LineEndingStyle::Crlf)),
-            LineEndingStyle::Lf
+    fn script_steps_fail_as_unsupported() {
+        let temp = tempfile::Builder::new()
+            .prefix("batch")
+            .tempdir()
+            .expect("temp dir");
+        fs::write(temp.path().join("fix.rhai"), "").unwrap();
+        let plan = temp.path().join("plan.yaml");
+        fs::write(
+            &plan,
+            format!(
+                "steps:\n  - command: script\n    script: {}\n",
+                temp.path().join("fix.rhai").display()
+            ),
+        )
+        .unwrap();
+        let err = run_batch(&plan, &["--apply", "--yes"]).expect_err("script steps fail");
+        let message = format!("{err:#}");
+        assert!(
+            message.contains("batch aborted at step 1 of 1"),
+            "{message}"
         );
-        assert_eq!(
-            LineEndingChoice::Crlf.resolve(Some(LineEndingStyle::Lf)),
-            LineEndingStyle::Crlf
+        assert!(
+            message.contains("script steps are not supported yet"),
+            "{message}"
         );
+    }
+
+    #[test]
+    fn resume_remembers_that_a_completed_step_changed_files() {
+        let (temp, _, file) = failing_plan("abort");
+        let later = temp.path().join("later.txt");
+        let plan = temp.path().join("changed.yaml");
+        fs::write(
+            &plan,
+            format!(
+                "steps:\n  - command: replace\n    pattern: alpha\n    replacement: ALPHA\n    common:\n      targets:\n        - {file}\n  - command: replace\n    pattern: old\n    replacement: new\n    when: {{ changed: true }}\n    common:\n      targets:\n        - {later}\n",
+                file = file.display(),
+                later = later.display()
+            ),
+        )
+        .unwrap();
+        // The second step fails because its file is missing, interrupting the
+        // run after the first step changed app.txt.
+        run_batch(&plan, &["--apply", "--yes"]).expect_err("missing file aborts");
+        assert_eq!(fs::read_to_string(&file).unwrap(), "ALPHA beta\n");
+
+        fs::write(&later, "old\n").unwrap();
+        run_batch(&plan, &["--apply", "--yes", "--resume"]).expect("resume");
+        assert_eq!(fs::read_to_string(&later).unwrap(), "new\n");
+    }
+
+    #[test]
+    fn summary_lists_each_outcome_and_unrun_steps() {
+        let outcomes = [
+            ("replace", StepOutcome::Done(2)),
+            ("block", StepOutcome::Done(0)),
+            ("rename", StepOutcome::Skipped("missing: a.txt".into())),
+            ("write", StepOutcome::Ignored("boom".into())),
+            ("normalize", StepOutcome::Failed("bad".into())),
+        ];
+        let lines = batch_summary_lines(&outcomes, 7);
         assert_eq!(
-            LineEndingChoice::Cr.resolve(Some(LineEndingStyle::Lf)),
-            LineEndingStyle::Cr
+            lines,
+            [
+                "  1. replace    ok, changed 2 file(s)",
+                "  2. block      ok, no changes",
+                "  3. rename     skipped (missing: a.txt)",
+                "  4. write      failed, ignored by on_error: skip (boom)",
+                "  5. normalize  FAILED (bad)",
+                "     2 later step(s) not run",
+            ]
         );
     }
 
-    #[test]
-    fn line_ending_choice_auto_uses_platform_default() {
-        let expected = if cfg!(windows) {
-            LineEndingStyle::Crlf
-        } else {
-            LineEndingStyle::Lf
+    fn review(plan: &Path, done: &[usize], answers: &[&str]) -> Vec<bool> {
+        let Command::Batch(cmd) = Cli::parse_from(["safeedit", "batch"]).command else {
+            unreachable!()
         };
-        assert_eq!(LineEndingChoice::Auto.resolve(None), expected);
+        let batch_plan = batch::load_plan(plan, &BTreeMap::new()).unwrap();
+        let mut progress = batch::BatchProgress::new(plan, &batch_plan);
+        let scratch = tempfile::tempdir().expect("temp dir");
+        for idx in done {
+            progress
+                .mark_done(*idx, false, &scratch.path().join("progress.json"))
+                .unwrap();
+        }
+        let mut answers: VecDeque<String> = answers.iter().map(|a| format!("{a}\n")).collect();
+        review_plan_steps_in(&cmd.common, &batch_plan, &progress, || {
+            Ok(answers.pop_front().unwrap_or_default())
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn review_plan_approves_all_none_or_a_selection() {
+        let (_temp, plan, file) = failing_plan("continue");
+        assert_eq!(review(&plan, &[], &["y"]), [true, true]);
+        assert_eq!(review(&plan, &[], &["maybe", "n"]), [false, false]);
+        assert_eq!(review(&plan, &[], &["s", "n", "yes"]), [false, true]);
+        // Completed steps are neither previewed nor asked about.
+        assert_eq!(review(&plan, &[0], &["s", "y"]), [false, true]);
+        assert_eq!(fs::read_to_string(&file).unwrap(), "alpha beta\n");
     }
 }
 
 #[cfg(test)]
-mod cleanup_tests {
-    use super::is_backup_file;
+mod undo_tests {
+    use super::{Cli, Command, UndoCommand, find_undo_source, handle_undo, latest_applied};
+    use crate::logging;
+    use clap::Parser;
+    use std::fs;
     use std::path::Path;
 
+    fn run(args: &[&str]) {
+        let cli = Cli::parse_from(["safeedit"].iter().chain(args));
+        match cli.command {
+            Command::Replace(cmd) => super::handle_replace(cmd).expect("replace"),
+            Command::Undo(cmd) => handle_undo(cmd).expect("undo"),
+            _ => unreachable!(),
+        }
+    }
+
+    fn undo_command(path: &Path) -> UndoCommand {
+        let cli = Cli::parse_from(["safeedit", "undo", "--path", path.to_str().unwrap()]);
+        let Command::Undo(cmd) = cli.command else {
+            unreachable!()
+        };
+        cmd
+    }
+
+    // The default `.tmp` prefix would be skipped as a hidden directory.
+    fn workspace() -> tempfile::TempDir {
+        tempfile::Builder::new()
+            .prefix("undo")
+            .tempdir()
+            .expect("temp dir")
+    }
+
     #[test]
-    fn backup_detector_flags_basic_suffix() {
-        assert!(is_backup_file(Path::new("foo.txt.bak")));
+    fn undo_reverts_only_the_latest_change() {
+        let temp = workspace();
+        let path = temp.path().join("notes.txt");
+        let file = path.to_str().unwrap();
+        fs::write(&path, "one\n").unwrap();
+        run(&[
+            "replace",
+            "--pattern",
+            "one",
+            "--with",
+            "two",
+            "--apply",
+            "--yes",
+            file,
+        ]);
+        run(&[
+            "replace",
+            "--pattern",
+            "two",
+            "--with",
+            "three",
+            "--apply",
+            "--yes",
+            file,
+        ]);
+
+        let logged = logging::read_all().unwrap();
+        let entry = latest_applied(&logged, &path).expect("logged change");
+        assert_eq!(
+            entry.after_sha256.as_deref(),
+            Some(crate::integrity::sha256_hex(b"three\n").as_str())
+        );
+        run(&["undo", "--path", file, "--apply", "--yes"]);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "two\n");
     }
 
     #[test]
-    fn backup_detector_flags_incremental_suffix() {
-        assert!(is_backup_file(Path::new("docs/plan.md.bak12")));
+    fn undo_sources_are_tried_patch_then_session_then_backup() {
+        let temp = workspace();
+        let path = temp.path().join("config.txt");
+        let patches = temp.path().join("undo-patches");
+        fs::write(&path, "port = 1\n").unwrap();
+        run(&[
+            "replace",
+            "--pattern",
+            "1",
+            "--with",
+            "2",
+            "--undo-log",
+            patches.to_str().unwrap(),
+            "--apply",
+            "--yes",
+            path.to_str().unwrap(),
+        ]);
+        let cmd = undo_command(&path);
+        let current = fs::read(&path).unwrap();
+        let mut logged = logging::read_all().unwrap();
+        let index = logged
+            .iter()
+            .rposition(|entry| entry.absolute_path() == path)
+            .expect("logged change");
+        let entry = &mut logged[index];
+
+        let (source, restored) =
+            find_undo_source(&cmd, entry, &path, Some(&current), false).unwrap();
+        assert!(source.starts_with("undo patch"), "{source}");
+        assert_eq!(restored.as_deref(), Some(&b"port = 1\n"[..]));
+
+        fs::remove_dir_all(&patches).unwrap();
+        let (source, restored) =
+            find_undo_source(&cmd, entry, &path, Some(&current), false).unwrap();
+        assert!(source.starts_with("session"), "{source}");
+        assert_eq!(restored.as_deref(), Some(&b"port = 1\n"[..]));
+
+        entry.session = None;
+        let (source, restored) =
+            find_undo_source(&cmd, entry, &path, Some(&current), false).unwrap();
+        assert!(source.starts_with("backup"), "{source}");
+        assert_eq!(restored.as_deref(), Some(&b"port = 1\n"[..]));
     }
 
     #[test]
-    fn backup_detector_ignores_other_files() {
-        assert!(!is_backup_file(Path::new("notes/backup-plan.md")));
-        assert!(!is_backup_file(Path::new("README.md")));
-        assert!(!is_backup_file(Path::new("file.bakup")));
+    fn undo_without_a_logged_change_fails() {
+        let temp = workspace();
+        let path = temp.path().join("untouched.txt");
+        fs::write(&path, "same\n").unwrap();
+        let err = handle_undo(undo_command(&path)).expect_err("nothing to undo");
+        assert!(err.to_string().contains("no applied change to"), "{err:#}");
+        assert_eq!(fs::read_to_string(&path).unwrap(), "same\n");
+    }
+}
+
+#[cfg(test)]
+mod binpatch_tests {
+    use super::{Cli, Command, handle_binpatch};
+    use clap::Parser;
+    use std::fs;
+    use std::time::{Duration, SystemTime};
+
+    #[test]
+    fn binpatch_honours_shared_write_settings() {
+        let temp = tempfile::tempdir().expect("temp dir");
+        let path = temp.path().join("fw.bin");
+        let backups = temp.path().join("backups");
+        fs::write(&path, [0u8, 1, 2, 3]).unwrap();
+        let old = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+        fs::File::options()
+            .write(true)
+            .open(&path)
+            .and_then(|file| file.set_modified(old))
+            .unwrap();
+
+        let cli = Cli::parse_from([
+            "safeedit",
+            "binpatch",
+            path.to_str().unwrap(),
+            "--at",
+            "0x1",
+            "--expect-bytes",
+            "0102",
+            "--write-bytes",
+            "0a0b",
+            "--backup-dir",
+            backups.to_str().unwrap(),
+            "--backup-compress",
+            "--backup-compress-threshold",
+            "0",
+            "--preserve-mtime",
+            "--verify-write",
+            "--apply",
+            "--yes",
+        ]);
+        let Command::Binpatch(cmd) = cli.command else {
+            unreachable!()
+        };
+        handle_binpatch(cmd).expect("binpatch");
+        assert_eq!(fs::read(&path).unwrap(), [0u8, 0x0a, 0x0b, 3]);
+        assert_eq!(fs::metadata(&path).unwrap().modified().unwrap(), old);
+        let saved = crate::backup::list(&path, Some(&backups)).unwrap();
+        assert_eq!(saved.len(), 1);
+        assert!(saved[0].to_string_lossy().ends_with(".gz"), "{saved:?}");
+    }
+}
+
+#[cfg(test)]
+mod filter_tests {
+    use super::{Cli, Command, FilterAction, filter_normalize};
+    use clap::Parser;
+
+    fn normalize_args(flags: &[&str]) -> super::FilterNormalizeArgs {
+        let argv = ["safeedit", "filter", "normalize"]
+            .into_iter()
+            .chain(flags.iter().copied());
+        match Cli::parse_from(argv).command {
+            Command::Filter(cmd) => match cmd.action {
+                FilterAction::Normalize(args) => args,
+                FilterAction::Replace(_) => unreachable!(),
+            },
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn normalize_filter_rewrites_text_in_memory() {
+        let args = normalize_args(&["--trim-trailing-space", "--convert-eol", "lf"]);
+        let (text, bom) = filter_normalize("a  \r\nb\r\n", &args).unwrap();
+        assert_eq!(text.as_deref(), Some("a\nb\n"));
+        assert_eq!(bom, None);
+
+        let (text, bom) = filter_normalize("clean\n", &normalize_args(&["--add-bom"])).unwrap();
+        assert_eq!(text, None);
+        assert_eq!(bom, Some(true));
+    }
+
+    #[test]
+    fn normalize_filter_rejects_zero_line_length() {
+        let args = normalize_args(&["--max-line-length", "0", "--wrap-long-lines"]);
+        assert!(filter_normalize("hi\n", &args).is_err());
     }
 }
 
+#[derive(Debug, Args)]
+struct SessionCommand {
+    #[command(subcommand)]
+    action: SessionAction,
+}
+
+#[derive(Debug, Subcommand)]
+enum SessionAction {
+    List(SessionListCommand),
+    Rollback(SessionRollbackCommand),
+}
+
+#[derive(Debug, Args)]
+struct PatchCommand {
+    #[command(subcommand)]
+    action: PatchAction,
+}
+
+#[derive(Debug, Subcommand)]
+enum PatchAction {
+    Split(PatchSplitCommand),
+    Lint(PatchLintCommand),
+}
+
+#[derive(Debug, Args)]
+struct PatchSplitCommand {
+    #[arg(value_name = "PATCH", value_hint = ValueHint::FilePath)]
+    patch: PathBuf,
+    #[arg(
+        long = "out-dir",
+        value_name = "DIR",
+        value_hint = ValueHint::DirPath,
+        help = "Directory that receives the split pieces."
+    )]
+    out_dir: PathBuf,
+    #[arg(
+        long = "only",
+        value_name = "GLOB",
+        help = "Keep only segments whose path matches GLOB (repeatable)."
+    )]
+    only: Vec<String>,
+    #[arg(
+        long = "by",
+        value_enum,
+        default_value = "file",
+        help = "Write one piece per file or per parent directory."
+    )]
+    by: patch::SplitBy,
+    #[arg(long, action = ArgAction::SetTrue, help = "Overwrite existing pieces in --out-dir.")]
+    force: bool,
+}
+
+#[derive(Debug, Args)]
+struct PatchLintCommand {
+    #[arg(value_name = "PATCH", value_hint = ValueHint::FilePath, required = true)]
+    patches: Vec<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+struct SessionListCommand {
+    #[arg(long, default_value_t = 20)]
+    limit: usize,
+}
+
+#[derive(Debug, Args)]
+struct SessionRollbackCommand {
+    #[arg(value_name = "ID")]
+    id: String,
+    #[arg(long, action = ArgAction::SetTrue)]
+    apply: bool,
+    #[arg(long = "yes", action = ArgAction::SetTrue)]
+    auto_apply: bool,
+    #[arg(long, action = ArgAction::SetTrue)]
+    force: bool,
+}
+
+#[derive(Debug, Args)]
+struct RecoverCommand {
+    #[arg(long = "root", value_name = "DIR", default_value = ".", value_hint = ValueHint::DirPath)]
+    root: PathBuf,
+    #[arg(long, action = ArgAction::SetTrue)]
+    apply: bool,
+    #[arg(long = "yes", action = ArgAction::SetTrue)]
+    auto_apply: bool,
+    #[arg(long = "action", value_enum)]
+    action: Option<RecoverAction>,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum, PartialEq, Eq)]
+enum RecoverAction {
+    Finish,
+    Rollback,
+}
+
 #[derive(Debug, Args)]
 struct CleanupCommand {
     #[arg(long = "root", value_name = "DIR", default_value = ".", value_hint = ValueHint::DirPath)]
@@ -3226,4 +9719,153 @@ struct CleanupCommand {
     auto_apply: bool,
     #[arg(long = "include-hidden", action = ArgAction::SetTrue)]
     include_hidden: bool,
+    #[arg(long = "backup-dir", value_name = "DIR", value_hint = ValueHint::DirPath)]
+    backup_dir: Option<PathBuf>,
+    #[arg(long = "older-than", value_name = "AGE")]
+    older_than: Option<String>,
+    #[arg(long = "min-size", value_name = "SIZE")]
+    min_size: Option<String>,
+    #[arg(long = "pattern", value_name = "GLOB")]
+    patterns: Vec<String>,
+    #[arg(long = "skip-backups", action = ArgAction::SetTrue)]
+    skip_backups: bool,
+    #[arg(long = "temp-files", action = ArgAction::SetTrue)]
+    temp_files: bool,
+    #[arg(long = "undo-log", value_name = "DIR", value_hint = ValueHint::DirPath)]
+    undo_logs: Vec<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+struct RestoreCommand {
+    #[arg(long = "target", value_name = "FILE", value_hint = ValueHint::FilePath)]
+    target: PathBuf,
+    #[arg(long = "backup-dir", value_name = "DIR", value_hint = ValueHint::DirPath)]
+    backup_dir: Option<PathBuf>,
+    #[arg(long = "from", value_name = "BACKUP", value_hint = ValueHint::FilePath)]
+    from: Option<PathBuf>,
+    #[arg(long, action = ArgAction::SetTrue)]
+    apply: bool,
+    #[arg(long = "yes", action = ArgAction::SetTrue)]
+    auto_apply: bool,
+    #[arg(long = "color", value_enum, default_value = "auto")]
+    color: ColorChoice,
+}
+
+#[derive(Debug, Args)]
+struct UndoCommand {
+    #[arg(
+        long = "path",
+        value_name = "FILE",
+        value_hint = ValueHint::FilePath,
+        help = "File whose most recent logged change should be reverted."
+    )]
+    path: PathBuf,
+    #[arg(long = "backup-dir", value_name = "DIR", value_hint = ValueHint::DirPath)]
+    backup_dir: Option<PathBuf>,
+    #[arg(
+        long,
+        action = ArgAction::SetTrue,
+        help = "Undo even if the file changed after the logged edit."
+    )]
+    force: bool,
+    #[arg(long, action = ArgAction::SetTrue)]
+    apply: bool,
+    #[arg(long = "yes", action = ArgAction::SetTrue)]
+    auto_apply: bool,
+    #[arg(long = "color", value_enum, default_value = "auto")]
+    color: ColorChoice,
+}
+
+#[derive(Debug, Args)]
+struct BinpatchCommand {
+    #[arg(value_name = "FILE", value_hint = ValueHint::FilePath)]
+    path: PathBuf,
+    #[arg(
+        long = "at",
+        value_name = "OFFSET",
+        help = "Byte offset of the edit (decimal or 0x-prefixed hex)."
+    )]
+    at: String,
+    #[arg(
+        long = "expect-bytes",
+        value_name = "HEX",
+        help = "Bytes that must currently be at OFFSET; the edit is refused otherwise."
+    )]
+    expect_bytes: String,
+    #[arg(
+        long = "write-bytes",
+        value_name = "HEX",
+        help = "Replacement bytes, the same length as --expect-bytes."
+    )]
+    write_bytes: String,
+    #[arg(
+        long = "context-rows",
+        value_name = "N",
+        default_value_t = 2,
+        help = "Unchanged 16-byte rows to show around the edit."
+    )]
+    context_rows: usize,
+    #[command(flatten)]
+    writes: WriteArgs,
+    #[arg(long, action = ArgAction::SetTrue)]
+    apply: bool,
+    #[arg(long = "yes", action = ArgAction::SetTrue)]
+    auto_apply: bool,
+    #[arg(long = "color", value_enum, default_value = "auto")]
+    color: ColorChoice,
+}
+
+#[cfg(test)]
+mod approval_protocol_tests {
+    use super::{ApprovalDecision, parse_json_approval};
+    use std::path::Path;
+
+    #[test]
+    fn json_decisions_map_to_approvals() {
+        let path = Path::new("/work/src/lib.rs");
+        let decision =
+            parse_json_approval(r#"{"decision":"apply","path":"/work/src/lib.rs"}"#, path).unwrap();
+        assert!(matches!(decision, ApprovalDecision::Apply));
+        let decision = parse_json_approval(r#"{"decision":"skip"}"#, path).unwrap();
+        assert!(matches!(decision, ApprovalDecision::Skip));
+        assert!(parse_json_approval(r#"{"decision":"maybe"}"#, path).is_err());
+        assert!(parse_json_approval(r#"{"decision":"apply","path":"other.rs"}"#, path).is_err());
+        assert!(parse_json_approval("y", path).is_err());
+    }
+}
+
+#[cfg(test)]
+mod invocation_tests {
+    use super::{REDACTED, normalize_invocation};
+    use std::ffi::OsString;
+
+    #[test]
+    fn recorded_invocations_split_flags_and_redact_bodies() {
+        let argv = [
+            "replace",
+            "--pattern=token",
+            "--with",
+            "s3cr3t",
+            "--with-url=https://user:pw@host/x",
+            "--var",
+            "KEY=value",
+            "src",
+        ];
+        let normalized = normalize_invocation(argv.iter().map(OsString::from));
+        assert_eq!(
+            normalized,
+            [
+                "replace",
+                "--pattern",
+                "token",
+                "--with",
+                REDACTED,
+                "--with-url",
+                REDACTED,
+                "--var",
+                REDACTED,
+                "src"
+            ]
+        );
+    }
 }
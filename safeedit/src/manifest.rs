@@ -0,0 +1,227 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::sync::OnceLock;
+
+use anyhow::{Context, Result, bail};
+use serde::{Deserialize, Serialize};
+use time::{OffsetDateTime, format_description::well_known::Rfc3339};
+
+use crate::integrity::{self, Drift};
+
+pub const MANIFEST_VERSION: u32 = 1;
+
+static AUTO: OnceLock<PathBuf> = OnceLock::new();
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Manifest {
+    pub version: u32,
+    pub generated: String,
+    pub files: BTreeMap<String, ManifestEntry>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    pub sha256: String,
+    pub bytes: u64,
+}
+
+pub struct Checked {
+    pub path: String,
+    pub drift: Drift,
+    pub expected: Option<String>,
+    pub actual: Option<String>,
+}
+
+impl Manifest {
+    pub fn load(path: &Path) -> Result<Self> {
+        let data =
+            fs::read(path).with_context(|| format!("reading manifest {}", path.display()))?;
+        let manifest: Manifest = serde_json::from_slice(&data)
+            .with_context(|| format!("invalid manifest {}", path.display()))?;
+        if manifest.version != MANIFEST_VERSION {
+            bail!(
+                "manifest {} has version {}; this safeedit reads version {MANIFEST_VERSION}",
+                path.display(),
+                manifest.version
+            );
+        }
+        Ok(manifest)
+    }
+
+    fn load_or_default(path: &Path) -> Result<Self> {
+        if path.exists() {
+            Self::load(path)
+        } else {
+            Ok(Manifest {
+                version: MANIFEST_VERSION,
+                ..Manifest::default()
+            })
+        }
+    }
+
+    pub fn save(&mut self, path: &Path) -> Result<()> {
+        self.version = MANIFEST_VERSION;
+        self.generated = OffsetDateTime::now_utc()
+            .format(&Rfc3339)
+            .unwrap_or_else(|_| "unknown".into());
+        if let Some(parent) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("creating directory {}", parent.display()))?;
+        }
+        fs::write(path, serde_json::to_string_pretty(self)? + "\n")
+            .with_context(|| format!("writing manifest {}", path.display()))
+    }
+
+    pub fn insert(&mut self, manifest_path: &Path, file: &Path, bytes: &[u8]) {
+        self.files.insert(
+            key_for(manifest_path, file),
+            ManifestEntry {
+                sha256: integrity::sha256_hex(bytes),
+                bytes: bytes.len() as u64,
+            },
+        );
+    }
+
+    pub fn verify(&self, manifest_path: &Path) -> Result<Vec<Checked>> {
+        let base = base_dir(manifest_path);
+        self.files
+            .iter()
+            .map(|(key, entry)| {
+                let (drift, actual) =
+                    integrity::check_recorded(&base.join(key), Some(&entry.sha256))?;
+                Ok(Checked {
+                    path: key.clone(),
+                    drift,
+                    expected: Some(entry.sha256.clone()),
+                    actual,
+                })
+            })
+            .collect()
+    }
+
+    pub fn contains(&self, manifest_path: &Path, file: &Path) -> bool {
+        self.files.contains_key(&key_for(manifest_path, file))
+    }
+}
+
+// Entries are keyed relative to the manifest's directory (with `/`
+// separators, `..` for siblings) so a manifest can be committed and checked
+// on another machine; paths with no common ancestor stay absolute.
+pub fn key_for(manifest_path: &Path, file: &Path) -> String {
+    let base = lexical_absolute(&base_dir(manifest_path));
+    let absolute = lexical_absolute(file);
+    let base: Vec<Component> = base.components().collect();
+    let target: Vec<Component> = absolute.components().collect();
+    let shared = base
+        .iter()
+        .zip(&target)
+        .take_while(|(left, right)| left == right)
+        .count();
+    if shared < 2 {
+        return absolute.to_string_lossy().into_owned();
+    }
+    let mut parts: Vec<String> = vec!["..".to_string(); base.len() - shared];
+    parts.extend(
+        target[shared..]
+            .iter()
+            .map(|part| part.as_os_str().to_string_lossy().into_owned()),
+    );
+    parts.join("/")
+}
+
+// `std::path::absolute` keeps `..`; fold it away without touching the disk
+// so `../out/manifest.json` and `src/./a.txt` compare like their targets.
+fn lexical_absolute(path: &Path) -> PathBuf {
+    let absolute = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+    let mut folded = PathBuf::new();
+    for part in absolute.components() {
+        match part {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                folded.pop();
+            }
+            other => folded.push(other),
+        }
+    }
+    folded
+}
+
+fn base_dir(manifest_path: &Path) -> PathBuf {
+    match manifest_path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+        _ => PathBuf::from("."),
+    }
+}
+
+// `--manifest FILE`: every applied write recorded in the change log also
+// refreshes its entry in FILE.
+pub fn set_auto(path: PathBuf) {
+    let _ = AUTO.set(path);
+}
+
+pub fn note_applied(file: &Path, deleted: bool) {
+    let Some(manifest_path) = AUTO.get() else {
+        return;
+    };
+    if let Err(err) = update(manifest_path, file, deleted) {
+        println!("warning: updating manifest: {err:#}");
+    }
+}
+
+fn update(manifest_path: &Path, file: &Path, deleted: bool) -> Result<()> {
+    let mut manifest = Manifest::load_or_default(manifest_path)?;
+    if deleted {
+        manifest.files.remove(&key_for(manifest_path, file));
+    } else {
+        let bytes = fs::read(file).with_context(|| format!("reading {}", file.display()))?;
+        manifest.insert(manifest_path, file, &bytes);
+    }
+    manifest.save(manifest_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn manifests_round_trip_and_detect_drift() {
+        let dir = tempdir().unwrap();
+        let manifest_path = dir.path().join("out/manifest.json");
+        let kept = dir.path().join("out/a.txt");
+        let gone = dir.path().join("b.txt");
+        fs::create_dir_all(kept.parent().unwrap()).unwrap();
+        fs::write(&kept, "a\n").unwrap();
+        fs::write(&gone, "b\n").unwrap();
+
+        let mut manifest = Manifest::load_or_default(&manifest_path).unwrap();
+        manifest.insert(&manifest_path, &kept, b"a\n");
+        manifest.insert(&manifest_path, &gone, b"b\n");
+        assert!(manifest.files.contains_key("a.txt"));
+        assert!(manifest.files.contains_key("../b.txt"));
+        manifest.save(&manifest_path).unwrap();
+
+        fs::write(&kept, "changed\n").unwrap();
+        fs::remove_file(&gone).unwrap();
+        let loaded = Manifest::load(&manifest_path).unwrap();
+        assert!(loaded.contains(&manifest_path, &kept));
+        let drift: Vec<Drift> = loaded
+            .verify(&manifest_path)
+            .unwrap()
+            .iter()
+            .map(|checked| checked.drift)
+            .collect();
+        assert!(drift.contains(&Drift::Modified));
+        assert!(drift.contains(&Drift::Missing));
+
+        update(&manifest_path, &kept, false).unwrap();
+        update(&manifest_path, &gone, true).unwrap();
+        let refreshed = Manifest::load(&manifest_path).unwrap();
+        assert_eq!(refreshed.files.len(), 1);
+        assert_eq!(
+            refreshed.verify(&manifest_path).unwrap()[0].drift,
+            Drift::Intact
+        );
+    }
+}
@@ -1,4 +1,9 @@
-use serde::Serialize;
+use std::collections::BTreeMap;
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use unicode_general_category::get_general_category;
+use unicode_normalization::UnicodeNormalization;
 
 #[derive(Debug, Serialize, Clone)]
 pub struct NormalizeReport {
@@ -6,18 +11,231 @@ pub struct NormalizeReport {
     pub control_chars: Option<usize>,
     pub trailing_spaces: Option<usize>,
     pub missing_final_newline: Option<bool>,
+    pub unicode_form: Option<usize>,
+    pub bidi_controls: Option<usize>,
+    pub bidi_locations: Vec<CharLocation>,
+    pub confusables: Option<usize>,
+    pub confusable_locations: Vec<CharLocation>,
+    pub indentation: Option<IndentSummary>,
+    pub line_endings: Option<LineEndingCounts>,
+    pub bom: Option<bool>,
+    pub smart_punctuation: Option<usize>,
+    pub excess_blank_lines: Option<usize>,
+    pub findings: Vec<Finding>,
+    pub strip_classes: BTreeMap<String, usize>,
+    pub long_lines: Option<usize>,
+    pub long_line_locations: Vec<Finding>,
+}
+
+#[derive(Debug, Clone)]
+pub struct CharClass {
+    pub name: String,
+    pub ranges: Vec<(u32, u32)>,
+    pub categories: Vec<String>,
+}
+
+impl CharClass {
+    pub fn contains(&self, ch: char) -> bool {
+        let code = ch as u32;
+        if self
+            .ranges
+            .iter()
+            .any(|(start, end)| (*start..=*end).contains(&code))
+        {
+            return true;
+        }
+        if self.categories.is_empty() {
+            return false;
+        }
+        let abbreviation = get_general_category(ch).abbreviation();
+        self.categories
+            .iter()
+            .any(|category| abbreviation.starts_with(category.as_str()))
+    }
+}
+
+#[derive(Debug, Serialize, Clone, PartialEq, Eq)]
+pub struct Finding {
+    pub kind: &'static str,
+    pub line: usize,
+    pub column: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub codepoint: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub length: Option<usize>,
+}
+
+impl Finding {
+    fn char(kind: &'static str, line: usize, column: usize, ch: char) -> Self {
+        Self {
+            kind,
+            line,
+            column,
+            codepoint: Some(format!("U+{:04X}", ch as u32)),
+            length: None,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Clone, Copy, Default, PartialEq, Eq)]
+pub struct IndentSummary {
+    pub tabs: usize,
+    pub spaces: usize,
+    pub mixed: usize,
+    pub converted: usize,
+}
+
+impl IndentSummary {
+    pub fn is_inconsistent(&self) -> bool {
+        self.mixed > 0 || (self.tabs > 0 && self.spaces > 0)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IndentConversion {
+    TabsToSpaces(usize),
+    SpacesToTabs(usize),
+}
+
+#[derive(Debug, Serialize, Clone, PartialEq, Eq)]
+pub struct CharLocation {
+    pub line: usize,
+    pub column: usize,
+    pub codepoint: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ascii: Option<char>,
+}
+
+impl CharLocation {
+    fn new(line: usize, column: usize, ch: char) -> Self {
+        Self {
+            line,
+            column,
+            codepoint: format!("U+{:04X}", ch as u32),
+            ascii: None,
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct NormalizeOptions {
     pub strip_zero_width: bool,
     pub strip_control: bool,
+    pub strip_bidi: bool,
+    pub map_confusables: bool,
+    pub ascii_punctuation: bool,
+    pub punctuation_map: Vec<(char, String)>,
+    pub strip_classes: Vec<CharClass>,
     pub trim_trailing_space: bool,
     pub ensure_eol: bool,
+    pub indent_conversion: Option<IndentConversion>,
+    pub max_blank_lines: Option<usize>,
+    pub max_line_length: Option<usize>,
+    pub wrap_long_lines: bool,
     pub detect_zero_width: bool,
     pub detect_control: bool,
+    pub detect_bidi: bool,
+    pub detect_confusables: bool,
+    pub detect_punctuation: bool,
     pub detect_trailing_space: bool,
+    pub detect_indentation: bool,
+    pub detect_line_endings: bool,
     pub detect_final_newline: bool,
+    pub record_locations: bool,
+    pub unicode_form: Option<UnicodeForm>,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, ValueEnum, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum UnicodeForm {
+    Nfc,
+    Nfd,
+    Nfkc,
+    Nfkd,
+}
+
+impl UnicodeForm {
+    pub fn label(&self) -> &'static str {
+        match self {
+            UnicodeForm::Nfc => "nfc",
+            UnicodeForm::Nfd => "nfd",
+            UnicodeForm::Nfkc => "nfkc",
+            UnicodeForm::Nfkd => "nfkd",
+        }
+    }
+
+    fn apply(&self, text: &str) -> String {
+        match self {
+            UnicodeForm::Nfc => text.nfc().collect(),
+            UnicodeForm::Nfd => text.nfd().collect(),
+            UnicodeForm::Nfkc => text.nfkc().collect(),
+            UnicodeForm::Nfkd => text.nfkd().collect(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LineEndingCounts {
+    pub lf: usize,
+    pub crlf: usize,
+    pub cr: usize,
+}
+
+impl LineEndingCounts {
+    pub fn label(&self) -> &'static str {
+        match (self.lf > 0, self.crlf > 0, self.cr > 0) {
+            (false, false, false) => "none",
+            (true, false, false) => "lf",
+            (false, true, false) => "crlf",
+            (false, false, true) => "cr",
+            _ => "mixed",
+        }
+    }
+}
+
+pub fn count_line_endings(text: &str) -> LineEndingCounts {
+    let mut counts = LineEndingCounts::default();
+    let bytes = text.as_bytes();
+    let mut idx = 0;
+    while idx < bytes.len() {
+        match bytes[idx] {
+            b'\r' if bytes.get(idx + 1) == Some(&b'\n') => {
+                counts.crlf += 1;
+                idx += 1;
+            }
+            b'\r' => counts.cr += 1,
+            b'\n' => counts.lf += 1,
+            _ => {}
+        }
+        idx += 1;
+    }
+    counts
+}
+
+// The most common step between the indents of consecutive space-indented
+// lines (ties go to the narrower width); blank lines are ignored.
+pub fn dominant_indent_width(text: &str) -> Option<usize> {
+    let mut steps: BTreeMap<usize, usize> = BTreeMap::new();
+    let mut previous = 0usize;
+    for line in text.lines() {
+        let body = line.trim_start_matches([' ', '\t']);
+        if body.is_empty() {
+            continue;
+        }
+        let indent = &line[..line.len() - body.len()];
+        if indent.contains('\t') {
+            previous = 0;
+            continue;
+        }
+        if indent.len() > previous {
+            *steps.entry(indent.len() - previous).or_default() += 1;
+        }
+        previous = indent.len();
+    }
+    steps
+        .into_iter()
+        .max_by(|a, b| a.1.cmp(&b.1).then(b.0.cmp(&a.0)))
+        .map(|(width, _)| width)
 }
 
 pub struct NormalizeOutcome {
@@ -28,7 +246,25 @@ pub struct NormalizeOutcome {
 pub fn normalize_text(text: &str, opts: &NormalizeOptions) -> NormalizeOutcome {
     let mut zero_width = opts.detect_zero_width.then_some(0usize);
     let mut control_chars = opts.detect_control.then_some(0usize);
+    let mut bidi_controls = opts.detect_bidi.then_some(0usize);
+    let mut bidi_locations = Vec::new();
+    let confusable_locations = if opts.detect_confusables || opts.map_confusables {
+        find_confusables(text)
+    } else {
+        Vec::new()
+    };
+    let mut pending_confusables = confusable_locations.iter().peekable();
+    let mut smart_punctuation = opts.detect_punctuation.then_some(0usize);
+    let mut findings = Vec::new();
+    let mut line_start = 0usize;
+    let mut class_counts: BTreeMap<String, usize> = opts
+        .strip_classes
+        .iter()
+        .map(|class| (class.name.clone(), 0))
+        .collect();
     let mut trailing_spaces = opts.detect_trailing_space.then_some(0usize);
+    let mut indentation =
+        (opts.detect_indentation || opts.indent_conversion.is_some()).then(IndentSummary::default);
     let missing_final_newline = opts
         .detect_final_newline
         .then_some(!text.is_empty() && !text.ends_with('\n'));
@@ -36,23 +272,57 @@ pub fn normalize_text(text: &str, opts: &NormalizeOptions) -> NormalizeOutcome {
     let mut cleaned = String::with_capacity(text.len());
     let mut line_buffer = String::new();
     let mut changed = false;
+    let mut line = 1usize;
+    let mut column = 0usize;
 
-    for ch in text.chars() {
+    for (offset, mut ch) in text.char_indices() {
+        column += 1;
+        if let Some(found) =
+            pending_confusables.next_if(|loc| loc.line == line && loc.column == column)
+            && opts.map_confusables
+            && let Some(ascii) = found.ascii
+        {
+            ch = ascii;
+            changed = true;
+        }
         if ch == '\n' {
+            if opts.record_locations && opts.detect_trailing_space {
+                record_trailing_finding(&text[line_start..offset], line, &mut findings);
+            }
+            line_start = offset + 1;
+            line += 1;
+            column = 0;
             flush_line(
                 &mut line_buffer,
                 &mut cleaned,
                 opts,
                 trailing_spaces.as_mut(),
+                indentation.as_mut(),
                 &mut changed,
                 true,
             );
             continue;
         }
 
+        if ch != '\r'
+            && let Some(class) = opts.strip_classes.iter().find(|class| class.contains(ch))
+        {
+            if let Some(count) = class_counts.get_mut(&class.name) {
+                *count += 1;
+            }
+            if opts.record_locations {
+                findings.push(Finding::char("strip-class", line, column, ch));
+            }
+            changed = true;
+            continue;
+        }
+
         if is_zero_width_char(ch) {
             if let Some(count) = zero_width.as_mut() {
                 *count += 1;
+                if opts.record_locations {
+                    findings.push(Finding::char("zero-width", line, column, ch));
+                }
             }
             if opts.strip_zero_width {
                 changed = true;
@@ -60,9 +330,36 @@ pub fn normalize_text(text: &str, opts: &NormalizeOptions) -> NormalizeOutcome {
             }
         }
 
+        if (opts.detect_punctuation || opts.ascii_punctuation)
+            && let Some(replacement) = punctuation_replacement(ch, &opts.punctuation_map)
+        {
+            if let Some(count) = smart_punctuation.as_mut() {
+                *count += 1;
+            }
+            if opts.ascii_punctuation {
+                line_buffer.push_str(replacement);
+                changed = true;
+                continue;
+            }
+        }
+
+        if is_bidi_control(ch) {
+            if let Some(count) = bidi_controls.as_mut() {
+                *count += 1;
+                bidi_locations.push(CharLocation::new(line, column, ch));
+            }
+            if opts.strip_bidi {
+                changed = true;
+                continue;
+            }
+        }
+
         if is_control_char(ch) {
             if let Some(count) = control_chars.as_mut() {
                 *count += 1;
+                if opts.record_locations {
+                    findings.push(Finding::char("control", line, column, ch));
+                }
             }
             if opts.strip_control {
                 changed = true;
@@ -73,25 +370,85 @@ pub fn normalize_text(text: &str, opts: &NormalizeOptions) -> NormalizeOutcome {
         line_buffer.push(ch);
     }
 
+    if opts.record_locations && opts.detect_trailing_space {
+        record_trailing_finding(&text[line_start..], line, &mut findings);
+    }
     flush_line(
         &mut line_buffer,
         &mut cleaned,
         opts,
         trailing_spaces.as_mut(),
+        indentation.as_mut(),
         &mut changed,
         false,
     );
 
+    let mut excess_blank_lines = None;
+    if let Some(max) = opts.max_blank_lines {
+        let (collapsed, removed) = collapse_blank_lines(&cleaned, max);
+        excess_blank_lines = Some(removed);
+        if removed > 0 {
+            cleaned = collapsed;
+            changed = true;
+        }
+    }
+
+    let mut long_lines = None;
+    let mut long_line_locations = Vec::new();
+    if let Some(max) = opts.max_line_length {
+        // Located in the input, like every other finding; blank-line collapse
+        // and stripping above would otherwise shift the reported lines.
+        long_line_locations = find_long_lines(text, max);
+        long_lines = Some(long_line_locations.len());
+        if opts.wrap_long_lines {
+            let wrapped = wrap_long_lines(&cleaned, max);
+            if wrapped != cleaned {
+                cleaned = wrapped;
+                changed = true;
+            }
+        }
+    }
+
     if opts.ensure_eol && !cleaned.ends_with('\n') {
         cleaned.push('\n');
         changed = true;
     }
 
+    let mut unicode_form = None;
+    if let Some(form) = opts.unicode_form {
+        let (normalized, lines) = normalize_unicode(&cleaned, form);
+        unicode_form = Some(lines);
+        if lines > 0 {
+            cleaned = normalized;
+            changed = true;
+        }
+    }
+
     let report = NormalizeReport {
         zero_width,
         control_chars,
         trailing_spaces,
         missing_final_newline,
+        unicode_form,
+        bidi_controls,
+        bidi_locations,
+        confusables: opts
+            .detect_confusables
+            .then_some(confusable_locations.len()),
+        confusable_locations: if opts.detect_confusables {
+            confusable_locations
+        } else {
+            Vec::new()
+        },
+        indentation,
+        line_endings: opts.detect_line_endings.then(|| count_line_endings(text)),
+        bom: None,
+        smart_punctuation,
+        excess_blank_lines,
+        findings,
+        strip_classes: class_counts,
+        long_lines,
+        long_line_locations,
     };
 
     if changed || cleaned != text {
@@ -107,14 +464,294 @@ pub fn normalize_text(text: &str, opts: &NormalizeOptions) -> NormalizeOutcome {
     }
 }
 
+fn split_line_ending(line: &str) -> (&str, &str) {
+    let body = line
+        .strip_suffix("\r\n")
+        .or_else(|| line.strip_suffix('\n'))
+        .unwrap_or(line);
+    (body, &line[body.len()..])
+}
+
+fn find_long_lines(text: &str, max: usize) -> Vec<Finding> {
+    text.split_inclusive('\n')
+        .enumerate()
+        .filter_map(|(idx, line)| {
+            let length = split_line_ending(line).0.chars().count();
+            (length > max).then_some(Finding {
+                kind: "long-line",
+                line: idx + 1,
+                column: max + 1,
+                codepoint: None,
+                length: Some(length),
+            })
+        })
+        .collect()
+}
+
+fn wrap_long_lines(text: &str, max: usize) -> String {
+    if max == 0 {
+        return text.to_string();
+    }
+    let mut out = String::with_capacity(text.len());
+    let default_ending = if text.contains("\r\n") { "\r\n" } else { "\n" };
+    for line in text.split_inclusive('\n') {
+        let (body, ending) = split_line_ending(line);
+        if body.chars().count() <= max {
+            out.push_str(line);
+            continue;
+        }
+        let wrap_ending = if ending.is_empty() {
+            default_ending
+        } else {
+            ending
+        };
+        let indent_len = body.len() - body.trim_start_matches([' ', '\t']).len();
+        let indent = if body[..indent_len].chars().count() * 2 > max {
+            ""
+        } else {
+            &body[..indent_len]
+        };
+        let mut rest = body.to_string();
+        while rest.chars().count() > max {
+            let limit = rest
+                .char_indices()
+                .nth(max)
+                .map(|(idx, _)| idx)
+                .unwrap_or(rest.len());
+            let split = if rest[limit..].starts_with([' ', '\t']) {
+                Some(limit)
+            } else {
+                rest[..limit]
+                    .rfind([' ', '\t'])
+                    .filter(|idx| *idx > indent.len())
+            }
+            .unwrap_or(limit);
+            out.push_str(rest[..split].trim_end());
+            out.push_str(wrap_ending);
+            let remainder = rest[split..].trim_start();
+            rest = format!("{indent}{remainder}");
+        }
+        out.push_str(&rest);
+        out.push_str(ending);
+    }
+    out
+}
+
+fn collapse_blank_lines(text: &str, max: usize) -> (String, usize) {
+    let mut out = String::with_capacity(text.len());
+    let mut run = 0usize;
+    let mut removed = 0usize;
+    for line in text.split_inclusive('\n') {
+        if line.trim().is_empty() && line.ends_with('\n') {
+            run += 1;
+            if run > max {
+                removed += 1;
+                continue;
+            }
+        } else {
+            run = 0;
+        }
+        out.push_str(line);
+    }
+    (out, removed)
+}
+
+fn convert_indent(indent: &str, conversion: IndentConversion) -> String {
+    let tab_width = match conversion {
+        IndentConversion::TabsToSpaces(width) | IndentConversion::SpacesToTabs(width) => {
+            width.max(1)
+        }
+    };
+    let mut columns = 0usize;
+    for ch in indent.chars() {
+        if ch == '\t' {
+            columns += tab_width - columns % tab_width;
+        } else {
+            columns += 1;
+        }
+    }
+    match conversion {
+        IndentConversion::TabsToSpaces(_) => " ".repeat(columns),
+        IndentConversion::SpacesToTabs(_) => {
+            let mut out = "\t".repeat(columns / tab_width);
+            out.push_str(&" ".repeat(columns % tab_width));
+            out
+        }
+    }
+}
+
+fn normalize_unicode(text: &str, form: UnicodeForm) -> (String, usize) {
+    let normalized = form.apply(text);
+    if normalized == text {
+        return (normalized, 0);
+    }
+    let lines = text
+        .split('\n')
+        .zip(normalized.split('\n'))
+        .filter(|(before, after)| before != after)
+        .count();
+    (normalized, lines)
+}
+
 fn is_zero_width_char(ch: char) -> bool {
     matches!(ch, '\u{200B}' | '\u{200C}' | '\u{200D}' | '\u{FEFF}')
 }
 
+fn find_confusables(text: &str) -> Vec<CharLocation> {
+    let mut found = Vec::new();
+    let mut word: Vec<(usize, usize, char)> = Vec::new();
+    let mut line = 1usize;
+    let mut column = 0usize;
+    for ch in text.chars().chain(std::iter::once('\n')) {
+        column += 1;
+        if ch.is_alphanumeric() || ch == '_' {
+            word.push((line, column, ch));
+            continue;
+        }
+        flag_confusable_word(&word, &mut found);
+        word.clear();
+        if let Some(ascii) = fullwidth_ascii(ch) {
+            found.push(CharLocation {
+                ascii: Some(ascii),
+                ..CharLocation::new(line, column, ch)
+            });
+        }
+        if ch == '\n' {
+            line += 1;
+            column = 0;
+        }
+    }
+    found
+}
+
+fn flag_confusable_word(word: &[(usize, usize, char)], found: &mut Vec<CharLocation>) {
+    let mixed_with_ascii = word.iter().any(|(_, _, ch)| ch.is_ascii_alphabetic());
+    for &(line, column, ch) in word {
+        let ascii = match fullwidth_ascii(ch) {
+            Some(ascii) => Some(ascii),
+            None if mixed_with_ascii => confusable_ascii(ch),
+            None => None,
+        };
+        if let Some(ascii) = ascii {
+            found.push(CharLocation {
+                ascii: Some(ascii),
+                ..CharLocation::new(line, column, ch)
+            });
+        }
+    }
+}
+
+pub fn parse_char_mapping(spec: &str) -> Result<(char, String), String> {
+    let (from, to) = spec
+        .split_once('=')
+        .ok_or_else(|| format!("mapping '{spec}' must look like CHAR=REPLACEMENT"))?;
+    let from = if let Some(hex) = from.strip_prefix("U+").or_else(|| from.strip_prefix("u+")) {
+        u32::from_str_radix(hex, 16)
+            .ok()
+            .and_then(char::from_u32)
+            .ok_or_else(|| format!("invalid code point '{from}'"))?
+    } else {
+        let mut chars = from.chars();
+        match (chars.next(), chars.next()) {
+            (Some(ch), None) => ch,
+            _ => {
+                return Err(format!(
+                    "mapping source '{from}' must be a single character"
+                ));
+            }
+        }
+    };
+    Ok((from, to.to_string()))
+}
+
+fn punctuation_replacement(ch: char, custom: &[(char, String)]) -> Option<&str> {
+    if let Some((_, replacement)) = custom.iter().find(|(from, _)| *from == ch) {
+        return Some(replacement.as_str());
+    }
+    let replacement = match ch {
+        '\u{2018}' | '\u{2019}' | '\u{201A}' | '\u{201B}' | '\u{2032}' => "'",
+        '\u{201C}' | '\u{201D}' | '\u{201E}' | '\u{201F}' | '\u{2033}' => "\"",
+        '\u{2010}' | '\u{2011}' | '\u{2012}' | '\u{2013}' | '\u{2212}' => "-",
+        '\u{2014}' | '\u{2015}' => "--",
+        '\u{2026}' => "...",
+        '\u{00A0}' | '\u{2007}' | '\u{202F}' => " ",
+        _ => return None,
+    };
+    Some(replacement)
+}
+
+fn fullwidth_ascii(ch: char) -> Option<char> {
+    match ch {
+        '\u{FF01}'..='\u{FF5E}' => char::from_u32(ch as u32 - 0xFEE0),
+        '\u{3000}' => Some(' '),
+        _ => None,
+    }
+}
+
+fn confusable_ascii(ch: char) -> Option<char> {
+    let ascii = match ch {
+        'а' => 'a',
+        'с' => 'c',
+        'ԁ' => 'd',
+        'е' => 'e',
+        'һ' => 'h',
+        'і' => 'i',
+        'ј' => 'j',
+        'о' | 'ο' => 'o',
+        'р' | 'ρ' => 'p',
+        'ԛ' => 'q',
+        'ѕ' => 's',
+        'ν' => 'v',
+        'ԝ' => 'w',
+        'х' => 'x',
+        'у' => 'y',
+        'А' | 'Α' => 'A',
+        'В' | 'Β' => 'B',
+        'С' => 'C',
+        'Е' | 'Ε' => 'E',
+        'Н' | 'Η' => 'H',
+        'І' | 'Ι' => 'I',
+        'Ј' => 'J',
+        'К' | 'Κ' => 'K',
+        'М' | 'Μ' => 'M',
+        'Ν' => 'N',
+        'О' | 'Ο' => 'O',
+        'Р' | 'Ρ' => 'P',
+        'Ѕ' => 'S',
+        'Т' | 'Τ' => 'T',
+        'Х' | 'Χ' => 'X',
+        'У' | 'Υ' => 'Y',
+        'Ζ' => 'Z',
+        _ => return None,
+    };
+    Some(ascii)
+}
+
+fn is_bidi_control(ch: char) -> bool {
+    matches!(
+        ch,
+        '\u{061C}' | '\u{200E}' | '\u{200F}' | '\u{202A}'..='\u{202E}' | '\u{2066}'..='\u{2069}'
+    )
+}
+
 fn is_control_char(ch: char) -> bool {
     ch.is_control() && ch != '\n' && ch != '\t' && ch != '\r'
 }
 
+fn record_trailing_finding(original_line: &str, line: usize, findings: &mut Vec<Finding>) {
+    let body = original_line.strip_suffix('\r').unwrap_or(original_line);
+    let trailing = count_trailing_ws(body);
+    if trailing > 0 {
+        findings.push(Finding {
+            kind: "trailing-space",
+            line,
+            column: body.chars().count() - trailing + 1,
+            codepoint: None,
+            length: Some(trailing),
+        });
+    }
+}
+
 fn count_trailing_ws(line: &str) -> usize {
     line.chars()
         .rev()
@@ -127,6 +764,7 @@ fn flush_line(
     cleaned: &mut String,
     opts: &NormalizeOptions,
     trailing_spaces: Option<&mut usize>,
+    indentation: Option<&mut IndentSummary>,
     changed: &mut bool,
     append_newline: bool,
 ) {
@@ -148,6 +786,27 @@ fn flush_line(
         }
     }
 
+    if let Some(summary) = indentation {
+        let indent_len = line_buffer.len() - line_buffer.trim_start_matches([' ', '\t']).len();
+        let indent = &line_buffer[..indent_len];
+        let has_tab = indent.contains('\t');
+        let has_space = indent.contains(' ');
+        match (has_tab, has_space) {
+            (true, true) => summary.mixed += 1,
+            (true, false) => summary.tabs += 1,
+            (false, true) => summary.spaces += 1,
+            (false, false) => {}
+        }
+        if let Some(conversion) = opts.indent_conversion {
+            let converted = convert_indent(indent, conversion);
+            if converted != indent {
+                summary.converted += 1;
+                line_buffer.replace_range(..indent_len, &converted);
+                *changed = true;
+            }
+        }
+    }
+
     if had_cr {
         line_buffer.push('\r');
     }
@@ -165,16 +824,39 @@ fn flush_line(
 mod tests {
     use super::*;
 
+    #[test]
+    fn dominant_indent_width_uses_common_step() {
+        let text = "fn a() {\n    if x {\n        y();\n    }\n\n  odd\n}\n";
+        assert_eq!(dominant_indent_width(text), Some(4));
+        assert_eq!(dominant_indent_width("a\n\tb\n"), None);
+    }
+
     fn base_opts() -> NormalizeOptions {
         NormalizeOptions {
             strip_zero_width: false,
             strip_control: false,
+            strip_bidi: false,
+            map_confusables: false,
+            ascii_punctuation: false,
+            punctuation_map: Vec::new(),
+            strip_classes: Vec::new(),
             trim_trailing_space: false,
             ensure_eol: false,
+            indent_conversion: None,
+            max_blank_lines: None,
+            max_line_length: None,
+            wrap_long_lines: false,
             detect_zero_width: true,
             detect_control: true,
+            detect_bidi: true,
+            detect_confusables: true,
+            detect_punctuation: true,
             detect_trailing_space: true,
+            detect_indentation: true,
+            detect_line_endings: true,
             detect_final_newline: true,
+            record_locations: false,
+            unicode_form: None,
         }
     }
 
@@ -192,6 +874,198 @@ mod tests {
         assert_eq!(report.missing_final_newline, Some(true));
     }
 
+    #[test]
+    fn line_ending_counts_detect_mixed() {
+        let counts = count_line_endings("a\r\nb\nc\rd");
+        assert_eq!(
+            counts,
+            LineEndingCounts {
+                lf: 1,
+                crlf: 1,
+                cr: 1
+            }
+        );
+        assert_eq!(counts.label(), "mixed");
+        assert_eq!(count_line_endings("a\r\nb\r\n").label(), "crlf");
+    }
+
+    #[test]
+    fn unicode_form_composes_decomposed_lines() {
+        let mut opts = base_opts();
+        opts.unicode_form = Some(UnicodeForm::Nfc);
+        let outcome = normalize_text("cafe\u{301}\nplain\nnai\u{308}ve\n", &opts);
+        assert_eq!(outcome.report.unicode_form, Some(2));
+        assert_eq!(
+            outcome.cleaned,
+            Some("caf\u{e9}\nplain\nna\u{ef}ve\n".to_string())
+        );
+        let untouched = normalize_text("caf\u{e9}\n", &opts);
+        assert_eq!(untouched.report.unicode_form, Some(0));
+        assert!(untouched.cleaned.is_none());
+    }
+
+    #[test]
+    fn bidi_controls_reported_with_positions() {
+        let mut opts = base_opts();
+        opts.strip_bidi = true;
+        let outcome = normalize_text("ok\nif x \u{202E}} \u{2066}admin\n", &opts);
+        assert_eq!(outcome.report.bidi_controls, Some(2));
+        assert_eq!(
+            outcome.report.bidi_locations,
+            vec![
+                CharLocation::new(2, 6, '\u{202E}'),
+                CharLocation::new(2, 9, '\u{2066}'),
+            ]
+        );
+        assert_eq!(outcome.report.bidi_locations[0].codepoint, "U+202E");
+        assert_eq!(outcome.cleaned, Some("ok\nif x } admin\n".to_string()));
+    }
+
+    #[test]
+    fn confusables_flag_mixed_script_words_only() {
+        let mut opts = base_opts();
+        opts.map_confusables = true;
+        let outcome = normalize_text("let \u{430}dmin = 1\u{FF1B}\nпривет\n", &opts);
+        assert_eq!(outcome.report.confusables, Some(2));
+        let first = &outcome.report.confusable_locations[0];
+        assert_eq!((first.line, first.column, first.ascii), (1, 5, Some('a')));
+        assert_eq!(outcome.report.confusable_locations[1].ascii, Some(';'));
+        assert_eq!(
+            outcome.cleaned,
+            Some("let admin = 1;\nпривет\n".to_string())
+        );
+    }
+
+    #[test]
+    fn indentation_conversion_touches_leading_whitespace_only() {
+        let mut opts = base_opts();
+        opts.indent_conversion = Some(IndentConversion::TabsToSpaces(4));
+        let outcome = normalize_text("\tif x {\r\n  \treturn\ta;\r\n    }\r\n", &opts);
+        assert_eq!(
+            outcome.cleaned,
+            Some("    if x {\r\n    return\ta;\r\n    }\r\n".to_string())
+        );
+        let summary = outcome.report.indentation.expect("indentation summary");
+        assert_eq!((summary.tabs, summary.spaces, summary.mixed), (1, 1, 1));
+        assert_eq!(summary.converted, 2);
+        assert!(summary.is_inconsistent());
+
+        opts.indent_conversion = Some(IndentConversion::SpacesToTabs(4));
+        let outcome = normalize_text("      x\n", &opts);
+        assert_eq!(outcome.cleaned, Some("\t  x\n".to_string()));
+    }
+
+    #[test]
+    fn line_endings_reported_for_original_text() {
+        let report = normalize_text("a\r\nb\n", &base_opts()).report;
+        assert_eq!(report.line_endings.map(|eol| eol.label()), Some("mixed"));
+    }
+
+    #[test]
+    fn smart_punctuation_maps_to_ascii_with_overrides() {
+        let mut opts = base_opts();
+        opts.ascii_punctuation = true;
+        opts.punctuation_map = vec![parse_char_mapping("U+2014=-").expect("mapping")];
+        let outcome = normalize_text(
+            "\u{201C}Hi\u{201D}\u{00A0}\u{2014} it\u{2019}s\u{2026}\n",
+            &opts,
+        );
+        assert_eq!(outcome.report.smart_punctuation, Some(6));
+        assert_eq!(outcome.cleaned, Some("\"Hi\" - it's...\n".to_string()));
+        assert!(parse_char_mapping("ab=c").is_err());
+    }
+
+    #[test]
+    fn max_blank_lines_squeezes_runs() {
+        let mut opts = base_opts();
+        opts.max_blank_lines = Some(1);
+        let outcome = normalize_text("a\n\n\n \nb\r\n\r\n\r\nc\n", &opts);
+        assert_eq!(outcome.report.excess_blank_lines, Some(3));
+        assert_eq!(outcome.cleaned, Some("a\n\nb\r\n\r\nc\n".to_string()));
+    }
+
+    #[test]
+    fn locations_recorded_for_each_finding() {
+        let mut opts = base_opts();
+        opts.record_locations = true;
+        opts.strip_zero_width = true;
+        let report = normalize_text("a\u{200B}b \t\r\nx\u{7}\u{200B}y  ", &opts).report;
+        assert_eq!(
+            report.findings,
+            vec![
+                Finding::char("zero-width", 1, 2, '\u{200B}'),
+                Finding {
+                    kind: "trailing-space",
+                    line: 1,
+                    column: 4,
+                    codepoint: None,
+                    length: Some(2),
+                },
+                Finding::char("control", 2, 2, '\u{7}'),
+                Finding::char("zero-width", 2, 3, '\u{200B}'),
+                Finding {
+                    kind: "trailing-space",
+                    line: 2,
+                    column: 5,
+                    codepoint: None,
+                    length: Some(2),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn strip_classes_remove_and_count_matches() {
+        let mut opts = base_opts();
+        opts.strip_classes = vec![CharClass {
+            name: "arrows".to_string(),
+            ranges: vec![(0x2190, 0x21FF)],
+            categories: vec!["Sc".to_string()],
+        }];
+        let outcome = normalize_text("a \u{2192} b $5\n", &opts);
+        assert_eq!(outcome.report.strip_classes.get("arrows"), Some(&2));
+        assert_eq!(outcome.cleaned, Some("a  b 5\n".to_string()));
+    }
+
+    #[test]
+    fn long_lines_reported_and_wrapped() {
+        let mut opts = base_opts();
+        opts.max_line_length = Some(12);
+        let text = "short\r\n  alpha beta gamma delta\r\nunbreakablewordhere";
+        let report = normalize_text(text, &opts).report;
+        assert_eq!(report.long_lines, Some(2));
+        assert_eq!(report.long_line_locations[0].line, 2);
+        assert_eq!(report.long_line_locations[0].length, Some(24));
+
+        opts.wrap_long_lines = true;
+        let outcome = normalize_text(text, &opts);
+        assert_eq!(
+            outcome.cleaned,
+            Some(
+                "short\r\n  alpha beta\r\n  gamma\r\n  delta\r\nunbreakablew\r\nordhere"
+                    .to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn long_lines_are_located_in_the_input_after_other_cleanups() {
+        let mut opts = base_opts();
+        opts.max_line_length = Some(8);
+        opts.max_blank_lines = Some(0);
+        opts.strip_zero_width = true;
+        let text = "a\n\n\n\u{200b}b\nshort\nmuch too long\n";
+        let outcome = normalize_text(text, &opts);
+        assert_eq!(
+            outcome.cleaned.as_deref(),
+            Some("a\nb\nshort\nmuch too long\n")
+        );
+        let report = outcome.report;
+        assert_eq!(report.long_lines, Some(1));
+        assert_eq!(report.long_line_locations[0].line, 6);
+        assert_eq!(report.long_line_locations[0].length, Some(13));
+    }
+
     #[test]
     fn trim_trailing_space_handles_crlf() {
         let mut opts = base_opts();
@@ -1,9 +1,13 @@
+use std::fmt;
 use std::fs;
 use std::mem;
-use std::path::{Path, PathBuf};
+use std::path::{Component, Path, PathBuf};
 
 use anyhow::{Context, Result, anyhow, bail};
-use diffy::Patch;
+use clap::ValueEnum;
+use diffy::{Line, Patch};
+
+pub const DEFAULT_OFFSET_WINDOW: usize = 100;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum PatchKind {
@@ -23,15 +27,40 @@ pub struct FilePatch {
     pub new_path: Option<PathBuf>,
 }
 
+#[derive(Clone, Copy, Debug, ValueEnum, PartialEq, Eq, Default)]
+pub enum SplitBy {
+    #[default]
+    File,
+    Dir,
+}
+
+impl FilePatch {
+    pub fn path(&self) -> &Path {
+        self.new_path
+            .as_deref()
+            .or(self.old_path.as_deref())
+            .expect("classified patch has a path")
+    }
+}
+
 pub fn load_file_patches(path: &Path) -> Result<Vec<FilePatch>> {
     let raw =
         fs::read_to_string(path).with_context(|| format!("reading patch {}", path.display()))?;
-    let segments = split_segments(&raw)?;
+    parse_file_patches(&raw, path)
+}
+
+fn parse_file_patches(raw: &str, path: &Path) -> Result<Vec<FilePatch>> {
+    let segments = split_segments(raw)?;
     let mut patches = Vec::new();
     for (idx, segment) in segments.into_iter().enumerate() {
         Patch::from_str(&segment.body).map_err(|err| {
+            let hint = lint(raw)
+                .issues
+                .first()
+                .map(|issue| format!(" (line {}: {})", issue.line, issue.message))
+                .unwrap_or_default();
             anyhow!(
-                "failed to parse patch {} segment {}: {err}",
+                "failed to parse patch {} segment {}: {err}{hint}; run `safeedit patch lint` for details",
                 path.display(),
                 idx + 1
             )
@@ -56,6 +85,349 @@ pub fn load_file_patches(path: &Path) -> Result<Vec<FilePatch>> {
     Ok(patches)
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HunkOffset {
+    pub hunk: usize,
+    pub line: usize,
+    pub offset: isize,
+}
+
+#[derive(Debug)]
+pub struct HunkMismatch {
+    pub hunk: usize,
+    pub line: usize,
+    pub window: usize,
+}
+
+impl fmt::Display for HunkMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "hunk {} does not match within {} line(s) of line {}",
+            self.hunk, self.window, self.line
+        )
+    }
+}
+
+// Applies hunks in order, looking for each hunk's context up to `window`
+// lines either side of where its header (plus the drift seen so far) puts it.
+// Returns the patched text and the hunks that landed somewhere other than
+// their header line.
+pub fn apply_with_offsets(
+    text: &str,
+    patch: &Patch<'_, str>,
+    window: usize,
+) -> Result<(String, Vec<HunkOffset>), HunkMismatch> {
+    let mut image: Vec<(&str, bool)> = text
+        .split_inclusive('\n')
+        .map(|line| (line, false))
+        .collect();
+    let mut offsets = Vec::new();
+    let mut drift = 0isize;
+    for (idx, hunk) in patch.hunks().iter().enumerate() {
+        let pre: Vec<&str> = hunk
+            .lines()
+            .iter()
+            .filter_map(|line| match line {
+                Line::Context(text) | Line::Delete(text) => Some(*text),
+                Line::Insert(_) => None,
+            })
+            .collect();
+        let expected = hunk.new_range().start().saturating_sub(1);
+        let center = expected as isize + drift;
+        let matches_at = |pos: isize| -> bool {
+            let Ok(pos) = usize::try_from(pos) else {
+                return false;
+            };
+            image.get(pos..pos + pre.len()).is_some_and(|slice| {
+                slice
+                    .iter()
+                    .zip(&pre)
+                    .all(|((line, patched), wanted)| !patched && line == wanted)
+            })
+        };
+        let found = (0..=window as isize)
+            .flat_map(|step| [center - step, center + step])
+            .find(|pos| matches_at(*pos))
+            .ok_or(HunkMismatch {
+                hunk: idx + 1,
+                line: expected + 1,
+                window,
+            })?;
+        let pos = found as usize;
+        drift = found - expected as isize;
+        if drift != 0 {
+            offsets.push(HunkOffset {
+                hunk: idx + 1,
+                line: pos + 1,
+                offset: drift,
+            });
+        }
+        let post = hunk.lines().iter().filter_map(|line| match line {
+            Line::Context(text) | Line::Insert(text) => Some((*text, true)),
+            Line::Delete(_) => None,
+        });
+        image.splice(pos..pos + pre.len(), post);
+    }
+    Ok((image.into_iter().map(|(line, _)| line).collect(), offsets))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LintIssue {
+    pub line: usize,
+    pub message: String,
+}
+
+#[derive(Debug, Default)]
+pub struct LintReport {
+    pub segments: usize,
+    pub hunks: usize,
+    pub issues: Vec<LintIssue>,
+}
+
+struct OpenHunk {
+    line: usize,
+    old_expected: usize,
+    new_expected: usize,
+    old_seen: usize,
+    new_seen: usize,
+}
+
+impl OpenHunk {
+    fn complete(&self) -> bool {
+        self.old_seen >= self.old_expected && self.new_seen >= self.new_expected
+    }
+
+    fn short_issue(&self) -> LintIssue {
+        LintIssue {
+            line: self.line,
+            message: format!(
+                "hunk declares {} old/{} new line(s) but only {}/{} follow",
+                self.old_expected, self.new_expected, self.old_seen, self.new_seen
+            ),
+        }
+    }
+}
+
+// Checks the structure apply relies on (labels, hunk headers, and the line
+// counts those headers declare) and reports problems by 1-based line number.
+pub fn lint(raw: &str) -> LintReport {
+    let mut report = LintReport::default();
+    let mut crlf_lines = Vec::new();
+    let mut pending_old: Option<usize> = None;
+    let mut in_segment = false;
+    let mut segment_start = 0usize;
+    let mut segment_hunks = 0usize;
+    let mut hunk: Option<OpenHunk> = None;
+
+    for (idx, chunk) in raw.split_inclusive('\n').enumerate() {
+        let number = idx + 1;
+        if chunk.ends_with("\r\n") {
+            crlf_lines.push(number);
+        }
+        let line = chunk.trim_end_matches('\n').trim_end_matches('\r');
+
+        if let Some(open) = hunk.as_mut() {
+            if !open.complete() {
+                let counted = match line.chars().next() {
+                    Some(' ') | None => {
+                        open.old_seen += 1;
+                        open.new_seen += 1;
+                        true
+                    }
+                    Some('-') if open.old_seen < open.old_expected => {
+                        open.old_seen += 1;
+                        true
+                    }
+                    Some('+') if open.new_seen < open.new_expected => {
+                        open.new_seen += 1;
+                        true
+                    }
+                    Some('\\') => true,
+                    _ => false,
+                };
+                if counted {
+                    if open.old_seen > open.old_expected || open.new_seen > open.new_expected {
+                        report.issues.push(LintIssue {
+                            line: number,
+                            message: format!(
+                                "context line overruns the hunk at line {} ({} old/{} new declared)",
+                                open.line, open.old_expected, open.new_expected
+                            ),
+                        });
+                    }
+                    continue;
+                }
+                report.issues.push(open.short_issue());
+                hunk = None;
+            } else if line.starts_with('\\') {
+                continue;
+            } else if matches!(line.chars().next(), Some(' ' | '+'))
+                || (line.starts_with('-') && !line.starts_with("--- "))
+            {
+                report.issues.push(LintIssue {
+                    line: number,
+                    message: format!(
+                        "extra line after the hunk at line {} ({} old/{} new declared)",
+                        open.line, open.old_expected, open.new_expected
+                    ),
+                });
+                continue;
+            } else {
+                hunk = None;
+            }
+        }
+
+        let after_old = pending_old.take();
+        if let Some(old_line) = after_old
+            && !line.starts_with("+++ ")
+        {
+            report.issues.push(LintIssue {
+                line: old_line,
+                message: "--- label is not followed by a +++ label".into(),
+            });
+        }
+
+        if line.starts_with("diff --") {
+            continue;
+        }
+        if line.starts_with("--- ") {
+            if in_segment && segment_hunks == 0 {
+                report.issues.push(LintIssue {
+                    line: segment_start,
+                    message: "file segment has no hunks".into(),
+                });
+            }
+            pending_old = Some(number);
+            in_segment = false;
+            continue;
+        }
+        if line.starts_with("+++ ") {
+            if after_old.is_none() {
+                report.issues.push(LintIssue {
+                    line: number,
+                    message: "+++ label without a preceding --- label".into(),
+                });
+            }
+            report.segments += 1;
+            in_segment = true;
+            segment_start = after_old.unwrap_or(number);
+            segment_hunks = 0;
+            continue;
+        }
+        if line.starts_with("@@") {
+            if !in_segment {
+                report.issues.push(LintIssue {
+                    line: number,
+                    message: "hunk appears before any ---/+++ file labels".into(),
+                });
+                continue;
+            }
+            match parse_hunk_header(line) {
+                Some((old_expected, new_expected)) => {
+                    report.hunks += 1;
+                    segment_hunks += 1;
+                    hunk = Some(OpenHunk {
+                        line: number,
+                        old_expected,
+                        new_expected,
+                        old_seen: 0,
+                        new_seen: 0,
+                    });
+                }
+                None => report.issues.push(LintIssue {
+                    line: number,
+                    message: format!("malformed hunk header '{line}' (expected @@ -A,B +C,D @@)"),
+                }),
+            }
+        }
+    }
+
+    if let Some(open) = hunk
+        && !open.complete()
+    {
+        report.issues.push(open.short_issue());
+    }
+    if let Some(old_line) = pending_old {
+        report.issues.push(LintIssue {
+            line: old_line,
+            message: "--- label is not followed by a +++ label".into(),
+        });
+    }
+    if in_segment && segment_hunks == 0 {
+        report.issues.push(LintIssue {
+            line: segment_start,
+            message: "file segment has no hunks".into(),
+        });
+    }
+    if let Some(first) = crlf_lines.first() {
+        report.issues.push(LintIssue {
+            line: *first,
+            message: format!(
+                "CRLF line endings on {} line(s); patches should use LF (SafeEdit restores each file's own endings)",
+                crlf_lines.len()
+            ),
+        });
+    }
+    report.issues.sort_by_key(|issue| issue.line);
+    report
+}
+
+// Returns the old and new line counts from `@@ -A[,B] +C[,D] @@`.
+fn parse_hunk_header(line: &str) -> Option<(usize, usize)> {
+    let rest = line.strip_prefix("@@ -")?;
+    let (ranges, _) = rest.split_once(" @@")?;
+    let (old, new) = ranges.split_once(" +")?;
+    let count = |range: &str| -> Option<usize> {
+        match range.split_once(',') {
+            Some((start, len)) => {
+                start.parse::<usize>().ok()?;
+                len.parse().ok()
+            }
+            None => range.parse::<usize>().ok().map(|_| 1),
+        }
+    };
+    Some((count(old)?, count(new)?))
+}
+
+// Groups segments into output files named after each file (numbered to keep
+// the original order) or after its parent directory.
+pub fn split_pieces(patches: &[FilePatch], by: SplitBy) -> Vec<(String, String)> {
+    let mut pieces: Vec<(String, String)> = Vec::new();
+    for patch in patches {
+        let path = patch.path();
+        let name = match by {
+            SplitBy::File => format!("{:03}-{}.diff", pieces.len() + 1, flatten_path(path)),
+            SplitBy::Dir => match path.parent().map(flatten_path) {
+                Some(dir) if !dir.is_empty() => format!("{dir}.diff"),
+                _ => "root.diff".to_string(),
+            },
+        };
+        match pieces.iter_mut().find(|(existing, _)| *existing == name) {
+            Some((_, body)) => {
+                if !body.ends_with('\n') {
+                    body.push('\n');
+                }
+                body.push_str(&patch.patch_text);
+            }
+            None => pieces.push((name, patch.patch_text.clone())),
+        }
+    }
+    pieces
+}
+
+// Only plain names survive, so labels such as `/etc/app.conf` or `../x` from a
+// plain `diff -u` cannot name a file outside the output directory.
+fn flatten_path(path: &Path) -> String {
+    path.components()
+        .filter_map(|part| match part {
+            Component::Normal(name) => Some(name.to_string_lossy()),
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join("__")
+}
+
 struct Segment {
     old_label: String,
     new_label: String,
@@ -99,12 +471,12 @@ fn split_segments(text: &str) -> Result<Vec<Segment>> {
             continue;
         }
 
-        if new_label.is_none() {
-            if let Some(rest) = trimmed.strip_prefix("+++ ") {
-                new_label = Some(rest.trim().to_string());
-                buffer.push_str(&cleaned);
-                continue;
-            }
+        if new_label.is_none()
+            && let Some(rest) = trimmed.strip_prefix("+++ ")
+        {
+            new_label = Some(rest.trim().to_string());
+            buffer.push_str(&cleaned);
+            continue;
         }
 
         buffer.push_str(&cleaned);
@@ -244,6 +616,131 @@ index 0000000..3333333
         );
     }
 
+    #[test]
+    fn split_pieces_group_by_file_or_directory() {
+        let text = "\
+--- a/src/main.rs
++++ b/src/main.rs
+@@ -1 +1 @@
+-a
++b
+--- a/README.md
++++ b/README.md
+@@ -1 +1 @@
+-c
++d
+--- /dev/null
++++ b/src/lib.rs
+@@ -0,0 +1 @@
++e
+";
+        let patches = parse_file_patches(text, Path::new("big.diff")).expect("patches");
+        let by_file = split_pieces(&patches, SplitBy::File);
+        let names: Vec<_> = by_file.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(
+            names,
+            [
+                "001-src__main.rs.diff",
+                "002-README.md.diff",
+                "003-src__lib.rs.diff"
+            ]
+        );
+        let by_dir = split_pieces(&patches, SplitBy::Dir);
+        assert_eq!(by_dir.len(), 2);
+        assert_eq!(by_dir[0].0, "src.diff");
+        assert!(
+            by_dir[0].1.contains("+++ b/src/main.rs") && by_dir[0].1.contains("+++ b/src/lib.rs")
+        );
+        assert_eq!(by_dir[1].0, "root.diff");
+    }
+
+    #[test]
+    fn split_pieces_keep_absolute_labels_inside_the_output_dir() {
+        let text = "\
+--- /etc/app/conf
++++ /etc/app/conf
+@@ -1 +1 @@
+-a
++b
+--- /motd
++++ /motd
+@@ -1 +1 @@
+-c
++d
+--- ../outside/x.txt
++++ ../outside/x.txt
+@@ -1 +1 @@
+-e
++f
+";
+        let patches = parse_file_patches(text, Path::new("plain.diff")).expect("patches");
+        let by_file = split_pieces(&patches, SplitBy::File);
+        let names: Vec<_> = by_file.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(
+            names,
+            [
+                "001-etc__app__conf.diff",
+                "002-motd.diff",
+                "003-outside__x.txt.diff"
+            ]
+        );
+        let by_dir = split_pieces(&patches, SplitBy::Dir);
+        let names: Vec<_> = by_dir.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["etc__app.diff", "root.diff", "outside.diff"]);
+    }
+
+    #[test]
+    fn lint_reports_problems_by_line() {
+        let clean = "--- a/x\n+++ b/x\n@@ -1,2 +1,2 @@\n keep\n-old\n+new\n";
+        let report = lint(clean);
+        assert!(report.issues.is_empty(), "{:?}", report.issues);
+        assert_eq!((report.segments, report.hunks), (1, 1));
+
+        let broken = "\
+--- a/x
+@@ -1 +1 @@
+--- a/y\r
++++ b/y
+@@ -1,3 +1,2 @@
+ keep
+-old
+@@ nonsense @@
+";
+        let issues: Vec<_> = lint(broken)
+            .issues
+            .into_iter()
+            .map(|issue| (issue.line, issue.message))
+            .collect();
+        assert_eq!(issues.len(), 5, "{issues:?}");
+        assert!(issues[0].0 == 1 && issues[0].1.contains("not followed by a +++"));
+        assert!(issues[1].0 == 2 && issues[1].1.contains("before any"));
+        assert!(issues[2].0 == 3 && issues[2].1.contains("CRLF"));
+        assert!(issues[3].0 == 5 && issues[3].1.contains("3 old/2 new"));
+        assert!(issues[4].0 == 8 && issues[4].1.contains("malformed"));
+    }
+
+    #[test]
+    fn hunks_apply_at_an_offset_within_the_window() {
+        let patch_text = "--- a/x\n+++ b/x\n@@ -2,2 +2,2 @@\n two\n-three\n+THREE\n";
+        let patch = Patch::from_str(patch_text).expect("patch");
+        let shifted = "new a\nnew b\none\ntwo\nthree\nfour\n";
+        let (patched, offsets) = apply_with_offsets(shifted, &patch, 5).expect("apply");
+        assert_eq!(patched, "new a\nnew b\none\ntwo\nTHREE\nfour\n");
+        assert_eq!(
+            offsets,
+            [HunkOffset {
+                hunk: 1,
+                line: 4,
+                offset: 2
+            }]
+        );
+        let err = apply_with_offsets(shifted, &patch, 1).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "hunk 1 does not match within 1 line(s) of line 2"
+        );
+    }
+
     #[test]
     fn label_to_path_strips_prefixes() {
         let path = label_to_path("a/src/main.rs").expect("path");
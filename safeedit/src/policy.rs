@@ -0,0 +1,110 @@
+use std::fs;
+use std::path::Path;
+
+use anyhow::{Context, Result, anyhow};
+use globset::{Glob, GlobMatcher};
+use serde::Deserialize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum PolicyDecision {
+    Apply,
+    Skip,
+    #[default]
+    Prompt,
+}
+
+#[derive(Debug, Deserialize)]
+struct PolicyFile {
+    #[serde(default)]
+    default: PolicyDecision,
+    #[serde(default)]
+    rules: Vec<PolicyRule>,
+}
+
+#[derive(Debug, Deserialize)]
+struct PolicyRule {
+    glob: String,
+    decision: PolicyDecision,
+}
+
+#[derive(Debug)]
+pub struct ApprovalPolicy {
+    default: PolicyDecision,
+    rules: Vec<(String, GlobMatcher, PolicyDecision)>,
+}
+
+impl ApprovalPolicy {
+    pub fn load(path: &Path) -> Result<Self> {
+        let raw = fs::read_to_string(path)
+            .with_context(|| format!("reading approval policy {}", path.display()))?;
+        Self::parse(&raw).with_context(|| format!("parsing approval policy {}", path.display()))
+    }
+
+    fn parse(raw: &str) -> Result<Self> {
+        let file: PolicyFile = toml::from_str(raw)?;
+        let rules = file
+            .rules
+            .into_iter()
+            .map(|rule| {
+                let matcher = Glob::new(&rule.glob)
+                    .map_err(|err| anyhow!("invalid policy glob '{}': {err}", rule.glob))?
+                    .compile_matcher();
+                Ok((rule.glob, matcher, rule.decision))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self {
+            default: file.default,
+            rules,
+        })
+    }
+
+    // Rules are checked in file order against the path relative to `root`
+    // (falling back to the path as given); the first match wins.
+    pub fn decide(&self, path: &Path, root: &Path) -> (PolicyDecision, Option<&str>) {
+        let relative = path.strip_prefix(root).unwrap_or(path);
+        let relative = relative.to_string_lossy().replace('\\', "/");
+        for (glob, matcher, decision) in &self.rules {
+            if matcher.is_match(&relative) || matcher.is_match(path) {
+                return (*decision, Some(glob));
+            }
+        }
+        (self.default, None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn first_matching_rule_decides() {
+        let policy = ApprovalPolicy::parse(
+            r#"
+default = "skip"
+
+[[rules]]
+glob = "docs/**"
+decision = "apply"
+
+[[rules]]
+glob = "**/*.md"
+decision = "prompt"
+"#,
+        )
+        .unwrap();
+        let root = Path::new("/work");
+        assert_eq!(
+            policy.decide(Path::new("/work/docs/guide.md"), root),
+            (PolicyDecision::Apply, Some("docs/**"))
+        );
+        assert_eq!(
+            policy.decide(Path::new("/work/README.md"), root),
+            (PolicyDecision::Prompt, Some("**/*.md"))
+        );
+        assert_eq!(
+            policy.decide(Path::new("/work/src/lib.rs"), root),
+            (PolicyDecision::Skip, None)
+        );
+    }
+}
@@ -0,0 +1,234 @@
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+use anyhow::{Context, Result, anyhow, bail};
+
+pub const SSH_ENV: &str = "SAFEEDIT_SSH";
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RemotePath {
+    pub destination: String,
+    pub port: Option<u16>,
+    pub path: String,
+}
+
+impl RemotePath {
+    // `ssh://[user@]host[:port]/path`; `/~/path` is relative to the remote home.
+    pub fn parse_url(raw: &str) -> Result<Option<Self>> {
+        let Some(rest) = raw.strip_prefix("ssh://") else {
+            return Ok(None);
+        };
+        let (authority, path) = rest
+            .split_once('/')
+            .ok_or_else(|| anyhow!("'{raw}' has no path after the host"))?;
+        let (destination, port) = match authority.rsplit_once(':') {
+            Some((host, port)) => (
+                host,
+                Some(
+                    port.parse()
+                        .map_err(|_| anyhow!("invalid port '{port}' in '{raw}'"))?,
+                ),
+            ),
+            None => (authority, None),
+        };
+        let path = match path.strip_prefix('~') {
+            Some(home) => format!("~{home}"),
+            None => format!("/{path}"),
+        };
+        Self::new(destination, port, &path).map(Some)
+    }
+
+    // `--remote user@host[:port]` applied to a plain target path.
+    pub fn with_remote(remote: &str, path: &str) -> Result<Self> {
+        let remote = remote.strip_suffix(':').unwrap_or(remote);
+        let (destination, port) = match remote.rsplit_once(':') {
+            Some((host, port)) if port.chars().all(|ch| ch.is_ascii_digit()) => (
+                host,
+                Some(
+                    port.parse()
+                        .map_err(|_| anyhow!("invalid port '{port}' in --remote"))?,
+                ),
+            ),
+            _ => (remote, None),
+        };
+        Self::new(destination, port, path)
+    }
+
+    fn new(destination: &str, port: Option<u16>, path: &str) -> Result<Self> {
+        if destination.is_empty() || destination.starts_with('-') {
+            bail!("invalid remote host '{destination}'");
+        }
+        if path.is_empty() || path == "/" {
+            bail!("remote target needs a file path");
+        }
+        Ok(RemotePath {
+            destination: destination.to_string(),
+            port,
+            path: path.to_string(),
+        })
+    }
+
+    // The `ssh://` form that parse_url reads back; relative paths are anchored
+    // at the remote home, where ssh starts.
+    pub fn url(&self) -> String {
+        let port = self.port.map(|port| format!(":{port}")).unwrap_or_default();
+        let path = match self.path.strip_prefix('/') {
+            Some(absolute) => absolute.to_string(),
+            None if self.path.starts_with("~/") => self.path.clone(),
+            None => format!("~/{}", self.path),
+        };
+        format!("ssh://{}{port}/{path}", self.destination)
+    }
+
+    // A file that does not exist yet reads as empty so `write` can create it.
+    pub fn fetch(&self) -> Result<Vec<u8>> {
+        let path = shell_quote(&self.path);
+        let script = format!("[ ! -e {path} ] || cat -- {path}");
+        let output = self
+            .ssh(&script)
+            .stdin(Stdio::null())
+            .output()
+            .with_context(|| format!("running ssh for {self}"))?;
+        if !output.status.success() {
+            bail!(
+                "fetching {self} failed ({}): {}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        Ok(output.stdout)
+    }
+
+    // Writes next to the target and renames over it, copying the original's
+    // mode first so the remote file keeps its permissions; a missing target is
+    // simply created.
+    pub fn upload(&self, data: &[u8]) -> Result<()> {
+        let script = upload_script(&self.path);
+        let mut child = self
+            .ssh(&script)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()
+            .with_context(|| format!("running ssh for {self}"))?;
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin
+                .write_all(data)
+                .with_context(|| format!("sending {self}"))?;
+        }
+        let output = child.wait_with_output()?;
+        if !output.status.success() {
+            bail!(
+                "uploading {self} failed ({}): {}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        Ok(())
+    }
+
+    fn ssh(&self, script: &str) -> Command {
+        let mut parts = ssh_command();
+        let mut command = Command::new(parts.remove(0));
+        command.args(parts);
+        if let Some(port) = self.port {
+            command.arg("-p").arg(port.to_string());
+        }
+        command.arg("--").arg(&self.destination).arg(script);
+        command
+    }
+}
+
+impl std::fmt::Display for RemotePath {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.port {
+            Some(port) => write!(
+                f,
+                "ssh://{}:{port}/{}",
+                self.destination,
+                self.path.trim_start_matches('/')
+            ),
+            None => write!(f, "{}:{}", self.destination, self.path),
+        }
+    }
+}
+
+fn ssh_command() -> Vec<String> {
+    let parts: Vec<String> = std::env::var(SSH_ENV)
+        .unwrap_or_default()
+        .split_whitespace()
+        .map(str::to_string)
+        .collect();
+    if parts.is_empty() {
+        vec!["ssh".to_string()]
+    } else {
+        parts
+    }
+}
+
+fn upload_script(path: &str) -> String {
+    let target = shell_quote(path);
+    let temp = shell_quote(&format!("{path}.safeedit-tmp"));
+    format!(
+        "if [ -e {target} ]; then cp -p -- {target} {temp}; else : > {temp}; fi && cat > {temp} && mv -f -- {temp} {target} || {{ rm -f -- {temp}; exit 1; }}"
+    )
+}
+
+fn shell_quote(path: &str) -> String {
+    let quote = |raw: &str| format!("'{}'", raw.replace('\'', r"'\''"));
+    match path.strip_prefix("~/") {
+        Some(rest) => format!("~/{}", quote(rest)),
+        None => quote(path),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ssh_urls_and_remote_prefixes_parse() {
+        let url = RemotePath::parse_url("ssh://deploy@web1:2222/etc/app.conf")
+            .unwrap()
+            .unwrap();
+        assert_eq!(url.destination, "deploy@web1");
+        assert_eq!(url.port, Some(2222));
+        assert_eq!(url.path, "/etc/app.conf");
+        let home = RemotePath::parse_url("ssh://web1/~/app.conf")
+            .unwrap()
+            .unwrap();
+        assert_eq!(home.path, "~/app.conf");
+        assert!(RemotePath::parse_url("local/app.conf").unwrap().is_none());
+        assert!(RemotePath::parse_url("ssh://web1").is_err());
+
+        let prefixed = RemotePath::with_remote("deploy@web1:", "conf/app.toml").unwrap();
+        assert_eq!(prefixed.to_string(), "deploy@web1:conf/app.toml");
+        assert!(RemotePath::with_remote("-oProxyCommand=x", "a").is_err());
+    }
+
+    #[test]
+    fn remote_paths_are_single_quoted() {
+        assert_eq!(shell_quote("/etc/it's.conf"), r"'/etc/it'\''s.conf'");
+        assert_eq!(shell_quote("~/app.conf"), "~/'app.conf'");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn upload_script_creates_missing_targets() {
+        use std::io::Write;
+        use std::process::{Command, Stdio};
+
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("new.conf");
+        let mut child = Command::new("sh")
+            .arg("-c")
+            .arg(upload_script(&target.to_string_lossy()))
+            .stdin(Stdio::piped())
+            .spawn()
+            .unwrap();
+        child.stdin.take().unwrap().write_all(b"fresh\n").unwrap();
+        assert!(child.wait().unwrap().success());
+        assert_eq!(std::fs::read(&target).unwrap(), b"fresh\n");
+        assert!(!dir.path().join("new.conf.safeedit-tmp").exists());
+    }
+}
@@ -0,0 +1,466 @@
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+
+use anyhow::{Result, anyhow};
+use clap::ValueEnum;
+use serde_json::json;
+
+use crate::logging::LoggedEntry;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReportOutput {
+    Table,
+    Json,
+    Csv,
+    Markdown,
+    Html,
+}
+
+impl ReportOutput {
+    pub fn from_str(value: &str) -> Result<Self> {
+        match value.to_lowercase().as_str() {
+            "table" => Ok(Self::Table),
+            "json" => Ok(Self::Json),
+            "csv" => Ok(Self::Csv),
+            "markdown" | "md" => Ok(Self::Markdown),
+            "html" => Ok(Self::Html),
+            other => Err(anyhow!(
+                "unsupported report format '{other}' (expected table, json, csv, markdown, or html)"
+            )),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum, PartialEq, Eq)]
+pub enum GroupBy {
+    Command,
+    File,
+}
+
+pub struct FileChurn {
+    pub path: String,
+    pub entries: usize,
+    pub applied: usize,
+    pub lines: usize,
+    pub last: String,
+}
+
+pub struct DayRow {
+    pub day: String,
+    pub entries: usize,
+    pub applied: usize,
+    pub lines: usize,
+}
+
+pub struct ReportData {
+    pub window: String,
+    pub entries: usize,
+    pub applied: usize,
+    pub lines_touched: usize,
+    pub summary: Vec<(String, String, usize)>,
+    pub days: Vec<DayRow>,
+    pub files: Vec<FileChurn>,
+}
+
+impl ReportData {
+    pub fn build(entries: &[LoggedEntry], window: &str) -> Self {
+        let mut summary: BTreeMap<(String, String), usize> = BTreeMap::new();
+        let mut days: BTreeMap<String, DayRow> = BTreeMap::new();
+        let mut applied = 0;
+        let mut lines_touched = 0;
+        for entry in entries {
+            *summary
+                .entry((entry.command.clone(), entry.action.clone()))
+                .or_default() += 1;
+            let day = entry.timestamp.get(..10).unwrap_or(&entry.timestamp);
+            let row = days.entry(day.to_string()).or_insert_with(|| DayRow {
+                day: day.to_string(),
+                entries: 0,
+                applied: 0,
+                lines: 0,
+            });
+            row.entries += 1;
+            if is_applied(entry) {
+                let lines = lines_in(entry);
+                row.applied += 1;
+                row.lines += lines;
+                applied += 1;
+                lines_touched += lines;
+            }
+        }
+        Self {
+            window: window.to_string(),
+            entries: entries.len(),
+            applied,
+            lines_touched,
+            summary: summary
+                .into_iter()
+                .map(|((command, action), count)| (command, action, count))
+                .collect(),
+            days: days.into_values().collect(),
+            files: rank_files(entries),
+        }
+    }
+}
+
+fn is_applied(entry: &LoggedEntry) -> bool {
+    entry.action.starts_with("applied")
+}
+
+fn lines_in(entry: &LoggedEntry) -> usize {
+    entry
+        .spans
+        .iter()
+        .map(|span| span.end.saturating_sub(span.start) + 1)
+        .sum()
+}
+
+pub fn rank_files(entries: &[LoggedEntry]) -> Vec<FileChurn> {
+    let mut by_path: BTreeMap<String, FileChurn> = BTreeMap::new();
+    for entry in entries {
+        let path = entry.absolute_path().to_string_lossy().into_owned();
+        let churn = by_path.entry(path.clone()).or_insert_with(|| FileChurn {
+            path,
+            entries: 0,
+            applied: 0,
+            lines: 0,
+            last: String::new(),
+        });
+        churn.entries += 1;
+        if is_applied(entry) {
+            churn.applied += 1;
+            churn.lines += lines_in(entry);
+        }
+        if entry.timestamp > churn.last {
+            churn.last = entry.timestamp.clone();
+        }
+    }
+    let mut ranked: Vec<_> = by_path.into_values().collect();
+    ranked.sort_by(|a, b| {
+        b.applied
+            .cmp(&a.applied)
+            .then(b.entries.cmp(&a.entries))
+            .then(a.path.cmp(&b.path))
+    });
+    ranked
+}
+
+pub fn render(
+    data: &ReportData,
+    format: ReportOutput,
+    group_by: GroupBy,
+    top: usize,
+) -> Result<String> {
+    let files = &data.files[..data.files.len().min(top)];
+    let hot: Vec<&FileChurn> = data
+        .files
+        .iter()
+        .filter(|churn| churn.applied > 0)
+        .take(top)
+        .collect();
+    let mut out = String::new();
+    match format {
+        ReportOutput::Table => match group_by {
+            GroupBy::File => {
+                writeln!(
+                    out,
+                    "Report entries: {} across {} file(s) (since {})",
+                    data.entries,
+                    data.files.len(),
+                    data.window
+                )?;
+                writeln!(
+                    out,
+                    "{:>7} {:>7}  {:<32} file",
+                    "applied", "entries", "last"
+                )?;
+                for churn in files {
+                    writeln!(
+                        out,
+                        "{:>7} {:>7}  {:<32} {}",
+                        churn.applied, churn.entries, churn.last, churn.path
+                    )?;
+                }
+            }
+            GroupBy::Command => {
+                writeln!(
+                    out,
+                    "Report entries: {} (since {}); {} applied, {} line(s) touched",
+                    data.entries, data.window, data.applied, data.lines_touched
+                )?;
+                for (command, action, count) in &data.summary {
+                    writeln!(out, "{command:<12} {action:<10} {count}")?;
+                }
+                if !hot.is_empty() {
+                    writeln!(out, "\nMost modified files:")?;
+                    for churn in hot {
+                        writeln!(out, "{:>5}  {}", churn.applied, churn.path)?;
+                    }
+                }
+            }
+        },
+        ReportOutput::Json => {
+            let rows: Vec<_> = match group_by {
+                GroupBy::File => files
+                    .iter()
+                    .map(|churn| {
+                        json!({
+                            "path": churn.path,
+                            "applied": churn.applied,
+                            "entries": churn.entries,
+                            "lines": churn.lines,
+                            "last": churn.last,
+                        })
+                    })
+                    .collect(),
+                GroupBy::Command => data
+                    .summary
+                    .iter()
+                    .map(|(command, action, count)| {
+                        json!({
+                            "command": command,
+                            "action": action,
+                            "count": count
+                        })
+                    })
+                    .collect(),
+            };
+            writeln!(out, "{}", serde_json::to_string(&rows)?)?;
+        }
+        ReportOutput::Csv => match group_by {
+            GroupBy::File => {
+                writeln!(out, "path,applied,entries,lines_touched,last")?;
+                for churn in files {
+                    writeln!(
+                        out,
+                        "{},{},{},{},{}",
+                        csv_field(&churn.path),
+                        churn.applied,
+                        churn.entries,
+                        churn.lines,
+                        churn.last
+                    )?;
+                }
+            }
+            GroupBy::Command => {
+                writeln!(out, "date,entries,applied,lines_touched")?;
+                for day in &data.days {
+                    writeln!(
+                        out,
+                        "{},{},{},{}",
+                        day.day, day.entries, day.applied, day.lines
+                    )?;
+                }
+            }
+        },
+        ReportOutput::Markdown => {
+            writeln!(out, "## SafeEdit report\n")?;
+            writeln!(
+                out,
+                "{} log entries since {}: **{} applied**, **{} line(s) touched** across {} file(s).\n",
+                data.entries,
+                data.window,
+                data.applied,
+                data.lines_touched,
+                data.files.len()
+            )?;
+            if group_by == GroupBy::Command {
+                writeln!(out, "| Command | Action | Count |\n| --- | --- | ---: |")?;
+                for (command, action, count) in &data.summary {
+                    writeln!(
+                        out,
+                        "| {} | {} | {count} |",
+                        markdown_cell(command),
+                        markdown_cell(action)
+                    )?;
+                }
+                writeln!(
+                    out,
+                    "\n### Per day\n\n| Date | Entries | Applied | Lines touched |\n| --- | ---: | ---: | ---: |"
+                )?;
+                for day in &data.days {
+                    writeln!(
+                        out,
+                        "| {} | {} | {} | {} |",
+                        day.day, day.entries, day.applied, day.lines
+                    )?;
+                }
+                writeln!(out)?;
+            }
+            let rows: Vec<&FileChurn> = match group_by {
+                GroupBy::File => files.iter().collect(),
+                GroupBy::Command => hot,
+            };
+            if !rows.is_empty() {
+                writeln!(
+                    out,
+                    "### Most modified files\n\n| File | Applied | Entries | Lines touched | Last change |\n| --- | ---: | ---: | ---: | --- |"
+                )?;
+                for churn in rows {
+                    writeln!(
+                        out,
+                        "| `{}` | {} | {} | {} | {} |",
+                        churn.path.replace('`', "'"),
+                        churn.applied,
+                        churn.entries,
+                        churn.lines,
+                        churn.last
+                    )?;
+                }
+            }
+        }
+        ReportOutput::Html => {
+            writeln!(
+                out,
+                "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>SafeEdit report</title></head>\n<body>\n<h1>SafeEdit report</h1>"
+            )?;
+            writeln!(
+                out,
+                "<p>{} log entries since {}: <strong>{} applied</strong>, <strong>{} line(s) touched</strong> across {} file(s).</p>",
+                data.entries,
+                html_escape(&data.window),
+                data.applied,
+                data.lines_touched,
+                data.files.len()
+            )?;
+            if group_by == GroupBy::Command {
+                writeln!(
+                    out,
+                    "<table>\n<tr><th>Command</th><th>Action</th><th>Count</th></tr>"
+                )?;
+                for (command, action, count) in &data.summary {
+                    writeln!(
+                        out,
+                        "<tr><td>{}</td><td>{}</td><td>{count}</td></tr>",
+                        html_escape(command),
+                        html_escape(action)
+                    )?;
+                }
+                writeln!(
+                    out,
+                    "</table>\n<h2>Per day</h2>\n<table>\n<tr><th>Date</th><th>Entries</th><th>Applied</th><th>Lines touched</th></tr>"
+                )?;
+                for day in &data.days {
+                    writeln!(
+                        out,
+                        "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                        html_escape(&day.day),
+                        day.entries,
+                        day.applied,
+                        day.lines
+                    )?;
+                }
+                writeln!(out, "</table>")?;
+            }
+            let rows: Vec<&FileChurn> = match group_by {
+                GroupBy::File => files.iter().collect(),
+                GroupBy::Command => hot,
+            };
+            if !rows.is_empty() {
+                writeln!(
+                    out,
+                    "<h2>Most modified files</h2>\n<table>\n<tr><th>File</th><th>Applied</th><th>Entries</th><th>Lines touched</th><th>Last change</th></tr>"
+                )?;
+                for churn in rows {
+                    writeln!(
+                        out,
+                        "<tr><td><code>{}</code></td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                        html_escape(&churn.path),
+                        churn.applied,
+                        churn.entries,
+                        churn.lines,
+                        html_escape(&churn.last)
+                    )?;
+                }
+                writeln!(out, "</table>")?;
+            }
+            writeln!(out, "</body>\n</html>")?;
+        }
+    }
+    Ok(out)
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn markdown_cell(value: &str) -> String {
+    value.replace('|', "\\|")
+}
+
+fn html_escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(path: &str, action: &str, timestamp: &str) -> LoggedEntry {
+        serde_json::from_value(json!({
+            "timestamp": timestamp,
+            "command": "replace",
+            "path": path,
+            "action": action,
+            "lines": "L1-L3",
+            "spans": [{"kind": "modified", "start": 1, "end": 3}],
+        }))
+        .unwrap()
+    }
+
+    fn sample() -> Vec<LoggedEntry> {
+        vec![
+            entry("/w/a.rs", "applied", "2025-11-01T09:00:00Z"),
+            entry("/w/b.rs", "applied", "2025-11-02T09:00:00Z"),
+            entry("/w/b.rs", "applied", "2025-11-02T10:00:00Z"),
+            entry("/w/c.rs", "dry-run", "2025-11-04T00:00:00Z"),
+            entry("/w/c.rs", "dry-run", "2025-11-05T00:00:00Z"),
+            entry("/w/c.rs", "dry-run", "2025-11-06T00:00:00Z"),
+        ]
+    }
+
+    #[test]
+    fn files_rank_by_applied_writes() {
+        let ranked = rank_files(&sample());
+        let order: Vec<_> = ranked.iter().map(|churn| churn.path.as_str()).collect();
+        assert_eq!(order, ["/w/b.rs", "/w/a.rs", "/w/c.rs"]);
+        assert_eq!(ranked[0].applied, 2);
+        assert_eq!(ranked[0].lines, 6);
+        assert_eq!(ranked[0].last, "2025-11-02T10:00:00Z");
+        assert_eq!(ranked[2].entries, 3);
+    }
+
+    #[test]
+    fn report_totals_break_down_per_day() {
+        let data = ReportData::build(&sample(), "beginning of log");
+        assert_eq!(data.applied, 3);
+        assert_eq!(data.lines_touched, 9);
+        assert_eq!(data.days.len(), 5);
+        assert_eq!(data.days[1].day, "2025-11-02");
+        assert_eq!(data.days[1].lines, 6);
+        let csv = render(&data, ReportOutput::Csv, GroupBy::Command, 10).unwrap();
+        assert!(csv.starts_with("date,entries,applied,lines_touched\n2025-11-01,1,1,3\n"));
+        let markdown = render(&data, ReportOutput::Markdown, GroupBy::Command, 10).unwrap();
+        assert!(markdown.contains("| replace | applied | 3 |"));
+        assert!(markdown.contains("| 2025-11-02 | 2 | 2 | 6 |"));
+        let html = render(&data, ReportOutput::Html, GroupBy::File, 1).unwrap();
+        assert!(html.contains("<code>/w/b.rs</code>"));
+        assert!(!html.contains("/w/a.rs"));
+    }
+
+    #[test]
+    fn exporters_escape_special_characters() {
+        assert_eq!(csv_field("a,b"), "\"a,b\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(html_escape("<a & b>"), "&lt;a &amp; b&gt;");
+        assert_eq!(markdown_cell("a|b"), "a\\|b");
+    }
+}
@@ -1,4 +1,7 @@
 use anyhow::{Context, Result, anyhow, bail};
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::terminal;
+use is_terminal::IsTerminal;
 use regex::{Captures, Regex};
 use std::fs;
 use std::io::{self, Write};
@@ -6,13 +9,15 @@ use std::thread;
 use std::time::Duration;
 use time::{OffsetDateTime, format_description::well_known::Rfc3339};
 
-use crate::diff::{DIFF_MAX_BYTES, DIFF_MAX_LINE_BYTES, DIFF_MAX_LINES};
+use crate::detect;
+use crate::diff::{DIFF_MAX_LINE_BYTES, DiffLimits};
+use crate::editor;
 use crate::encoding::{DecodedText, EncodingStrategy};
 use crate::files::FileEntry;
+use crate::normalize::{self, NormalizeOptions};
 
 const DEFAULT_HEAD_LINES: usize = 40;
-const REVIEW_MAX_LINES: usize = DIFF_MAX_LINES;
-const REVIEW_MAX_BYTES: usize = DIFF_MAX_BYTES;
+const DEFAULT_STEP_WINDOW: usize = 15;
 const REVIEW_MAX_LINE_BYTES: usize = DIFF_MAX_LINE_BYTES;
 const REVIEW_LINE_TRUNCATION_SUFFIX: &str = "... (line truncated)";
 
@@ -26,14 +31,22 @@ pub struct ReviewInput<'a> {
     pub step: bool,
     pub search: Option<&'a str>,
     pub regex: bool,
+    pub filter: Option<&'a str>,
+    pub step_window: Option<usize>,
+    pub report: bool,
+    pub limits: DiffLimits,
 }
 
 #[derive(Debug, Clone)]
 pub struct ReviewOptions {
     slices: Vec<ReviewSlice>,
     matcher: Option<Regex>,
+    filter: Option<Regex>,
     follow: bool,
     step: bool,
+    step_window: usize,
+    report: bool,
+    limits: DiffLimits,
 }
 
 #[derive(Debug, Clone)]
@@ -49,6 +62,9 @@ impl ReviewOptions {
         if input.follow && input.step {
             bail!("--follow cannot be combined with --step mode");
         }
+        if input.filter.is_some() && !input.follow {
+            bail!("--filter only applies to --follow mode");
+        }
 
         let mut slices = Vec::new();
 
@@ -75,12 +91,22 @@ impl ReviewOptions {
         }
 
         let matcher = build_matcher(input.search, input.regex)?;
+        let filter = match input.filter {
+            Some(pattern) => {
+                Some(Regex::new(pattern).map_err(|err| anyhow!("invalid filter pattern: {err}"))?)
+            }
+            None => None,
+        };
 
         Ok(Self {
             slices,
             matcher,
+            filter,
             follow: input.follow,
             step: input.step,
+            step_window: input.step_window.unwrap_or(DEFAULT_STEP_WINDOW),
+            report: input.report,
+            limits: input.limits,
         })
     }
 
@@ -91,6 +117,63 @@ impl ReviewOptions {
     pub fn step_mode(&self) -> bool {
         self.step
     }
+
+    pub fn step_window(&self) -> usize {
+        self.step_window
+    }
+
+    pub fn filter(&self) -> Option<&Regex> {
+        self.filter.as_ref()
+    }
+
+    // The selected lines as plain text (no numbers, highlighting, or
+    // truncation) for --copy / --out; returns the text and its line count.
+    fn excerpt(&self, text: &str) -> (String, usize) {
+        let lines: Vec<&str> = text.lines().collect();
+        let mut excerpt = String::new();
+        let mut count = 0usize;
+        for slice in &self.slices {
+            let (start_idx, end_idx) = match slice {
+                ReviewSlice::Head(count) => (0, (*count).min(lines.len())),
+                ReviewSlice::Tail(count) => (lines.len().saturating_sub(*count), lines.len()),
+                ReviewSlice::Range { start, end } => to_indices(*start, *end, lines.len()),
+                ReviewSlice::Around { line, context } => {
+                    to_indices(line.saturating_sub(*context), line + *context, lines.len())
+                }
+            };
+            for line in &lines[start_idx..end_idx] {
+                excerpt.push_str(line);
+                excerpt.push('\n');
+                count += 1;
+            }
+        }
+        (excerpt, count)
+    }
+}
+
+// Collects the selected ranges from every readable text file; with more than
+// one file each excerpt is headed by its path.
+pub fn collect_excerpt(
+    entries: &[FileEntry],
+    encoding: &EncodingStrategy,
+    options: &ReviewOptions,
+) -> Result<(String, usize)> {
+    let mut combined = String::new();
+    let mut total = 0usize;
+    for entry in entries {
+        if entry.metadata.is_probably_binary {
+            continue;
+        }
+        let bytes = fs::read(&entry.path)
+            .with_context(|| format!("failed to read {}", entry.path.display()))?;
+        let (excerpt, count) = options.excerpt(&encoding.decode(&bytes).text);
+        if entries.len() > 1 {
+            combined.push_str(&format!("=== {} ===\n", entry.path.display()));
+        }
+        combined.push_str(&excerpt);
+        total += count;
+    }
+    Ok((combined, total))
 }
 
 pub fn run(
@@ -117,9 +200,8 @@ fn review_file(
     encoding: &EncodingStrategy,
     options: &ReviewOptions,
 ) -> Result<()> {
-    println!("=== {} ===", entry.path.display());
-
     if entry.metadata.is_probably_binary {
+        println!("=== {} ===", entry.path.display());
         println!("skipping (suspected binary file)");
         return Ok(());
     }
@@ -127,16 +209,33 @@ fn review_file(
     let bytes = fs::read(&entry.path)
         .with_context(|| format!("failed to read {}", entry.path.display()))?;
     let decoded = encoding.decode(&bytes);
+    println!(
+        "=== {} ({}) ===",
+        entry.path.display(),
+        describe_line_total(decoded.text.lines().count())
+    );
 
     println!(
-        "decoded as {} via {} (errors: {})",
+        "decoded as {} via {} (detection score: {:.2}, errors: {})",
         decoded.decision.encoding.name(),
         decoded.decision.source,
+        decoded.decision.score,
         if decoded.had_errors { "yes" } else { "no" }
     );
+    if options.report {
+        for line in file_report(&decoded) {
+            println!("{line}");
+        }
+    }
 
     if options.step_mode() {
-        run_step_mode(&decoded, options.matcher())?;
+        run_step_mode(
+            entry,
+            encoding,
+            decoded,
+            options.matcher(),
+            options.step_window(),
+        )?;
     } else {
         render_content(&decoded, options)?;
     }
@@ -156,7 +255,11 @@ fn follow_file(
     }
 
     println!("Press Ctrl+C to stop following.");
+    if let Some(filter) = options.filter() {
+        println!("echoing appended lines matching /{}/", filter.as_str());
+    }
     let mut last_snapshot: Option<String> = None;
+    let mut consumed_lines = 0usize;
 
     loop {
         match fs::read(&entry.path) {
@@ -165,11 +268,24 @@ fn follow_file(
                 let current = decoded.text.clone();
                 if last_snapshot.as_deref() == Some(current.as_str()) {
                     // no change
+                } else if let (Some(filter), Some(previous)) =
+                    (options.filter(), last_snapshot.as_deref())
+                {
+                    if !current.starts_with(previous) {
+                        println!("--- file rewritten; rescanning from the first line ---");
+                        consumed_lines = 0;
+                    }
+                    consumed_lines =
+                        echo_appended_lines(&current, consumed_lines, filter, options.matcher());
+                    last_snapshot = Some(current);
                 } else {
                     let timestamp = OffsetDateTime::now_utc()
                         .format(&Rfc3339)
                         .unwrap_or_else(|_| "unknown time".into());
-                    println!("--- updated at {timestamp} ---");
+                    println!(
+                        "--- updated at {timestamp} ({}) ---",
+                        describe_line_total(current.lines().count())
+                    );
                     println!(
                         "decoded as {} via {} (errors: {})",
                         decoded.decision.encoding.name(),
@@ -177,6 +293,7 @@ fn follow_file(
                         if decoded.had_errors { "yes" } else { "no" }
                     );
                     render_content(&decoded, options)?;
+                    consumed_lines = complete_line_count(&current);
                     last_snapshot = Some(current);
                 }
             }
@@ -189,6 +306,116 @@ fn follow_file(
     }
 }
 
+fn echo_appended_lines(
+    text: &str,
+    consumed: usize,
+    filter: &Regex,
+    matcher: Option<&Regex>,
+) -> usize {
+    let mut seen = 0usize;
+    for line in text.split_inclusive('\n') {
+        let Some(body) = line.strip_suffix('\n') else {
+            break;
+        };
+        seen += 1;
+        if seen <= consumed {
+            continue;
+        }
+        let body = body.strip_suffix('\r').unwrap_or(body);
+        if !filter.is_match(body) {
+            continue;
+        }
+        let mut rendered = highlight_line(body, matcher.or(Some(filter)));
+        truncate_line_to_limit(&mut rendered);
+        println!("{seen:>6} | {rendered}");
+    }
+    seen.max(consumed)
+}
+
+// --report: line-ending mix, indentation style, and how sure the decoder was,
+// using the same detectors as `normalize` and `detect`.
+fn file_report(decoded: &DecodedText) -> Vec<String> {
+    let text = &decoded.text;
+    let eol = normalize::count_line_endings(text);
+    let indent = normalize::normalize_text(text, &scan_indentation())
+        .report
+        .indentation
+        .unwrap_or_default();
+    let style = match (indent.tabs, indent.spaces) {
+        _ if indent.mixed > 0 || (indent.tabs > 0 && indent.spaces > 0) => "mixed",
+        (0, 0) => "none",
+        (_, 0) => "tabs",
+        _ => "spaces",
+    };
+    let width = match normalize::dominant_indent_width(text) {
+        Some(width) if indent.spaces > 0 => format!(", dominant width {width}"),
+        _ => String::new(),
+    };
+    let replacement_chars = text.matches('\u{FFFD}').count();
+    vec![
+        "-- report --".to_string(),
+        format!(
+            "line endings: {} (lf={}, crlf={}, cr={})",
+            eol.label(),
+            eol.lf,
+            eol.crlf,
+            eol.cr
+        ),
+        format!(
+            "indentation: {style}{width} (lines indented with tabs={}, spaces={}, mixed={})",
+            indent.tabs, indent.spaces, indent.mixed
+        ),
+        format!(
+            "encoding: {} via {}, detection score {:.2}, replacement chars {replacement_chars}, error likelihood {}",
+            decoded.decision.encoding.name(),
+            decoded.decision.source,
+            decoded.decision.score,
+            detect::error_likelihood(
+                decoded.had_errors,
+                replacement_chars,
+                decoded.decision.score
+            )
+        ),
+    ]
+}
+
+fn scan_indentation() -> NormalizeOptions {
+    NormalizeOptions {
+        strip_zero_width: false,
+        strip_control: false,
+        strip_bidi: false,
+        map_confusables: false,
+        ascii_punctuation: false,
+        punctuation_map: Vec::new(),
+        strip_classes: Vec::new(),
+        trim_trailing_space: false,
+        ensure_eol: false,
+        indent_conversion: None,
+        max_blank_lines: None,
+        max_line_length: None,
+        wrap_long_lines: false,
+        detect_zero_width: false,
+        detect_control: false,
+        detect_bidi: false,
+        detect_confusables: false,
+        detect_punctuation: false,
+        detect_trailing_space: false,
+        detect_indentation: true,
+        detect_line_endings: false,
+        detect_final_newline: false,
+        record_locations: false,
+        unicode_form: None,
+    }
+}
+
+fn describe_line_total(total: usize) -> String {
+    format!("{total} line{}", if total == 1 { "" } else { "s" })
+}
+
+fn complete_line_count(text: &str) -> usize {
+    text.matches('\n').count()
+}
+
 fn render_content(decoded: &DecodedText, options: &ReviewOptions) -> Result<()> {
     let lines: Vec<&str> = decoded.text.lines().collect();
 
@@ -197,7 +424,7 @@ fn render_content(decoded: &DecodedText, options: &ReviewOptions) -> Result<()>
         return Ok(());
     }
 
-    let mut limiter = ReviewLimiter::new();
+    let mut limiter = ReviewLimiter::new(options.limits);
     for slice in &options.slices {
         if limiter.truncated() {
             break;
@@ -284,6 +511,7 @@ struct ReviewLimiter {
     truncated: bool,
     reason: Option<ReviewTruncateReason>,
     line_truncations: usize,
+    limits: DiffLimits,
 }
 
 #[derive(Clone, Copy)]
@@ -293,13 +521,14 @@ enum ReviewTruncateReason {
 }
 
 impl ReviewLimiter {
-    fn new() -> Self {
+    fn new(limits: DiffLimits) -> Self {
         Self {
             total_bytes: 0,
             total_lines: 0,
             truncated: false,
             reason: None,
             line_truncations: 0,
+            limits,
         }
     }
 
@@ -312,10 +541,10 @@ impl ReviewLimiter {
         }
         self.total_bytes = self.total_bytes.saturating_add(line.len() + 1);
         self.total_lines = self.total_lines.saturating_add(1);
-        if self.total_lines >= REVIEW_MAX_LINES {
+        if self.limits.lines_exceeded(self.total_lines) {
             self.truncated = true;
             self.reason = Some(ReviewTruncateReason::LineCount);
-        } else if self.total_bytes >= REVIEW_MAX_BYTES {
+        } else if self.limits.bytes_exceeded(self.total_bytes) {
             self.truncated = true;
             self.reason = Some(ReviewTruncateReason::ByteCount);
         }
@@ -339,80 +568,167 @@ impl ReviewLimiter {
         if let Some(reason) = self.reason {
             match reason {
                 ReviewTruncateReason::LineCount => println!(
-                    "(output truncated at ~{REVIEW_MAX_LINES} lines; narrow your selection or review smaller ranges)"
+                    "(output truncated at ~{} lines; narrow your selection or raise --max-diff-lines)",
+                    self.limits.max_lines
                 ),
                 ReviewTruncateReason::ByteCount => println!(
-                    "(output truncated after ~{REVIEW_MAX_BYTES} bytes; narrow your selection or limit context)"
+                    "(output truncated after ~{} bytes; narrow your selection or raise --max-diff-bytes)",
+                    self.limits.max_bytes
                 ),
             }
         }
     }
 }
 
-fn run_step_mode(decoded: &DecodedText, matcher: Option<&Regex>) -> Result<()> {
-    let lines: Vec<&str> = decoded.text.lines().collect();
-    if lines.is_empty() {
+fn run_step_mode(
+    entry: &FileEntry,
+    encoding: &EncodingStrategy,
+    decoded: DecodedText,
+    matcher: Option<&Regex>,
+    window: usize,
+) -> Result<()> {
+    let mut text = decoded.text;
+    if text.lines().next().is_none() {
         println!("(file is empty)");
         return Ok(());
     }
 
-    println!(
-        "Entering step mode. Commands: [Enter]/j=next line, b/p/k=previous line, g/G=head/tail, n/N=next/prev match, /pattern=set search, m=mark, '=jump mark, q=quit, ?=help"
-    );
-
-    let mut index = 0usize;
-    let mut bookmark: Option<usize> = None;
-    let mut dynamic_search: Option<Regex> = None;
-
-    loop {
-        print_step_line(
-            &lines,
-            index,
-            active_search(dynamic_search.as_ref(), matcher),
+    let raw_keys = io::stdin().is_terminal() && io::stdout().is_terminal();
+    if raw_keys {
+        println!(
+            "Entering step mode ({window}-line window). Keys: j/Down=next line, k/Up=previous line, Space/PgDn=next page, b/PgUp=previous page, Ctrl-D/Ctrl-U=half page, g/G=head/tail, %=go to percentage, n/N=next/prev match, /=search, :=go to line, m=mark, '=jump mark, e=edit, q=quit, ?=help"
         );
-        print!("step> ");
-        io::stdout().flush()?;
-        let mut input = String::new();
-        let bytes = io::stdin()
-            .read_line(&mut input)
-            .context("reading step input")?;
-        if bytes == 0 {
-            println!("stdin closed; exiting step mode.");
-            break;
+    } else {
+        println!(
+            "Entering step mode. Commands: [Enter]/j=next line, b/p/k=previous line, f/d=next page/half page, u=previous half page, g/G=head/tail, 50%=go to percentage, n/N=next/prev match, /pattern=set search, m=mark, '=jump mark, e=edit, q=quit, ?=help"
+        );
+    }
+
+    let mut state = StepState::new(window);
+    'reload: loop {
+        let lines: Vec<&str> = text.lines().collect();
+        if lines.is_empty() {
+            println!("(file is empty)");
+            return Ok(());
         }
+        state.index = state.index.min(lines.len() - 1);
+        loop {
+            print_step_window(
+                &lines,
+                state.index,
+                state.window,
+                active_search(state.dynamic_search.as_ref(), matcher),
+            );
+            let command = if raw_keys {
+                match read_step_key()? {
+                    Some(command) => command,
+                    None => continue,
+                }
+            } else {
+                print!("step> ");
+                io::stdout().flush()?;
+                let input = crate::input::read_line().context("reading step input")?;
+                if input.is_empty() {
+                    println!("stdin closed; exiting step mode.");
+                    break 'reload;
+                }
+                parse_step_command(input.trim())
+            };
 
-        match parse_step_command(input.trim()) {
-            StepCommand::NextLine => {
-                if index + 1 < lines.len() {
-                    index += 1;
-                } else {
-                    println!("(end of file)");
+            if command == StepCommand::Edit {
+                if let Err(err) = editor::open_in_editor(&entry.path, Some(state.index + 1)) {
+                    println!("editor failed: {err:#}");
+                    continue;
                 }
+                let bytes = fs::read(&entry.path)
+                    .with_context(|| format!("failed to re-read {}", entry.path.display()))?;
+                let reloaded = encoding.decode(&bytes);
+                println!(
+                    "reloaded {} ({} lines, decoded as {})",
+                    entry.path.display(),
+                    reloaded.text.lines().count(),
+                    reloaded.decision.encoding.name()
+                );
+                text = reloaded.text;
+                continue 'reload;
             }
-            StepCommand::PrevLine => {
-                if index > 0 {
-                    index -= 1;
-                } else {
-                    println!("(start of file)");
-                }
+
+            if !state.apply(command, &lines, matcher) {
+                break 'reload;
             }
-            StepCommand::Head => index = 0,
-            StepCommand::Tail => index = lines.len().saturating_sub(1),
+        }
+    }
+
+    Ok(())
+}
+
+struct StepState {
+    index: usize,
+    window: usize,
+    bookmark: Option<usize>,
+    dynamic_search: Option<Regex>,
+}
+
+impl StepState {
+    fn new(window: usize) -> Self {
+        Self {
+            index: 0,
+            window: window.max(1),
+            bookmark: None,
+            dynamic_search: None,
+        }
+    }
+
+    fn last_top(&self, total: usize) -> usize {
+        total.saturating_sub(1)
+    }
+
+    fn scroll_down(&mut self, amount: usize, total: usize) {
+        if self.index >= self.last_top(total) {
+            println!("(end of file)");
+            return;
+        }
+        self.index = (self.index + amount.max(1)).min(self.last_top(total));
+    }
+
+    fn scroll_up(&mut self, amount: usize) {
+        if self.index == 0 {
+            println!("(start of file)");
+            return;
+        }
+        self.index = self.index.saturating_sub(amount.max(1));
+    }
+
+    fn apply(&mut self, command: StepCommand, lines: &[&str], matcher: Option<&Regex>) -> bool {
+        let total = lines.len();
+        match command {
+            StepCommand::NextLine => self.scroll_down(1, total),
+            StepCommand::PrevLine => self.scroll_up(1),
+            StepCommand::PageDown => self.scroll_down(self.window, total),
+            StepCommand::PageUp => self.scroll_up(self.window),
+            StepCommand::HalfPageDown => self.scroll_down(self.window / 2, total),
+            StepCommand::HalfPageUp => self.scroll_up(self.window / 2),
+            StepCommand::Head => self.index = 0,
+            StepCommand::Tail => self.index = total.saturating_sub(self.window),
             StepCommand::Jump(target) => {
-                if target < lines.len() {
-                    index = target;
+                if target < total {
+                    self.index = target;
                 } else {
-                    println!("line {} is out of range (1-{})", target + 1, lines.len());
+                    println!("line {} is out of range (1-{})", target + 1, total);
                 }
             }
+            StepCommand::Percent(percent) => {
+                let line = (total * percent / 100).max(1);
+                self.index = (line - 1).min(total.saturating_sub(1));
+            }
             StepCommand::Search(pattern) => {
                 if pattern.trim().is_empty() {
-                    dynamic_search = None;
+                    self.dynamic_search = None;
                     println!("cleared interactive search pattern.");
                 } else {
                     match build_interactive_regex(pattern.trim()) {
                         Ok(regex) => {
-                            dynamic_search = Some(regex);
+                            self.dynamic_search = Some(regex);
                             println!("search set; use 'n'/'N' to jump between matches.");
                         }
                         Err(err) => println!("invalid search pattern: {err}"),
@@ -420,9 +736,9 @@ fn run_step_mode(decoded: &DecodedText, matcher: Option<&Regex>) -> Result<()> {
                 }
             }
             StepCommand::FindNext => {
-                if let Some(regex) = active_search(dynamic_search.as_ref(), matcher) {
-                    if let Some(hit) = find_next_match(&lines, index, regex) {
-                        index = hit;
+                if let Some(regex) = active_search(self.dynamic_search.as_ref(), matcher) {
+                    if let Some(hit) = find_next_match(lines, self.index, regex) {
+                        self.index = hit;
                     } else {
                         println!("no later matches.");
                     }
@@ -431,9 +747,9 @@ fn run_step_mode(decoded: &DecodedText, matcher: Option<&Regex>) -> Result<()> {
                 }
             }
             StepCommand::FindPrev => {
-                if let Some(regex) = active_search(dynamic_search.as_ref(), matcher) {
-                    if let Some(hit) = find_prev_match(&lines, index, regex) {
-                        index = hit;
+                if let Some(regex) = active_search(self.dynamic_search.as_ref(), matcher) {
+                    if let Some(hit) = find_prev_match(lines, self.index, regex) {
+                        self.index = hit;
                     } else {
                         println!("no earlier matches.");
                     }
@@ -442,22 +758,134 @@ fn run_step_mode(decoded: &DecodedText, matcher: Option<&Regex>) -> Result<()> {
                 }
             }
             StepCommand::SetBookmark => {
-                bookmark = Some(index);
-                println!("bookmark set at line {}", index + 1);
+                self.bookmark = Some(self.index);
+                println!("bookmark set at line {}", self.index + 1);
             }
             StepCommand::JumpBookmark => {
-                if let Some(mark) = bookmark {
-                    index = mark;
+                if let Some(mark) = self.bookmark {
+                    self.index = mark;
                 } else {
                     println!("no bookmark set. Type 'm' to set one.");
                 }
             }
             StepCommand::Help => print_step_help(),
-            StepCommand::Quit => break,
+            StepCommand::Edit => {}
+            StepCommand::Quit => return false,
         }
+        true
     }
+}
 
-    Ok(())
+struct RawModeGuard;
+
+impl RawModeGuard {
+    fn enable() -> Result<Self> {
+        terminal::enable_raw_mode().context("enabling raw terminal mode")?;
+        Ok(Self)
+    }
+}
+
+impl Drop for RawModeGuard {
+    fn drop(&mut self) {
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+fn read_step_key() -> Result<Option<StepCommand>> {
+    print!("step (? for keys)> ");
+    io::stdout().flush()?;
+    let key = {
+        let _guard = RawModeGuard::enable()?;
+        loop {
+            if let Event::Key(key) = event::read().context("reading step key")?
+                && key.kind != KeyEventKind::Release
+            {
+                break key;
+            }
+        }
+    };
+    println!();
+    match key_to_step_action(&key) {
+        KeyAction::Command(command) => Ok(Some(command)),
+        KeyAction::PromptSearch => {
+            let pattern = prompt_line("/")?;
+            Ok(Some(StepCommand::Search(pattern)))
+        }
+        KeyAction::PromptJump => {
+            let raw = prompt_line("line: ")?;
+            if let Some(percent) = parse_percent_target(&raw) {
+                return Ok(Some(StepCommand::Percent(percent)));
+            }
+            match parse_jump_target(&raw) {
+                Some(target) => Ok(Some(StepCommand::Jump(target))),
+                None => {
+                    println!("expected a line number or a percentage such as 50%");
+                    Ok(None)
+                }
+            }
+        }
+        KeyAction::PromptPercent => {
+            let raw = prompt_line("percent: ")?;
+            match parse_percent_target(&format!("{}%", raw.trim().trim_end_matches('%'))) {
+                Some(percent) => Ok(Some(StepCommand::Percent(percent))),
+                None => {
+                    println!("expected a percentage between 0 and 100");
+                    Ok(None)
+                }
+            }
+        }
+        KeyAction::Ignore => Ok(None),
+    }
+}
+
+fn prompt_line(prompt: &str) -> Result<String> {
+    print!("{prompt}");
+    io::stdout().flush()?;
+    let input = crate::input::read_line().context("reading step input")?;
+    Ok(input.trim_end_matches(['\r', '\n']).to_string())
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum KeyAction {
+    Command(StepCommand),
+    PromptSearch,
+    PromptJump,
+    PromptPercent,
+    Ignore,
+}
+
+fn key_to_step_action(key: &KeyEvent) -> KeyAction {
+    if key.modifiers.contains(KeyModifiers::CONTROL) {
+        return match key.code {
+            KeyCode::Char('d') => KeyAction::Command(StepCommand::HalfPageDown),
+            KeyCode::Char('u') => KeyAction::Command(StepCommand::HalfPageUp),
+            KeyCode::Char('f') => KeyAction::Command(StepCommand::PageDown),
+            KeyCode::Char('b') => KeyAction::Command(StepCommand::PageUp),
+            KeyCode::Char('c') => KeyAction::Command(StepCommand::Quit),
+            _ => KeyAction::Ignore,
+        };
+    }
+    match key.code {
+        KeyCode::Char('j') | KeyCode::Down | KeyCode::Enter => {
+            KeyAction::Command(StepCommand::NextLine)
+        }
+        KeyCode::Char('k') | KeyCode::Up => KeyAction::Command(StepCommand::PrevLine),
+        KeyCode::Char(' ') | KeyCode::PageDown => KeyAction::Command(StepCommand::PageDown),
+        KeyCode::Char('b') | KeyCode::PageUp => KeyAction::Command(StepCommand::PageUp),
+        KeyCode::Char('g') | KeyCode::Home => KeyAction::Command(StepCommand::Head),
+        KeyCode::Char('G') | KeyCode::End => KeyAction::Command(StepCommand::Tail),
+        KeyCode::Char('n') => KeyAction::Command(StepCommand::FindNext),
+        KeyCode::Char('N') => KeyAction::Command(StepCommand::FindPrev),
+        KeyCode::Char('m') => KeyAction::Command(StepCommand::SetBookmark),
+        KeyCode::Char('e') => KeyAction::Command(StepCommand::Edit),
+        KeyCode::Char('\'') => KeyAction::Command(StepCommand::JumpBookmark),
+        KeyCode::Char('q') | KeyCode::Esc => KeyAction::Command(StepCommand::Quit),
+        KeyCode::Char('?') => KeyAction::Command(StepCommand::Help),
+        KeyCode::Char('/') => KeyAction::PromptSearch,
+        KeyCode::Char(':') => KeyAction::PromptJump,
+        KeyCode::Char('%') => KeyAction::PromptPercent,
+        _ => KeyAction::Ignore,
+    }
 }
 
 fn active_search<'a>(dynamic: Option<&'a Regex>, fallback: Option<&'a Regex>) -> Option<&'a Regex> {
@@ -486,31 +914,48 @@ fn find_prev_match(lines: &[&str], index: usize, regex: &Regex) -> Option<usize>
     None
 }
 
-fn print_step_line(lines: &[&str], index: usize, matcher: Option<&Regex>) {
-    if let Some(line) = lines.get(index) {
+fn print_step_window(lines: &[&str], index: usize, window: usize, matcher: Option<&Regex>) {
+    let end = (index + window).min(lines.len());
+    if window > 1 {
+        println!("-- lines {}-{} of {} --", index + 1, end, lines.len());
+    }
+    let mut truncated = false;
+    for (offset, line) in lines[index.min(end)..end].iter().enumerate() {
         let mut rendered = highlight_line(line, matcher);
-        let truncated = truncate_line_to_limit(&mut rendered);
-        println!("{:>6} | {}", index + 1, rendered);
-        if truncated {
-            println!(
-                "(line truncated to ~{REVIEW_MAX_LINE_BYTES} bytes; narrow your selection to view the full content)"
-            );
-        }
+        truncated |= truncate_line_to_limit(&mut rendered);
+        println!("{:>6} | {}", index + offset + 1, rendered);
     }
+    if truncated {
+        println!(
+            "(line truncated to ~{REVIEW_MAX_LINE_BYTES} bytes; narrow your selection to view the full content)"
+        );
+    }
+    println!("-- {} --", position_status(index, lines.len()));
+}
+
+fn position_status(index: usize, total: usize) -> String {
+    let line = index + 1;
+    format!("line {line} of {total} ({}%)", line * 100 / total.max(1))
 }
 
 #[derive(Debug, PartialEq, Eq)]
 enum StepCommand {
     NextLine,
     PrevLine,
+    PageDown,
+    PageUp,
+    HalfPageDown,
+    HalfPageUp,
     Head,
     Tail,
     Jump(usize),
+    Percent(usize),
     Search(String),
     FindNext,
     FindPrev,
     SetBookmark,
     JumpBookmark,
+    Edit,
     Help,
     Quit,
 }
@@ -534,14 +979,20 @@ fn parse_step_command(input: &str) -> StepCommand {
     match lower.as_str() {
         "j" | "next" => StepCommand::NextLine,
         "k" | "p" | "b" | "prev" => StepCommand::PrevLine,
+        "f" | "page" => StepCommand::PageDown,
+        "d" => StepCommand::HalfPageDown,
+        "u" => StepCommand::HalfPageUp,
         "g" | "h" | "head" => StepCommand::Head,
         "t" | "tail" => StepCommand::Tail,
         "q" | "quit" => StepCommand::Quit,
         "?" | "help" => StepCommand::Help,
         "m" | "mark" => StepCommand::SetBookmark,
+        "e" | "edit" => StepCommand::Edit,
         "jumpmark" | "return" => StepCommand::JumpBookmark,
         _ => {
-            if let Some(target) = parse_jump_target(trimmed) {
+            if let Some(percent) = parse_percent_target(trimmed) {
+                StepCommand::Percent(percent)
+            } else if let Some(target) = parse_jump_target(trimmed) {
                 StepCommand::Jump(target)
             } else {
                 StepCommand::Help
@@ -550,7 +1001,7 @@ fn parse_step_command(input: &str) -> StepCommand {
     }
 }
 
-fn build_interactive_regex(pattern: &str) -> Result<Regex> {
+pub(crate) fn build_interactive_regex(pattern: &str) -> Result<Regex> {
     if let Some(rest) = pattern.strip_prefix("re:") {
         let trimmed = rest.trim();
         if trimmed.is_empty() {
@@ -590,9 +1041,18 @@ fn parse_jump_target(raw: &str) -> Option<usize> {
         .and_then(|val| if val == 0 { None } else { Some(val - 1) })
 }
 
+// Accepts `50%` or `%50`; percentages above 100 are rejected.
+fn parse_percent_target(raw: &str) -> Option<usize> {
+    let trimmed = raw.trim();
+    let value = trimmed
+        .strip_suffix('%')
+        .or_else(|| trimmed.strip_prefix('%'))?;
+    value.trim().parse::<usize>().ok().filter(|pct| *pct <= 100)
+}
+
 fn print_step_help() {
     println!(
-        "commands: [Enter]/j next line, b/p/k previous line, g/G head/tail, n/N next/prev match, /pattern set search, m bookmark, ' jump bookmark, number or g <n> jump, q quit"
+        "commands: [Enter]/j/Down next line, b/p/k/Up previous line, f/Space/PgDn next page, PgUp previous page, d/u or Ctrl-D/Ctrl-U half page, g/G head/tail, n/N next/prev match, /pattern set search, m bookmark, ' jump bookmark, e open $EDITOR at the current line, number, g <n> or :<n> jump, 50% or % <n> jump to a percentage of the file, q quit"
     );
 }
 
@@ -688,6 +1148,69 @@ mod tests {
         );
     }
 
+    #[test]
+    fn excerpt_keeps_selected_lines_verbatim() {
+        let input = ReviewInput {
+            head: None,
+            tail: Some(1),
+            lines: Some("2:3"),
+            around: None,
+            follow: false,
+            step: false,
+            search: Some("two"),
+            regex: false,
+            filter: None,
+            step_window: None,
+            report: false,
+            limits: DiffLimits::default(),
+        };
+        let options = ReviewOptions::from_input(input).unwrap();
+        assert_eq!(
+            options.excerpt("one\r\ntwo\nthree\nfour\n"),
+            ("two\nthree\nfour\n".to_string(), 3)
+        );
+    }
+
+    #[test]
+    fn report_summarizes_line_endings_and_indentation() {
+        let decoded = EncodingStrategy::new(None)
+            .unwrap()
+            .decode(b"fn a() {\r\n    b();\n\tc();\n}\n");
+        let report = file_report(&decoded);
+        assert_eq!(report[1], "line endings: mixed (lf=3, crlf=1, cr=0)");
+        assert_eq!(
+            report[2],
+            "indentation: mixed, dominant width 4 (lines indented with tabs=1, spaces=1, mixed=0)"
+        );
+        assert!(report[3].contains("error likelihood low"));
+    }
+
+    #[test]
+    fn filter_requires_follow() {
+        let input = ReviewInput {
+            head: None,
+            tail: None,
+            lines: None,
+            around: None,
+            follow: false,
+            step: false,
+            search: None,
+            regex: false,
+            filter: Some("ERROR"),
+            step_window: None,
+            report: false,
+            limits: DiffLimits::default(),
+        };
+        assert!(ReviewOptions::from_input(input).is_err());
+    }
+
+    #[test]
+    fn echo_appended_lines_skips_partial_tail() {
+        let filter = Regex::new("ERROR").unwrap();
+        let consumed = echo_appended_lines("ok\nERROR one\nERROR part", 1, &filter, None);
+        assert_eq!(consumed, 2);
+    }
+
     #[test]
     fn parse_step_command_numeric_jump() {
         assert_eq!(parse_step_command("12"), StepCommand::Jump(11));
@@ -717,6 +1240,65 @@ mod tests {
         assert_eq!(parse_step_command("N"), StepCommand::FindPrev);
     }
 
+    #[test]
+    fn parse_step_command_paging() {
+        assert_eq!(parse_step_command("f"), StepCommand::PageDown);
+        assert_eq!(parse_step_command("d"), StepCommand::HalfPageDown);
+        assert_eq!(parse_step_command("u"), StepCommand::HalfPageUp);
+    }
+
+    #[test]
+    fn raw_keys_map_to_step_commands() {
+        let space = KeyEvent::new(KeyCode::Char(' '), KeyModifiers::NONE);
+        assert_eq!(
+            key_to_step_action(&space),
+            KeyAction::Command(StepCommand::PageDown)
+        );
+        let ctrl_d = KeyEvent::new(KeyCode::Char('d'), KeyModifiers::CONTROL);
+        assert_eq!(
+            key_to_step_action(&ctrl_d),
+            KeyAction::Command(StepCommand::HalfPageDown)
+        );
+        let slash = KeyEvent::new(KeyCode::Char('/'), KeyModifiers::NONE);
+        assert_eq!(key_to_step_action(&slash), KeyAction::PromptSearch);
+    }
+
+    #[test]
+    fn step_state_pages_within_bounds() {
+        let lines: Vec<&str> = (0..40).map(|_| "x").collect();
+        let mut state = StepState::new(15);
+        state.apply(StepCommand::PageDown, &lines, None);
+        assert_eq!(state.index, 15);
+        state.apply(StepCommand::HalfPageUp, &lines, None);
+        assert_eq!(state.index, 8);
+        state.apply(StepCommand::Tail, &lines, None);
+        assert_eq!(state.index, 25);
+    }
+
+    #[test]
+    fn percent_jumps_and_position_status() {
+        assert_eq!(parse_step_command("50%"), StepCommand::Percent(50));
+        assert_eq!(parse_step_command("%25"), StepCommand::Percent(25));
+        assert_eq!(parse_step_command("150%"), StepCommand::Help);
+        let lines: Vec<&str> = (0..200).map(|_| "x").collect();
+        let mut state = StepState::new(15);
+        state.apply(StepCommand::Percent(50), &lines, None);
+        assert_eq!(state.index, 99);
+        assert_eq!(
+            position_status(state.index, lines.len()),
+            "line 100 of 200 (50%)"
+        );
+        state.apply(StepCommand::Percent(0), &lines, None);
+        assert_eq!(state.index, 0);
+        state.apply(StepCommand::Percent(100), &lines, None);
+        assert_eq!(state.index, 199);
+    }
+
+    #[test]
+    fn parse_step_command_edit() {
+        assert_eq!(parse_step_command("e"), StepCommand::Edit);
+    }
+
     #[test]
     fn parse_step_command_bookmarks() {
         assert_eq!(parse_step_command("m"), StepCommand::SetBookmark);
@@ -0,0 +1,455 @@
+use clap::ValueEnum;
+use serde_json::{Map as JsonMap, Value as JsonValue, json};
+
+use crate::events;
+
+const DRAFT: &str = "https://json-schema.org/draft/2020-12/schema";
+const EVENT_COMMANDS: [&str; 6] = ["replace", "normalize", "block", "rename", "write", "apply"];
+const EVENT_ACTIONS: [&str; 8] = [
+    "applied",
+    "dry-run",
+    "no-op",
+    "skipped",
+    "already-done",
+    "markers-missing",
+    "expect-mismatch",
+    "failed",
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum SchemaKind {
+    Plan,
+    Events,
+}
+
+pub fn render(kind: SchemaKind) -> JsonValue {
+    match kind {
+        SchemaKind::Plan => plan_schema(),
+        SchemaKind::Events => events_schema(),
+    }
+}
+
+fn string() -> JsonValue {
+    json!({ "type": "string" })
+}
+
+fn boolean() -> JsonValue {
+    json!({ "type": "boolean" })
+}
+
+fn count() -> JsonValue {
+    json!({ "type": "integer", "minimum": 0 })
+}
+
+fn strings() -> JsonValue {
+    json!({ "type": "array", "items": string() })
+}
+
+fn sha256() -> JsonValue {
+    json!({ "type": "string", "pattern": "^[0-9a-fA-F]{64}$" })
+}
+
+fn one_of<T: ValueEnum>() -> JsonValue {
+    let names: Vec<String> = T::value_variants()
+        .iter()
+        .filter_map(|variant| variant.to_possible_value())
+        .map(|value| value.get_name().to_string())
+        .collect();
+    json!({ "type": "string", "enum": names })
+}
+
+fn object(properties: Vec<(&str, JsonValue)>, required: &[&str]) -> JsonValue {
+    let properties: JsonMap<String, JsonValue> = properties
+        .into_iter()
+        .map(|(name, schema)| (name.to_string(), schema))
+        .collect();
+    json!({ "type": "object", "properties": properties, "required": required })
+}
+
+// Mirrors batch::PlanCommon; keep the two in step when adding flags.
+fn common_schema() -> JsonValue {
+    object(
+        vec![
+            ("targets", strings()),
+            ("globs", strings()),
+            ("encoding", string()),
+            ("apply", boolean()),
+            ("auto_apply", boolean()),
+            ("approve_policy", string()),
+            ("no_backup", boolean()),
+            ("backup_dir", string()),
+            ("backup_naming", one_of::<crate::backup::BackupNaming>()),
+            ("backup_keep", count()),
+            ("backup_max_age", count()),
+            ("backup_compress", boolean()),
+            ("backup_compress_threshold", count()),
+            ("context", count()),
+            ("pager", one_of::<crate::PagerMode>()),
+            ("diff_format", one_of::<crate::DiffFormat>()),
+            ("line_numbers", boolean()),
+            ("page_lines", count()),
+            ("max_diff_lines", count()),
+            ("max_diff_bytes", count()),
+            ("color", one_of::<crate::ColorChoice>()),
+            ("json", boolean()),
+            ("json_file", string()),
+            ("include_hidden", boolean()),
+            ("exclude", strings()),
+            ("undo_log", string()),
+            ("save_applied_patch", string()),
+            ("preview_dir", string()),
+            ("strict_encoding", boolean()),
+            ("verify_write", boolean()),
+            ("no_preserve_metadata", boolean()),
+            ("preserve_mtime", boolean()),
+            ("force_readonly", boolean()),
+            ("max_file_size", count()),
+            ("force_large", boolean()),
+            ("keep_going", boolean()),
+            (
+                "set_mtime",
+                json!({ "type": "string", "format": "date-time" }),
+            ),
+            (
+                "min_detection_score",
+                json!({ "type": "number", "minimum": 0, "maximum": 1 }),
+            ),
+        ],
+        &[],
+    )
+}
+
+// (command tag, fields beyond `common`, required fields)
+type CommandSchema = (
+    &'static str,
+    Vec<(&'static str, JsonValue)>,
+    Vec<&'static str>,
+);
+
+fn command_schemas() -> Vec<CommandSchema> {
+    let normalize_flags = [
+        "strip_zero_width",
+        "strip_control",
+        "strip_bidi",
+        "fail_on_bidi",
+        "map_confusables",
+        "ascii_punctuation",
+        "trim_trailing_space",
+        "ensure_eol",
+        "wrap_long_lines",
+        "fail_on_long_lines",
+        "strip_bom",
+        "add_bom",
+        "locations",
+        "scan_encoding",
+        "scan_zero_width",
+        "scan_control",
+        "scan_bidi",
+        "scan_confusables",
+        "scan_punctuation",
+        "scan_trailing_space",
+        "scan_indentation",
+        "scan_eol",
+        "scan_final_newline",
+    ];
+    let mut normalize = vec![
+        ("convert_encoding", string()),
+        ("map_char", strings()),
+        ("strip_class", strings()),
+        ("tabs_to_spaces", count()),
+        ("spaces_to_tabs", count()),
+        ("max_blank_lines", count()),
+        ("max_line_length", count()),
+        ("convert_eol", one_of::<crate::LineEndingStyle>()),
+        ("unicode_form", one_of::<crate::normalize::UnicodeForm>()),
+        ("report_format", string()),
+    ];
+    normalize.extend(normalize_flags.map(|flag| (flag, boolean())));
+    vec![
+        (
+            "replace",
+            vec![
+                ("pattern", string()),
+                ("replacement", string()),
+                ("with_url", string()),
+                ("url_sha256", sha256()),
+                ("regex", boolean()),
+                ("literal", boolean()),
+                ("no_expand", boolean()),
+                ("case_aware", boolean()),
+                ("allow_binary_literal", boolean()),
+                ("diff_only", boolean()),
+                ("count", count()),
+                ("expect", count()),
+                ("total_count", count()),
+                ("expect_total", count()),
+                ("after_line", count()),
+                ("skip_if_contains", string()),
+                ("with_stdin", boolean()),
+                ("with_clipboard", boolean()),
+                ("if_hash", string()),
+                ("if_unchanged_since", string()),
+            ],
+            vec!["pattern"],
+        ),
+        ("normalize", normalize, vec![]),
+        (
+            "block",
+            vec![
+                ("body", string()),
+                ("body_file", string()),
+                ("body_url", string()),
+                ("url_sha256", sha256()),
+                ("start_marker", string()),
+                ("end_marker", string()),
+                ("insert_after", string()),
+                ("insert_before", string()),
+                ("mode", string()),
+                ("expect_blocks", count()),
+                ("allow_marker_overlap", boolean()),
+            ],
+            vec![],
+        ),
+        (
+            "rename",
+            vec![
+                ("from", string()),
+                ("to", string()),
+                ("word_boundary", boolean()),
+                ("case_aware", boolean()),
+            ],
+            vec!["from", "to"],
+        ),
+        (
+            "write",
+            vec![
+                ("path", string()),
+                ("body", string()),
+                ("body_file", string()),
+                ("template", string()),
+                ("body_url", string()),
+                ("url_sha256", sha256()),
+                (
+                    "vars",
+                    json!({ "type": "object", "additionalProperties": string() }),
+                ),
+                ("allow_overwrite", boolean()),
+                ("mode", one_of::<crate::WriteMode>()),
+                ("if_hash", string()),
+                ("if_unchanged_since", string()),
+                ("line_ending", one_of::<crate::LineEndingChoice>()),
+            ],
+            vec!["path"],
+        ),
+        (
+            "apply",
+            vec![
+                ("patches", strings()),
+                ("root", string()),
+                ("offset_window", count()),
+            ],
+            vec!["patches"],
+        ),
+        (
+            "insert",
+            vec![
+                ("after", string()),
+                ("before", string()),
+                ("body", string()),
+                ("body_file", string()),
+            ],
+            vec![],
+        ),
+        (
+            "script",
+            vec![("script", string()), ("args", strings())],
+            vec!["script"],
+        ),
+    ]
+}
+
+fn step_schema() -> JsonValue {
+    let condition = json!({
+        "type": "object",
+        "properties": {
+            "exists": string(),
+            "missing": string(),
+            "glob": string(),
+            "changed": boolean(),
+            "env": { "type": "string", "description": "NAME or NAME=value" },
+        },
+        "additionalProperties": false,
+    });
+    let shared = || {
+        vec![
+            ("when", condition.clone()),
+            (
+                "on_error",
+                json!({ "type": "string", "enum": ["abort", "skip", "continue"] }),
+            ),
+        ]
+    };
+    let mut variants: Vec<JsonValue> = command_schemas()
+        .into_iter()
+        .map(|(command, fields, mut required)| {
+            let mut properties = shared();
+            properties.push(("command", json!({ "const": command })));
+            properties.push(("common", json!({ "$ref": "#/$defs/common" })));
+            properties.extend(fields);
+            required.insert(0, "command");
+            object(properties, &required)
+        })
+        .collect();
+    // Includes are expanded before the plan is parsed and take nothing else.
+    variants.push(json!({
+        "type": "object",
+        "properties": {
+            "command": { "const": "include" },
+            "plan": string(),
+            "vars": {
+                "type": "object",
+                "additionalProperties": { "type": ["string", "number", "boolean", "null"] },
+            },
+        },
+        "required": ["command", "plan"],
+        "additionalProperties": false,
+    }));
+    json!({ "oneOf": variants })
+}
+
+fn plan_schema() -> JsonValue {
+    json!({
+        "$schema": DRAFT,
+        "title": "safeedit batch plan",
+        "description": "Steps run by `safeedit batch`; string values may use {{var}} placeholders.",
+        "type": "object",
+        "properties": {
+            "vars": {
+                "type": "object",
+                "additionalProperties": { "type": ["string", "number", "boolean", "null"] },
+            },
+            "steps": { "type": "array", "items": { "$ref": "#/$defs/step" } },
+        },
+        "required": ["steps"],
+        "$defs": {
+            "common": common_schema(),
+            "step": step_schema(),
+        },
+    })
+}
+
+fn events_schema() -> JsonValue {
+    let nullable_sha = json!({ "type": ["string", "null"], "pattern": "^[0-9a-f]{64}$" });
+    let change = json!({
+        "type": "object",
+        "properties": {
+            "schema_version": { "const": events::SCHEMA_VERSION },
+            "command": { "type": "string", "enum": EVENT_COMMANDS },
+            "path": string(),
+            "action": { "type": "string", "enum": EVENT_ACTIONS },
+            "line_summary": string(),
+            "spans": {
+                "type": "array",
+                "items": {
+                    "type": "object",
+                    "properties": {
+                        "kind": { "type": "string", "enum": ["modified", "added"] },
+                        "start": count(),
+                        "end": count(),
+                    },
+                    "required": ["kind", "start", "end"],
+                },
+            },
+            "before_sha256": nullable_sha,
+            "after_sha256": nullable_sha,
+            "diff": { "type": ["string", "null"] },
+            "applied": boolean(),
+            "dry_run": boolean(),
+            "diff_only": boolean(),
+            "occurrences": count(),
+            "matched_lines": count(),
+            "patch_kind": {
+                "type": "string",
+                "enum": ["modify", "create", "delete", "rename"],
+            },
+            "decision": { "const": "prompt-timeout" },
+        },
+        "required": [
+            "schema_version",
+            "command",
+            "path",
+            "action",
+            "line_summary",
+            "spans",
+            "before_sha256",
+            "after_sha256",
+            "diff",
+        ],
+    });
+    let approval = json!({
+        "type": "object",
+        "properties": {
+            "schema_version": { "const": events::SCHEMA_VERSION },
+            "event": { "const": "approval_request" },
+            "path": string(),
+            "diff": { "type": ["string", "null"] },
+            "decisions": strings(),
+        },
+        "required": ["schema_version", "event", "path", "decisions"],
+    });
+    json!({
+        "$schema": DRAFT,
+        "title": "safeedit NDJSON event",
+        "description": "One object per line from --json or --json-file.",
+        "oneOf": [
+            { "$ref": "#/$defs/change" },
+            { "$ref": "#/$defs/approval_request" },
+        ],
+        "$defs": {
+            "change": change,
+            "approval_request": approval,
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::batch::PlanStep;
+
+    #[test]
+    fn minimal_steps_from_the_schema_deserialize() {
+        let schema = render(SchemaKind::Plan);
+        let variants = schema["$defs"]["step"]["oneOf"].as_array().unwrap();
+        assert_eq!(variants.len(), 9);
+        for variant in variants {
+            let command = variant["properties"]["command"]["const"].as_str().unwrap();
+            if command == "include" {
+                continue;
+            }
+            let mut step = JsonMap::new();
+            for field in variant["required"].as_array().unwrap() {
+                let field = field.as_str().unwrap();
+                let value = match variant["properties"][field]["type"].as_str() {
+                    Some("array") => json!(["x"]),
+                    _ if field == "command" => json!(command),
+                    _ => json!("x"),
+                };
+                step.insert(field.to_string(), value);
+            }
+            let parsed: PlanStep = serde_json::from_value(JsonValue::Object(step))
+                .unwrap_or_else(|err| panic!("{command}: {err}"));
+            assert_eq!(parsed.entry.kind(), command);
+        }
+        assert_eq!(
+            schema["$defs"]["common"]["properties"]["diff_format"]["enum"],
+            json!(["inline", "unified", "json"])
+        );
+
+        let events = render(SchemaKind::Events);
+        assert_eq!(
+            events["$defs"]["change"]["properties"]["schema_version"]["const"],
+            json!(events::SCHEMA_VERSION)
+        );
+    }
+}
@@ -0,0 +1,526 @@
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{IpAddr, SocketAddr, TcpListener, TcpStream};
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result, anyhow, bail};
+use serde_json::{Value as JsonValue, json};
+
+use crate::integrity::sha256_hex;
+
+const EDIT_COMMANDS: [&str; 6] = ["replace", "block", "rename", "normalize", "write", "apply"];
+// The server owns the first five; letting a client pass them would bypass the
+// token. The rest write side files at client-chosen paths, which /preview must
+// never do.
+const RESERVED_FLAGS: [&str; 11] = [
+    "--apply",
+    "--yes",
+    "--json",
+    "--json-file",
+    "--record",
+    "--preview-dir",
+    "--undo-log",
+    "--save-applied-patch",
+    "--backup-dir",
+    "--log-file",
+    "--manifest",
+];
+const MAX_BODY_BYTES: usize = 1024 * 1024;
+const TOKEN_TTL: Duration = Duration::from_secs(600);
+// Requests are served one at a time, so a client that stalls mid-request must
+// not hold the server forever.
+const IO_TIMEOUT: Duration = Duration::from_secs(30);
+
+struct Pending {
+    args: Vec<String>,
+    digest: String,
+    issued: Instant,
+}
+
+struct Request {
+    method: String,
+    path: String,
+    host: Option<String>,
+    origin: Option<String>,
+    content_type: Option<String>,
+    body: Vec<u8>,
+}
+
+struct Response {
+    status: u16,
+    body: JsonValue,
+}
+
+impl Response {
+    fn ok(body: JsonValue) -> Self {
+        Response { status: 200, body }
+    }
+
+    fn error(status: u16, message: impl std::fmt::Display) -> Self {
+        Response {
+            status,
+            body: json!({ "error": message.to_string() }),
+        }
+    }
+}
+
+struct Run {
+    success: bool,
+    events: Vec<JsonValue>,
+    output: String,
+}
+
+pub fn serve(addr: &str, allow_remote: bool) -> Result<()> {
+    let addr: SocketAddr = addr
+        .parse()
+        .map_err(|_| anyhow!("--http expects HOST:PORT such as 127.0.0.1:8787, got '{addr}'"))?;
+    if !addr.ip().is_loopback() && !allow_remote {
+        bail!(
+            "refusing to listen on non-loopback address {addr}; pass --allow-remote to expose it"
+        );
+    }
+    let listener = TcpListener::bind(addr).with_context(|| format!("binding {addr}"))?;
+    println!(
+        "safeedit serving on http://{} (POST /preview, POST /apply, GET /health)",
+        listener.local_addr()?
+    );
+    let loopback = addr.ip().is_loopback();
+    let exe = std::env::current_exe().context("locating the safeedit executable")?;
+    let mut pending: HashMap<String, Pending> = HashMap::new();
+    // One request at a time keeps preview/apply pairs from interleaving on
+    // the same files.
+    for stream in listener.incoming() {
+        let mut stream = match stream {
+            Ok(stream) => stream,
+            Err(err) => {
+                println!("warning: accepting connection failed: {err}");
+                continue;
+            }
+        };
+        if let Err(err) = stream
+            .set_read_timeout(Some(IO_TIMEOUT))
+            .and_then(|()| stream.set_write_timeout(Some(IO_TIMEOUT)))
+        {
+            println!("warning: configuring connection failed: {err}");
+            continue;
+        }
+        let response = match read_request(&mut BufReader::new(&stream)) {
+            Ok(request) => match refuse(&request, loopback) {
+                Some(refused) => refused,
+                None => {
+                    pending.retain(|_, entry| entry.issued.elapsed() < TOKEN_TTL);
+                    route(&exe, &request, &mut pending)
+                }
+            },
+            Err(err) => Response::error(400, format!("{err:#}")),
+        };
+        if let Err(err) = write_response(&mut stream, &response) {
+            println!("warning: writing response failed: {err}");
+        }
+    }
+    Ok(())
+}
+
+// Browser pages are the threat here. A page on another origin can resolve
+// its own name to 127.0.0.1 (DNS rebinding), but its requests still carry that
+// name in Host; a plain cross-site `fetch` sends Host 127.0.0.1 but names its
+// page in Origin; and a `no-cors` request cannot set a JSON Content-Type.
+fn refuse(request: &Request, loopback: bool) -> Option<Response> {
+    if loopback && !request.host.as_deref().is_some_and(is_loopback_host) {
+        return Some(Response::error(
+            403,
+            "Host header must name a loopback address",
+        ));
+    }
+    if let Some(origin) = &request.origin
+        && !is_loopback_origin(origin)
+    {
+        return Some(Response::error(
+            403,
+            format!("requests from origin {origin} are not accepted"),
+        ));
+    }
+    let json = request.content_type.as_deref().is_some_and(|value| {
+        value
+            .split(';')
+            .next()
+            .is_some_and(|kind| kind.trim().eq_ignore_ascii_case("application/json"))
+    });
+    if request.method == "POST" && !json {
+        return Some(Response::error(
+            415,
+            "POST bodies must be sent as Content-Type: application/json",
+        ));
+    }
+    None
+}
+
+fn route(exe: &Path, request: &Request, pending: &mut HashMap<String, Pending>) -> Response {
+    match (request.method.as_str(), request.path.as_str()) {
+        ("GET", "/health") => Response::ok(json!({ "status": "ok" })),
+        ("POST", "/preview") => match parse_args(&request.body) {
+            Ok(args) => preview(exe, args, pending),
+            Err(err) => Response::error(400, format!("{err:#}")),
+        },
+        ("POST", "/apply") => apply(exe, &request.body, pending),
+        (_, "/health" | "/preview" | "/apply") => Response::error(405, "method not allowed"),
+        _ => Response::error(404, "unknown endpoint"),
+    }
+}
+
+fn preview(exe: &Path, args: Vec<String>, pending: &mut HashMap<String, Pending>) -> Response {
+    let run = match run_safeedit(exe, &args, false) {
+        Ok(run) => run,
+        Err(err) => return Response::error(500, format!("{err:#}")),
+    };
+    if !run.success {
+        return Response {
+            status: 422,
+            body: json!({ "error": "preview failed", "output": run.output }),
+        };
+    }
+    let changes = run
+        .events
+        .iter()
+        .filter(|event| event["diff"].as_str().is_some_and(|diff| !diff.is_empty()))
+        .count();
+    let token = (changes > 0).then(|| {
+        let digest = preview_digest(&args, &run.events);
+        let token = issue_token(&digest);
+        pending.insert(
+            token.clone(),
+            Pending {
+                args,
+                digest,
+                issued: Instant::now(),
+            },
+        );
+        token
+    });
+    Response::ok(json!({
+        "token": token,
+        "changes": changes,
+        "events": run.events,
+        "output": run.output,
+    }))
+}
+
+fn apply(exe: &Path, body: &[u8], pending: &mut HashMap<String, Pending>) -> Response {
+    let token = match serde_json::from_slice::<JsonValue>(body)
+        .ok()
+        .and_then(|value| value["token"].as_str().map(str::to_string))
+    {
+        Some(token) => token,
+        None => return Response::error(400, "expected a JSON body with a \"token\" from /preview"),
+    };
+    let Some(entry) = pending.remove(&token) else {
+        return Response::error(403, "unknown or expired token; request a new preview");
+    };
+    let check = match run_safeedit(exe, &entry.args, false) {
+        Ok(run) => run,
+        Err(err) => return Response::error(500, format!("{err:#}")),
+    };
+    if !check.success || preview_digest(&entry.args, &check.events) != entry.digest {
+        return Response {
+            status: 409,
+            body: json!({
+                "error": "the files changed since the preview; request a new preview",
+                "output": check.output,
+            }),
+        };
+    }
+    match run_safeedit(exe, &entry.args, true) {
+        Ok(run) => Response {
+            status: if run.success { 200 } else { 422 },
+            body: json!({
+                "applied": run.success,
+                "events": run.events,
+                "output": run.output,
+            }),
+        },
+        Err(err) => Response::error(500, format!("{err:#}")),
+    }
+}
+
+fn parse_args(body: &[u8]) -> Result<Vec<String>> {
+    let value: JsonValue = serde_json::from_slice(body).context("request body is not JSON")?;
+    let args: Vec<String> = value["args"]
+        .as_array()
+        .ok_or_else(|| anyhow!("expected {{\"args\": [\"replace\", ...]}}"))?
+        .iter()
+        .map(|arg| {
+            arg.as_str()
+                .map(str::to_string)
+                .ok_or_else(|| anyhow!("every entry in \"args\" must be a string"))
+        })
+        .collect::<Result<_>>()?;
+    let command = args.first().map(String::as_str).unwrap_or_default();
+    if !EDIT_COMMANDS.contains(&command) {
+        bail!(
+            "\"args\" must start with one of: {}",
+            EDIT_COMMANDS.join(", ")
+        );
+    }
+    if let Some(flag) = args.iter().find(|arg| {
+        RESERVED_FLAGS
+            .iter()
+            .any(|flag| *arg == flag || arg.starts_with(&format!("{flag}=")))
+    }) {
+        bail!("{flag} is managed by the server; use /preview then /apply");
+    }
+    Ok(args)
+}
+
+fn run_safeedit(exe: &Path, args: &[String], apply: bool) -> Result<Run> {
+    let events_path = std::env::temp_dir().join(format!(
+        "safeedit-serve-{}-{}.ndjson",
+        std::process::id(),
+        nonce()
+    ));
+    let mut command = Command::new(exe);
+    command
+        .args(args)
+        .arg("--no-input")
+        .arg("--json-file")
+        .arg(&events_path);
+    if apply {
+        command.args(["--apply", "--yes"]);
+    }
+    let output = command
+        .stdin(Stdio::null())
+        .env_remove("RUST_BACKTRACE")
+        .output()
+        .context("running safeedit")?;
+    let events = std::fs::read_to_string(&events_path)
+        .unwrap_or_default()
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect();
+    let _ = std::fs::remove_file(&events_path);
+    let mut text = String::from_utf8_lossy(&output.stdout).into_owned();
+    text.push_str(&String::from_utf8_lossy(&output.stderr));
+    Ok(Run {
+        success: output.status.success(),
+        events,
+        output: text,
+    })
+}
+
+// Binds a token to the exact arguments and the before-hash plus diff of every
+// file, so /apply only writes what the client was shown.
+fn preview_digest(args: &[String], events: &[JsonValue]) -> String {
+    let mut material = args.join("\0");
+    for event in events {
+        for key in ["path", "before_sha256", "diff"] {
+            material.push('\0');
+            material.push_str(event[key].as_str().unwrap_or_default());
+        }
+    }
+    sha256_hex(material.as_bytes())
+}
+
+fn issue_token(digest: &str) -> String {
+    sha256_hex(format!("{digest}:{}:{}", std::process::id(), nonce()).as_bytes())[..32].to_string()
+}
+
+fn nonce() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_nanos())
+        .unwrap_or_default()
+}
+
+fn read_request(reader: &mut impl BufRead) -> Result<Request> {
+    let mut line = String::new();
+    reader
+        .read_line(&mut line)
+        .context("reading request line")?;
+    let mut parts = line.split_whitespace();
+    let (Some(method), Some(path)) = (parts.next(), parts.next()) else {
+        bail!("malformed request line");
+    };
+    let (method, path) = (method.to_string(), path.to_string());
+    let mut length = 0usize;
+    let mut host = None;
+    let mut origin = None;
+    let mut content_type = None;
+    loop {
+        line.clear();
+        if reader.read_line(&mut line).context("reading headers")? == 0 {
+            break;
+        }
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        let Some((name, value)) = header.split_once(':') else {
+            continue;
+        };
+        if name.trim().eq_ignore_ascii_case("content-length") {
+            length = value
+                .trim()
+                .parse()
+                .map_err(|_| anyhow!("invalid Content-Length"))?;
+        } else if name.trim().eq_ignore_ascii_case("host") {
+            host = Some(value.trim().to_string());
+        } else if name.trim().eq_ignore_ascii_case("origin") {
+            origin = Some(value.trim().to_string());
+        } else if name.trim().eq_ignore_ascii_case("content-type") {
+            content_type = Some(value.trim().to_string());
+        }
+    }
+    if length > MAX_BODY_BYTES {
+        bail!("request body exceeds {MAX_BODY_BYTES} bytes");
+    }
+    let mut body = vec![0; length];
+    reader
+        .read_exact(&mut body)
+        .context("reading request body")?;
+    Ok(Request {
+        method,
+        path,
+        host,
+        origin,
+        content_type,
+        body,
+    })
+}
+
+fn is_loopback_host(host: &str) -> bool {
+    let name = match host.strip_prefix('[') {
+        Some(bracketed) => bracketed.split(']').next().unwrap_or_default(),
+        None => host.split(':').next().unwrap_or_default(),
+    };
+    name.eq_ignore_ascii_case("localhost")
+        || name.parse::<IpAddr>().is_ok_and(|ip| ip.is_loopback())
+}
+
+fn is_loopback_origin(origin: &str) -> bool {
+    origin
+        .strip_prefix("http://")
+        .or_else(|| origin.strip_prefix("https://"))
+        .is_some_and(|authority| !authority.contains('/') && is_loopback_host(authority))
+}
+
+fn write_response(stream: &mut TcpStream, response: &Response) -> Result<()> {
+    let reason = match response.status {
+        200 => "OK",
+        400 => "Bad Request",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        409 => "Conflict",
+        415 => "Unsupported Media Type",
+        422 => "Unprocessable Entity",
+        _ => "Internal Server Error",
+    };
+    let body = serde_json::to_string(&response.body)?;
+    write!(
+        stream,
+        "HTTP/1.1 {} {reason}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        response.status,
+        body.len()
+    )?;
+    stream.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn requests_parse_and_reserved_flags_are_refused() {
+        let raw = b"POST /preview HTTP/1.1\r\nHost: x\r\ncontent-length: 18\r\n\r\n{\"args\":[\"stats\"]}";
+        let request = read_request(&mut &raw[..]).unwrap();
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.path, "/preview");
+        assert!(parse_args(&request.body).is_err());
+
+        let args =
+            parse_args(br#"{"args":["replace","--pattern","a","--with","b","x.txt"]}"#).unwrap();
+        assert_eq!(args.len(), 6);
+        assert!(parse_args(br#"{"args":["replace","--apply"]}"#).is_err());
+        assert!(parse_args(br#"{"args":["replace","--json-file=out"]}"#).is_err());
+        for flag in ["--preview-dir", "--undo-log=x", "--log-file", "--manifest"] {
+            let body = json!({ "args": ["replace", flag, "/tmp/x"] }).to_string();
+            assert!(parse_args(body.as_bytes()).is_err(), "{flag}");
+        }
+    }
+
+    #[test]
+    fn foreign_origins_and_non_json_posts_are_refused() {
+        let request = |origin: Option<&str>, content_type: Option<&str>| Request {
+            method: "POST".into(),
+            path: "/preview".into(),
+            host: Some("127.0.0.1:8787".into()),
+            origin: origin.map(str::to_string),
+            content_type: content_type.map(str::to_string),
+            body: Vec::new(),
+        };
+        let status = |request: &Request| refuse(request, true).map(|response| response.status);
+        assert_eq!(status(&request(None, Some("application/json"))), None);
+        assert_eq!(
+            status(&request(
+                Some("http://localhost:3000"),
+                Some("application/json; charset=utf-8")
+            )),
+            None
+        );
+        assert_eq!(
+            status(&request(
+                Some("https://evil.example"),
+                Some("application/json")
+            )),
+            Some(403)
+        );
+        assert_eq!(
+            status(&request(Some("null"), Some("application/json"))),
+            Some(403)
+        );
+        assert_eq!(status(&request(None, Some("text/plain"))), Some(415));
+        assert_eq!(status(&request(None, None)), Some(415));
+    }
+
+    #[test]
+    fn only_loopback_host_headers_pass() {
+        let raw = b"GET /health HTTP/1.1\r\nhost: 127.0.0.1:8787\r\n\r\n";
+        let request = read_request(&mut &raw[..]).unwrap();
+        assert_eq!(request.host.as_deref(), Some("127.0.0.1:8787"));
+        for host in [
+            "127.0.0.1:8787",
+            "localhost",
+            "LOCALHOST:80",
+            "[::1]:8787",
+            "127.0.0.2",
+        ] {
+            assert!(is_loopback_host(host), "{host}");
+        }
+        for host in [
+            "attacker.example:8787",
+            "localhost.attacker.example",
+            "10.0.0.1",
+            "[::2]:80",
+            "",
+        ] {
+            assert!(!is_loopback_host(host), "{host}");
+        }
+    }
+
+    #[test]
+    fn digest_tracks_file_state() {
+        let args = vec!["replace".to_string()];
+        let before = [json!({"path": "a", "before_sha256": "1", "diff": "-a\n+b\n"})];
+        let after = [json!({"path": "a", "before_sha256": "2", "diff": "-a\n+b\n"})];
+        assert_ne!(
+            preview_digest(&args, &before),
+            preview_digest(&args, &after)
+        );
+        assert_eq!(
+            preview_digest(&args, &before),
+            preview_digest(&args, &before)
+        );
+    }
+}
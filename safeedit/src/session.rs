@@ -0,0 +1,190 @@
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use anyhow::{Context, Result, bail};
+use serde::{Deserialize, Serialize};
+use time::{OffsetDateTime, format_description::well_known::Rfc3339};
+
+use crate::integrity::sha256_hex;
+use crate::logging;
+
+const SESSION_DIR: &str = "sessions";
+const RECORDS_FILE: &str = "records.jsonl";
+pub const SESSION_ENV: &str = "SAFEEDIT_SESSION";
+
+static SESSION_ID: OnceLock<String> = OnceLock::new();
+
+pub fn current_id() -> &'static str {
+    SESSION_ID.get_or_init(|| {
+        if let Ok(id) = env::var(SESSION_ENV)
+            && is_valid_id(&id)
+        {
+            return id;
+        }
+        let now = OffsetDateTime::now_utc();
+        format!(
+            "{:04}{:02}{:02}T{:02}{:02}{:02}Z-{}",
+            now.year(),
+            u8::from(now.month()),
+            now.day(),
+            now.hour(),
+            now.minute(),
+            now.second(),
+            std::process::id()
+        )
+    })
+}
+
+fn is_valid_id(id: &str) -> bool {
+    !id.is_empty()
+        && id
+            .chars()
+            .all(|ch| ch.is_ascii_alphanumeric() || matches!(ch, '-' | '_' | '.'))
+        && id != "."
+        && id != ".."
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionRecord {
+    pub seq: usize,
+    pub timestamp: String,
+    pub path: PathBuf,
+    pub original_hash: Option<String>,
+    pub new_hash: Option<String>,
+}
+
+pub fn record(path: &Path, original: Option<&[u8]>, new: Option<&[u8]>) -> Result<()> {
+    let path = logging::staged_name(path)
+        .unwrap_or_else(|| std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf()));
+    record_in(&session_root(), current_id(), &path, original, new)
+}
+
+fn record_in(
+    root: &Path,
+    id: &str,
+    path: &Path,
+    original: Option<&[u8]>,
+    new: Option<&[u8]>,
+) -> Result<()> {
+    let dir = root.join(id);
+    fs::create_dir_all(&dir).with_context(|| format!("creating {}", dir.display()))?;
+    let seq = load_in(root, id)?.len();
+    if let Some(bytes) = original {
+        let snapshot = dir.join(format!("{seq:05}.orig"));
+        fs::write(&snapshot, bytes)
+            .with_context(|| format!("writing session snapshot {}", snapshot.display()))?;
+    }
+    let entry = SessionRecord {
+        seq,
+        timestamp: OffsetDateTime::now_utc()
+            .format(&Rfc3339)
+            .unwrap_or_else(|_| "unknown".into()),
+        path: path.to_path_buf(),
+        original_hash: original.map(sha256_hex),
+        new_hash: new.map(sha256_hex),
+    };
+    let records = dir.join(RECORDS_FILE);
+    let mut file = OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(&records)
+        .with_context(|| format!("opening {}", records.display()))?;
+    writeln!(file, "{}", serde_json::to_string(&entry)?)?;
+    Ok(())
+}
+
+pub fn load(id: &str) -> Result<Vec<SessionRecord>> {
+    if !is_valid_id(id) {
+        bail!("invalid session id '{id}'");
+    }
+    load_in(&session_root(), id)
+}
+
+fn load_in(root: &Path, id: &str) -> Result<Vec<SessionRecord>> {
+    let path = root.join(id).join(RECORDS_FILE);
+    let Ok(file) = fs::File::open(&path) else {
+        return Ok(Vec::new());
+    };
+    let mut records = Vec::new();
+    for line in BufReader::new(file).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        records.push(
+            serde_json::from_str(&line)
+                .with_context(|| format!("parsing session record in {}", path.display()))?,
+        );
+    }
+    Ok(records)
+}
+
+pub fn snapshot(id: &str, record: &SessionRecord) -> Result<Option<Vec<u8>>> {
+    snapshot_in(&session_root(), id, record)
+}
+
+fn snapshot_in(root: &Path, id: &str, record: &SessionRecord) -> Result<Option<Vec<u8>>> {
+    if record.original_hash.is_none() {
+        return Ok(None);
+    }
+    let path = root.join(id).join(format!("{:05}.orig", record.seq));
+    fs::read(&path)
+        .map(Some)
+        .with_context(|| format!("reading session snapshot {}", path.display()))
+}
+
+pub fn has_records(id: &str) -> bool {
+    session_root().join(id).join(RECORDS_FILE).exists()
+}
+
+fn session_root() -> PathBuf {
+    logging::state_dir().join(SESSION_DIR)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn records_and_snapshots_round_trip() {
+        let temp = tempdir().unwrap();
+        let root = temp.path().join("sessions");
+        let target = temp.path().join("file.txt");
+        record_in(&root, "s1", &target, Some(b"old"), Some(b"new")).unwrap();
+        record_in(&root, "s1", &target, Some(b"new"), Some(b"newer")).unwrap();
+        record_in(
+            &root,
+            "s1",
+            &temp.path().join("created.txt"),
+            None,
+            Some(b"x"),
+        )
+        .unwrap();
+        let records = load_in(&root, "s1").unwrap();
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[1].seq, 1);
+        assert_eq!(
+            records[1].new_hash.as_deref(),
+            Some(sha256_hex(b"newer").as_str())
+        );
+        assert_eq!(
+            snapshot_in(&root, "s1", &records[0]).unwrap().unwrap(),
+            b"old"
+        );
+        assert!(snapshot_in(&root, "s1", &records[2]).unwrap().is_none());
+        assert!(load_in(&root, "missing").unwrap().is_empty());
+    }
+
+    #[test]
+    fn session_ids_are_path_safe() {
+        assert!(is_valid_id("20251108T140000Z-42"));
+        assert!(is_valid_id("refactor_sweep.1"));
+        assert!(!is_valid_id("../etc"));
+        assert!(!is_valid_id(".."));
+        assert!(!is_valid_id(""));
+    }
+}
@@ -0,0 +1,130 @@
+use std::fs;
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+
+use crate::encoding::EncodingStrategy;
+use crate::files::FileEntry;
+use crate::normalize::{self, LineEndingCounts, NormalizeOptions};
+
+#[derive(Debug, Serialize)]
+pub struct FileStats {
+    pub path: String,
+    pub bytes: u64,
+    pub binary: bool,
+    pub lines: Option<usize>,
+    pub encoding: Option<String>,
+    pub encoding_source: Option<String>,
+    pub encoding_score: Option<f32>,
+    pub decode_errors: Option<bool>,
+    pub line_endings: Option<LineEndingCounts>,
+    pub line_ending_style: Option<&'static str>,
+    pub longest_line: Option<usize>,
+    pub longest_line_number: Option<usize>,
+    pub trailing_whitespace: Option<usize>,
+}
+
+pub fn collect(entry: &FileEntry, encoding: &EncodingStrategy) -> Result<FileStats> {
+    let mut stats = FileStats {
+        path: entry.path.display().to_string(),
+        bytes: entry.metadata.len,
+        binary: entry.metadata.is_probably_binary,
+        lines: None,
+        encoding: None,
+        encoding_source: None,
+        encoding_score: None,
+        decode_errors: None,
+        line_endings: None,
+        line_ending_style: None,
+        longest_line: None,
+        longest_line_number: None,
+        trailing_whitespace: None,
+    };
+    if stats.binary {
+        return Ok(stats);
+    }
+
+    let bytes =
+        fs::read(&entry.path).with_context(|| format!("reading {}", entry.path.display()))?;
+    let decoded = encoding.decode(&bytes);
+    let text = &decoded.text;
+    let eol = normalize::count_line_endings(text);
+    let mut longest = (0usize, 0usize);
+    let mut line_count = 0usize;
+    for (idx, line) in text.lines().enumerate() {
+        line_count += 1;
+        let width = line.chars().count();
+        if width > longest.0 {
+            longest = (width, idx + 1);
+        }
+    }
+    let report = normalize::normalize_text(text, &scan_trailing_only()).report;
+
+    stats.bytes = bytes.len() as u64;
+    stats.lines = Some(line_count);
+    stats.encoding = Some(decoded.decision.encoding.name().to_string());
+    stats.encoding_source = Some(decoded.decision.source.to_string());
+    stats.encoding_score = Some(decoded.decision.score);
+    stats.decode_errors = Some(decoded.had_errors);
+    stats.line_endings = Some(eol);
+    stats.line_ending_style = Some(eol.label());
+    stats.longest_line = Some(longest.0);
+    stats.longest_line_number = (longest.1 > 0).then_some(longest.1);
+    stats.trailing_whitespace = report.trailing_spaces;
+    Ok(stats)
+}
+
+fn scan_trailing_only() -> NormalizeOptions {
+    NormalizeOptions {
+        strip_zero_width: false,
+        strip_control: false,
+        strip_bidi: false,
+        map_confusables: false,
+        ascii_punctuation: false,
+        punctuation_map: Vec::new(),
+        strip_classes: Vec::new(),
+        trim_trailing_space: false,
+        ensure_eol: false,
+        indent_conversion: None,
+        max_blank_lines: None,
+        max_line_length: None,
+        wrap_long_lines: false,
+        detect_zero_width: false,
+        detect_control: false,
+        detect_bidi: false,
+        detect_confusables: false,
+        detect_punctuation: false,
+        detect_trailing_space: true,
+        detect_indentation: false,
+        detect_line_endings: false,
+        detect_final_newline: false,
+        record_locations: false,
+        unicode_form: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::files::FileMetadata;
+    use tempfile::tempdir;
+
+    #[test]
+    fn collect_reports_longest_line_and_eol_style() {
+        let temp = tempdir().expect("temp dir");
+        let path = temp.path().join("sample.txt");
+        fs::write(&path, "short\r\na much longer line  \r\n").expect("write sample");
+        let entry = FileEntry {
+            path,
+            metadata: FileMetadata {
+                len: 0,
+                is_probably_binary: false,
+            },
+        };
+        let stats = collect(&entry, &EncodingStrategy::new(None).unwrap()).expect("stats");
+        assert_eq!(stats.lines, Some(2));
+        assert_eq!(stats.line_ending_style, Some("crlf"));
+        assert_eq!(stats.longest_line_number, Some(2));
+        assert_eq!(stats.trailing_whitespace, Some(2));
+    }
+}
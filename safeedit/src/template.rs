@@ -0,0 +1,81 @@
+use std::collections::BTreeMap;
+
+use anyhow::{Result, anyhow, bail};
+
+pub fn parse_var(spec: &str) -> Result<(String, String)> {
+    let (key, value) = spec
+        .split_once('=')
+        .ok_or_else(|| anyhow!("variable '{spec}' must look like key=value"))?;
+    let key = key.trim();
+    if key.is_empty()
+        || !key
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == '_' || c == '-')
+    {
+        bail!("invalid variable name '{key}'");
+    }
+    Ok((key.to_string(), value.to_string()))
+}
+
+pub fn render(template: &str, vars: &BTreeMap<String, String>) -> Result<String> {
+    let mut out = String::with_capacity(template.len());
+    let mut missing = Vec::new();
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let Some(end) = after.find("}}") else {
+            bail!("unterminated '{{{{' placeholder in template");
+        };
+        let name = after[..end].trim();
+        match lookup(name, vars) {
+            Some(value) => out.push_str(&value),
+            None => {
+                if !missing.iter().any(|m| m == name) {
+                    missing.push(name.to_string());
+                }
+            }
+        }
+        rest = &after[end + 2..];
+    }
+    out.push_str(rest);
+    if !missing.is_empty() {
+        bail!(
+            "template references undefined variable(s): {} (pass --var name=value or set the environment variable)",
+            missing.join(", ")
+        );
+    }
+    Ok(out)
+}
+
+fn lookup(name: &str, vars: &BTreeMap<String, String>) -> Option<String> {
+    if let Some(env_name) = name.strip_prefix("env:") {
+        return std::env::var(env_name.trim()).ok();
+    }
+    vars.get(name).cloned().or_else(|| std::env::var(name).ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_substitutes_vars_and_env() {
+        let mut vars = BTreeMap::new();
+        vars.insert("name".to_string(), "widget".to_string());
+        let home = std::env::var("PATH").expect("PATH is set");
+        let rendered = render("mod {{ name }}; // {{env:PATH}}", &vars).expect("render");
+        assert_eq!(rendered, format!("mod widget; // {home}"));
+    }
+
+    #[test]
+    fn render_reports_every_missing_variable() {
+        let err = render("{{a}} {{b}} {{a}}", &BTreeMap::new()).expect_err("missing vars");
+        assert!(err.to_string().contains("a, b"));
+        assert!(parse_var("novalue").is_err());
+        assert_eq!(
+            parse_var("k=v=w").expect("var"),
+            ("k".to_string(), "v=w".to_string())
+        );
+    }
+}
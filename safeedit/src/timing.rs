@@ -0,0 +1,210 @@
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use clap::ValueEnum;
+use serde_json::{Value as JsonValue, json};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Phase {
+    Resolve,
+    Decode,
+    Transform,
+    Diff,
+    Write,
+}
+
+impl Phase {
+    const ALL: [Phase; 5] = [
+        Phase::Resolve,
+        Phase::Decode,
+        Phase::Transform,
+        Phase::Diff,
+        Phase::Write,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            Phase::Resolve => "resolve",
+            Phase::Decode => "decode",
+            Phase::Transform => "transform",
+            Phase::Diff => "diff",
+            Phase::Write => "write",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum TimingFormat {
+    Table,
+    Json,
+}
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static SAMPLES: Mutex<Vec<(Phase, Option<PathBuf>, Duration)>> = Mutex::new(Vec::new());
+
+// Table rows beyond this are folded into the aggregate; JSON lists every file.
+const TABLE_FILES: usize = 20;
+
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+// Records the elapsed time for `phase` when dropped; a no-op unless --timing
+// is on.
+pub struct Timer {
+    phase: Phase,
+    path: Option<PathBuf>,
+    start: Option<Instant>,
+}
+
+pub fn start(phase: Phase, path: Option<&Path>) -> Timer {
+    let enabled = ENABLED.load(Ordering::Relaxed);
+    Timer {
+        phase,
+        path: path.filter(|_| enabled).map(Path::to_path_buf),
+        start: enabled.then(Instant::now),
+    }
+}
+
+impl Drop for Timer {
+    fn drop(&mut self) {
+        if let Some(start) = self.start
+            && let Ok(mut samples) = SAMPLES.lock()
+        {
+            samples.push((self.phase, self.path.take(), start.elapsed()));
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Summary {
+    totals: BTreeMap<Phase, Duration>,
+    files: Vec<(PathBuf, BTreeMap<Phase, Duration>)>,
+}
+
+fn summarize(samples: &[(Phase, Option<PathBuf>, Duration)]) -> Summary {
+    let mut summary = Summary::default();
+    let mut by_file: BTreeMap<PathBuf, BTreeMap<Phase, Duration>> = BTreeMap::new();
+    for (phase, path, elapsed) in samples {
+        *summary.totals.entry(*phase).or_default() += *elapsed;
+        if let Some(path) = path {
+            *by_file
+                .entry(path.clone())
+                .or_default()
+                .entry(*phase)
+                .or_default() += *elapsed;
+        }
+    }
+    summary.files = by_file.into_iter().collect();
+    summary
+        .files
+        .sort_by_key(|(_, phases)| std::cmp::Reverse(phases.values().sum::<Duration>()));
+    summary
+}
+
+fn millis(duration: Duration) -> f64 {
+    (duration.as_secs_f64() * 100_000.0).round() / 100.0
+}
+
+pub fn report(format: TimingFormat) -> Option<String> {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return None;
+    }
+    let samples = SAMPLES.lock().ok()?;
+    let summary = summarize(&samples);
+    Some(match format {
+        TimingFormat::Json => render_json(&summary).to_string(),
+        TimingFormat::Table => render_table(&summary),
+    })
+}
+
+fn render_json(summary: &Summary) -> JsonValue {
+    let phases = |map: &BTreeMap<Phase, Duration>| {
+        let mut object = serde_json::Map::new();
+        for phase in Phase::ALL {
+            object.insert(
+                format!("{}_ms", phase.name()),
+                json!(millis(map.get(&phase).copied().unwrap_or_default())),
+            );
+        }
+        object.insert(
+            "total_ms".into(),
+            json!(millis(map.values().sum::<Duration>())),
+        );
+        JsonValue::Object(object)
+    };
+    let files: Vec<JsonValue> = summary
+        .files
+        .iter()
+        .map(|(path, map)| {
+            let mut row = phases(map);
+            row["path"] = json!(path.display().to_string());
+            row
+        })
+        .collect();
+    json!({ "timing": { "total": phases(&summary.totals), "files": files } })
+}
+
+fn render_table(summary: &Summary) -> String {
+    let mut out = String::from("timing (ms):\n");
+    let _ = writeln!(
+        out,
+        "  {:>9} {:>9} {:>9} {:>9} {:>9} {:>9}  file",
+        "resolve", "decode", "transform", "diff", "write", "total"
+    );
+    let row = |out: &mut String, map: &BTreeMap<Phase, Duration>, label: &str| {
+        let cells: Vec<String> = Phase::ALL
+            .iter()
+            .map(|phase| match map.get(phase) {
+                Some(elapsed) => format!("{:>9.2}", millis(*elapsed)),
+                None => format!("{:>9}", "-"),
+            })
+            .collect();
+        let total = millis(map.values().sum::<Duration>());
+        let _ = writeln!(out, "  {} {total:>9.2}  {label}", cells.join(" "));
+    };
+    for (path, map) in summary.files.iter().take(TABLE_FILES) {
+        row(&mut out, map, &path.display().to_string());
+    }
+    let hidden = summary.files.len().saturating_sub(TABLE_FILES);
+    if hidden > 0 {
+        let _ = writeln!(
+            out,
+            "  ... {hidden} faster file(s) omitted (use --timing-format json for all)"
+        );
+    }
+    row(
+        &mut out,
+        &summary.totals,
+        &format!("TOTAL ({} file(s))", summary.files.len()),
+    );
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn samples_aggregate_per_file_and_phase() {
+        let ms = Duration::from_millis;
+        let samples = vec![
+            (Phase::Resolve, None, ms(4)),
+            (Phase::Decode, Some(PathBuf::from("a")), ms(1)),
+            (Phase::Transform, Some(PathBuf::from("a")), ms(2)),
+            (Phase::Decode, Some(PathBuf::from("b")), ms(5)),
+            (Phase::Write, Some(PathBuf::from("b")), ms(3)),
+        ];
+        let summary = summarize(&samples);
+        assert_eq!(summary.totals[&Phase::Decode], ms(6));
+        assert_eq!(summary.files[0].0, PathBuf::from("b"));
+        let json = render_json(&summary);
+        assert_eq!(json["timing"]["total"]["total_ms"], json!(15.0));
+        assert_eq!(json["timing"]["files"][1]["transform_ms"], json!(2.0));
+        assert!(render_table(&summary).contains("TOTAL (2 file(s))"));
+    }
+}
@@ -4,6 +4,9 @@ use anyhow::{Context, Result};
 
 use crate::encoding::{DecodedText, EncodingStrategy};
 use crate::files::FileEntry;
+use crate::integrity::sha256_hex;
+use crate::logging;
+use crate::timing::{self, Phase};
 
 pub struct TransformContext<'a> {
     pub entry: &'a FileEntry,
@@ -13,6 +16,7 @@ pub struct TransformContext<'a> {
 pub struct TransformResult {
     pub decoded: DecodedText,
     pub new_text: String,
+    pub source_hash: Option<String>,
 }
 
 pub fn run_transform<F>(
@@ -30,9 +34,15 @@ where
         return Ok(None);
     }
 
+    let decode_timer = timing::start(Phase::Decode, Some(&ctx.entry.path));
     let bytes = fs::read(&ctx.entry.path)
         .with_context(|| format!("failed to read {}", ctx.entry.path.display()))?;
     let decoded = ctx.encoding.decode(&bytes);
+    drop(decode_timer);
+    crate::explain::note_decoded(&ctx.entry.path, &decoded);
+    if !ctx.encoding.accept(&ctx.entry.path, &decoded.decision)? {
+        return Ok(None);
+    }
 
     if decoded.had_errors {
         println!(
@@ -41,10 +51,20 @@ where
         );
     }
 
-    let Some(new_text) = transformer(&decoded)? else {
-        println!("no changes for {}", ctx.entry.path.display());
+    let transform_timer = timing::start(Phase::Transform, Some(&ctx.entry.path));
+    let transformed = transformer(&decoded)?;
+    drop(transform_timer);
+    let Some(new_text) = transformed else {
+        println!(
+            "no changes for {}",
+            logging::recorded_name(&ctx.entry.path).display()
+        );
         return Ok(None);
     };
 
-    Ok(Some(TransformResult { decoded, new_text }))
+    Ok(Some(TransformResult {
+        decoded,
+        new_text,
+        source_hash: Some(sha256_hex(&bytes)),
+    }))
 }
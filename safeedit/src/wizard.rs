@@ -0,0 +1,212 @@
+use std::io::{self, Write};
+
+use anyhow::{Result, anyhow, bail};
+
+const OPERATIONS: [&str; 3] = ["replace", "rename", "normalize"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NextStep {
+    Preview,
+    Apply,
+    Save,
+    Quit,
+}
+
+// Asks for the operation, targets, and the handful of options most runs need,
+// returning the argument list after `safeedit`. Anything rarer can be typed
+// as extra flags at the end.
+pub fn collect_command() -> Result<Vec<String>> {
+    let operation = loop {
+        let answer = ask("Operation [replace/rename/normalize]", Some("replace"))?;
+        let answer = answer.to_lowercase();
+        if let Some(found) = OPERATIONS.iter().find(|name| name.starts_with(&answer)) {
+            break *found;
+        }
+        println!("Please choose one of: {}.", OPERATIONS.join(", "));
+    };
+    let mut args = vec![operation.to_string()];
+    let targets = loop {
+        let answer = split_args(&ask("Files or globs (space-separated)", None)?)?;
+        if !answer.is_empty() {
+            break answer;
+        }
+        println!("At least one file or glob is required.");
+    };
+    args.extend(targets);
+    match operation {
+        "replace" => {
+            args.push("--pattern".into());
+            args.push(ask_required("Pattern to find")?);
+            args.push("--with".into());
+            args.push(ask("Replacement text", Some(""))?);
+            if confirm("Treat the pattern as a regex?", false)? {
+                args.push("--regex".into());
+            }
+            let expect = ask("Expected number of matches (blank to skip)", Some(""))?;
+            if !expect.is_empty() {
+                expect
+                    .parse::<usize>()
+                    .map_err(|_| anyhow!("expected a number of matches, got '{expect}'"))?;
+                args.push("--expect".into());
+                args.push(expect);
+            }
+        }
+        "rename" => {
+            args.push("--from".into());
+            args.push(ask_required("Identifier to rename")?);
+            args.push("--to".into());
+            args.push(ask_required("New name")?);
+            if confirm("Match whole words only?", true)? {
+                args.push("--word-boundary".into());
+            }
+        }
+        _ => {
+            for (question, flag, default) in [
+                ("Trim trailing whitespace?", "--trim-trailing-space", true),
+                ("Ensure a final newline?", "--ensure-eol", true),
+                ("Strip zero-width characters?", "--strip-zero-width", false),
+            ] {
+                if confirm(question, default)? {
+                    args.push(flag.into());
+                }
+            }
+        }
+    }
+    let extra = ask(
+        "Extra flags (optional, e.g. --exclude '*.min.js')",
+        Some(""),
+    )?;
+    args.extend(split_args(&extra)?);
+    Ok(args)
+}
+
+pub fn choose_next_step() -> Result<NextStep> {
+    loop {
+        let answer = ask(
+            "[p]review (dry run), [a]pply, [s]ave as batch step, or [q]uit",
+            Some("p"),
+        )?;
+        match answer.to_lowercase().as_str() {
+            "p" | "preview" => return Ok(NextStep::Preview),
+            "a" | "apply" => return Ok(NextStep::Apply),
+            "s" | "save" => return Ok(NextStep::Save),
+            "q" | "quit" => return Ok(NextStep::Quit),
+            _ => println!("Please enter p, a, s, or q."),
+        }
+    }
+}
+
+pub fn ask(question: &str, default: Option<&str>) -> Result<String> {
+    match default {
+        Some(value) if !value.is_empty() => print!("{question} [{value}]: "),
+        _ => print!("{question}: "),
+    }
+    io::stdout().flush()?;
+    let input = crate::input::read_line()?;
+    if input.is_empty() {
+        bail!("input closed before the wizard finished");
+    }
+    let answer = input.trim_end_matches(['\r', '\n']).trim().to_string();
+    Ok(match default {
+        Some(value) if answer.is_empty() => value.to_string(),
+        _ => answer,
+    })
+}
+
+fn ask_required(question: &str) -> Result<String> {
+    loop {
+        let answer = ask(question, None)?;
+        if !answer.is_empty() {
+            return Ok(answer);
+        }
+        println!("A value is required.");
+    }
+}
+
+fn confirm(question: &str, default: bool) -> Result<bool> {
+    let hint = if default { "Y/n" } else { "y/N" };
+    loop {
+        let answer = ask(&format!("{question} [{hint}]"), Some(""))?;
+        match answer.to_lowercase().as_str() {
+            "" => return Ok(default),
+            "y" | "yes" => return Ok(true),
+            "n" | "no" => return Ok(false),
+            _ => println!("Please enter y or n."),
+        }
+    }
+}
+
+// Splits on whitespace, honouring single and double quotes so answers such
+// as `"my file.txt" src/*.rs` keep their spaces.
+pub fn split_args(raw: &str) -> Result<Vec<String>> {
+    let mut args = Vec::new();
+    let mut current = String::new();
+    let mut quote: Option<char> = None;
+    let mut in_arg = false;
+    for ch in raw.chars() {
+        match quote {
+            Some(open) if ch == open => quote = None,
+            Some(_) => current.push(ch),
+            None if ch == '\'' || ch == '"' => {
+                quote = Some(ch);
+                in_arg = true;
+            }
+            None if ch.is_whitespace() => {
+                if in_arg {
+                    args.push(std::mem::take(&mut current));
+                    in_arg = false;
+                }
+            }
+            None => {
+                current.push(ch);
+                in_arg = true;
+            }
+        }
+    }
+    if quote.is_some() {
+        bail!("unterminated quote in '{raw}'");
+    }
+    if in_arg {
+        args.push(current);
+    }
+    Ok(args)
+}
+
+pub fn command_line(args: &[String]) -> String {
+    let mut line = String::from("safeedit");
+    for arg in args {
+        line.push(' ');
+        line.push_str(&quote_arg(arg));
+    }
+    line
+}
+
+fn quote_arg(arg: &str) -> String {
+    let plain = !arg.is_empty()
+        && arg
+            .chars()
+            .all(|ch| ch.is_ascii_alphanumeric() || "-_./:=@%+,".contains(ch));
+    if plain {
+        arg.to_string()
+    } else {
+        format!("'{}'", arg.replace('\'', "'\\''"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn answers_split_and_quote_round_trip() {
+        let args = split_args(r#"src/*.rs "my file.txt" it's"#).unwrap_err();
+        assert!(args.to_string().contains("unterminated"));
+        let args = split_args(r#"src/*.rs "my file.txt"  '' plain"#).unwrap();
+        assert_eq!(args, ["src/*.rs", "my file.txt", "", "plain"]);
+        assert_eq!(
+            command_line(&args),
+            "safeedit 'src/*.rs' 'my file.txt' '' plain"
+        );
+        assert_eq!(command_line(&["it's".into()]), r"safeedit 'it'\''s'");
+    }
+}
@@ -0,0 +1,76 @@
+use std::fs;
+use std::path::Path;
+use std::process::{Command, Output};
+
+use serde_json::Value;
+
+fn compare(dir: &Path, left: &str, right: &str) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_safeedit"))
+        .args(["compare", left, right, "--json"])
+        .args(["--pager", "never", "--color", "never"])
+        .current_dir(dir)
+        .env_remove("RUST_BACKTRACE")
+        .output()
+        .expect("running safeedit compare")
+}
+
+fn json_event(stdout: &str) -> Value {
+    let line = stdout
+        .lines()
+        .rev()
+        .find(|line| line.starts_with('{'))
+        .unwrap_or_else(|| panic!("no JSON event in:\n{stdout}"));
+    serde_json::from_str(line).unwrap()
+}
+
+#[test]
+fn identical_files_compare_clean() {
+    let temp = tempfile::Builder::new()
+        .prefix("compare")
+        .tempdir()
+        .unwrap();
+    fs::create_dir(temp.path().join(".git")).unwrap();
+    fs::write(temp.path().join("a.txt"), "same\ntext\n").unwrap();
+    fs::write(temp.path().join("b.txt"), "same\ntext\n").unwrap();
+
+    let output = compare(temp.path(), "a.txt", "b.txt");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{stdout}");
+    assert!(
+        stdout.contains("files are identical after decoding."),
+        "{stdout}"
+    );
+    assert!(!stdout.contains("+++ b.txt"), "{stdout}");
+    let event = json_event(&stdout);
+    assert_eq!(event["identical"], true);
+    assert_eq!(event["spans"], Value::Array(Vec::new()));
+}
+
+#[test]
+fn differing_files_show_a_diff_without_touching_either() {
+    let temp = tempfile::Builder::new()
+        .prefix("compare")
+        .tempdir()
+        .unwrap();
+    fs::create_dir(temp.path().join(".git")).unwrap();
+    fs::write(temp.path().join("a.txt"), "one\ntwo\nthree\n").unwrap();
+    fs::write(temp.path().join("b.txt"), "one\nTWO\nthree\n").unwrap();
+
+    let output = compare(temp.path(), "a.txt", "b.txt");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{stdout}");
+    assert!(stdout.contains("--- a.txt\n+++ b.txt"), "{stdout}");
+    assert!(stdout.contains("- two\n+ TWO\n"), "{stdout}");
+    assert!(!stdout.contains("identical after decoding"), "{stdout}");
+    let event = json_event(&stdout);
+    assert_eq!(event["identical"], false);
+    assert_eq!(event["spans"][0]["start"], 2);
+    assert_eq!(
+        fs::read_to_string(temp.path().join("a.txt")).unwrap(),
+        "one\ntwo\nthree\n"
+    );
+
+    let missing = compare(temp.path(), "a.txt", "gone.txt");
+    assert!(!missing.status.success());
+    assert!(String::from_utf8_lossy(&missing.stderr).contains("reading gone.txt"));
+}
@@ -0,0 +1,158 @@
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::process::{Child, Command, Stdio};
+
+use serde_json::{Value, json};
+
+struct Server {
+    child: Child,
+    addr: String,
+}
+
+impl Server {
+    fn start(dir: &std::path::Path) -> Self {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_safeedit"))
+            .args(["serve", "--http", "127.0.0.1:0"])
+            .current_dir(dir)
+            .stdout(Stdio::piped())
+            .spawn()
+            .expect("starting safeedit serve");
+        let mut banner = String::new();
+        BufReader::new(child.stdout.as_mut().unwrap())
+            .read_line(&mut banner)
+            .unwrap();
+        let addr = banner
+            .split("http://")
+            .nth(1)
+            .and_then(|rest| rest.split_whitespace().next())
+            .unwrap_or_else(|| panic!("unexpected banner: {banner}"))
+            .to_string();
+        Server { child, addr }
+    }
+
+    fn request(&self, method: &str, path: &str, host: &str, body: &Value) -> (u16, Value) {
+        self.request_with(
+            method,
+            path,
+            &[("Host", host), ("Content-Type", "application/json")],
+            body,
+        )
+    }
+
+    fn request_with(
+        &self,
+        method: &str,
+        path: &str,
+        headers: &[(&str, &str)],
+        body: &Value,
+    ) -> (u16, Value) {
+        let body = body.to_string();
+        let mut stream = TcpStream::connect(&self.addr).unwrap();
+        let headers: String = headers
+            .iter()
+            .map(|(name, value)| format!("{name}: {value}\r\n"))
+            .collect();
+        write!(
+            stream,
+            "{method} {path} HTTP/1.1\r\n{headers}Content-Length: {}\r\nConnection: close\r\n\r\n{body}",
+            body.len()
+        )
+        .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        let status = head.split_whitespace().nth(1).unwrap().parse().unwrap();
+        (status, serde_json::from_str(body).unwrap())
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+#[test]
+fn preview_then_apply_round_trip() {
+    let temp = tempfile::Builder::new().prefix("serve").tempdir().unwrap();
+    fs::create_dir(temp.path().join(".git")).unwrap();
+    let target = temp.path().join("a.txt");
+    fs::write(&target, "hello world\n").unwrap();
+    let server = Server::start(temp.path());
+    let host = server.addr.clone();
+
+    let args = json!({ "args": ["replace", "--pattern", "hello", "--with", "bye", "a.txt"] });
+    let (status, preview) = server.request("POST", "/preview", &host, &args);
+    assert_eq!(status, 200, "{preview}");
+    assert_eq!(preview["changes"], 1, "{preview}");
+    assert_eq!(fs::read_to_string(&target).unwrap(), "hello world\n");
+
+    let token = json!({ "token": preview["token"] });
+    let (status, applied) = server.request("POST", "/apply", &host, &token);
+    assert_eq!(status, 200, "{applied}");
+    assert_eq!(applied["applied"], true);
+    assert_eq!(fs::read_to_string(&target).unwrap(), "bye world\n");
+
+    // Tokens are single use.
+    let (status, _) = server.request("POST", "/apply", &host, &token);
+    assert_eq!(status, 403);
+}
+
+#[test]
+fn loopback_server_rejects_foreign_host_headers() {
+    let temp = tempfile::Builder::new().prefix("serve").tempdir().unwrap();
+    fs::create_dir(temp.path().join(".git")).unwrap();
+    let server = Server::start(temp.path());
+
+    let (status, _) = server.request("GET", "/health", "attacker.example:8787", &json!({}));
+    assert_eq!(status, 403);
+    let (status, health) = server.request("GET", "/health", "localhost", &json!({}));
+    assert_eq!(status, 200);
+    assert_eq!(health["status"], "ok");
+}
+
+#[test]
+fn cross_site_requests_cannot_drive_preview() {
+    let temp = tempfile::Builder::new().prefix("serve").tempdir().unwrap();
+    fs::create_dir(temp.path().join(".git")).unwrap();
+    fs::write(temp.path().join("a.txt"), "hello\n").unwrap();
+    let server = Server::start(temp.path());
+    let host = server.addr.clone();
+    let args = json!({ "args": ["replace", "--pattern", "hello", "--with", "bye", "a.txt"] });
+
+    // What a `no-cors` fetch from any page can send.
+    let (status, _) = server.request_with(
+        "POST",
+        "/preview",
+        &[
+            ("Host", &host),
+            ("Content-Type", "text/plain;charset=UTF-8"),
+        ],
+        &args,
+    );
+    assert_eq!(status, 415);
+    let (status, _) = server.request_with(
+        "POST",
+        "/preview",
+        &[
+            ("Host", &host),
+            ("Origin", "https://attacker.example"),
+            ("Content-Type", "application/json"),
+        ],
+        &args,
+    );
+    assert_eq!(status, 403);
+
+    let side_file = temp.path().join("previews");
+    let (status, refused) = server.request(
+        "POST",
+        "/preview",
+        &host,
+        &json!({ "args": ["replace", "--pattern", "hello", "--with", "bye",
+            "--preview-dir", side_file.to_str().unwrap(), "a.txt"] }),
+    );
+    assert_eq!(status, 400, "{refused}");
+    assert!(!side_file.exists());
+}